@@ -1827,7 +1827,6 @@ const SyncService = {
      */
     async uploadLocalStorage() {
         const keysToSync = [
-            'systemUsers',
             'productGroups',
             'writtenOffGroups',
             'confirmedInvoices',
@@ -2089,8 +2088,9 @@ const SyncService = {
                         const serverData = item.data;
                         const localData = localStorage.getItem(key);
                         
+                        // Пользователи живут в локальной базе (users), старую копию systemUsers с сервера не берём
                         // Объединяем данные (серверные + локальные без дубликатов)
-                        if (serverData && key !== 'syncDeviceId') {
+                        if (serverData && key !== 'syncDeviceId' && key !== 'systemUsers') {
                             if (!localData) {
                                 // Если локально нет данных, просто используем серверные
                                 localStorage.setItem(key, serverData);
@@ -2128,8 +2128,6 @@ const SyncService = {
         
         if (key === 'confirmedInvoices' || key === 'confirmedDeliveries') {
             idKey = 'number';
-        } else if (key === 'systemUsers') {
-            idKey = 'login';
         } else if (key === 'productGroups' || key === 'consumableGroups') {
             idKey = 'code';
         }
@@ -2834,6 +2832,14 @@ const SyncService = {
                     continue;
                 }
                 
+                // Ставка документа всегда явная: старые документы без vatRate
+                // восстанавливаем по сохранённой сумме ПДВ (нет суммы — документ без ПДВ)
+                const subtotal = Number(doc.subtotal) || 0;
+                const vatAmount = Number(doc.vatAmount) || 0;
+                const vatRate = typeof doc.vatRate === 'number'
+                    ? doc.vatRate
+                    : (vatAmount > 0 && subtotal > 0 ? Math.round(vatAmount / subtotal * 100) : 0);
+
                 // Подготавливаем данные для Rust API
                 const invoice = {
                     invoiceNumber: docNumber,
@@ -2846,7 +2852,10 @@ const SyncService = {
                     status: doc.status || 'confirmed',
                     notes: doc.notes || null,
                     paid: doc.paid || false,
-                    delivered: doc.delivered || false
+                    delivered: doc.delivered || false,
+                    // Суммы и ПДВ пересчитываются в Rust, total сверяется с расчётом
+                    vatRate: vatRate,
+                    vatExemptionCode: vatRate === 0 ? (doc.vatExemptionCode || DEFAULT_VAT_EXEMPTION_CODE) : null
                 };
                 
                 // Подготавливаем items
@@ -2856,7 +2865,8 @@ const SyncService = {
                     productName: item.product?.name || item.productName || 'Неизвестный товар',
                     quantity: item.quantity || 1,
                     price: item.price || item.product?.price || 0,
-                    total: item.total || (item.quantity || 1) * (item.price || 0)
+                    discountPct: item.discount || 0,
+                    total: item.total || (item.quantity || 1) * (item.price || 0) * (1 - (item.discount || 0) / 100)
                 }));
                 
                 // Сохраняем через Tauri API
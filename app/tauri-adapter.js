@@ -7,7 +7,7 @@
 console.log('🔧 Загрузка Tauri API adapter...');
 
 // Функция для вызова Tauri команд
-async function invoke(cmd, args = {}) {
+async function invokeRaw(cmd, args) {
     if (window.__TAURI_INTERNALS__) {
         return window.__TAURI_INTERNALS__.invoke(cmd, args);
     } else if (window.__TAURI__ && window.__TAURI__.core) {
@@ -19,8 +19,35 @@ async function invoke(cmd, args = {}) {
     }
 }
 
+// Backend закрывает сессию по бездействию: любая команда отвечает 'Session expired' / 'Not signed in'.
+// Сообщаем UI событием 'session-expired' — он возвращает форму входа.
+const SESSION_ERRORS = ['Session expired', 'Not signed in'];
+
+async function invoke(cmd, args = {}) {
+    try {
+        return await invokeRaw(cmd, args);
+    } catch (error) {
+        if (SESSION_ERRORS.includes(error) && cmd !== 'login') {
+            window.dispatchEvent(new CustomEvent('session-expired', { detail: { command: cmd } }));
+        }
+        throw error;
+    }
+}
+
 console.log('✅ Tauri invoke функция готова');
 
+// Команды с проверкой полей (клиенты, поставщики) возвращают ошибку объектом
+// { kind: 'validation', message, fields: [{ field, code, message }] } — переносим fields в Error.
+// Удаление записи, на которую ссылаются документы: { kind: 'inUse', entity, id, usages: [{ table, count }] }
+function commandError(prefix, error) {
+    const message = error && error.message ? error.message : error;
+    const err = new Error(`${prefix}: ${message}`);
+    err.kind = (error && error.kind) || 'other';
+    err.fields = (error && error.fields) || [];
+    err.usages = (error && error.usages) || [];
+    return err;
+}
+
 // API объект для работы с данными
 // ВСЕ данные сохраняются ЛОКАЛЬНО на устройстве в SQLite!
 window.api = {
@@ -46,7 +73,7 @@ window.api = {
                 return client;
             } catch (error) {
                 console.error('❌ Ошибка создания клиента:', error);
-                throw new Error(`Не удалось создать клиента: ${error}`);
+                throw commandError('Не удалось создать клиента', error);
             }
         },
         
@@ -60,10 +87,42 @@ window.api = {
                 return client;
             } catch (error) {
                 console.error('❌ Ошибка обновления клиента:', error);
-                throw new Error(`Не удалось обновить клиента: ${error}`);
+                throw commandError('Не удалось обновить клиента', error);
             }
         },
         
+        // PIB/MB без сохранения: { errors, warnings } — warnings у иностранных клиентов
+        validate: async (data) => {
+            return await invoke('validate_client', { client: data });
+        },
+
+        // Возможные дубли: [{ client, duplicate, score, matched: ['pib','mb','name',...] }]
+        findDuplicates: async (clientId = null) => {
+            try {
+                return await invoke('find_duplicate_clients', { clientId });
+            } catch (error) {
+                console.error('❌ Ошибка поиска дублей клиентов:', error);
+                throw new Error(`Не удалось найти дубли клиентов: ${error}`);
+            }
+        },
+
+        // Документы дублей переносятся на survivingId, дубли удаляются
+        merge: async (survivingId, mergedIds) => {
+            try {
+                console.log('📡 Объединяем клиентов:', mergedIds, '->', survivingId);
+                const result = await invoke('merge_clients', { survivingId, mergedIds });
+                console.log('✅ Клиенты объединены:', result);
+                return result;
+            } catch (error) {
+                console.error('❌ Ошибка объединения клиентов:', error);
+                throw new Error(`Не удалось объединить клиентов: ${error}`);
+            }
+        },
+
+        getMerges: async (clientId = null) => {
+            return await invoke('get_client_merges', { clientId });
+        },
+
         delete: async (id) => {
             try {
                 console.log('📡 Удаляем клиента из ЛОКАЛЬНОЙ базы:', id);
@@ -75,7 +134,7 @@ window.api = {
                 console.log('✅ Клиент удален');
             } catch (error) {
                 console.error('❌ Ошибка удаления клиента:', error);
-                throw new Error(`Не удалось удалить клиента: ${error}`);
+                throw commandError('Не удалось удалить клиента', error);
             }
         },
     },
@@ -147,7 +206,7 @@ window.api = {
                 console.log('✅ Товар удален');
             } catch (error) {
                 console.error('❌ Ошибка удаления товара:', error);
-                throw new Error(`Не удалось удалить товар: ${error}`);
+                throw commandError('Не удалось удалить товар', error);
             }
         },
     },
@@ -165,7 +224,207 @@ window.api = {
                 return user;
             } catch (error) {
                 console.error('❌ Ошибка авторизации:', error);
-                throw new Error('Неверный логин или пароль');
+                const message = String(error);
+                let text = 'Неверный логин или пароль';
+                if (message.includes('disabled')) {
+                    text = 'Пользователь отключён';
+                } else if (message.startsWith('Account locked until ')) {
+                    const until = new Date(message.substring('Account locked until '.length));
+                    text = `Слишком много неудачных попыток. Вход заблокирован до ${until.toLocaleTimeString('ru-RU')}`;
+                } else if (message.startsWith('Too many failed attempts')) {
+                    const seconds = (message.match(/(\d+) s/) || [])[1] || '30';
+                    text = `Слишком много неудачных попыток. Повторите через ${seconds} с`;
+                }
+                const err = new Error(text);
+                err.loginError = true;
+                throw err;
+            }
+        },
+
+        logout: async () => {
+            await invoke('logout');
+            console.log('✅ Сессия закрыта');
+        },
+
+        // Текущая сессия или ошибка, если входа не было / сессия истекла
+        getSession: async () => {
+            return await invoke('get_session');
+        },
+
+        changePassword: async (oldPassword, newPassword) => {
+            try {
+                await invoke('change_password', { oldPassword, newPassword });
+                console.log('✅ Пароль изменён');
+            } catch (error) {
+                console.error('❌ Ошибка смены пароля:', error);
+                throw commandError('Не удалось сменить пароль', error);
+            }
+        },
+    },
+
+    // ==================== SETUP ====================
+    // Первый запуск: пока пользователей нет, создаётся администратор
+    setup: {
+        status: async () => {
+            return await invoke('get_setup_status');
+        },
+
+        createInitialAdmin: async (username, password, fullName) => {
+            try {
+                const user = await invoke('create_initial_admin', { username, password, fullName });
+                console.log('✅ Администратор создан:', user.username);
+                return user;
+            } catch (error) {
+                console.error('❌ Ошибка создания администратора:', error);
+                throw commandError('Не удалось создать администратора', error);
+            }
+        },
+    },
+
+    // ==================== DATABASE ====================
+    // Шифрование файла базы (SQLCipher). Статус: { supported, encrypted, locked }
+    database: {
+        encryptionStatus: async () => {
+            return await invoke('get_database_encryption');
+        },
+
+        // Пароль владельца при запуске, до входа в систему
+        unlock: async (passphrase) => {
+            try {
+                const status = await invoke('unlock_database', { passphrase });
+                console.log('🔓 База открыта');
+                return status;
+            } catch (error) {
+                console.error('❌ Ошибка открытия базы:', error);
+                throw commandError('Не удалось открыть базу', error);
+            }
+        },
+
+        encrypt: async (passphrase) => {
+            try {
+                const status = await invoke('encrypt_database', { passphrase });
+                console.log('🔒 База зашифрована');
+                return status;
+            } catch (error) {
+                console.error('❌ Ошибка шифрования базы:', error);
+                throw commandError('Не удалось зашифровать базу', error);
+            }
+        },
+
+        rotateKey: async (currentPassphrase, newPassphrase) => {
+            try {
+                const status = await invoke('rotate_database_key', { currentPassphrase, newPassphrase });
+                console.log('🔑 Ключ базы заменён');
+                return status;
+            } catch (error) {
+                console.error('❌ Ошибка смены ключа базы:', error);
+                throw commandError('Не удалось сменить ключ базы', error);
+            }
+        },
+    },
+
+    // ==================== BACKUPS ====================
+    // Снимки базы и invoices/ (.tar.gz). Настройки: { directory, intervalHours, keepLast, keepDays }
+    backups: {
+        getSettings: async () => {
+            return await invoke('get_backup_settings');
+        },
+
+        saveSettings: async (settings) => {
+            try {
+                return await invoke('save_backup_settings', { settings });
+            } catch (error) {
+                console.error('❌ Ошибка сохранения настроек копирования:', error);
+                throw commandError('Не удалось сохранить настройки', error);
+            }
+        },
+
+        // Новые первыми: { name, path, createdAt, kind: 'scheduled' | 'manual' | 'pre-restore', sizeBytes }
+        list: async () => {
+            try {
+                return await invoke('list_backups');
+            } catch (error) {
+                console.error('❌ Ошибка получения копий:', error);
+                throw commandError('Не удалось загрузить список копий', error);
+            }
+        },
+
+        create: async () => {
+            try {
+                const backup = await invoke('create_backup');
+                console.log('💾 Резервная копия создана:', backup.name);
+                return backup;
+            } catch (error) {
+                console.error('❌ Ошибка создания копии:', error);
+                throw commandError('Не удалось создать копию', error);
+            }
+        },
+
+        // После восстановления backend закрывает сессию — нужен повторный вход
+        restore: async (name) => {
+            try {
+                const result = await invoke('restore_backup', { name });
+                console.log('♻️ Восстановлено из', name, '— страховочная копия:', result.safetyBackup.name);
+                return result;
+            } catch (error) {
+                console.error('❌ Ошибка восстановления:', error);
+                throw commandError('Не удалось восстановить копию', error);
+            }
+        },
+    },
+
+    // ==================== USERS ====================
+    // Роли и права проверяет backend; здесь только вызовы команд
+    users: {
+        getAll: async () => {
+            try {
+                return await invoke('get_users');
+            } catch (error) {
+                console.error('❌ Ошибка получения пользователей:', error);
+                throw commandError('Не удалось загрузить пользователей', error);
+            }
+        },
+
+        getRoles: async () => {
+            return await invoke('get_roles');
+        },
+
+        create: async (data) => {
+            try {
+                console.log('📡 Создаем пользователя:', data.username);
+                const user = await invoke('create_user', { user: data });
+                console.log('✅ Пользователь создан:', user.id);
+                return user;
+            } catch (error) {
+                console.error('❌ Ошибка создания пользователя:', error);
+                throw commandError('Не удалось создать пользователя', error);
+            }
+        },
+
+        update: async (id, data) => {
+            try {
+                return await invoke('update_user', { id, update: data });
+            } catch (error) {
+                console.error('❌ Ошибка обновления пользователя:', error);
+                throw commandError('Не удалось обновить пользователя', error);
+            }
+        },
+
+        setActive: async (id, active) => {
+            try {
+                return await invoke('set_user_active', { id, active });
+            } catch (error) {
+                console.error('❌ Ошибка изменения статуса пользователя:', error);
+                throw commandError('Не удалось изменить статус пользователя', error);
+            }
+        },
+
+        resetPassword: async (id, newPassword) => {
+            try {
+                await invoke('reset_user_password', { id, newPassword });
+            } catch (error) {
+                console.error('❌ Ошибка сброса пароля:', error);
+                throw commandError('Не удалось сбросить пароль', error);
             }
         },
     },
@@ -262,7 +521,47 @@ window.api = {
                 throw new Error(`Не удалось обновить инвойс: ${error}`);
             }
         },
-        
+
+        updateWithItems: async (id, invoice, items) => {
+            try {
+                console.log('📡 Обновляем инвойс вместе с позициями:', id, items.length);
+                const idStr = String(id);
+                const updated = await invoke('update_invoice_with_items', { id: idStr, invoice, items });
+                console.log('✅ Инвойс обновлен, сумма:', updated.total);
+                return updated;
+            } catch (error) {
+                console.error('❌ Ошибка обновления инвойса:', error);
+                throw new Error(`Не удалось обновить инвойс: ${error}`);
+            }
+        },
+
+        // Предрачун -> рачун ('racun') или отпремница ('otpremnica')
+        convert: async (sourceId, targetType, date = null, invoiceNumber = null) => {
+            try {
+                console.log('📡 Конвертируем документ:', sourceId, '->', targetType);
+                const created = await invoke('convert_document', { sourceId: String(sourceId), targetType, date, invoiceNumber });
+                console.log('✅ Создан документ:', created.invoiceNumber);
+                return created;
+            } catch (error) {
+                console.error('❌ Ошибка конвертации документа:', error);
+                throw new Error(`Не удалось конвертировать документ: ${error}`);
+            }
+        },
+
+        // Књижно одобрење: lines = [{ itemId, quantity }], пустой список — сторно всего документа
+        createCreditNote: async (originalInvoiceId, lines = [], reason = null, date = null) => {
+            try {
+                console.log('📡 Создаем кредит-ноту к инвойсу:', originalInvoiceId);
+                const request = { originalInvoiceId: String(originalInvoiceId), lines, reason, date };
+                const created = await invoke('create_credit_note', { request });
+                console.log('✅ Кредит-нота создана:', created.invoiceNumber, 'сумма:', created.total);
+                return created;
+            } catch (error) {
+                console.error('❌ Ошибка создания кредит-ноты:', error);
+                throw new Error(`Не удалось создать кредит-ноту: ${error}`);
+            }
+        },
+
         delete: async (id) => {
             try {
                 console.log('📡 Удаляем инвойс:', id);
@@ -272,7 +571,7 @@ window.api = {
                 return true;
             } catch (error) {
                 console.error('❌ Ошибка удаления инвойса:', error);
-                throw new Error(`Не удалось удалить инвойс: ${error}`);
+                throw commandError('Не удалось удалить инвойс', error);
             }
         },
         
@@ -289,6 +588,74 @@ window.api = {
         },
     },
 
+    // ==================== PAYMENTS ====================
+    // Статус оплаты инвойса (paymentStatus, balance, amountPaid) вычисляется по этому журналу
+    payments: {
+        getByInvoice: async (invoiceId) => {
+            try {
+                return await invoke('get_payments', { invoiceId: String(invoiceId) });
+            } catch (error) {
+                console.error('❌ Ошибка загрузки оплат:', error);
+                throw new Error(`Не удалось загрузить оплаты: ${error}`);
+            }
+        },
+
+        // payment: { invoiceId, date, amount, currency?, method?, reference?, note? }
+        record: async (payment) => {
+            try {
+                console.log('📡 Проводим оплату:', payment.invoiceId, payment.amount);
+                const saved = await invoke('record_payment', { payment });
+                console.log('✅ Оплата проведена:', saved.id);
+                return saved;
+            } catch (error) {
+                console.error('❌ Ошибка проведения оплаты:', error);
+                throw new Error(`Не удалось провести оплату: ${error}`);
+            }
+        },
+
+        reverse: async (id, reason = null) => {
+            try {
+                console.log('📡 Сторнируем оплату:', id);
+                return await invoke('reverse_payment', { id: String(id), reason });
+            } catch (error) {
+                console.error('❌ Ошибка сторно оплаты:', error);
+                throw new Error(`Не удалось сторнировать оплату: ${error}`);
+            }
+        },
+    },
+
+    // ==================== NUMBERING ====================
+    numbering: {
+        getSequences: async () => {
+            try {
+                return await invoke('get_document_sequences');
+            } catch (error) {
+                console.error('❌ Ошибка загрузки нумерации:', error);
+                throw new Error(`Не удалось загрузить нумерацию: ${error}`);
+            }
+        },
+
+        updateSequence: async (sequence) => {
+            try {
+                console.log('📡 Обновляем нумерацию:', sequence.documentType);
+                return await invoke('update_document_sequence', { sequence });
+            } catch (error) {
+                console.error('❌ Ошибка обновления нумерации:', error);
+                throw new Error(`Не удалось обновить нумерацию: ${error}`);
+            }
+        },
+
+        // Только подсказка: номер резервируется при create_invoice
+        next: async (documentType, date = null) => {
+            try {
+                return await invoke('get_next_document_number', { documentType, date });
+            } catch (error) {
+                console.error('❌ Ошибка получения следующего номера:', error);
+                throw new Error(`Не удалось получить номер: ${error}`);
+            }
+        },
+    },
+
     // ==================== FORECAST ====================
     forecast: {
         getReport: async (req) => {
@@ -304,6 +671,140 @@ window.api = {
         }
     },
 
+    // ==================== RECEIVABLES ====================
+    receivables: {
+        // req: { as_of?: 'YYYY-MM-DD', client_id?: string }
+        getReport: async (req = {}) => {
+            try {
+                console.log('📡 Receivables: get_report', req);
+                const report = await invoke('get_receivables_report', { req: { as_of: null, client_id: null, ...req } });
+                console.log('✅ Receivables report loaded:', report.open_invoices, 'open invoices');
+                return report;
+            } catch (error) {
+                console.error('❌ Receivables: get_report failed:', error);
+                throw new Error(`Не удалось построить отчёт по задолженности: ${error}`);
+            }
+        }
+    },
+
+    // ==================== ЖУРНАЛ ИЗМЕНЕНИЙ ====================
+    audit: {
+        // filter: { entity?: 'client' | 'product' | 'invoice' | ..., entityId?, user?, limit? }
+        // Запись: { username, action, entity, entityId, diff: { поле: { old, new } }, createdAt }
+        getLog: async (filter = {}) => {
+            try {
+                return await invoke('get_audit_log', { filter });
+            } catch (error) {
+                console.error('❌ Audit: get_log failed:', error);
+                throw new Error(`Не удалось загрузить журнал изменений: ${error}`);
+            }
+        }
+    },
+
+    // ==================== EFAKTURA (SEF) ====================
+    efaktura: {
+        getSettings: async () => {
+            try {
+                return await invoke('get_efaktura_settings');
+            } catch (error) {
+                console.error('❌ eFaktura: get_settings failed:', error);
+                throw new Error(`Не удалось загрузить настройки eFaktura: ${error}`);
+            }
+        },
+
+        // settings: { baseUrl, apiKey }
+        updateSettings: async (settings) => {
+            try {
+                return await invoke('update_efaktura_settings', { settings: { updatedAt: null, ...settings } });
+            } catch (error) {
+                console.error('❌ eFaktura: update_settings failed:', error);
+                throw new Error(`Не удалось сохранить настройки eFaktura: ${error}`);
+            }
+        },
+
+        // UBL 2.1 (профиль SEF) сохранённого рачуна; otpremnica: { number, pdfBase64, pdfFilename, pdfMime }
+        exportUbl: async (invoiceId, otpremnica = null) => {
+            try {
+                return await invoke('export_invoice_ubl', { id: String(invoiceId), otpremnica });
+            } catch (error) {
+                console.error('❌ eFaktura: export_ubl failed:', error);
+                throw new Error(`Не удалось сформировать UBL: ${error}`);
+            }
+        },
+
+        // Возвращает обновлённый инвойс (sefInvoiceId, sefStatus).
+        // UBL backend формирует сам из базы (как exportUbl).
+        sendInvoice: async (invoiceId, otpremnica = null) => {
+            try {
+                console.log('📤 eFaktura: send_invoice', invoiceId);
+                const invoice = await invoke('send_invoice_to_efaktura', { request: { invoiceId: String(invoiceId), otpremnica } });
+                console.log('✅ eFaktura: sent, SalesInvoiceId =', invoice.sefInvoiceId);
+                return invoice;
+            } catch (error) {
+                console.error('❌ eFaktura: send_invoice failed:', error);
+                throw new Error(`Ошибка отправки в eFaktura: ${error}`);
+            }
+        },
+
+        // Внеочередной опрос SEF: статусы отправленных + новые входящие фактуры
+        sync: async () => {
+            try {
+                const summary = await invoke('sync_efaktura');
+                console.log('✅ eFaktura: sync', summary);
+                return summary;
+            } catch (error) {
+                console.error('❌ eFaktura: sync failed:', error);
+                throw new Error(`Ошибка синхронизации с eFaktura: ${error}`);
+            }
+        },
+
+        // Входящие фактуры поставщиков; supplierId = null — все
+        getPurchaseInvoices: async (supplierId = null) => {
+            try {
+                return await invoke('get_purchase_invoices', { supplierId });
+            } catch (error) {
+                console.error('❌ eFaktura: get_purchase_invoices failed:', error);
+                throw new Error(`Не удалось загрузить входящие фактуры: ${error}`);
+            }
+        },
+
+        // Повторная загрузка входящей фактуры, сохранённой с ошибкой (loadError)
+        retryPurchaseInvoice: async (id) => {
+            try {
+                const invoice = await invoke('retry_purchase_invoice', { id: String(id) });
+                console.log('✅ eFaktura: purchase invoice loaded', invoice.invoiceNumber);
+                return invoice;
+            } catch (error) {
+                console.error('❌ eFaktura: retry_purchase_invoice failed:', error);
+                throw new Error(`Не удалось загрузить входящую фактуру: ${error}`);
+            }
+        }
+    },
+
+    // ==================== COMPANY PROFILE ====================
+    company: {
+        getProfile: async () => {
+            try {
+                return await invoke('get_company_profile');
+            } catch (error) {
+                console.error('❌ Company: get_profile failed:', error);
+                throw new Error(`Не удалось загрузить профиль фирмы: ${error}`);
+            }
+        },
+
+        // profile: { name, legalName, pib, mb, street, city, postalCode, countryCode, email, phone,
+        //            website, logo, invoiceFooter, bankAccounts: [{ id, accountNumber, bankName, currency, isDefault }] }
+        updateProfile: async (profile) => {
+            try {
+                console.log('📤 Company: update_profile', profile.name);
+                return await invoke('update_company_profile', { profile: { updatedAt: null, ...profile } });
+            } catch (error) {
+                console.error('❌ Company: update_profile failed:', error);
+                throw new Error(`Не удалось сохранить профиль фирмы: ${error}`);
+            }
+        }
+    },
+
     // ==================== EXCHANGE RATES (NBS) ====================
     exchangeRates: {
         fetchNbsRate: async (dateIso, currency) => {
@@ -526,7 +1027,7 @@ window.api = {
                 console.log('✅ Сектор удален');
             } catch (error) {
                 console.error('❌ Ошибка удаления сектора:', error);
-                throw new Error(`Не удалось удалить сектор: ${error}`);
+                throw commandError('Не удалось удалить сектор', error);
             }
         },
     },
@@ -577,7 +1078,7 @@ window.api = {
                 console.log('✅ Продукция удалена');
             } catch (error) {
                 console.error('❌ Ошибка удаления продукции:', error);
-                throw new Error(`Не удалось удалить продукцию: ${error}`);
+                throw commandError('Не удалось удалить продукцию', error);
             }
         },
     },
@@ -604,7 +1105,7 @@ window.api = {
                 return supplier;
             } catch (error) {
                 console.error('❌ Ошибка создания поставщика:', error);
-                throw new Error(`Не удалось создать поставщика: ${error}`);
+                throw commandError('Не удалось создать поставщика', error);
             }
         },
         
@@ -617,10 +1118,14 @@ window.api = {
                 return supplier;
             } catch (error) {
                 console.error('❌ Ошибка обновления поставщика:', error);
-                throw new Error(`Не удалось обновить поставщика: ${error}`);
+                throw commandError('Не удалось обновить поставщика', error);
             }
         },
         
+        validate: async (data) => {
+            return await invoke('validate_supplier', { supplier: data });
+        },
+
         delete: async (id) => {
             try {
                 console.log('📡 Удаляем поставщика:', id);
@@ -686,6 +1191,44 @@ window.api = {
                 throw new Error(`Не удалось удалить HTML: ${error}`);
             }
         }
+    },
+
+    // ==================== PDF ИНВОЙСОВ ====================
+    invoicePdf: {
+        // PDF из данных базы -> invoices/{type}/{year}/{month}/{number}.pdf; возвращает путь
+        generate: async (invoiceId) => {
+            try {
+                console.log(`📄 Генерируем PDF документа ${invoiceId}`);
+                const path = await invoke('generate_invoice_pdf', { id: String(invoiceId) });
+                console.log(`✅ PDF сохранен: ${path}`);
+                return path;
+            } catch (error) {
+                console.error('❌ Ошибка генерации PDF:', error);
+                throw new Error(`Не удалось сформировать PDF: ${error}`);
+            }
+        },
+
+        // request: { ids } или { documentType, dateFrom, dateTo }; возвращает { generated, failed }
+        generateBatch: async (request = {}) => {
+            try {
+                const result = await invoke('generate_invoice_pdfs', { request });
+                console.log(`✅ PDF: ${result.generated.length} сформировано, ${result.failed.length} с ошибкой`);
+                return result;
+            } catch (error) {
+                console.error('❌ Ошибка пакетной генерации PDF:', error);
+                throw new Error(`Не удалось сформировать PDF: ${error}`);
+            }
+        },
+
+        // NBS IPS QR рачуна/предрачуна (только RSD): { payload, svg, pngDataUrl }
+        getIpsQr: async (invoiceId) => {
+            try {
+                return await invoke('get_invoice_ips_qr', { id: String(invoiceId) });
+            } catch (error) {
+                console.error('❌ Ошибка IPS QR:', error);
+                throw new Error(`Не удалось сформировать IPS QR: ${error}`);
+            }
+        }
     }
 };
 
@@ -708,6 +1251,11 @@ console.log('  - window.api.suppliers.getAll/create/update/delete()');
 console.log('  - window.api.supplierSectors.getAll/create/delete()');
 console.log('  - window.api.supplierProducts.getAll/getBySector/create/delete()');
 console.log('  - window.api.countries.getAll()');
-console.log('  - window.api.auth.login(username, password)');
+console.log('  - window.api.auth.login(username, password) / logout() / getSession()');
+console.log('  - window.api.setup.status/createInitialAdmin()');
+console.log('  - window.api.database.encryptionStatus/unlock/encrypt/rotateKey()');
+console.log('  - window.api.backups.getSettings/saveSettings/list/create/restore()');
+console.log('  - window.api.users.getAll/create/update/setActive/resetPassword()');
 console.log('  - window.api.invoiceHtml.save/load/delete()');
-console.log('  - window.api.warehouseGroups.getAll/create/update/delete()');
\ No newline at end of file
+console.log('  - window.api.warehouseGroups.getAll/create/update/delete()');
+console.log('  - window.api.audit.getLog(filter)');
\ No newline at end of file
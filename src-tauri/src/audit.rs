@@ -0,0 +1,316 @@
+use chrono::Utc;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, params_from_iter, Connection, ToSql};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+pub const CREATE: &str = "create";
+pub const UPDATE: &str = "update";
+pub const DELETE: &str = "delete";
+pub const MERGE: &str = "merge";
+pub const RESTORE: &str = "restore";
+
+/// Сколько записей журнала отдаём, если лимит не задан
+pub const DEFAULT_LIMIT: i64 = 200;
+
+/// Значения этих полей в журнал не попадают — только факт изменения
+const MASKED_FIELDS: [&str; 3] = ["api_key", "password", "logo"];
+const MASK: &str = "***";
+/// Меняется при каждом сохранении, в diff только мешает
+const IGNORED_FIELDS: [&str; 1] = ["updated_at"];
+
+/// Таблицы с колонками created_by / modified_by (migrations::m015_ownership)
+pub const OWNED_TABLES: [&str; 7] = [
+    "clients",
+    "products",
+    "invoices",
+    "deliveries",
+    "warehouse_groups",
+    "suppliers",
+    "payments",
+];
+
+/// Автор изменения (см. session.rs)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Actor {
+    pub user_id: Option<String>,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: i64,
+    pub user_id: Option<String>,
+    pub username: String,
+    /// create / update / delete / merge
+    pub action: String,
+    /// client, product, invoice, payment, supplier, ...
+    pub entity: String,
+    pub entity_id: String,
+    /// `{ "поле": { "old": ..., "new": ... } }` — только изменившиеся поля
+    pub diff: Value,
+    pub created_at: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditFilter {
+    pub entity: Option<String>,
+    pub entity_id: Option<String>,
+    /// Имя или id пользователя
+    pub user: Option<String>,
+    pub limit: Option<i64>,
+}
+
+fn json_value(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => json!(i),
+        ValueRef::Real(f) => json!(f),
+        ValueRef::Text(t) => json!(String::from_utf8_lossy(t)),
+        ValueRef::Blob(b) => json!(format!("<{} bytes>", b.len())),
+    }
+}
+
+/// Строки запроса как JSON-объекты «колонка → значение».
+pub fn rows(conn: &Connection, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<Value>, String> {
+    let mut stmt = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let columns: Vec<String> = stmt.column_names().into_iter().map(str::to_string).collect();
+    let rows = stmt
+        .query_map(params_from_iter(params), |row| {
+            let mut object = Map::new();
+            for (idx, column) in columns.iter().enumerate() {
+                object.insert(column.clone(), json_value(row.get_ref(idx)?));
+            }
+            Ok(Value::Object(object))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows)
+}
+
+/// Снимок записи таблицы целиком (`SELECT *`) — до и после изменения.
+pub fn row(conn: &Connection, table: &str, key: &str, id: &dyn ToSql) -> Result<Option<Value>, String> {
+    let sql = format!("SELECT * FROM {} WHERE {} = ?1", table, key);
+    Ok(rows(conn, &sql, &[id])?.into_iter().next())
+}
+
+/// Документ вместе с позициями и оплатами: их изменение тоже попадает в diff (items, payments).
+pub fn invoice(conn: &Connection, id: &str) -> Result<Option<Value>, String> {
+    let Some(mut header) = row(conn, "invoices", "id", &id)? else {
+        return Ok(None);
+    };
+    let items = rows(conn, "SELECT * FROM invoice_items WHERE invoice_id = ?1 ORDER BY rowid", &[&id])?;
+    let payments = rows(conn, "SELECT * FROM payments WHERE invoice_id = ?1 ORDER BY created_at, id", &[&id])?;
+    header["items"] = Value::Array(items);
+    header["payments"] = Value::Array(payments);
+    Ok(Some(header))
+}
+
+/// Профиль фирмы вместе с текущими рачунами.
+pub fn company_profile(conn: &Connection) -> Result<Option<Value>, String> {
+    let Some(mut profile) = row(conn, "company_profile", "id", &1)? else {
+        return Ok(None);
+    };
+    let accounts = rows(conn, "SELECT * FROM company_bank_accounts ORDER BY id", &[])?;
+    profile["bank_accounts"] = Value::Array(accounts);
+    Ok(Some(profile))
+}
+
+/// Поля, которые отличаются между снимками. Для создания old = null, для удаления new = null.
+pub fn diff(before: Option<&Value>, after: Option<&Value>) -> Map<String, Value> {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut changes = Map::new();
+    for field in before.keys().chain(after.keys().filter(|k| !before.contains_key(*k))) {
+        if IGNORED_FIELDS.contains(&field.as_str()) {
+            continue;
+        }
+        let old = before.get(field).unwrap_or(&Value::Null);
+        let new = after.get(field).unwrap_or(&Value::Null);
+        if old == new {
+            continue;
+        }
+        let change = if MASKED_FIELDS.contains(&field.as_str()) {
+            let mask = |v: &Value| if v.is_null() { Value::Null } else { json!(MASK) };
+            json!({ "old": mask(old), "new": mask(new) })
+        } else {
+            json!({ "old": old, "new": new })
+        };
+        changes.insert(field.clone(), change);
+    }
+    changes
+}
+
+/// Проставляет автора записи: при создании created_by и modified_by, дальше только modified_by.
+/// Вызывается до снимка `after`, чтобы автор попал и в журнал.
+pub fn stamp(conn: &Connection, table: &str, key: &str, id: &dyn ToSql, actor: &Actor, action: &str) -> Result<(), String> {
+    debug_assert!(OWNED_TABLES.contains(&table), "{} has no ownership columns", table);
+    let sql = if action == CREATE {
+        format!("UPDATE {} SET created_by = ?1, modified_by = ?1 WHERE {} = ?2", table, key)
+    } else {
+        format!("UPDATE {} SET modified_by = ?1 WHERE {} = ?2", table, key)
+    };
+    conn.execute(&sql, params![actor.username, id]).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Пишет запись журнала. Вызывается в той же транзакции, что и само изменение;
+/// сохранение без фактических изменений в журнал не попадает.
+pub fn record(
+    conn: &Connection,
+    actor: &Actor,
+    action: &str,
+    entity: &str,
+    entity_id: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+) -> Result<(), String> {
+    let changes = diff(before.as_ref(), after.as_ref());
+    if changes.is_empty() && action == UPDATE {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO audit_log (user_id, username, action, entity, entity_id, diff, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            actor.user_id,
+            actor.username,
+            action,
+            entity,
+            entity_id,
+            Value::Object(changes).to_string(),
+            Utc::now().to_rfc3339(),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Журнал, новые записи первыми. Все условия фильтра необязательные.
+pub fn load(conn: &Connection, filter: &AuditFilter) -> Result<Vec<AuditEntry>, String> {
+    let limit = filter.limit.filter(|l| *l > 0).unwrap_or(DEFAULT_LIMIT);
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, user_id, username, action, entity, entity_id, diff, created_at FROM audit_log
+             WHERE (?1 IS NULL OR entity = ?1)
+               AND (?2 IS NULL OR entity_id = ?2)
+               AND (?3 IS NULL OR username = ?3 OR user_id = ?3)
+             ORDER BY id DESC LIMIT ?4",
+        )
+        .map_err(|e| e.to_string())?;
+    let entries = stmt
+        .query_map(
+            params![filter.entity, filter.entity_id, filter.user, limit],
+            |row| {
+                let diff: String = row.get(6)?;
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    user_id: row.get(1)?,
+                    username: row.get(2)?,
+                    action: row.get(3)?,
+                    entity: row.get(4)?,
+                    entity_id: row.get(5)?,
+                    diff: serde_json::from_str(&diff).unwrap_or(Value::Null),
+                    created_at: row.get(7)?,
+                })
+            },
+        )
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_diff_keeps_changed_fields_and_masks_secrets() {
+        let before = json!({ "name": "Klijent", "mb": "21000001", "api_key": "old", "updated_at": "a" });
+        let after = json!({ "name": "Klijent DOO", "mb": "21000001", "api_key": "new", "updated_at": "b" });
+        let changes = diff(Some(&before), Some(&after));
+        assert_eq!(
+            Value::Object(changes),
+            json!({
+                "name": { "old": "Klijent", "new": "Klijent DOO" },
+                "api_key": { "old": "***", "new": "***" }
+            })
+        );
+
+        let created = diff(None, Some(&json!({ "name": "X", "pib": null })));
+        assert_eq!(Value::Object(created), json!({ "name": { "old": null, "new": "X" } }));
+    }
+
+    #[test]
+    fn test_record_and_filter() {
+        let conn = test_conn();
+        let admin = Actor {
+            user_id: Some("u1".to_string()),
+            username: "admin".to_string(),
+        };
+        conn.execute(
+            "INSERT INTO clients (id, name, mb, created_at) VALUES (1, 'Klijent', '21000001', 'now')",
+            [],
+        )
+        .unwrap();
+        let before = row(&conn, "clients", "id", &1).unwrap();
+        assert_eq!(before.as_ref().unwrap()["name"], json!("Klijent"));
+        conn.execute("UPDATE clients SET name = 'Klijent DOO' WHERE id = 1", []).unwrap();
+        let after = row(&conn, "clients", "id", &1).unwrap();
+        let system = Actor {
+            user_id: None,
+            username: "system".to_string(),
+        };
+
+        record(&conn, &admin, CREATE, "client", "1", None, before.clone()).unwrap();
+        record(&conn, &admin, UPDATE, "client", "1", before, after.clone()).unwrap();
+        // сохранение без изменений не пишется
+        record(&conn, &system, UPDATE, "client", "1", after.clone(), after).unwrap();
+        record(&conn, &system, CREATE, "product", "p1", None, Some(json!({ "code": "A" }))).unwrap();
+
+        let clients = load(&conn, &AuditFilter { entity: Some("client".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[0].action, UPDATE);
+        assert_eq!(clients[0].diff, json!({ "name": { "old": "Klijent", "new": "Klijent DOO" } }));
+        assert_eq!(clients[1].diff["mb"], json!({ "old": null, "new": "21000001" }));
+
+        let by_user = load(&conn, &AuditFilter { user: Some("u1".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(by_user.len(), 2);
+        let by_system = load(&conn, &AuditFilter { user: Some("system".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(by_system.len(), 1);
+        assert_eq!(by_system[0].entity, "product");
+    }
+
+    #[test]
+    fn test_stamp_keeps_creator() {
+        let conn = test_conn();
+        let actor = |name: &str| Actor {
+            user_id: None,
+            username: name.to_string(),
+        };
+        conn.execute(
+            "INSERT INTO clients (id, name, mb, created_at) VALUES (1, 'Klijent', '21000001', 'now')",
+            [],
+        )
+        .unwrap();
+        stamp(&conn, "clients", "id", &1, &actor("ana"), CREATE).unwrap();
+        stamp(&conn, "clients", "id", &1, &actor("marko"), UPDATE).unwrap();
+        let client = row(&conn, "clients", "id", &1).unwrap().unwrap();
+        assert_eq!(client["created_by"], json!("ana"));
+        assert_eq!(client["modified_by"], json!("marko"));
+    }
+}
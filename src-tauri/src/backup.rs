@@ -0,0 +1,751 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::database::Database;
+use crate::encryption;
+use crate::migrations;
+
+/// Как часто фоновая задача проверяет, не пора ли сделать очередную копию
+pub const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Первая проверка после запуска — когда окно уже загрузилось
+pub const FIRST_CHECK_DELAY: Duration = Duration::from_secs(2 * 60);
+
+const DEFAULT_DIR: &str = "backups";
+const INVOICES_DIR: &str = "invoices";
+const DB_ENTRY: &str = "srecha-invoice.db";
+const MANIFEST_ENTRY: &str = "manifest.json";
+const FILE_PREFIX: &str = "srecha-backup-";
+const FILE_SUFFIX: &str = ".tar.gz";
+const NAME_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+/// Версия формата архива (manifest.json → format)
+const FORMAT_VERSION: u32 = 1;
+
+/// Копия по расписанию и кнопка «Создать копию» / восстановление не идут одновременно
+static BACKUP_LOCK: Mutex<()> = Mutex::new(());
+
+/// Настройки резервного копирования (таблица backup_settings, одна строка)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupSettings {
+    /// Папка для копий; None — backups рядом с базой
+    pub directory: Option<String>,
+    /// Копия делается, если последней больше стольких часов; 0 — расписание выключено
+    pub interval_hours: u32,
+    /// Сколько последних копий хранится всегда
+    pub keep_last: u32,
+    /// Копии старше стольких дней удаляются, кроме keep_last последних
+    pub keep_days: u32,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        BackupSettings {
+            directory: None,
+            interval_hours: 24,
+            keep_last: 7,
+            keep_days: 30,
+            updated_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackupKind {
+    Scheduled,
+    Manual,
+    /// Состояние перед восстановлением — им восстановление можно отменить
+    PreRestore,
+}
+
+impl BackupKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BackupKind::Scheduled => "scheduled",
+            BackupKind::Manual => "manual",
+            BackupKind::PreRestore => "pre-restore",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [BackupKind::Scheduled, BackupKind::Manual, BackupKind::PreRestore]
+            .into_iter()
+            .find(|kind| kind.as_str() == value)
+    }
+}
+
+/// manifest.json внутри архива
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub format: u32,
+    pub created_at: String,
+    pub kind: BackupKind,
+    pub app_version: String,
+    pub schema_version: i64,
+    /// Снимок базы зашифрован тем же ключом, что и рабочая база на момент копии
+    pub encrypted: bool,
+    pub invoice_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    pub name: String,
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+    pub kind: BackupKind,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreResult {
+    pub restored: BackupInfo,
+    pub manifest: Manifest,
+    /// Копия состояния до восстановления
+    pub safety_backup: BackupInfo,
+}
+
+// ==================== НАСТРОЙКИ ====================
+
+pub fn load_settings(conn: &Connection) -> rusqlite::Result<BackupSettings> {
+    let settings = conn
+        .query_row(
+            "SELECT directory, interval_hours, keep_last, keep_days, updated_at FROM backup_settings WHERE id = 1",
+            [],
+            |row| {
+                Ok(BackupSettings {
+                    directory: row.get(0)?,
+                    interval_hours: row.get(1)?,
+                    keep_last: row.get(2)?,
+                    keep_days: row.get(3)?,
+                    updated_at: row.get(4)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(settings.unwrap_or_default())
+}
+
+pub fn save_settings(conn: &Connection, settings: &BackupSettings) -> Result<BackupSettings, String> {
+    if settings.keep_last == 0 {
+        return Err("At least one backup must be kept".to_string());
+    }
+    let directory = settings
+        .directory
+        .as_deref()
+        .map(str::trim)
+        .filter(|d| !d.is_empty());
+    if let Some(directory) = directory {
+        if !Path::new(directory).is_absolute() {
+            return Err(format!("Backup directory must be an absolute path: {}", directory));
+        }
+    }
+    conn.execute(
+        "INSERT INTO backup_settings (id, directory, interval_hours, keep_last, keep_days, updated_at)
+         VALUES (1, ?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(id) DO UPDATE SET directory = excluded.directory, interval_hours = excluded.interval_hours,
+             keep_last = excluded.keep_last, keep_days = excluded.keep_days, updated_at = excluded.updated_at",
+        params![
+            directory,
+            settings.interval_hours,
+            settings.keep_last,
+            settings.keep_days,
+            Utc::now().to_rfc3339()
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    load_settings(conn).map_err(|e| e.to_string())
+}
+
+pub fn backup_dir(settings: &BackupSettings, app_data_dir: &Path) -> PathBuf {
+    match &settings.directory {
+        Some(directory) => PathBuf::from(directory),
+        None => app_data_dir.join(DEFAULT_DIR),
+    }
+}
+
+fn current_settings(db: &Database) -> Result<BackupSettings, String> {
+    let conn = db.conn()?;
+    load_settings(&conn).map_err(|e| e.to_string())
+}
+
+// ==================== КОПИИ ====================
+
+/// Копии в папке настроек, новые первыми. Чужие файлы в папке пропускаются.
+pub fn list(db: &Database, app_data_dir: &Path) -> Result<Vec<BackupInfo>, String> {
+    let settings = current_settings(db)?;
+    list_dir(&backup_dir(&settings, app_data_dir))
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<BackupInfo>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read backup directory {}: {}", dir.display(), e)),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        if parse_name(name).is_some() && path.is_file() {
+            backups.push(info(&path)?);
+        }
+    }
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    Ok(backups)
+}
+
+/// Снимок базы (online backup API) и архива invoices/ в один .tar.gz.
+/// Архив пишется во временный файл и переименовывается только целиком.
+pub fn create(db: &Database, app_data_dir: &Path, kind: BackupKind) -> Result<BackupInfo, String> {
+    let _guard = BACKUP_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let settings = current_settings(db)?;
+    let created = create_locked(db, app_data_dir, &settings, kind)?;
+    if kind != BackupKind::PreRestore {
+        prune(&backup_dir(&settings, app_data_dir), &settings, Utc::now())?;
+    }
+    Ok(created)
+}
+
+fn create_locked(
+    db: &Database,
+    app_data_dir: &Path,
+    settings: &BackupSettings,
+    kind: BackupKind,
+) -> Result<BackupInfo, String> {
+    let dir = backup_dir(settings, app_data_dir);
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create backup directory {}: {}", dir.display(), e))?;
+
+    let created_at = Utc::now();
+    let name = file_name(created_at, kind);
+    let path = dir.join(&name);
+    if path.exists() {
+        return Err(format!("Backup {} already exists", name));
+    }
+    let partial = dir.join(format!(".{}.partial", name));
+    let snapshot = dir.join(format!(".{}.db", name));
+
+    let written = write_archive(db, app_data_dir, &snapshot, &partial, created_at, kind);
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        fs::remove_file(format!("{}{}", snapshot.display(), suffix)).ok();
+    }
+    if let Err(e) = written {
+        fs::remove_file(&partial).ok();
+        return Err(e);
+    }
+    fs::rename(&partial, &path).map_err(|e| format!("Failed to save backup: {}", e))?;
+
+    println!("💾 Резервная копия: {}", path.display());
+    info(&path)
+}
+
+fn write_archive(
+    db: &Database,
+    app_data_dir: &Path,
+    snapshot: &Path,
+    target: &Path,
+    created_at: DateTime<Utc>,
+    kind: BackupKind,
+) -> Result<(), String> {
+    // Файл снимка создаём сами, чтобы он сразу был 0600
+    drop(create_private(snapshot).map_err(|e| e.to_string())?);
+    db.backup_into(snapshot)?;
+    let schema_version = {
+        let conn = db.open_snapshot(snapshot)?;
+        migrations::current_version(&conn).map_err(|e| e.to_string())?
+    };
+
+    let invoices = collect_files(&app_data_dir.join(INVOICES_DIR), INVOICES_DIR)?;
+    let manifest = Manifest {
+        format: FORMAT_VERSION,
+        created_at: created_at.to_rfc3339(),
+        kind,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version,
+        encrypted: encryption::is_encrypted(snapshot).map_err(|e| e.to_string())?,
+        invoice_files: invoices.len(),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+
+    let file = create_private(target).map_err(|e| format!("Failed to create backup file: {}", e))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mtime = created_at.timestamp();
+    let archive = (|| -> io::Result<()> {
+        append_entry(&mut tar, MANIFEST_ENTRY, manifest.len() as u64, mtime, manifest.as_slice())?;
+        let db_file = fs::File::open(snapshot)?;
+        append_entry(&mut tar, DB_ENTRY, db_file.metadata()?.len(), mtime, db_file)?;
+        for (path, entry) in &invoices {
+            let file = fs::File::open(path)?;
+            append_entry(&mut tar, entry, file.metadata()?.len(), mtime, file)?;
+        }
+        tar.into_inner()?.finish()?.sync_all()
+    })();
+    archive.map_err(|e| format!("Failed to write backup archive: {}", e))
+}
+
+/// Копия по расписанию, если последней копии больше interval_hours.
+/// Зашифрованная и ещё не открытая база пропускается.
+pub fn run_scheduled(db: &Database, app_data_dir: &Path) -> Result<Option<BackupInfo>, String> {
+    if db.is_locked() {
+        return Ok(None);
+    }
+    let settings = current_settings(db)?;
+    let latest = list_dir(&backup_dir(&settings, app_data_dir))?
+        .first()
+        .map(|b| b.created_at);
+    if !is_due(latest, settings.interval_hours, Utc::now()) {
+        return Ok(None);
+    }
+    create(db, app_data_dir, BackupKind::Scheduled).map(Some)
+}
+
+fn is_due(latest: Option<DateTime<Utc>>, interval_hours: u32, now: DateTime<Utc>) -> bool {
+    if interval_hours == 0 {
+        return false;
+    }
+    match latest {
+        Some(latest) => now - latest >= chrono::Duration::hours(interval_hours as i64),
+        None => true,
+    }
+}
+
+/// Удаляет копии сверх keep_last последних, которые старше keep_days.
+fn prune(dir: &Path, settings: &BackupSettings, now: DateTime<Utc>) -> Result<Vec<String>, String> {
+    let backups = list_dir(dir)?;
+    let mut removed = Vec::new();
+    for backup in expired(&backups, settings, now) {
+        fs::remove_file(&backup.path).map_err(|e| format!("Failed to remove old backup {}: {}", backup.name, e))?;
+        println!("🗑️ Удалена старая копия: {}", backup.name);
+        removed.push(backup.name.clone());
+    }
+    Ok(removed)
+}
+
+/// `backups` — новые первыми, как возвращает list_dir
+fn expired<'a>(backups: &'a [BackupInfo], settings: &BackupSettings, now: DateTime<Utc>) -> Vec<&'a BackupInfo> {
+    let cutoff = now - chrono::Duration::days(settings.keep_days as i64);
+    backups
+        .iter()
+        .skip(settings.keep_last as usize)
+        .filter(|b| b.created_at < cutoff)
+        .collect()
+}
+
+// ==================== ВОССТАНОВЛЕНИЕ ====================
+
+/// Распаковывает копию во временную папку, проверяет её и только потом подменяет
+/// базу и invoices/. Перед подменой делается копия текущего состояния (pre-restore).
+pub fn restore(db: &Database, app_data_dir: &Path, name: &str) -> Result<RestoreResult, String> {
+    let _guard = BACKUP_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if parse_name(name).is_none() || name.contains(['/', '\\']) {
+        return Err(format!("Unknown backup: {}", name));
+    }
+    let settings = current_settings(db)?;
+    let archive = backup_dir(&settings, app_data_dir).join(name);
+    if !archive.is_file() {
+        return Err(format!("Backup not found: {}", name));
+    }
+
+    let staging = app_data_dir.join(format!("restore-{}", uuid::Uuid::new_v4()));
+    let result = restore_staged(db, app_data_dir, &settings, &archive, &staging);
+    fs::remove_dir_all(&staging).ok();
+    result
+}
+
+fn restore_staged(
+    db: &Database,
+    app_data_dir: &Path,
+    settings: &BackupSettings,
+    archive: &Path,
+    staging: &Path,
+) -> Result<RestoreResult, String> {
+    extract(archive, staging)?;
+    let (manifest, snapshot) = validate(db, staging)?;
+
+    let safety_backup = create_locked(db, app_data_dir, settings, BackupKind::PreRestore)?;
+    db.restore_from(&snapshot)?;
+    drop(snapshot);
+    swap_invoices(app_data_dir, staging).map_err(|e| {
+        format!(
+            "Database restored, but invoice files were not ({}). Previous state is in {}",
+            e, safety_backup.name
+        )
+    })?;
+
+    println!("♻️ Восстановлено из {}", archive.display());
+    Ok(RestoreResult {
+        restored: info(archive)?,
+        manifest,
+        safety_backup,
+    })
+}
+
+/// Распаковывает только manifest.json, базу и файлы под invoices/ — без «..» и абсолютных путей.
+fn extract(archive: &Path, staging: &Path) -> Result<(), String> {
+    let file = fs::File::open(archive).map_err(|e| format!("Failed to open backup: {}", e))?;
+    fs::create_dir_all(staging).map_err(|e| e.to_string())?;
+    read_entries(GzDecoder::new(file), |name, data| {
+        let relative = safe_entry_path(name).ok_or_else(|| format!("Unexpected entry in backup: {}", name))?;
+        let target = staging.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut out = create_private(&target).map_err(|e| e.to_string())?;
+        io::copy(data, &mut out).map_err(|e| e.to_string())
+    })
+    .map_err(|e| format!("Backup archive is damaged: {}", e))
+}
+
+fn safe_entry_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let depth = path.components().count();
+    let allowed = match path.components().next()?.as_os_str().to_str()? {
+        MANIFEST_ENTRY | DB_ENTRY => depth == 1,
+        INVOICES_DIR => depth > 1,
+        _ => false,
+    };
+    allowed.then(|| path.to_path_buf())
+}
+
+/// Снимок открывается ключом рабочей базы, целостен, ссылки не нарушены,
+/// а схема не новее той, что знает это приложение.
+fn validate(db: &Database, staging: &Path) -> Result<(Manifest, Connection), String> {
+    let manifest = fs::read(staging.join(MANIFEST_ENTRY)).map_err(|_| "Backup has no manifest".to_string())?;
+    let manifest: Manifest =
+        serde_json::from_slice(&manifest).map_err(|e| format!("Backup manifest is invalid: {}", e))?;
+    if manifest.format != FORMAT_VERSION {
+        return Err(format!("Unsupported backup format: {}", manifest.format));
+    }
+
+    let db_path = staging.join(DB_ENTRY);
+    if !db_path.is_file() {
+        return Err("Backup has no database".to_string());
+    }
+    let conn = db
+        .open_snapshot(&db_path)
+        .map_err(|e| format!("Backup cannot be opened with the current database key: {}", e))?;
+
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| format!("Backup database is damaged: {}", e))?;
+    if integrity != "ok" {
+        return Err(format!("Backup database is damaged: {}", integrity));
+    }
+    let broken: i64 = conn
+        .query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if broken > 0 {
+        return Err(format!("Backup database has {} broken references", broken));
+    }
+    let schema = migrations::current_version(&conn).map_err(|e| format!("Backup database has no schema: {}", e))?;
+    if schema > migrations::latest_version() {
+        return Err(format!(
+            "Backup was made by a newer version of the app (schema {}), update the app first",
+            schema
+        ));
+    }
+    Ok((manifest, conn))
+}
+
+/// Подменяет invoices/ распакованной; при сбое возвращает прежнюю папку на место.
+fn swap_invoices(app_data_dir: &Path, staging: &Path) -> io::Result<()> {
+    let live = app_data_dir.join(INVOICES_DIR);
+    let restored = staging.join(INVOICES_DIR);
+    fs::create_dir_all(&restored)?;
+    let previous = staging.join("invoices.previous");
+    if live.exists() {
+        fs::rename(&live, &previous)?;
+    }
+    if let Err(e) = fs::rename(&restored, &live) {
+        if previous.exists() {
+            fs::rename(&previous, &live).ok();
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+// ==================== ФАЙЛЫ ====================
+
+/// `srecha-backup-20261017-153000-manual.tar.gz`
+fn file_name(created_at: DateTime<Utc>, kind: BackupKind) -> String {
+    format!(
+        "{}{}-{}{}",
+        FILE_PREFIX,
+        created_at.format(NAME_TIME_FORMAT),
+        kind.as_str(),
+        FILE_SUFFIX
+    )
+}
+
+fn parse_name(name: &str) -> Option<(DateTime<Utc>, BackupKind)> {
+    let stem = name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_SUFFIX)?;
+    let (time, kind) = (stem.get(..15)?, stem.get(15..)?);
+    let created_at = NaiveDateTime::parse_from_str(time, NAME_TIME_FORMAT).ok()?.and_utc();
+    Some((created_at, BackupKind::parse(kind.strip_prefix('-')?)?))
+}
+
+fn info(path: &Path) -> Result<BackupInfo, String> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+    let (created_at, kind) = parse_name(&name).ok_or_else(|| format!("Unknown backup: {}", name))?;
+    let size_bytes = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    Ok(BackupInfo {
+        name,
+        path: path.to_string_lossy().to_string(),
+        created_at,
+        kind,
+        size_bytes,
+    })
+}
+
+/// Все файлы под `dir` с путями вида `<prefix>/a/b.html`
+fn collect_files(dir: &Path, prefix: &str) -> Result<Vec<(PathBuf, String)>, String> {
+    let mut files = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(format!("Failed to read {}: {}", dir.display(), e)),
+    };
+    for entry in entries {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path();
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        let file_type = entry.file_type().map_err(|e| e.to_string())?;
+        if file_type.is_dir() {
+            files.extend(collect_files(&path, &name)?);
+        } else if file_type.is_file() {
+            files.push((path, name));
+        }
+    }
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(files)
+}
+
+/// Файл с правами 0600: в копиях те же ПИБ и банковские данные, что и в базе
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+// ==================== TAR ====================
+// Обычный tar.gz: копию можно распаковать и вручную (tar xzf)
+
+/// Обычный файл с правами 0600. Файл, изменившийся во время записи, — ошибка, а не битый архив.
+fn append_entry<W: Write>(tar: &mut tar::Builder<W>, name: &str, size: u64, mtime: i64, data: impl Read) -> io::Result<()> {
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(size);
+    header.set_mode(0o600);
+    header.set_mtime(mtime.max(0) as u64);
+    let mut data = data.take(size);
+    tar.append_data(&mut header, name, &mut data)?;
+    if data.limit() > 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} changed while archiving", name)));
+    }
+    Ok(())
+}
+
+/// Передаёт `visit` каждый обычный файл архива; каталоги и прочие записи пропускает.
+/// `visit` возвращает, сколько байт прочитал.
+fn read_entries<R: Read>(
+    input: R,
+    mut visit: impl FnMut(&str, &mut dyn Read) -> Result<u64, String>,
+) -> Result<(), String> {
+    let mut archive = tar::Archive::new(input);
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+        let path = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let size = entry.size();
+        // Обрезанный архив tar читает без ошибки — сверяем размер с заголовком
+        if visit(&path, &mut entry)? != size {
+            return Err(format!("{} is truncated", path));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_app() -> (PathBuf, Database) {
+        let dir = std::env::temp_dir().join(format!("srecha-backup-test-{}", uuid::Uuid::new_v4()));
+        let db = Database::new(dir.clone()).unwrap();
+        db.init().unwrap();
+        (dir, db)
+    }
+
+    fn category_count(db: &Database) -> i64 {
+        db.conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM categories", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_tar_round_trip() {
+        let long_path = format!("invoices/{}/2026/10/R-1.html", "predracun-".repeat(30));
+        let mut tar = tar::Builder::new(Vec::new());
+        append_entry(&mut tar, "manifest.json", 2, 0, &b"{}"[..]).unwrap();
+        append_entry(&mut tar, &long_path, 600, 0, &[7u8; 600][..]).unwrap();
+        let bytes = tar.into_inner().unwrap();
+
+        let read = |bytes: &[u8]| {
+            let mut seen = Vec::new();
+            read_entries(bytes, |name, data| {
+                let mut content = Vec::new();
+                data.read_to_end(&mut content).unwrap();
+                seen.push((name.to_string(), content.len()));
+                Ok(content.len() as u64)
+            })
+            .map(|_| seen)
+        };
+        assert_eq!(read(&bytes).unwrap(), vec![("manifest.json".to_string(), 2), (long_path.clone(), 600)]);
+        // без конца архива и последнего блока данных
+        let err = read(&bytes[..bytes.len() - 1536]).unwrap_err();
+        assert_eq!(err, format!("{} is truncated", long_path));
+
+        // файл стал короче, чем в заголовке
+        let mut tar = tar::Builder::new(Vec::new());
+        let err = append_entry(&mut tar, "invoices/R-1.html", 10, 0, &b"abc"[..]).unwrap_err();
+        assert!(err.to_string().contains("changed while archiving"));
+    }
+
+    #[test]
+    fn test_unsafe_entries_rejected() {
+        assert!(safe_entry_path("invoices/racun/2026/10/R-1.html").is_some());
+        assert!(safe_entry_path(DB_ENTRY).is_some());
+        assert!(safe_entry_path("../srecha-invoice.db").is_none());
+        assert!(safe_entry_path("invoices/../../etc/passwd").is_none());
+        assert!(safe_entry_path("/etc/passwd").is_none());
+        assert!(safe_entry_path("other.db").is_none());
+        assert!(safe_entry_path("invoices").is_none());
+    }
+
+    #[test]
+    fn test_retention_and_schedule() {
+        let now = Utc::now();
+        let backup = |days: i64| BackupInfo {
+            name: format!("b{}", days),
+            path: String::new(),
+            created_at: now - chrono::Duration::days(days),
+            kind: BackupKind::Scheduled,
+            size_bytes: 0,
+        };
+        let backups: Vec<_> = [0, 1, 2, 40, 50].into_iter().map(backup).collect();
+        let settings = BackupSettings { keep_last: 2, keep_days: 30, ..Default::default() };
+        let names: Vec<_> = expired(&backups, &settings, now).iter().map(|b| b.name.clone()).collect();
+        assert_eq!(names, vec!["b40", "b50"]);
+        let settings = BackupSettings { keep_last: 4, keep_days: 30, ..Default::default() };
+        assert_eq!(expired(&backups, &settings, now).len(), 1);
+
+        assert!(is_due(None, 24, now));
+        assert!(!is_due(Some(now - chrono::Duration::hours(23)), 24, now));
+        assert!(is_due(Some(now - chrono::Duration::hours(24)), 24, now));
+        assert!(!is_due(None, 0, now));
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let (dir, db) = test_app();
+        let invoice = dir.join("invoices/racun/2026/10/R-1.html");
+        fs::create_dir_all(invoice.parent().unwrap()).unwrap();
+        fs::write(&invoice, "<html>R-1</html>").unwrap();
+        let categories = category_count(&db);
+
+        let backup = create(&db, &dir, BackupKind::Manual).unwrap();
+        assert_eq!(parse_name(&backup.name), Some((backup.created_at, BackupKind::Manual)));
+        assert_eq!(list(&db, &dir).unwrap().len(), 1);
+
+        db.conn()
+            .unwrap()
+            .execute("INSERT INTO categories (id, name, created_at) VALUES ('x', 'После копии', '')", [])
+            .unwrap();
+        fs::write(&invoice, "<html>changed</html>").unwrap();
+        fs::write(dir.join("invoices/racun/2026/10/R-2.html"), "<html>R-2</html>").unwrap();
+
+        let restored = restore(&db, &dir, &backup.name).unwrap();
+        assert_eq!(restored.manifest.invoice_files, 1);
+        assert_eq!(restored.safety_backup.kind, BackupKind::PreRestore);
+        assert_eq!(category_count(&db), categories);
+        assert_eq!(fs::read_to_string(&invoice).unwrap(), "<html>R-1</html>");
+        assert!(!dir.join("invoices/racun/2026/10/R-2.html").exists());
+        assert_eq!(list(&db, &dir).unwrap().len(), 2);
+
+        drop(db);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_damaged_backup_is_not_restored() {
+        let (dir, db) = test_app();
+        let backups = dir.join(DEFAULT_DIR);
+        fs::create_dir_all(&backups).unwrap();
+        let name = file_name(Utc::now(), BackupKind::Manual);
+
+        // Архив без базы
+        let mut tar = tar::Builder::new(GzEncoder::new(fs::File::create(backups.join(&name)).unwrap(), Compression::default()));
+        append_entry(&mut tar, "manifest.json", 2, 0, &b"{}"[..]).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+        let err = restore(&db, &dir, &name).unwrap_err();
+        assert!(err.contains("manifest is invalid"), "{}", err);
+
+        fs::write(backups.join(&name), b"not a backup").unwrap();
+        let err = restore(&db, &dir, &name).unwrap_err();
+        assert!(err.contains("damaged"), "{}", err);
+
+        assert!(restore(&db, &dir, "../srecha-invoice.db").is_err());
+        // Рабочая база не тронута, страховочная копия не создавалась
+        assert!(category_count(&db) > 0);
+        assert_eq!(list(&db, &dir).unwrap().len(), 1);
+
+        drop(db);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_encrypted_backup_stays_encrypted() {
+        let (dir, db) = test_app();
+        db.encrypt("kljuc-vlasnika-1").unwrap();
+        let backup = create(&db, &dir, BackupKind::Manual).unwrap();
+
+        let restored = restore(&db, &dir, &backup.name).unwrap();
+        assert!(restored.manifest.encrypted);
+        assert!(category_count(&db) > 0);
+
+        // Копию со старым ключом после смены ключа открыть нельзя
+        db.rotate_key("kljuc-vlasnika-1", "kljuc-vlasnika-2").unwrap();
+        let err = restore(&db, &dir, &backup.name).unwrap_err();
+        assert!(err.contains("current database key"), "{}", err);
+
+        drop(db);
+        fs::remove_dir_all(dir).ok();
+    }
+}
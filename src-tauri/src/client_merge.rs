@@ -0,0 +1,440 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::audit;
+use crate::commands::{self, Client};
+
+/// Пары с меньшей оценкой не показываем: одно совпадение имени без адреса — не дубль.
+pub const MIN_SCORE: u32 = 40;
+
+const SCORE_PIB: u32 = 50;
+const SCORE_MB: u32 = 40;
+const SCORE_NAME: u32 = 30;
+const SCORE_SIMILAR_NAME: u32 = 15;
+const SCORE_ADDRESS: u32 = 10;
+
+/// Организационно-правовые формы, которые пишут как попало («д.о.о.», «DOO», «доо Београд»)
+const LEGAL_FORMS: [&str; 9] = ["doo", "ad", "pr", "szr", "str", "sur", "preduzetnik", "ooo", "llc"];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateCandidate {
+    pub client: Client,
+    pub duplicate: Client,
+    pub score: u32,
+    /// pib / mb / name / similarName / address
+    pub matched: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    pub surviving: Client,
+    pub merged_ids: Vec<i64>,
+    pub invoices_moved: usize,
+    pub deliveries_moved: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientMerge {
+    pub id: i64,
+    pub surviving_id: i64,
+    pub merged_id: i64,
+    /// Удалённая запись клиента в JSON, как её отдавал get_clients
+    pub merged_snapshot: String,
+    pub invoices_moved: i64,
+    pub deliveries_moved: i64,
+    pub merged_at: String,
+}
+
+/// Латиница без диакритики: клиентов заводят то кириллицей, то латиницей.
+fn transliterate(c: char) -> &'static str {
+    match c {
+        'а' => "a", 'б' => "b", 'в' => "v", 'г' => "g", 'д' => "d", 'ђ' | 'đ' => "dj",
+        'е' => "e", 'ж' | 'ž' => "z", 'з' => "z", 'и' => "i", 'ј' => "j", 'к' => "k",
+        'л' => "l", 'љ' => "lj", 'м' => "m", 'н' => "n", 'њ' => "nj", 'о' => "o",
+        'п' => "p", 'р' => "r", 'с' => "s", 'т' => "t", 'ћ' | 'ч' | 'ć' | 'č' => "c",
+        'у' => "u", 'ф' => "f", 'х' => "h", 'ц' => "c", 'џ' => "dz", 'ш' | 'š' => "s",
+        _ => "",
+    }
+}
+
+/// Нормализованные слова: нижний регистр, латиница, без пунктуации («d.o.o.» -> «doo»).
+fn tokens(value: &str) -> Vec<String> {
+    let mut normalized = String::with_capacity(value.len());
+    for c in value.to_lowercase().chars() {
+        match transliterate(c) {
+            "" if c.is_alphanumeric() => normalized.push(c),
+            "" if c == '.' || c == '\'' => {}
+            "" => normalized.push(' '),
+            latin => normalized.push_str(latin),
+        }
+    }
+    normalized.split_whitespace().map(str::to_string).collect()
+}
+
+pub fn normalize_name(name: &str) -> String {
+    tokens(name)
+        .into_iter()
+        .filter(|t| !LEGAL_FORMS.contains(&t.as_str()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn address_key(client: &Client) -> Option<String> {
+    let street = match client.street.as_deref().filter(|s| !s.trim().is_empty()) {
+        Some(street) => format!("{} {}", street, client.house_number.as_deref().unwrap_or("")),
+        None => client.address.clone()?,
+    };
+    let street = tokens(&street).join(" ");
+    if street.is_empty() {
+        return None;
+    }
+    Some(format!("{}|{}", street, tokens(client.city.as_deref().unwrap_or("")).join(" ")))
+}
+
+fn same_number(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a.map(str::trim), b.map(str::trim)) {
+        (Some(a), Some(b)) => !a.is_empty() && a == b,
+        _ => false,
+    }
+}
+
+fn names(client: &Client) -> Vec<String> {
+    [Some(client.name.as_str()), client.legal_name.as_deref()]
+        .into_iter()
+        .flatten()
+        .map(normalize_name)
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+/// Доля общих слов (Жаккар) — «Kafana Dva Jelena» и «Dva Jelena Kafana Beograd» похожи.
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: HashSet<&str> = a.split(' ').collect();
+    let b: HashSet<&str> = b.split(' ').collect();
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+pub fn score(a: &Client, b: &Client) -> (u32, Vec<&'static str>) {
+    let mut score = 0;
+    let mut matched = Vec::new();
+    if same_number(a.pib.as_deref(), b.pib.as_deref()) {
+        score += SCORE_PIB;
+        matched.push("pib");
+    }
+    if same_number(Some(&a.mb), Some(&b.mb)) {
+        score += SCORE_MB;
+        matched.push("mb");
+    }
+
+    let (names_a, names_b) = (names(a), names(b));
+    let pairs = || names_a.iter().flat_map(|x| names_b.iter().map(move |y| (x, y)));
+    if pairs().any(|(x, y)| x == y) {
+        score += SCORE_NAME;
+        matched.push("name");
+    } else if pairs().any(|(x, y)| similarity(x, y) >= 0.6) {
+        score += SCORE_SIMILAR_NAME;
+        matched.push("similarName");
+    }
+
+    if let (Some(x), Some(y)) = (address_key(a), address_key(b)) {
+        if x == y {
+            score += SCORE_ADDRESS;
+            matched.push("address");
+        }
+    }
+    (score, matched)
+}
+
+fn load_clients(conn: &Connection) -> Result<Vec<Client>, String> {
+    let mut stmt = conn
+        .prepare(&format!("{} ORDER BY id", commands::client_select()))
+        .map_err(|e| e.to_string())?;
+    let clients = stmt
+        .query_map([], commands::client_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(clients)
+}
+
+fn load_client(conn: &Connection, id: i64) -> Result<Client, String> {
+    conn.query_row(
+        &format!("{} WHERE id = ?1", commands::client_select()),
+        [id],
+        commands::client_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Client {} not found", id))
+}
+
+/// Пары возможных дублей, лучшие сверху. С `client_id` — только дубли этого клиента
+/// (он всегда в поле `client`).
+pub fn find_duplicates(conn: &Connection, client_id: Option<i64>) -> Result<Vec<DuplicateCandidate>, String> {
+    let clients = load_clients(conn)?;
+    let mut candidates = Vec::new();
+    for (i, a) in clients.iter().enumerate() {
+        for b in &clients[i + 1..] {
+            let (a, b) = match client_id {
+                None => (a, b),
+                Some(id) if a.id == Some(id) => (a, b),
+                Some(id) if b.id == Some(id) => (b, a),
+                Some(_) => continue,
+            };
+            let (score, matched) = score(a, b);
+            if score >= MIN_SCORE {
+                candidates.push(DuplicateCandidate {
+                    client: a.clone(),
+                    duplicate: b.clone(),
+                    score,
+                    matched,
+                });
+            }
+        }
+    }
+    candidates.sort_by(|x, y| y.score.cmp(&x.score).then(x.client.id.cmp(&y.client.id)));
+    Ok(candidates)
+}
+
+/// Переносит документы дублей на `surviving_id` и удаляет дубли. Документы ссылаются на
+/// клиента по id или по МБ (синхронизация) — переписываем оба варианта на id оставшегося.
+/// Выданные рачуни сохраняют имя покупателя, под которым они выставлены; каждый перенесённый
+/// инвойс пишется в журнал аудита.
+pub fn merge(tx: &Transaction, actor: &audit::Actor, surviving_id: i64, merged_ids: &[i64]) -> Result<MergeResult, String> {
+    if merged_ids.is_empty() {
+        return Err("No clients to merge".to_string());
+    }
+    if merged_ids.contains(&surviving_id) {
+        return Err(format!("Client {} cannot be merged into itself", surviving_id));
+    }
+    let surviving = load_client(tx, surviving_id)?;
+    let surviving_ref = surviving_id.to_string();
+    let merged_at = Utc::now().to_rfc3339();
+
+    let mut invoices_moved = 0;
+    let mut deliveries_moved = 0;
+    for &merged_id in merged_ids {
+        let merged = load_client(tx, merged_id)?;
+        let merged_ref = merged_id.to_string();
+        let merged_mb = merged.mb.trim();
+
+        let invoices = move_invoices(tx, actor, (&surviving_ref, &surviving.name), &merged_ref, merged_mb)?;
+        let deliveries = tx
+            .execute(
+                "UPDATE deliveries SET client_id = ?1, client_name = ?2
+                 WHERE client_id = ?3 OR (?4 != '' AND client_id = ?4)",
+                params![surviving_ref, surviving.name, merged_ref, merged_mb],
+            )
+            .map_err(|e| e.to_string())?;
+
+        tx.execute("UPDATE users SET client_id = ?1 WHERE client_id = ?2", params![surviving_id, merged_id])
+            .map_err(|e| e.to_string())?;
+
+        let snapshot = serde_json::to_string(&merged).map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT INTO client_merges (surviving_id, merged_id, merged_snapshot, invoices_moved, deliveries_moved, merged_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![surviving_id, merged_id, snapshot, invoices as i64, deliveries as i64, merged_at],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM clients WHERE id = ?1", [merged_id])
+            .map_err(|e| e.to_string())?;
+
+        invoices_moved += invoices;
+        deliveries_moved += deliveries;
+    }
+
+    Ok(MergeResult {
+        surviving,
+        merged_ids: merged_ids.to_vec(),
+        invoices_moved,
+        deliveries_moved,
+    })
+}
+
+/// Переносит инвойсы дубля (по id или МБ) на оставшегося клиента; возвращает их число.
+fn move_invoices(
+    tx: &Transaction,
+    actor: &audit::Actor,
+    (surviving_ref, surviving_name): (&str, &str),
+    merged_ref: &str,
+    merged_mb: &str,
+) -> Result<usize, String> {
+    let invoices = tx
+        .prepare("SELECT id, document_type, status FROM invoices WHERE client_id = ?1 OR (?2 != '' AND client_id = ?2)")
+        .and_then(|mut stmt| {
+            stmt.query_map(params![merged_ref, merged_mb], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .map_err(|e| e.to_string())?;
+
+    for (id, document_type, status) in &invoices {
+        let before = audit::invoice(tx, id)?;
+        if commands::is_issued(document_type, status) {
+            tx.execute("UPDATE invoices SET client_id = ?1 WHERE id = ?2", params![surviving_ref, id])
+        } else {
+            tx.execute(
+                "UPDATE invoices SET client_id = ?1, client_name = ?2 WHERE id = ?3",
+                params![surviving_ref, surviving_name, id],
+            )
+        }
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "invoices", "id", id, actor, audit::UPDATE)?;
+        let after = audit::invoice(tx, id)?;
+        audit::record(tx, actor, audit::MERGE, "invoice", id, before, after)?;
+    }
+    Ok(invoices.len())
+}
+
+pub fn load_merges(conn: &Connection, client_id: Option<i64>) -> Result<Vec<ClientMerge>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, surviving_id, merged_id, merged_snapshot, invoices_moved, deliveries_moved, merged_at
+             FROM client_merges
+             WHERE ?1 IS NULL OR surviving_id = ?1 OR merged_id = ?1
+             ORDER BY id DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let merges = stmt
+        .query_map([client_id], |row| {
+            Ok(ClientMerge {
+                id: row.get(0)?,
+                surviving_id: row.get(1)?,
+                merged_id: row.get(2)?,
+                merged_snapshot: row.get(3)?,
+                invoices_moved: row.get(4)?,
+                deliveries_moved: row.get(5)?,
+                merged_at: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(merges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn insert_client(conn: &Connection, name: &str, mb: &str, pib: Option<&str>, street: Option<&str>) -> i64 {
+        conn.execute(
+            "INSERT INTO clients (name, mb, pib, street, city, created_at) VALUES (?1, ?2, ?3, ?4, 'Beograd', '')",
+            params![name, mb, pib, street],
+        )
+        .unwrap();
+        conn.last_insert_rowid()
+    }
+
+    fn actor() -> audit::Actor {
+        audit::Actor { user_id: None, username: "test".to_string() }
+    }
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("Кафана Два Јелена Д.О.О."), "kafana dva jelena");
+        assert_eq!(normalize_name("KAFANA DVA JELENA doo"), "kafana dva jelena");
+        assert_eq!(normalize_name("Ćevabdžinica Željko PR"), "cevabdzinica zeljko");
+    }
+
+    #[test]
+    fn test_find_duplicates_scores() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        let a = insert_client(&conn, "Кафана Два Јелена ДОО", "20000001", Some("114407658"), Some("Скадарска 32"));
+        let b = insert_client(&conn, "Kafana Dva Jelena d.o.o.", "20000002", Some("114407658"), Some("Skadarska 32"));
+        let c = insert_client(&conn, "Dva Jelena", "20000001", None, None);
+        insert_client(&conn, "Pekara Centar", "30000000", None, Some("Skadarska 32"));
+
+        let all = find_duplicates(&conn, None).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!((all[0].client.id, all[0].duplicate.id), (Some(a), Some(b)));
+        assert_eq!(all[0].score, SCORE_PIB + SCORE_NAME + SCORE_ADDRESS);
+        assert_eq!(all[0].matched, vec!["pib", "name", "address"]);
+        // тот же МБ, имя похоже
+        assert_eq!((all[1].client.id, all[1].duplicate.id), (Some(a), Some(c)));
+        assert_eq!(all[1].matched, vec!["mb", "similarName"]);
+
+        let for_c = find_duplicates(&conn, Some(c)).unwrap();
+        assert_eq!(for_c.len(), 1);
+        assert_eq!((for_c[0].client.id, for_c[0].duplicate.id), (Some(c), Some(a)));
+    }
+
+    #[test]
+    fn test_merge_repoints_documents_and_keeps_trail() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        let keep = insert_client(&conn, "Kafana Dva Jelena", "20000001", None, None);
+        let dup = insert_client(&conn, "Kafana 2 Jelena", "20000002", None, None);
+        conn.execute_batch(&format!(
+            "INSERT INTO invoices (id, invoice_number, document_type, client_id, client_name, date, total, status, created_at) VALUES
+                ('i1', '1/2026', 'racun', '{dup}', 'Kafana 2 Jelena', '2026-01-10', 100, 'confirmed', ''),
+                ('i2', '2/2026', 'racun', '{dup}', 'Kafana 2 Jelena', '2026-01-11', 100, 'confirmed', ''),
+                ('i3', '3/2026', 'racun', '{keep}', 'Kafana Dva Jelena', '2026-01-12', 100, 'confirmed', ''),
+                ('i4', '', 'racun', '{dup}', 'Kafana 2 Jelena', '2026-01-13', 100, 'draft', '');
+             INSERT INTO deliveries (id, delivery_number, client_id, client_name, date, status, created_at) VALUES
+                ('d1', 'O-1', '{dup}', 'Kafana 2 Jelena', '2026-01-10', 'delivered', ''),
+                ('d2', 'O-2', '20000002', 'Kafana 2 Jelena', '2026-01-11', 'delivered', '');"
+        ))
+        .unwrap();
+
+        let tx = conn.transaction().unwrap();
+        let result = merge(&tx, &actor(), keep, &[dup]).unwrap();
+        tx.commit().unwrap();
+        assert_eq!((result.invoices_moved, result.deliveries_moved), (3, 2));
+
+        let history: Vec<(String, String)> = conn
+            .prepare("SELECT id, client_name FROM invoices WHERE client_id = ?1 ORDER BY id")
+            .unwrap()
+            .query_map([keep.to_string()], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        // Выданные рачуни сохраняют имя, под которым выставлены; черновик получает новое
+        let names: Vec<(&str, &str)> = history.iter().map(|(id, name)| (id.as_str(), name.as_str())).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("i1", "Kafana 2 Jelena"),
+                ("i2", "Kafana 2 Jelena"),
+                ("i3", "Kafana Dva Jelena"),
+                ("i4", "Kafana Dva Jelena"),
+            ]
+        );
+        assert!(load_client(&conn, dup).is_err());
+
+        let moved: Vec<String> = conn
+            .prepare("SELECT entity_id FROM audit_log WHERE entity = 'invoice' AND action = 'merge' ORDER BY entity_id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(moved, vec!["i1", "i2", "i4"]);
+
+        let trail = load_merges(&conn, Some(keep)).unwrap();
+        assert_eq!(trail.len(), 1);
+        assert_eq!(trail[0].merged_id, dup);
+        assert!(trail[0].merged_snapshot.contains("Kafana 2 Jelena"));
+
+        // Ошибка в середине — транзакция откатывается целиком
+        let other = insert_client(&conn, "Treći", "20000003", None, None);
+        let tx = conn.transaction().unwrap();
+        assert!(merge(&tx, &actor(), keep, &[other, 999]).is_err());
+        drop(tx);
+        assert!(load_client(&conn, other).is_ok());
+        assert!(merge(&conn.transaction().unwrap(), &actor(), keep, &[keep]).is_err());
+    }
+}
@@ -1,10 +1,29 @@
 use serde::{Deserialize, Serialize};
 use tauri::{State, Manager};
+use crate::audit::{self, AuditEntry, AuditFilter};
+use crate::backup::{self, BackupInfo, BackupKind, BackupSettings, RestoreResult};
 use crate::database::Database;
+use crate::client_merge::{self, ClientMerge, DuplicateCandidate, MergeResult};
+use crate::company::{self, CompanyProfile};
+use crate::efaktura::{self, EfakturaClient, EfakturaError, EfakturaSettings, SalesInvoiceRequest, SalesInvoiceResponse};
+use crate::encryption::EncryptionStatus;
+use crate::efaktura_poller::{self, PollSummary, PurchaseInvoice};
+use crate::ips;
+use crate::session::{Session, SessionInfo};
+use crate::users::{self, NewUser, Permission, Role, RoleInfo, SetupStatus, UserAccount, UserUpdate};
+use crate::pdf::{self, PdfInput};
+use crate::validation::{self, CommandError, Usage, ValidationReport};
+use crate::ubl::{self, OtpremnicaAttachment, UblInput};
 use crate::forecast_service::{ForecastReport, ForecastRequest, ForecastService};
-use rusqlite::params;
+use crate::receivables_service::{ReceivablesReport, ReceivablesRequest, ReceivablesService};
+use crate::migrations::{self, SchemaVersion};
+use crate::numbering::{self, DocumentSequence};
+use crate::payments::{self, NewPayment, Payment};
+use crate::pricing::{self, InvoiceTotals, LineInput, TaxBreakdown};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
 use chrono::Utc;
 use reqwest;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NbsRateRequest {
@@ -14,14 +33,23 @@ pub struct NbsRateRequest {
 
 // ==================== СТРУКТУРЫ ====================
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct User {
     pub id: String,
     pub username: String,
     pub role: String,
+    pub full_name: Option<String>,
+    pub client_id: Option<i64>,
+    /// МБ клиента для профиля клиента — UI фильтрует по нему документы
+    pub client_mb: Option<String>,
+    /// Права роли — UI по ним прячет разделы, проверяются они в командах
+    pub permissions: &'static [Permission],
+    /// Открытая сессия; закрывается logout или по бездействию
+    pub session_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Client {
     pub id: Option<i64>,
@@ -112,6 +140,27 @@ pub struct Invoice {
     pub currency: Option<String>,
     pub exchange_rate: Option<f64>,
     pub exchange_rate_date: Option<String>,
+    /// Основа без ПДВ — считается на сервере
+    pub subtotal: Option<f64>,
+    pub vat_total: Option<f64>,
+    /// Ставка ПДВ документа по умолчанию (для строк без своей ставки)
+    pub vat_rate: Option<f64>,
+    pub vat_exemption_code: Option<String>,
+    /// Документ, из которого создан этот (предрачун -> рачун/отпремница)
+    pub source_document_id: Option<String>,
+    /// Вычисляются по журналу оплат (payments), при записи игнорируются
+    pub amount_paid: Option<f64>,
+    pub balance: Option<f64>,
+    /// paid / partial / unpaid / overdue
+    pub payment_status: Option<String>,
+    /// eFaktura (SEF): SalesInvoiceId, статус и последняя ошибка отправки; пишутся только модулем efaktura
+    pub sef_invoice_id: Option<i64>,
+    pub sef_status: Option<String>,
+    pub sef_sent_at: Option<String>,
+    pub sef_error: Option<String>,
+    /// Кто создал и кто последним изменил документ (имя пользователя); проставляет сервер
+    pub created_by: Option<String>,
+    pub modified_by: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -125,13 +174,121 @@ pub struct InvoiceItem {
     pub unit_weight_g: Option<f64>,
     pub price: f64,
     pub total: f64,
+    /// Рабат в процентах
+    pub discount_pct: Option<f64>,
+    pub vat_rate: Option<f64>,
+    pub vat_exemption_code: Option<String>,
+    pub vat_amount: Option<f64>,
+    /// Для кредит-ноты: строка исходного рачуна, которую она сторнирует
+    pub credited_item_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct InvoiceWithItems {
     #[serde(flatten)]
     pub invoice: Invoice,
     pub items: Vec<InvoiceItem>,
+    #[serde(default)]
+    pub tax_breakdown: Vec<TaxBreakdown>,
+    /// Цепочка источников: ближайший первым (рачун -> предрачун -> ...)
+    #[serde(default)]
+    pub source_documents: Vec<LinkedDocument>,
+    /// Документы, созданные из этого
+    #[serde(default)]
+    pub derived_documents: Vec<LinkedDocument>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkedDocument {
+    pub id: String,
+    pub invoice_number: String,
+    pub document_type: String,
+    pub date: String,
+    pub total: f64,
+}
+
+const INVOICE_COLUMNS: &str = "id, invoice_number, document_type, client_id, client_name, date, due_date, total, status, notes, created_at, delivered, currency, exchange_rate, exchange_rate_date, subtotal, vat_total, vat_rate, vat_exemption_code, source_document_id, sef_invoice_id, sef_status, sef_sent_at, sef_error, created_by, modified_by";
+
+/// Колонки шапки + сумма оплат и кредит-нот для вычисления статуса оплаты.
+fn invoice_select() -> String {
+    format!(
+        "SELECT {}, {}, {} FROM invoices",
+        INVOICE_COLUMNS,
+        payments::AMOUNT_PAID_SQL,
+        payments::CREDITED_TOTAL_SQL
+    )
+}
+
+fn invoice_from_row(row: &rusqlite::Row) -> rusqlite::Result<Invoice> {
+    // SQLite хранит INTEGER (0/1), конвертируем в bool
+    let delivered_int: Option<i32> = row.get(11).ok();
+    let document_type: String = row.get(2)?;
+    let due_date: Option<String> = row.get(6)?;
+    let total: f64 = row.get(7)?;
+    let state = payments::payment_state(
+        &document_type,
+        total,
+        row.get(27)?,
+        row.get(26)?,
+        due_date.as_deref(),
+        payments::today(),
+    );
+    
+    Ok(Invoice {
+        id: Some(row.get(0)?),
+        invoice_number: row.get(1)?,
+        document_type,
+        client_id: row.get(3)?,
+        client_name: row.get(4)?,
+        date: row.get(5)?,
+        due_date,
+        total,
+        status: row.get(8)?,
+        notes: row.get(9)?,
+        created_at: Some(row.get(10)?),
+        paid: Some(state.status == Some(payments::STATUS_PAID)),
+        delivered: delivered_int.map(|v| v != 0),
+        currency: row.get(12).ok(),
+        exchange_rate: row.get(13).ok(),
+        exchange_rate_date: row.get(14).ok(),
+        subtotal: row.get(15)?,
+        vat_total: row.get(16)?,
+        vat_rate: row.get(17)?,
+        vat_exemption_code: row.get(18)?,
+        source_document_id: row.get(19)?,
+        amount_paid: Some(state.amount_paid),
+        balance: Some(state.balance),
+        payment_status: state.status.map(str::to_string),
+        sef_invoice_id: row.get(20)?,
+        sef_status: row.get(21)?,
+        sef_sent_at: row.get(22)?,
+        sef_error: row.get(23)?,
+        created_by: row.get(24)?,
+        modified_by: row.get(25)?,
+    })
+}
+
+/// invoice_items.product_id хранит products.id, наружу отдаём код товара, как его присылает UI
+const INVOICE_ITEM_COLUMNS: &str = "id, invoice_id, COALESCE((SELECT COALESCE(NULLIF(p.internal_code, ''), p.code) FROM products p WHERE p.id = invoice_items.product_id), product_id), product_name, quantity, unit_weight_g, price, total, discount_pct, vat_rate, vat_exemption_code, vat_amount, credited_item_id";
+
+fn invoice_item_from_row(row: &rusqlite::Row) -> rusqlite::Result<InvoiceItem> {
+    Ok(InvoiceItem {
+        id: Some(row.get(0)?),
+        invoice_id: row.get(1)?,
+        product_id: row.get(2)?,
+        product_name: row.get(3)?,
+        quantity: row.get(4)?,
+        unit_weight_g: row.get(5)?,
+        price: row.get(6)?,
+        total: row.get(7)?,
+        discount_pct: row.get(8)?,
+        vat_rate: row.get(9)?,
+        vat_exemption_code: row.get(10)?,
+        vat_amount: row.get(11)?,
+        credited_item_id: row.get(12)?,
+    })
 }
 
 fn iso_to_ddmmyyyy(iso: &str) -> Option<String> {
@@ -147,7 +304,7 @@ fn iso_to_ddmmyyyy(iso: &str) -> Option<String> {
 }
 
 fn parse_serbian_decimal(s: &str) -> Option<f64> {
-    let t = s.trim().replace('\u{a0}', "").replace(' ', "").replace('.', "").replace(',', ".");
+    let t = s.trim().replace(['\u{a0}', ' ', '.'], "").replace(',', ".");
     t.parse::<f64>().ok()
 }
 
@@ -208,7 +365,8 @@ async fn fetch_rate_from_nbs(date_ddmmyyyy: &str, currency: &str) -> Result<f64,
 }
 
 #[tauri::command]
-pub async fn fetch_nbs_rate(req: NbsRateRequest, db: State<'_, Database>) -> Result<f64, String> {
+pub async fn fetch_nbs_rate(req: NbsRateRequest, db: State<'_, Database>, session: State<'_, Session>) -> Result<f64, String> {
+    session.signed_in()?;
     let iso = req.date.trim().to_string();
     let currency = req.currency.trim().to_uppercase();
     if currency == "RSD" {
@@ -216,9 +374,14 @@ pub async fn fetch_nbs_rate(req: NbsRateRequest, db: State<'_, Database>) -> Res
     }
     let ddmmyyyy = iso_to_ddmmyyyy(&iso).ok_or_else(|| "Invalid date format (expected YYYY-MM-DD)".to_string())?;
 
-    // cache lookup
-    if let Ok(mut stmt) = db.conn().prepare("SELECT rate FROM nbs_rates WHERE date = ?1 AND currency = ?2") {
-        let cached: Result<f64, _> = stmt.query_row(params![ddmmyyyy, currency], |row| row.get(0));
+    // cache lookup (соединение возвращается в пул до сетевого запроса)
+    {
+        let conn = db.conn()?;
+        let cached: Result<f64, _> = conn.query_row(
+            "SELECT rate FROM nbs_rates WHERE date = ?1 AND currency = ?2",
+            params![ddmmyyyy, currency],
+            |row| row.get(0),
+        );
         if let Ok(rate) = cached {
             return Ok(rate);
         }
@@ -226,7 +389,7 @@ pub async fn fetch_nbs_rate(req: NbsRateRequest, db: State<'_, Database>) -> Res
 
     let rate = fetch_rate_from_nbs(&ddmmyyyy, &currency).await?;
     let fetched_at = Utc::now().to_rfc3339();
-    let _ = db.conn().execute(
+    let _ = db.conn()?.execute(
         "INSERT INTO nbs_rates (date, currency, rate, fetched_at) VALUES (?1, ?2, ?3, ?4)
          ON CONFLICT(date, currency) DO UPDATE SET rate = excluded.rate, fetched_at = excluded.fetched_at",
         params![ddmmyyyy, currency, rate, fetched_at],
@@ -282,76 +445,238 @@ pub struct WarehouseItem {
 // ==================== КОМАНДЫ: АВТОРИЗАЦИЯ ====================
 
 #[tauri::command]
-pub fn login(username: String, password: String, db: State<Database>) -> Result<User, String> {
-    let mut stmt = db.conn()
-        .prepare("SELECT id, username, password, role FROM users WHERE username = ?1")
-        .map_err(|e| e.to_string())?;
-    
-    let user_result = stmt.query_row([&username], |row| {
-        Ok((
-            row.get::<_, String>(0)?,
-            row.get::<_, String>(1)?,
-            row.get::<_, String>(2)?,
-            row.get::<_, String>(3)?,
-        ))
-    });
-    
-    match user_result {
-        Ok((id, username, password_hash, role)) => {
-            if bcrypt::verify(&password, &password_hash).unwrap_or(false) {
-                Ok(User { id, username, role })
-            } else {
-                Err("Invalid credentials".to_string())
+pub fn login(username: String, password: String, db: State<Database>, session: State<Session>) -> Result<User, String> {
+    // Новый вход закрывает предыдущую сессию, даже если не удался
+    session.sign_out();
+    let conn = db.conn()?;
+    let now = Utc::now();
+    // Неизвестный логин, неверный пароль и отключённая учётная запись неотличимы снаружи
+    let credentials = users::credentials(&conn, &username)?;
+    if let Some(credentials) = &credentials {
+        users::check_throttle(credentials, now)?;
+    }
+    if !users::verify_password(credentials.as_ref(), &password) {
+        if let Some(credentials) = &credentials {
+            let id = &credentials.account.id;
+            if db.with_transaction(|tx| users::record_failed_login(tx, id, now))? {
+                println!("🔒 Учётная запись {} заблокирована после неудачных входов", credentials.account.username);
             }
         }
-        Err(_) => Err("User not found".to_string()),
+        return Err("Invalid credentials".to_string());
+    }
+
+    let account = credentials.ok_or("Invalid credentials")?.account;
+    if !account.is_active {
+        return Err("Invalid credentials".to_string());
+    }
+    let role = Role::parse(&account.role).ok_or_else(|| format!("Unknown role: {}", account.role))?;
+    users::record_successful_login(&conn, &account.id, now)?;
+    let client_mb: Option<String> = match account.client_id {
+        Some(client_id) => conn
+            .query_row("SELECT mb FROM clients WHERE id = ?1", [client_id], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?,
+        None => None,
+    };
+    let session_id = session.sign_in(
+        audit::Actor {
+            user_id: Some(account.id.clone()),
+            username: account.username.clone(),
+        },
+        role,
+        account.client_id,
+    );
+    println!("🔐 Вход: {} ({})", account.username, account.role);
+    Ok(User {
+        id: account.id,
+        username: account.username,
+        role: account.role,
+        full_name: account.full_name,
+        client_id: account.client_id,
+        client_mb,
+        permissions: role.permissions(),
+        session_id,
+    })
+}
+
+/// Первый запуск: пользователей ещё нет, UI предлагает создать администратора.
+#[tauri::command]
+pub fn get_setup_status(db: State<Database>) -> Result<SetupStatus, String> {
+    let conn = db.conn()?;
+    users::setup_status(&conn)
+}
+
+/// Владелец приложения. Работает только на пустой таблице users — дальше пользователей
+/// заводит администратор (create_user).
+#[tauri::command]
+pub fn create_initial_admin(
+    username: String,
+    password: String,
+    full_name: Option<String>,
+    db: State<Database>,
+) -> Result<UserAccount, String> {
+    let created = db.with_transaction(|tx| {
+        let created = users::create_initial_admin(tx, &username, &password, full_name.as_deref())?;
+        let actor = audit::Actor {
+            user_id: Some(created.id.clone()),
+            username: created.username.clone(),
+        };
+        let after = audit::row(tx, "users", "id", &created.id)?;
+        audit::record(tx, &actor, audit::CREATE, "user", &created.id, None, after)?;
+        Ok(created)
+    })?;
+    println!("👑 Создан администратор: {}", created.username);
+    Ok(created)
+}
+
+#[tauri::command]
+pub fn logout(session: State<Session>) -> Result<(), String> {
+    if let Some(username) = session.sign_out() {
+        println!("🚪 Выход: {}", username);
+    }
+    Ok(())
+}
+
+/// Открытая сессия; ошибка, если входа не было или сессия истекла.
+#[tauri::command]
+pub fn get_session(session: State<Session>) -> Result<SessionInfo, String> {
+    session.info()
+}
+
+// ==================== КОМАНДЫ: ПОЛЬЗОВАТЕЛИ ====================
+
+#[tauri::command]
+pub fn get_users(db: State<Database>, session: State<Session>) -> Result<Vec<UserAccount>, String> {
+    session.require(Permission::UsersManage)?;
+    let conn = db.conn()?;
+    users::list(&conn)
+}
+
+#[tauri::command]
+pub fn get_roles(session: State<Session>) -> Result<Vec<RoleInfo>, String> {
+    session.signed_in()?;
+    Ok(users::roles())
+}
+
+#[tauri::command]
+pub fn create_user(user: NewUser, db: State<Database>, session: State<Session>) -> Result<UserAccount, String> {
+    let actor = session.require(Permission::UsersManage)?;
+    let created = db.with_transaction(|tx| {
+        let created = users::create(tx, &user)?;
+        let after = audit::row(tx, "users", "id", &created.id)?;
+        audit::record(tx, &actor, audit::CREATE, "user", &created.id, None, after)?;
+        Ok(created)
+    })?;
+    println!("👤 Создан пользователь: {} ({})", created.username, created.role);
+    Ok(created)
+}
+
+#[tauri::command]
+pub fn update_user(id: String, update: UserUpdate, db: State<Database>, session: State<Session>) -> Result<UserAccount, String> {
+    let actor = session.require(Permission::UsersManage)?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "users", "id", &id)?;
+        let updated = users::update(tx, &id, &update)?;
+        let after = audit::row(tx, "users", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "user", &id, before, after)?;
+        Ok(updated)
+    })
+}
+
+/// Отключённый пользователь не может войти; записи и журнал с его именем остаются.
+#[tauri::command]
+pub fn set_user_active(id: String, active: bool, db: State<Database>, session: State<Session>) -> Result<UserAccount, String> {
+    let actor = session.require(Permission::UsersManage)?;
+    if !active && actor.user_id.as_deref() == Some(id.as_str()) {
+        return Err("You cannot disable your own account".to_string());
     }
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "users", "id", &id)?;
+        let updated = users::set_active(tx, &id, active)?;
+        let after = audit::row(tx, "users", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "user", &id, before, after)?;
+        Ok(updated)
+    })
+}
+
+#[tauri::command]
+pub fn reset_user_password(id: String, new_password: String, db: State<Database>, session: State<Session>) -> Result<(), String> {
+    let actor = session.require(Permission::UsersManage)?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "users", "id", &id)?;
+        users::reset_password(tx, &id, &new_password)?;
+        let after = audit::row(tx, "users", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "user", &id, before, after)
+    })
+}
+
+/// Смена собственного пароля — доступна любой роли.
+#[tauri::command]
+pub fn change_password(old_password: String, new_password: String, db: State<Database>, session: State<Session>) -> Result<(), String> {
+    let actor = session.signed_in()?.actor;
+    let id = actor.user_id.clone().ok_or("Not signed in")?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "users", "id", &id)?;
+        users::change_password(tx, &id, &old_password, &new_password)?;
+        let after = audit::row(tx, "users", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "user", &id, before, after)
+    })
 }
 
 // ==================== КОМАНДЫ: КЛИЕНТЫ ====================
 
+const CLIENT_COLUMNS: &str = "id, name, legal_name, mb, pib, address, city, postal_code, country, phone, email, tax_id, bank, client_type, abbreviation, municipality, street, house_number, is_manual_address, google_maps, contact_person, contact_person_status, telegram, instagram, installment, installment_term, showcase, bar, notes, contact, created_at, updated_at";
+
+pub fn client_select() -> String {
+    format!("SELECT {} FROM clients", CLIENT_COLUMNS)
+}
+
+pub fn client_from_row(row: &rusqlite::Row) -> rusqlite::Result<Client> {
+    Ok(Client {
+        id: Some(row.get(0)?),
+        name: row.get(1)?,
+        legal_name: row.get(2)?,
+        mb: row.get(3)?,
+        pib: row.get(4)?,
+        address: row.get(5)?,
+        city: row.get(6)?,
+        postal_code: row.get(7)?,
+        country: row.get(8)?,
+        phone: row.get(9)?,
+        email: row.get(10)?,
+        tax_id: row.get(11)?,
+        bank: row.get(12)?,
+        client_type: row.get(13)?,
+        abbreviation: row.get(14)?,
+        municipality: row.get(15)?,
+        street: row.get(16)?,
+        house_number: row.get(17)?,
+        is_manual_address: row.get(18)?,
+        google_maps: row.get(19)?,
+        contact_person: row.get(20)?,
+        contact_person_status: row.get(21)?,
+        telegram: row.get(22)?,
+        instagram: row.get(23)?,
+        installment: row.get(24)?,
+        installment_term: row.get(25)?,
+        showcase: row.get(26)?,
+        bar: row.get(27)?,
+        notes: row.get(28)?,
+        contact: row.get(29)?,
+        created_at: Some(row.get(30)?),
+        updated_at: row.get(31)?,
+    })
+}
+
 #[tauri::command]
-pub fn get_clients(db: State<Database>) -> Result<Vec<Client>, String> {
-    let mut stmt = db.conn()
-        .prepare("SELECT id, name, legal_name, mb, pib, address, city, postal_code, country, phone, email, tax_id, bank, client_type, abbreviation, municipality, street, house_number, is_manual_address, google_maps, contact_person, contact_person_status, telegram, instagram, installment, installment_term, showcase, bar, notes, contact, created_at, updated_at FROM clients ORDER BY created_at DESC")
+pub fn get_clients(db: State<Database>, session: State<Session>) -> Result<Vec<Client>, String> {
+    session.require(Permission::ClientsView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
+        .prepare(&format!("{} ORDER BY created_at DESC", client_select()))
         .map_err(|e| e.to_string())?;
     
-    let clients = stmt.query_map([], |row| {
-        Ok(Client {
-            id: Some(row.get(0)?),
-            name: row.get(1)?,
-            legal_name: row.get(2)?,
-            mb: row.get(3)?,
-            pib: row.get(4)?,
-            address: row.get(5)?,
-            city: row.get(6)?,
-            postal_code: row.get(7)?,
-            country: row.get(8)?,
-            phone: row.get(9)?,
-            email: row.get(10)?,
-            tax_id: row.get(11)?,
-            bank: row.get(12)?,
-            client_type: row.get(13)?,
-            abbreviation: row.get(14)?,
-            municipality: row.get(15)?,
-            street: row.get(16)?,
-            house_number: row.get(17)?,
-            is_manual_address: row.get(18)?,
-            google_maps: row.get(19)?,
-            contact_person: row.get(20)?,
-            contact_person_status: row.get(21)?,
-            telegram: row.get(22)?,
-            instagram: row.get(23)?,
-            installment: row.get(24)?,
-            installment_term: row.get(25)?,
-            showcase: row.get(26)?,
-            bar: row.get(27)?,
-            notes: row.get(28)?,
-            contact: row.get(29)?,
-            created_at: Some(row.get(30)?),
-            updated_at: row.get(31)?,
-        })
-    })
+    let clients = stmt.query_map([], client_from_row)
     .map_err(|e| e.to_string())?
     .collect::<Result<Vec<_>, _>>()
     .map_err(|e| e.to_string())?;
@@ -360,48 +685,56 @@ pub fn get_clients(db: State<Database>) -> Result<Vec<Client>, String> {
 }
 
 #[tauri::command]
-pub fn create_client(client: Client, db: State<Database>) -> Result<Client, String> {
+pub fn create_client(client: Client, db: State<Database>, session: State<Session>) -> Result<Client, CommandError> {
+    let actor = session.require(Permission::ClientsEdit)?;
+    validation::validate_client(&client).into_result("create_client")?;
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "INSERT INTO clients (name, legal_name, mb, pib, address, city, postal_code, country, phone, email, tax_id, bank, client_type, abbreviation, municipality, street, house_number, is_manual_address, google_maps, contact_person, contact_person_status, telegram, instagram, installment, installment_term, showcase, bar, notes, contact, created_at) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30)",
-        params![
-            client.name,
-            client.legal_name,
-            client.mb,
-            client.pib,
-            client.address,
-            client.city,
-            client.postal_code,
-            client.country,
-            client.phone,
-            client.email,
-            client.tax_id,
-            client.bank,
-            client.client_type,
-            client.abbreviation,
-            client.municipality,
-            client.street,
-            client.house_number,
-            client.is_manual_address,
-            client.google_maps,
-            client.contact_person,
-            client.contact_person_status,
-            client.telegram,
-            client.instagram,
-            client.installment,
-            client.installment_term,
-            client.showcase,
-            client.bar,
-            client.notes,
-            client.contact,
-            created_at,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
-    
-    let id = db.conn().last_insert_rowid();
+    let id = db.with_transaction(|tx| {
+        tx.execute(
+            "INSERT INTO clients (name, legal_name, mb, pib, address, city, postal_code, country, phone, email, tax_id, bank, client_type, abbreviation, municipality, street, house_number, is_manual_address, google_maps, contact_person, contact_person_status, telegram, instagram, installment, installment_term, showcase, bar, notes, contact, created_at) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30)",
+            params![
+                client.name,
+                client.legal_name,
+                client.mb,
+                client.pib,
+                client.address,
+                client.city,
+                client.postal_code,
+                client.country,
+                client.phone,
+                client.email,
+                client.tax_id,
+                client.bank,
+                client.client_type,
+                client.abbreviation,
+                client.municipality,
+                client.street,
+                client.house_number,
+                client.is_manual_address,
+                client.google_maps,
+                client.contact_person,
+                client.contact_person_status,
+                client.telegram,
+                client.instagram,
+                client.installment,
+                client.installment_term,
+                client.showcase,
+                client.bar,
+                client.notes,
+                client.contact,
+                created_at,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        
+        let id = tx.last_insert_rowid();
+        audit::stamp(tx, "clients", "id", &id, &actor, audit::CREATE)?;
+        let after = audit::row(tx, "clients", "id", &id)?;
+        audit::record(tx, &actor, audit::CREATE, "client", &id.to_string(), None, after)?;
+        Ok(id)
+    })?;
     
     Ok(Client {
         id: Some(id),
@@ -412,47 +745,55 @@ pub fn create_client(client: Client, db: State<Database>) -> Result<Client, Stri
 }
 
 #[tauri::command]
-pub fn update_client(client: Client, db: State<Database>) -> Result<Client, String> {
+pub fn update_client(client: Client, db: State<Database>, session: State<Session>) -> Result<Client, CommandError> {
+    let actor = session.require(Permission::ClientsEdit)?;
+    validation::validate_client(&client).into_result("update_client")?;
     let id = client.id.ok_or("Client ID is required")?;
     let updated_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "UPDATE clients SET name = ?1, legal_name = ?2, mb = ?3, pib = ?4, address = ?5, city = ?6, postal_code = ?7, country = ?8, phone = ?9, email = ?10, tax_id = ?11, bank = ?12, client_type = ?13, abbreviation = ?14, municipality = ?15, street = ?16, house_number = ?17, is_manual_address = ?18, google_maps = ?19, contact_person = ?20, contact_person_status = ?21, telegram = ?22, instagram = ?23, installment = ?24, installment_term = ?25, showcase = ?26, bar = ?27, notes = ?28, contact = ?29, updated_at = ?30 WHERE id = ?31",
-        params![
-            client.name,
-            client.legal_name,
-            client.mb,
-            client.pib,
-            client.address,
-            client.city,
-            client.postal_code,
-            client.country,
-            client.phone,
-            client.email,
-            client.tax_id,
-            client.bank,
-            client.client_type,
-            client.abbreviation,
-            client.municipality,
-            client.street,
-            client.house_number,
-            client.is_manual_address,
-            client.google_maps,
-            client.contact_person,
-            client.contact_person_status,
-            client.telegram,
-            client.instagram,
-            client.installment,
-            client.installment_term,
-            client.showcase,
-            client.bar,
-            client.notes,
-            client.contact,
-            updated_at,
-            id,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "clients", "id", &id)?;
+        tx.execute(
+            "UPDATE clients SET name = ?1, legal_name = ?2, mb = ?3, pib = ?4, address = ?5, city = ?6, postal_code = ?7, country = ?8, phone = ?9, email = ?10, tax_id = ?11, bank = ?12, client_type = ?13, abbreviation = ?14, municipality = ?15, street = ?16, house_number = ?17, is_manual_address = ?18, google_maps = ?19, contact_person = ?20, contact_person_status = ?21, telegram = ?22, instagram = ?23, installment = ?24, installment_term = ?25, showcase = ?26, bar = ?27, notes = ?28, contact = ?29, updated_at = ?30 WHERE id = ?31",
+            params![
+                client.name,
+                client.legal_name,
+                client.mb,
+                client.pib,
+                client.address,
+                client.city,
+                client.postal_code,
+                client.country,
+                client.phone,
+                client.email,
+                client.tax_id,
+                client.bank,
+                client.client_type,
+                client.abbreviation,
+                client.municipality,
+                client.street,
+                client.house_number,
+                client.is_manual_address,
+                client.google_maps,
+                client.contact_person,
+                client.contact_person_status,
+                client.telegram,
+                client.instagram,
+                client.installment,
+                client.installment_term,
+                client.showcase,
+                client.bar,
+                client.notes,
+                client.contact,
+                updated_at,
+                id,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "clients", "id", &id, &actor, audit::UPDATE)?;
+        let after = audit::row(tx, "clients", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "client", &id.to_string(), before, after)
+    })?;
     
     Ok(Client {
         updated_at: Some(updated_at),
@@ -460,19 +801,112 @@ pub fn update_client(client: Client, db: State<Database>) -> Result<Client, Stri
     })
 }
 
+/// Проверка PIB/MB без сохранения — UI показывает ошибки и предупреждения у полей.
 #[tauri::command]
-pub fn delete_client(id: i64, db: State<Database>) -> Result<(), String> {
-    db.conn()
-        .execute("DELETE FROM clients WHERE id = ?1", [id])
-        .map_err(|e| e.to_string())?;
-    Ok(())
+pub fn validate_client(client: Client, session: State<Session>) -> Result<ValidationReport, String> {
+    session.require(Permission::ClientsEdit)?;
+    Ok(validation::validate_client(&client))
+}
+
+/// Сколько строк ссылается на запись: (таблица, SELECT COUNT(*) ... ?1). Пустой список — ссылок нет.
+fn count_usages(conn: &Connection, checks: &[(&'static str, &str)], id: &dyn rusqlite::ToSql) -> Result<Vec<Usage>, String> {
+    let mut usages = Vec::new();
+    for (table, sql) in checks {
+        let count: i64 = conn.query_row(sql, [id], |row| row.get(0)).map_err(|e| e.to_string())?;
+        if count > 0 {
+            usages.push(Usage { table, count });
+        }
+    }
+    Ok(usages)
+}
+
+/// Клиента с документами не удаляем: история нужна для отчётов (дубли — через merge_clients).
+#[tauri::command]
+pub fn delete_client(id: i64, db: State<Database>, session: State<Session>) -> Result<(), CommandError> {
+    let actor = session.require(Permission::ClientsEdit)?;
+    db.try_transaction(|tx| {
+        // Проверка внутри транзакции: между проверкой и DELETE никто не добавит документ
+        let usages = count_usages(
+            tx,
+            &[
+                ("invoices", "SELECT COUNT(*) FROM invoices WHERE client_id = CAST(?1 AS TEXT)"),
+                (
+                    "deliveries",
+                    "SELECT COUNT(*) FROM deliveries
+                     WHERE client_id = CAST(?1 AS TEXT) OR client_id = (SELECT mb FROM clients WHERE id = ?1)",
+                ),
+            ],
+            &id,
+        )?;
+        if !usages.is_empty() {
+            return Err(CommandError::in_use("client", id, usages));
+        }
+        
+        let before = audit::row(tx, "clients", "id", &id)?;
+        tx.execute("DELETE FROM clients WHERE id = ?1", [id])
+            .map_err(|e| e.to_string())?;
+        audit::record(tx, &actor, audit::DELETE, "client", &id.to_string(), before, None)?;
+        Ok(())
+    })
+}
+
+// ==================== КОМАНДЫ: ДУБЛИ КЛИЕНТОВ ====================
+
+/// Возможные дубли (PIB, МБ, имя, адрес). С client_id — только дубли одного клиента.
+#[tauri::command]
+pub fn find_duplicate_clients(
+    client_id: Option<i64>,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<Vec<DuplicateCandidate>, String> {
+    session.require(Permission::ClientsEdit)?;
+    let conn = db.conn()?;
+    client_merge::find_duplicates(&conn, client_id)
+}
+
+/// Переносит инвойсы и доставки дублей на оставшегося клиента и удаляет дубли — одной транзакцией.
+#[tauri::command]
+pub fn merge_clients(
+    surviving_id: i64,
+    merged_ids: Vec<i64>,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<MergeResult, String> {
+    let actor = session.require(Permission::ClientsEdit)?;
+    println!("🔀 Объединяем клиентов {:?} -> {}", merged_ids, surviving_id);
+    let result = db.with_transaction(|tx| {
+        let snapshots = merged_ids
+            .iter()
+            .map(|id| Ok((*id, audit::row(tx, "clients", "id", id)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let result = client_merge::merge(tx, &actor, surviving_id, &merged_ids)?;
+        for (id, before) in snapshots {
+            let after = Some(serde_json::json!({ "merged_into": surviving_id }));
+            audit::record(tx, &actor, audit::MERGE, "client", &id.to_string(), before, after)?;
+        }
+        Ok(result)
+    })?;
+    println!(
+        "✅ Клиенты объединены: инвойсов {}, доставок {}",
+        result.invoices_moved, result.deliveries_moved
+    );
+    Ok(result)
+}
+
+#[tauri::command]
+pub fn get_client_merges(client_id: Option<i64>, db: State<Database>, session: State<Session>) -> Result<Vec<ClientMerge>, String> {
+    session.require(Permission::ClientsView)?;
+    let conn = db.conn()?;
+    client_merge::load_merges(&conn, client_id)
 }
 
 // ==================== КОМАНДЫ: ТОВАРЫ ====================
 
 #[tauri::command]
-pub fn get_products(db: State<Database>) -> Result<Vec<Product>, String> {
-    let mut stmt = db.conn()
+pub fn get_products(db: State<Database>, session: State<Session>) -> Result<Vec<Product>, String> {
+    session.require(Permission::ProductsView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, code, name, description, price, category, subcategory, weight, supplier, internal_code, is_active, created_at, updated_at FROM products WHERE is_active = 1 ORDER BY created_at DESC")
         .map_err(|e| e.to_string())?;
     
@@ -501,11 +935,62 @@ pub fn get_products(db: State<Database>) -> Result<Vec<Product>, String> {
 }
 
 #[tauri::command]
-pub fn create_product(product: Product, db: State<Database>) -> Result<Product, String> {
-    let id = uuid::Uuid::new_v4().to_string();
+pub fn create_product(product: Product, db: State<Database>, session: State<Session>) -> Result<Product, String> {
+    let actor = session.require(Permission::ProductsEdit)?;
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
+    db.with_transaction(|tx| create_or_reactivate_product(tx, &actor, product, created_at))
+}
+
+/// Новый товар или возврат деактивированного с тем же кодом. Вызывается внутри транзакции.
+fn create_or_reactivate_product(
+    tx: &Transaction,
+    actor: &audit::Actor,
+    product: Product,
+    created_at: String,
+) -> Result<Product, String> {
+    // Код занят деактивированным товаром (delete_product) — возвращаем его с новыми данными,
+    // старые документы продолжают ссылаться на тот же products.id
+    let archived: Option<String> = tx
+        .query_row(
+            "SELECT id FROM products WHERE code = ?1 AND is_active = 0",
+            params![product.code],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(id) = archived {
+        let before = audit::row(tx, "products", "id", &id)?;
+        tx.execute(
+            "UPDATE products SET name = ?1, description = ?2, price = ?3, category = ?4, subcategory = ?5, weight = ?6, supplier = ?7, internal_code = ?8, is_active = ?9, updated_at = ?10 WHERE id = ?11",
+            params![
+                product.name,
+                product.description,
+                product.price.unwrap_or(0.0),
+                product.category,
+                product.subcategory,
+                product.weight,
+                product.supplier,
+                product.internal_code,
+                product.is_active.unwrap_or(1),
+                created_at,
+                id,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "products", "id", &id, actor, audit::UPDATE)?;
+        let after = audit::row(tx, "products", "id", &id)?;
+        audit::record(tx, actor, audit::CREATE, "product", &id, before, after)?;
+        return Ok(Product {
+            id: Some(id),
+            created_at: Some(created_at.clone()),
+            updated_at: Some(created_at),
+            ..product
+        });
+    }
+    
+    let id = uuid::Uuid::new_v4().to_string();
+    tx.execute(
         "INSERT INTO products (id, code, name, description, price, category, subcategory, weight, supplier, internal_code, is_active, created_at, updated_at) 
          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
         params![
@@ -525,6 +1010,9 @@ pub fn create_product(product: Product, db: State<Database>) -> Result<Product,
         ],
     )
     .map_err(|e| e.to_string())?;
+    audit::stamp(tx, "products", "id", &id, actor, audit::CREATE)?;
+    let after = audit::row(tx, "products", "id", &id)?;
+    audit::record(tx, actor, audit::CREATE, "product", &id, None, after)?;
     
     Ok(Product {
         id: Some(id),
@@ -535,8 +1023,10 @@ pub fn create_product(product: Product, db: State<Database>) -> Result<Product,
 }
 
 #[tauri::command]
-pub fn get_product_by_code(code: String, db: State<Database>) -> Result<Option<Product>, String> {
-    let mut stmt = db.conn()
+pub fn get_product_by_code(code: String, db: State<Database>, session: State<Session>) -> Result<Option<Product>, String> {
+    session.require(Permission::ProductsView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, code, name, description, price, category, subcategory, weight, supplier, internal_code, is_active, created_at, updated_at FROM products WHERE code = ?1")
         .map_err(|e| e.to_string())?;
     
@@ -566,27 +1056,34 @@ pub fn get_product_by_code(code: String, db: State<Database>) -> Result<Option<P
 }
 
 #[tauri::command]
-pub fn update_product(id: String, product: Product, db: State<Database>) -> Result<Product, String> {
+pub fn update_product(id: String, product: Product, db: State<Database>, session: State<Session>) -> Result<Product, String> {
+    let actor = session.require(Permission::ProductsEdit)?;
     let updated_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "UPDATE products SET code = ?1, name = ?2, description = ?3, price = ?4, category = ?5, subcategory = ?6, weight = ?7, supplier = ?8, internal_code = ?9, is_active = ?10, updated_at = ?11 WHERE id = ?12",
-        params![
-            product.code,
-            product.name,
-            product.description,
-            product.price.unwrap_or(0.0),
-            product.category,
-            product.subcategory,
-            product.weight,
-            product.supplier,
-            product.internal_code,
-            product.is_active.unwrap_or(1),
-            updated_at,
-            id,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "products", "id", &id)?;
+        tx.execute(
+            "UPDATE products SET code = ?1, name = ?2, description = ?3, price = ?4, category = ?5, subcategory = ?6, weight = ?7, supplier = ?8, internal_code = ?9, is_active = ?10, updated_at = ?11 WHERE id = ?12",
+            params![
+                product.code,
+                product.name,
+                product.description,
+                product.price.unwrap_or(0.0),
+                product.category,
+                product.subcategory,
+                product.weight,
+                product.supplier,
+                product.internal_code,
+                product.is_active.unwrap_or(1),
+                updated_at,
+                id,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "products", "id", &id, &actor, audit::UPDATE)?;
+        let after = audit::row(tx, "products", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "product", &id, before, after)
+    })?;
     
     Ok(Product {
         id: Some(id),
@@ -595,59 +1092,89 @@ pub fn update_product(id: String, product: Product, db: State<Database>) -> Resu
     })
 }
 
+/// Товар на складе не удаляется. Товар из документов деактивируется (как delete_supplier),
+/// остальные удаляются полностью.
 #[tauri::command]
-pub fn delete_product(id: String, db: State<Database>) -> Result<(), String> {
-    println!("🗑️ delete_product: ПОЛНОЕ УДАЛЕНИЕ товара {}", id);
-    
-    // Полное удаление товара из базы данных (не деактивация!)
-    db.conn()
-        .execute("DELETE FROM products WHERE id = ?1", params![id])
-        .map_err(|e| {
-            println!("❌ delete_product: Ошибка: {}", e);
-            e.to_string()
-        })?;
-    
-    println!("✅ delete_product: Товар {} полностью удалён", id);
-    Ok(())
+pub fn delete_product(id: String, db: State<Database>, session: State<Session>) -> Result<(), CommandError> {
+    let actor = session.require(Permission::ProductsEdit)?;
+    db.try_transaction(|tx| {
+        let stock = count_usages(
+            tx,
+            &[("warehouse_items", "SELECT COUNT(*) FROM warehouse_items WHERE product_id = ?1")],
+            &id,
+        )?;
+        if !stock.is_empty() {
+            return Err(CommandError::in_use("product", &id, stock));
+        }
+        let documents = count_usages(
+            tx,
+            &[
+                ("invoice_items", "SELECT COUNT(*) FROM invoice_items WHERE product_id = ?1"),
+                (
+                    "delivery_items",
+                    "SELECT COUNT(*) FROM delivery_items d JOIN products p ON p.id = ?1
+                     WHERE d.product_id IN (p.id, p.internal_code, p.code)",
+                ),
+            ],
+            &id,
+        )?;
+        
+        let before = audit::row(tx, "products", "id", &id)?;
+        if !documents.is_empty() {
+            println!("🗑️ delete_product: товар {} есть в документах — деактивируем", id);
+            tx.execute(
+                "UPDATE products SET is_active = 0, updated_at = ?1 WHERE id = ?2",
+                params![Utc::now().to_rfc3339(), id],
+            )
+            .map_err(|e| e.to_string())?;
+            audit::stamp(tx, "products", "id", &id, &actor, audit::UPDATE)?;
+            let after = audit::row(tx, "products", "id", &id)?;
+            audit::record(tx, &actor, audit::DELETE, "product", &id, before, after)?;
+            return Ok(());
+        }
+        
+        println!("🗑️ delete_product: ПОЛНОЕ УДАЛЕНИЕ товара {}", id);
+        tx.execute("DELETE FROM products WHERE id = ?1", params![id])
+            .map_err(|e| {
+                println!("❌ delete_product: Ошибка: {}", e);
+                e.to_string()
+            })?;
+        audit::record(tx, &actor, audit::DELETE, "product", &id, before, None)?;
+        println!("✅ delete_product: Товар {} полностью удалён", id);
+        Ok(())
+    })
 }
 
 // ==================== КОМАНДЫ: ИНВОЙСЫ ====================
 
 #[tauri::command]
-pub fn get_invoices(db: State<Database>) -> Result<Vec<Invoice>, String> {
+pub fn get_invoices(db: State<Database>, session: State<Session>) -> Result<Vec<Invoice>, String> {
+    let scope = session.require_scoped(Permission::InvoicesView)?;
+    let conn = db.conn()?;
     println!("🔍 get_invoices: Starting to fetch invoices...");
+    let invoices = list_invoices(&conn, scope)?;
+    
+    println!("✅ get_invoices: Successfully fetched {} invoices", invoices.len());
+    
+    // Логируем статусы paid/delivered для диагностики
+    for (idx, inv) in invoices.iter().take(5).enumerate() {
+        println!("   [{}] {}: paid={:?}, delivered={:?}", 
+            idx, inv.invoice_number, inv.paid, inv.delivered);
+    }
     
-    let mut stmt = db.conn()
-        .prepare("SELECT id, invoice_number, document_type, client_id, client_name, date, due_date, total, status, notes, created_at, paid, delivered, currency, exchange_rate, exchange_rate_date FROM invoices ORDER BY created_at DESC")
+    Ok(invoices)
+}
+
+/// Все документы; `scope` — клиент, которым ограничен профиль роли client (Session::require_scoped)
+fn list_invoices(conn: &Connection, scope: Option<i64>) -> Result<Vec<Invoice>, String> {
+    let mut stmt = conn
+        .prepare(&format!("{} WHERE (?1 IS NULL OR client_id = ?1) ORDER BY created_at DESC", invoice_select()))
         .map_err(|e| {
             println!("❌ get_invoices: Failed to prepare statement: {}", e);
             e.to_string()
         })?;
     
-    let invoices = stmt.query_map([], |row| {
-        // SQLite хранит INTEGER (0/1), конвертируем в bool
-        let paid_int: Option<i32> = row.get(11).ok();
-        let delivered_int: Option<i32> = row.get(12).ok();
-        
-        Ok(Invoice {
-            id: Some(row.get(0)?),
-            invoice_number: row.get(1)?,
-            document_type: row.get(2)?,
-            client_id: row.get(3)?,
-            client_name: row.get(4)?,
-            date: row.get(5)?,
-            due_date: row.get(6)?,
-            total: row.get(7)?,
-            status: row.get(8)?,
-            notes: row.get(9)?,
-            created_at: Some(row.get(10)?),
-            paid: paid_int.map(|v| v != 0),
-            delivered: delivered_int.map(|v| v != 0),
-            currency: row.get(13).ok(),
-            exchange_rate: row.get(14).ok(),
-            exchange_rate_date: row.get(15).ok(),
-        })
-    })
+    let invoices = stmt.query_map([scope.map(|id| id.to_string())], invoice_from_row)
     .map_err(|e| {
         println!("❌ get_invoices: Failed to query_map: {}", e);
         e.to_string()
@@ -658,165 +1185,348 @@ pub fn get_invoices(db: State<Database>) -> Result<Vec<Invoice>, String> {
         e.to_string()
     })?;
     
-    println!("✅ get_invoices: Successfully fetched {} invoices", invoices.len());
-    
-    // Логируем статусы paid/delivered для диагностики
-    for (idx, inv) in invoices.iter().take(5).enumerate() {
-        println!("   [{}] {}: paid={:?}, delivered={:?}", 
-            idx, inv.invoice_number, inv.paid, inv.delivered);
-    }
-    
     Ok(invoices)
 }
 
 #[tauri::command]
-pub fn get_invoice_by_id(id: String, db: State<Database>) -> Result<Option<InvoiceWithItems>, String> {
-    let mut stmt = db.conn()
-        .prepare("SELECT id, invoice_number, document_type, client_id, client_name, date, due_date, total, status, notes, created_at, paid, delivered, currency, exchange_rate, exchange_rate_date FROM invoices WHERE id = ?1")
+pub fn get_invoice_by_id(id: String, db: State<Database>, session: State<Session>) -> Result<Option<InvoiceWithItems>, String> {
+    let scope = session.require_scoped(Permission::InvoicesView)?;
+    let conn = db.conn()?;
+    ensure_in_scope(&conn, &id, scope)?;
+    load_invoice_with_items(&conn, &id)
+}
+
+/// Профиль роли client читает только документы своего клиента; чужой документ для него не существует.
+fn ensure_in_scope(conn: &Connection, id: &str, scope: Option<i64>) -> Result<(), String> {
+    let Some(client_id) = scope else {
+        return Ok(());
+    };
+    let owner: Option<String> = conn
+        .query_row("SELECT client_id FROM invoices WHERE id = ?1", [id], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .flatten();
+    if owner != Some(client_id.to_string()) {
+        return Err(format!("Invoice {} not found", id));
+    }
+    Ok(())
+}
+
+fn load_invoice(conn: &Connection, id: &str) -> Result<Option<Invoice>, String> {
+    let mut stmt = conn
+        .prepare(&format!("{} WHERE id = ?1", invoice_select()))
         .map_err(|e| e.to_string())?;
     
-    let invoice_result = stmt.query_row([&id], |row| {
-        let paid_int: Option<i32> = row.get(11).ok();
-        let delivered_int: Option<i32> = row.get(12).ok();
-        
-        Ok(Invoice {
-            id: Some(row.get(0)?),
-            invoice_number: row.get(1)?,
-            document_type: row.get(2)?,
-            client_id: row.get(3)?,
-            client_name: row.get(4)?,
-            date: row.get(5)?,
-            due_date: row.get(6)?,
-            total: row.get(7)?,
-            status: row.get(8)?,
-            notes: row.get(9)?,
-            created_at: Some(row.get(10)?),
-            paid: paid_int.map(|v| v != 0),
-            delivered: delivered_int.map(|v| v != 0),
-            currency: row.get(13).ok(),
-            exchange_rate: row.get(14).ok(),
-            exchange_rate_date: row.get(15).ok(),
-        })
-    });
-    
-    match invoice_result {
-        Ok(invoice) => {
-            let mut items_stmt = db.conn()
-                .prepare("SELECT id, invoice_id, product_id, product_name, quantity, unit_weight_g, price, total FROM invoice_items WHERE invoice_id = ?1")
-                .map_err(|e| e.to_string())?;
-            
-            let items = items_stmt.query_map([&id], |row| {
-                Ok(InvoiceItem {
-                    id: Some(row.get(0)?),
-                    invoice_id: row.get(1)?,
-                    product_id: row.get(2)?,
-                    product_name: row.get(3)?,
-                    quantity: row.get(4)?,
-                    unit_weight_g: row.get(5)?,
-                    price: row.get(6)?,
-                    total: row.get(7)?,
-                })
-            })
-            .map_err(|e| e.to_string())?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| e.to_string())?;
-            
-            Ok(Some(InvoiceWithItems { invoice, items }))
-        }
+    match stmt.query_row([id], invoice_from_row) {
+        Ok(invoice) => Ok(Some(invoice)),
         Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
         Err(e) => Err(e.to_string()),
     }
 }
 
-#[tauri::command]
-pub fn create_invoice(invoice: Invoice, items: Vec<InvoiceItem>, db: State<Database>) -> Result<String, String> {
-    let id = uuid::Uuid::new_v4().to_string();
-    let created_at = Utc::now().to_rfc3339();
+fn load_invoice_with_items(conn: &Connection, id: &str) -> Result<Option<InvoiceWithItems>, String> {
+    let Some(invoice) = load_invoice(conn, id)? else {
+        return Ok(None);
+    };
     
-    db.conn().execute(
-        "INSERT INTO invoices (id, invoice_number, document_type, client_id, client_name, date, due_date, total, status, notes, created_at, paid, delivered, currency, exchange_rate, exchange_rate_date) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
-        params![
-            id,
-            invoice.invoice_number,
-            invoice.document_type,
-            invoice.client_id,
-            invoice.client_name,
-            invoice.date,
-            invoice.due_date,
-            invoice.total,
-            invoice.status,
-            invoice.notes,
-            created_at,
-            invoice.paid.unwrap_or(false),
-            invoice.delivered.unwrap_or(false),
-            invoice.currency.unwrap_or_else(|| "RSD".to_string()),
-            invoice.exchange_rate,
-            invoice.exchange_rate_date,
+    let mut items_stmt = conn
+        .prepare(&format!("SELECT {} FROM invoice_items WHERE invoice_id = ?1", INVOICE_ITEM_COLUMNS))
+        .map_err(|e| e.to_string())?;
+    
+    let items = items_stmt.query_map([id], invoice_item_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    
+    let tax_breakdown = load_tax_breakdown(conn, id)?;
+    let source_documents = load_source_chain(conn, invoice.source_document_id.as_deref())?;
+    let derived_documents = load_derived_documents(conn, id)?;
+    
+    Ok(Some(InvoiceWithItems {
+        invoice,
+        items,
+        tax_breakdown,
+        source_documents,
+        derived_documents,
+    }))
+}
+
+fn linked_document_from_row(row: &rusqlite::Row) -> rusqlite::Result<LinkedDocument> {
+    Ok(LinkedDocument {
+        id: row.get(0)?,
+        invoice_number: row.get(1)?,
+        document_type: row.get(2)?,
+        date: row.get(3)?,
+        total: row.get(4)?,
+    })
+}
+
+/// Поднимается по `source_document_id` до исходного документа.
+fn load_source_chain(conn: &Connection, source_id: Option<&str>) -> Result<Vec<LinkedDocument>, String> {
+    let mut chain: Vec<LinkedDocument> = Vec::new();
+    let mut next = source_id.map(str::to_string);
+    while let Some(id) = next.take() {
+        // Защита от циклов в испорченных данных
+        if chain.iter().any(|doc| doc.id == id) {
+            break;
+        }
+        let row = conn
+            .query_row(
+                "SELECT id, invoice_number, document_type, date, total, source_document_id FROM invoices WHERE id = ?1",
+                params![id],
+                |row| Ok((linked_document_from_row(row)?, row.get::<_, Option<String>>(5)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some((doc, parent)) = row {
+            chain.push(doc);
+            next = parent;
+        }
+    }
+    Ok(chain)
+}
+
+fn load_derived_documents(conn: &Connection, id: &str) -> Result<Vec<LinkedDocument>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, invoice_number, document_type, date, total FROM invoices WHERE source_document_id = ?1 ORDER BY created_at")
+        .map_err(|e| e.to_string())?;
+    let docs = stmt
+        .query_map([id], linked_document_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(docs)
+}
+
+#[tauri::command]
+pub fn create_invoice(
+    invoice: Invoice,
+    items: Vec<InvoiceItem>,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<String, String> {
+    let actor = session.require(Permission::InvoicesEdit)?;
+    // Связь с исходным документом ставят только convert_document и create_credit_note
+    let invoice = Invoice { source_document_id: None, ..invoice };
+    // Шапка и все позиции пишутся одной транзакцией: ошибка в любой позиции
+    // откатывает весь документ, а не оставляет инвойс с половиной строк
+    db.with_transaction(|tx| {
+        let id = insert_invoice(tx, &invoice, &items)?;
+        audit_invoice_created(tx, &actor, id)
+    })
+}
+
+/// Запись журнала о новом документе (create_invoice, convert_document, create_credit_note).
+fn audit_invoice_created(tx: &Transaction, actor: &audit::Actor, id: String) -> Result<String, String> {
+    audit::stamp(tx, "invoices", "id", &id, actor, audit::CREATE)?;
+    let after = audit::invoice(tx, &id)?;
+    audit::record(tx, actor, audit::CREATE, "invoice", &id, None, after)?;
+    Ok(id)
+}
+
+/// Текст ошибки для позиции документа: номер строки (с 1) и название товара.
+fn item_error(index: usize, product_name: &str, e: impl std::fmt::Display) -> String {
+    format!("Item {} ({}): {}", index + 1, product_name, e)
+}
+
+/// unit_weight_g: сохраняем вес единицы товара в транзакции.
+/// Берем актуальный вес из products (product_id — уже products.id).
+fn lookup_unit_weight_g(conn: &Connection, product_id: &str) -> Option<f64> {
+    conn.query_row(
+        "SELECT weight FROM products WHERE id = ?1",
+        params![product_id],
+        |row| row.get(0),
+    )
+    .ok()
+    .flatten()
+}
+
+/// invoice_items.product_id ссылается на products.id, а UI присылает внутренний код или код товара.
+/// Удалённые (неактивные) товары тоже находятся — они остаются в старых документах.
+/// Неизвестный код становится неактивным товаром-заглушкой, как в миграции m012.
+fn resolve_product_id(conn: &Connection, product_ref: &str, product_name: &str) -> Result<String, String> {
+    let product_ref = product_ref.trim();
+    let found: Option<String> = conn
+        .query_row(
+            "SELECT id FROM products WHERE id = ?1 OR internal_code = ?1 OR code = ?1
+             ORDER BY id = ?1 DESC, is_active DESC LIMIT 1",
+            params![product_ref],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    match found {
+        Some(id) => Ok(id),
+        None => {
+            println!("⚠️ resolve_product_id: товара {} нет в справочнике — создаём неактивную заглушку", product_ref);
+            migrations::insert_placeholder_product(conn, product_ref, product_name).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// invoices.client_id ссылается на clients.id; UI присылает id или МБ клиента.
+/// Неизвестная ссылка обнуляется (client_name в документе остаётся), как в миграции m012.
+fn resolve_client_id(conn: &Connection, client_ref: Option<&str>) -> Result<Option<String>, String> {
+    let Some(client_ref) = client_ref.map(str::trim).filter(|c| !c.is_empty()) else {
+        return Ok(None);
+    };
+    let found = conn
+        .query_row(
+            "SELECT CAST(id AS TEXT) FROM clients WHERE CAST(id AS TEXT) = ?1 OR mb = ?1
+             ORDER BY CAST(id AS TEXT) = ?1 DESC, id LIMIT 1",
+            params![client_ref],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if found.is_none() {
+        println!("⚠️ resolve_client_id: клиента {} нет в справочнике — ссылка обнулена", client_ref);
+    }
+    Ok(found)
+}
+
+/// Вставляет шапку инвойса и его позиции. Вызывается внутри транзакции.
+fn insert_invoice(tx: &Transaction, invoice: &Invoice, items: &[InvoiceItem]) -> Result<String, String> {
+    let totals = price_document(invoice, items)?;
+    let invoice_number = numbering::assign_number(tx, &invoice.document_type, &invoice.invoice_number, &invoice.date)?;
+    let client_id = resolve_client_id(tx, invoice.client_id.as_deref())?;
+    let id = uuid::Uuid::new_v4().to_string();
+    let created_at = Utc::now().to_rfc3339();
+    
+    tx.execute(
+        "INSERT INTO invoices (id, invoice_number, document_type, client_id, client_name, date, due_date, total, status, notes, created_at, delivered, currency, exchange_rate, exchange_rate_date, subtotal, vat_total, vat_rate, vat_exemption_code, source_document_id) 
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+        params![
+            id,
+            invoice_number,
+            invoice.document_type,
+            client_id,
+            invoice.client_name,
+            invoice.date,
+            invoice.due_date,
+            totals.total,
+            invoice.status,
+            invoice.notes,
+            created_at,
+            invoice.delivered.unwrap_or(false),
+            invoice.currency.clone().unwrap_or_else(|| "RSD".to_string()),
+            invoice.exchange_rate,
+            invoice.exchange_rate_date,
+            totals.subtotal,
+            totals.vat_total,
+            invoice.vat_rate,
+            invoice.vat_exemption_code,
+            invoice.source_document_id,
         ],
     )
     .map_err(|e| e.to_string())?;
     
-    for item in items {
+    for (idx, (item, line)) in items.iter().zip(&totals.lines).enumerate() {
         let item_id = uuid::Uuid::new_v4().to_string();
+        let product_id = resolve_product_id(tx, &item.product_id, &item.product_name).map_err(|e| item_error(idx, &item.product_name, e))?;
 
-        // unit_weight_g: сохраняем вес единицы товара в транзакции.
-        // Сначала пробуем взять актуальный вес из products по internal_code или code.
-        let unit_weight_g: Option<f64> = db
-            .conn()
-            .query_row(
-                "SELECT weight FROM products WHERE internal_code = ?1 OR code = ?1 LIMIT 1",
-                params![item.product_id],
-                |row| row.get(0),
-            )
-            .ok()
-            .flatten();
+        // Снимок веса из исходного документа (конвертация, кредит-нота), иначе текущий вес товара
+        let unit_weight_g = item
+            .unit_weight_g
+            .or_else(|| lookup_unit_weight_g(tx, &product_id));
 
-        db.conn().execute(
-            "INSERT INTO invoice_items (id, invoice_id, product_id, product_name, quantity, unit_weight_g, price, total) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        tx.execute(
+            "INSERT INTO invoice_items (id, invoice_id, product_id, product_name, quantity, unit_weight_g, price, total, discount_pct, vat_rate, vat_exemption_code, vat_amount, credited_item_id) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
             params![
                 item_id,
                 id,
-                item.product_id,
+                product_id,
                 item.product_name,
                 item.quantity,
                 unit_weight_g,
                 item.price,
-                item.total,
+                line.net,
+                item.discount_pct.unwrap_or(0.0),
+                line.vat_rate,
+                line.vat_exemption_code,
+                line.vat_amount,
+                item.credited_item_id,
             ],
         )
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| item_error(idx, &item.product_name, e))?;
+    }
+    
+    write_tax_breakdown(tx, &id, &totals.breakdown)?;
+    
+    // Импорт уже оплаченного документа (синхронизация): проводим оплату в журнал
+    if invoice.paid == Some(true) {
+        payments::set_paid_flag(tx, &id, true, &invoice.date)?;
     }
     
     Ok(id)
 }
 
 #[tauri::command]
-pub fn update_invoice_status(id: String, status: String, db: State<Database>) -> Result<(), String> {
-    db.conn()
-        .execute("UPDATE invoices SET status = ?1 WHERE id = ?2", params![status, id])
+pub fn update_invoice_status(id: String, status: String, db: State<Database>, session: State<Session>) -> Result<(), String> {
+    let actor = session.require(Permission::InvoicesEdit)?;
+    db.with_transaction(|tx| set_invoice_status(tx, &actor, &id, &status))
+}
+
+fn set_invoice_status(tx: &Transaction, actor: &audit::Actor, id: &str, status: &str) -> Result<(), String> {
+    if let Some(issued) = issued_invoice(tx, id)? {
+        if issued.status != status {
+            return Err(issued_error(&issued, "status"));
+        }
+    }
+    let before = audit::invoice(tx, id)?;
+    tx.execute("UPDATE invoices SET status = ?1 WHERE id = ?2", params![status, id])
         .map_err(|e| e.to_string())?;
-    Ok(())
+    audit::stamp(tx, "invoices", "id", &id, actor, audit::UPDATE)?;
+    let after = audit::invoice(tx, id)?;
+    audit::record(tx, actor, audit::UPDATE, "invoice", id, before, after)
 }
 
 #[tauri::command]
-pub fn update_invoice(id: String, invoice: Invoice, db: State<Database>) -> Result<Invoice, String> {
+pub fn update_invoice(id: String, invoice: Invoice, db: State<Database>, session: State<Session>) -> Result<Invoice, String> {
+    let actor = session.require(Permission::InvoicesEdit)?;
     println!("🔄 update_invoice: Updating invoice {}", id);
     
-    db.conn().execute(
-        "UPDATE invoices SET invoice_number = ?1, document_type = ?2, client_id = ?3, client_name = ?4, date = ?5, due_date = ?6, total = ?7, status = ?8, notes = ?9, paid = ?10, delivered = ?11, currency = ?12, exchange_rate = ?13, exchange_rate_date = ?14 WHERE id = ?15",
+    db.with_transaction(|tx| update_invoice_header(tx, &actor, &id, &invoice))?;
+    
+    println!("✅ update_invoice: Successfully updated invoice {}", id);
+    let conn = db.conn()?;
+    load_invoice(&conn, &id)?.ok_or_else(|| format!("Invoice {} not found", id))
+}
+
+/// Суммы документа меняются только вместе с позициями (update_invoice_with_items),
+/// оплаты — только через журнал (record_payment / update_invoice_payment_status)
+fn update_invoice_header(tx: &Transaction, actor: &audit::Actor, id: &str, invoice: &Invoice) -> Result<(), String> {
+    if let Some(issued) = issued_invoice(tx, id)? {
+        let changed = issued_header_changes(tx, &issued, invoice)?;
+        if !changed.is_empty() {
+            return Err(issued_error(&issued, &changed.join(", ")));
+        }
+        // У выданного документа меняются только примечание и отметка о доставке
+        let before = audit::invoice(tx, id)?;
+        tx.execute(
+            "UPDATE invoices SET notes = ?1, delivered = ?2 WHERE id = ?3",
+            params![invoice.notes, invoice.delivered.unwrap_or(false), id],
+        )
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "invoices", "id", &id, actor, audit::UPDATE)?;
+        let after = audit::invoice(tx, id)?;
+        return audit::record(tx, actor, audit::UPDATE, "invoice", id, before, after);
+    }
+    let before = audit::invoice(tx, id)?;
+    let invoice_number = numbering::renumber(tx, id, &invoice.document_type, &invoice.invoice_number, &invoice.date)?;
+    let client_id = resolve_client_id(tx, invoice.client_id.as_deref())?;
+    tx.execute(
+        "UPDATE invoices SET invoice_number = ?1, document_type = ?2, client_id = ?3, client_name = ?4, date = ?5, due_date = ?6, status = ?7, notes = ?8, delivered = ?9, currency = ?10, exchange_rate = ?11, exchange_rate_date = ?12 WHERE id = ?13",
         params![
-            invoice.invoice_number,
+            invoice_number,
             invoice.document_type,
-            invoice.client_id,
+            client_id,
             invoice.client_name,
             invoice.date,
             invoice.due_date,
-            invoice.total,
             invoice.status,
             invoice.notes,
-            invoice.paid.unwrap_or(false),
             invoice.delivered.unwrap_or(false),
             invoice.currency.clone().unwrap_or_else(|| "RSD".to_string()),
             invoice.exchange_rate,
@@ -828,81 +1538,623 @@ pub fn update_invoice(id: String, invoice: Invoice, db: State<Database>) -> Resu
         println!("❌ update_invoice: Failed to update: {}", e);
         e.to_string()
     })?;
+    audit::stamp(tx, "invoices", "id", &id, actor, audit::UPDATE)?;
+    let after = audit::invoice(tx, id)?;
+    audit::record(tx, actor, audit::UPDATE, "invoice", id, before, after)
+}
+
+/// Входные данные для расчёта: ставка строки, иначе ставка документа, иначе общая 20%.
+/// Код освобождения документа наследуется только строками со ставкой 0%.
+pub(crate) fn pricing_lines(invoice: &Invoice, items: &[InvoiceItem]) -> Vec<LineInput> {
+    items
+        .iter()
+        .map(|item| {
+            let vat_rate = item.vat_rate.or(invoice.vat_rate).unwrap_or(pricing::VAT_STANDARD);
+            let vat_exemption_code = item.vat_exemption_code.clone().or_else(|| {
+                if vat_rate == pricing::VAT_EXEMPT {
+                    invoice.vat_exemption_code.clone()
+                } else {
+                    None
+                }
+            });
+            LineInput {
+                quantity: item.quantity,
+                unit_price: item.price,
+                discount_pct: item.discount_pct.unwrap_or(0.0),
+                vat_rate,
+                vat_exemption_code,
+            }
+        })
+        .collect()
+}
+
+/// Считает документ на сервере и сверяет с суммами из UI.
+fn price_document(invoice: &Invoice, items: &[InvoiceItem]) -> Result<InvoiceTotals, String> {
+    let totals = pricing::price_invoice(&pricing_lines(invoice, items)).map_err(|e| e.to_string())?;
+    let client_line_totals: Vec<f64> = items.iter().map(|item| item.total).collect();
+    pricing::check_client_totals(&totals, &client_line_totals, invoice.total).map_err(|e| e.to_string())?;
+    Ok(totals)
+}
+
+fn write_tax_breakdown(tx: &Transaction, invoice_id: &str, breakdown: &[TaxBreakdown]) -> Result<(), String> {
+    tx.execute("DELETE FROM invoice_tax_breakdown WHERE invoice_id = ?1", params![invoice_id])
+        .map_err(|e| e.to_string())?;
+    for row in breakdown {
+        tx.execute(
+            "INSERT INTO invoice_tax_breakdown (id, invoice_id, vat_rate, vat_exemption_code, taxable_amount, vat_amount) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                uuid::Uuid::new_v4().to_string(),
+                invoice_id,
+                row.vat_rate,
+                row.vat_exemption_code,
+                row.taxable_amount,
+                row.vat_amount,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn load_tax_breakdown(conn: &Connection, invoice_id: &str) -> Result<Vec<TaxBreakdown>, String> {
+    let mut stmt = conn
+        .prepare("SELECT vat_rate, vat_exemption_code, taxable_amount, vat_amount FROM invoice_tax_breakdown WHERE invoice_id = ?1 ORDER BY vat_rate DESC, vat_exemption_code")
+        .map_err(|e| e.to_string())?;
+    let breakdown = stmt
+        .query_map([invoice_id], |row| {
+            Ok(TaxBreakdown {
+                vat_rate: row.get(0)?,
+                vat_exemption_code: row.get(1)?,
+                taxable_amount: row.get(2)?,
+                vat_amount: row.get(3)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(breakdown)
+}
+
+/// Обновляет шапку и позиции документа. Позиции сопоставляются по `id`:
+/// известные — обновляются, новые — добавляются, отсутствующие в списке — удаляются.
+/// `total` документа пересчитывается из позиций.
+#[tauri::command]
+pub fn update_invoice_with_items(
+    id: String,
+    invoice: Invoice,
+    items: Vec<InvoiceItem>,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<InvoiceWithItems, String> {
+    let actor = session.require(Permission::InvoicesEdit)?;
+    println!("🔄 update_invoice_with_items: Updating invoice {} ({} items)", id, items.len());
+    
+    db.with_transaction(|tx| {
+        let Some(before) = audit::invoice(tx, &id)? else {
+            return Err(format!("Invoice {} not found", id));
+        };
+        if let Some(issued) = issued_invoice(tx, &id)? {
+            return Err(issued_error(&issued, "items"));
+        }
+        
+        let invoice_number = numbering::renumber(tx, &id, &invoice.document_type, &invoice.invoice_number, &invoice.date)?;
+        let totals = price_document(&invoice, &items)?;
+        sync_invoice_items(tx, &id, &items, &totals)?;
+        let client_id = resolve_client_id(tx, invoice.client_id.as_deref())?;
+        
+        tx.execute(
+            "UPDATE invoices SET invoice_number = ?1, document_type = ?2, client_id = ?3, client_name = ?4, date = ?5, due_date = ?6, total = ?7, status = ?8, notes = ?9, delivered = ?10, currency = ?11, exchange_rate = ?12, exchange_rate_date = ?13, subtotal = ?14, vat_total = ?15, vat_rate = ?16, vat_exemption_code = ?17 WHERE id = ?18",
+            params![
+                invoice_number,
+                invoice.document_type,
+                client_id,
+                invoice.client_name,
+                invoice.date,
+                invoice.due_date,
+                totals.total,
+                invoice.status,
+                invoice.notes,
+                invoice.delivered.unwrap_or(false),
+                invoice.currency.clone().unwrap_or_else(|| "RSD".to_string()),
+                invoice.exchange_rate,
+                invoice.exchange_rate_date,
+                totals.subtotal,
+                totals.vat_total,
+                invoice.vat_rate,
+                invoice.vat_exemption_code,
+                id,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "invoices", "id", &id, &actor, audit::UPDATE)?;
+        let after = audit::invoice(tx, &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "invoice", &id, Some(before), after)
+    })?;
     
-    println!("✅ update_invoice: Successfully updated invoice {}", id);
-    Ok(Invoice {
-        id: Some(id),
-        invoice_number: invoice.invoice_number,
-        document_type: invoice.document_type,
-        client_id: invoice.client_id,
-        client_name: invoice.client_name,
-        date: invoice.date,
-        due_date: invoice.due_date,
-        total: invoice.total,
-        status: invoice.status,
-        notes: invoice.notes,
-        created_at: invoice.created_at,
-        paid: invoice.paid,
-        delivered: invoice.delivered,
-        currency: invoice.currency,
-        exchange_rate: invoice.exchange_rate,
-        exchange_rate_date: invoice.exchange_rate_date,
+    println!("✅ update_invoice_with_items: Successfully updated invoice {}", id);
+    let conn = db.conn()?;
+    load_invoice_with_items(&conn, &id)?.ok_or_else(|| format!("Invoice {} not found", id))
+}
+
+/// Создаёт рачун или отпремницу из предрачуна: копирует клиента, позиции и ПДВ,
+/// номер резервируется в последовательности целевого типа.
+#[tauri::command]
+pub fn convert_document(
+    source_id: String,
+    target_type: String,
+    date: Option<String>,
+    invoice_number: Option<String>,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<InvoiceWithItems, String> {
+    let actor = session.require(Permission::InvoicesEdit)?;
+    println!("🔄 convert_document: {} -> {}", source_id, target_type);
+    
+    let id = db.with_transaction(|tx| {
+        let id = convert_predracun(tx, &source_id, &target_type, date.as_deref(), invoice_number.as_deref())?;
+        audit_invoice_created(tx, &actor, id)
     })
+    .map_err(|e| {
+        println!("❌ convert_document: {}", e);
+        e
+    })?;
+    
+    println!("✅ convert_document: Created {} {}", target_type, id);
+    let conn = db.conn()?;
+    load_invoice_with_items(&conn, &id)?.ok_or_else(|| format!("Invoice {} not found", id))
+}
+
+fn convert_predracun(
+    tx: &Transaction,
+    source_id: &str,
+    target_type: &str,
+    date: Option<&str>,
+    invoice_number: Option<&str>,
+) -> Result<String, String> {
+    // UI хранит отпремницу как document_type = "delivery"
+    let document_type = match target_type.trim() {
+        "racun" => numbering::RACUN,
+        "otpremnica" | "delivery" => "delivery",
+        other => return Err(format!("Cannot convert to {}: expected racun or otpremnica", other)),
+    };
+    
+    let source = load_invoice_with_items(tx, source_id)?
+        .ok_or_else(|| format!("Invoice {} not found", source_id))?;
+    if numbering::sequence_key(&source.invoice.document_type) != numbering::PREDRACUN {
+        return Err(format!(
+            "Only predracun can be converted, {} is {}",
+            source.invoice.invoice_number, source.invoice.document_type
+        ));
+    }
+    if let Some(existing) = source.derived_documents.iter().find(|doc| doc.document_type == document_type) {
+        return Err(format!(
+            "Predracun {} is already converted to {} {}",
+            source.invoice.invoice_number, document_type, existing.invoice_number
+        ));
+    }
+    
+    let invoice = Invoice {
+        id: None,
+        invoice_number: invoice_number.unwrap_or_default().to_string(),
+        document_type: document_type.to_string(),
+        date: date
+            .map(str::to_string)
+            .unwrap_or_else(|| Utc::now().format("%Y-%m-%d").to_string()),
+        created_at: None,
+        paid: None,
+        delivered: None,
+        subtotal: None,
+        vat_total: None,
+        source_document_id: Some(source_id.to_string()),
+        ..source.invoice
+    };
+    let items: Vec<InvoiceItem> = source
+        .items
+        .into_iter()
+        .map(|item| InvoiceItem { id: None, invoice_id: String::new(), vat_amount: None, ..item })
+        .collect();
+    
+    insert_invoice(tx, &invoice, &items)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditNoteLine {
+    /// id строки исходного рачуна
+    pub item_id: String,
+    /// Кредитуемое количество (положительное)
+    pub quantity: f64,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditNoteRequest {
+    pub original_invoice_id: String,
+    /// Пустой список — сторно всего остатка по документу
+    #[serde(default)]
+    pub lines: Vec<CreditNoteLine>,
+    pub reason: Option<String>,
+    pub date: Option<String>,
+}
+
+/// Кредит-нота (knjižno odobrenje) к выставленному рачуну. Количества и суммы
+/// хранятся со знаком минус, поэтому отчёты и прогноз учитывают её без отдельной логики.
 #[tauri::command]
-pub fn delete_invoice(id: String, db: State<Database>) -> Result<(), String> {
-    println!("🗑️ delete_invoice: Deleting invoice {}", id);
+pub fn create_credit_note(
+    request: CreditNoteRequest,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<InvoiceWithItems, String> {
+    let actor = session.require(Permission::InvoicesEdit)?;
+    println!(
+        "🔄 create_credit_note: {} ({} lines)",
+        request.original_invoice_id,
+        request.lines.len()
+    );
     
-    // Сначала удаляем items
-    db.conn()
-        .execute("DELETE FROM invoice_items WHERE invoice_id = ?1", params![id])
+    let id = db
+        .with_transaction(|tx| {
+            let id = insert_credit_note(tx, &request)?;
+            audit_invoice_created(tx, &actor, id)
+        })
         .map_err(|e| {
-            println!("❌ delete_invoice: Failed to delete items: {}", e);
-            e.to_string()
+            println!("❌ create_credit_note: {}", e);
+            e
         })?;
     
-    // Затем удаляем сам invoice
-    db.conn()
-        .execute("DELETE FROM invoices WHERE id = ?1", params![id])
-        .map_err(|e| {
-            println!("❌ delete_invoice: Failed to delete invoice: {}", e);
-            e.to_string()
-        })?;
+    println!("✅ create_credit_note: Created credit note {}", id);
+    let conn = db.conn()?;
+    load_invoice_with_items(&conn, &id)?.ok_or_else(|| format!("Invoice {} not found", id))
+}
+
+fn credited_quantity(conn: &Connection, item_id: &str) -> Result<f64, String> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(-quantity), 0) FROM invoice_items WHERE credited_item_id = ?1",
+        params![item_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn insert_credit_note(tx: &Transaction, request: &CreditNoteRequest) -> Result<String, String> {
+    let original = load_invoice_with_items(tx, &request.original_invoice_id)?
+        .ok_or_else(|| format!("Invoice {} not found", request.original_invoice_id))?;
+    if numbering::sequence_key(&original.invoice.document_type) != numbering::RACUN {
+        return Err(format!(
+            "Credit note can only reference a racun, {} is {}",
+            original.invoice.invoice_number, original.invoice.document_type
+        ));
+    }
+    // Кредитуется только действующий выставленный рачун
+    if matches!(original.invoice.status.as_str(), "draft" | "cancelled" | "rejected") {
+        return Err(format!(
+            "Credit note cannot reference invoice {} with status {}",
+            original.invoice.invoice_number, original.invoice.status
+        ));
+    }
+    
+    // (строка исходного рачуна, кредитуемое количество)
+    let mut credited: Vec<(&InvoiceItem, f64)> = Vec::new();
+    if request.lines.is_empty() {
+        for item in &original.items {
+            let item_id = item.id.as_deref().unwrap_or_default();
+            let remaining = item.quantity - credited_quantity(tx, item_id)?;
+            if remaining > 0.0 {
+                credited.push((item, remaining));
+            }
+        }
+        if credited.is_empty() {
+            return Err(format!("Invoice {} is already fully credited", original.invoice.invoice_number));
+        }
+    } else {
+        for line in &request.lines {
+            let item = original
+                .items
+                .iter()
+                .find(|item| item.id.as_deref() == Some(line.item_id.as_str()))
+                .ok_or_else(|| format!("Item {} does not belong to invoice {}", line.item_id, original.invoice.invoice_number))?;
+            if !line.quantity.is_finite() || line.quantity <= 0.0 {
+                return Err(format!("{}: credited quantity must be positive", item.product_name));
+            }
+            let remaining = item.quantity - credited_quantity(tx, &line.item_id)?;
+            if line.quantity > remaining + 1e-9 {
+                return Err(format!(
+                    "{}: cannot credit {} (remaining {})",
+                    item.product_name, line.quantity, remaining
+                ));
+            }
+            credited.push((item, line.quantity));
+        }
+    }
+    
+    let mut items: Vec<InvoiceItem> = credited
+        .into_iter()
+        .map(|(item, quantity)| InvoiceItem {
+            id: None,
+            invoice_id: String::new(),
+            product_id: item.product_id.clone(),
+            product_name: item.product_name.clone(),
+            quantity: -quantity,
+            unit_weight_g: item.unit_weight_g,
+            price: item.price,
+            total: 0.0,
+            discount_pct: item.discount_pct,
+            vat_rate: item.vat_rate,
+            vat_exemption_code: item.vat_exemption_code.clone(),
+            vat_amount: None,
+            credited_item_id: item.id.clone(),
+        })
+        .collect();
+    
+    let mut invoice = Invoice {
+        id: None,
+        invoice_number: String::new(),
+        document_type: numbering::KNJIZNO_ODOBRENJE.to_string(),
+        date: request
+            .date
+            .clone()
+            .unwrap_or_else(|| Utc::now().format("%Y-%m-%d").to_string()),
+        due_date: None,
+        status: "confirmed".to_string(),
+        notes: request.reason.clone(),
+        created_at: None,
+        paid: None,
+        delivered: None,
+        subtotal: None,
+        vat_total: None,
+        source_document_id: original.invoice.id.clone(),
+        ..original.invoice
+    };
+    // Суммы кредит-ноты считает сервер — проставляем их до общей проверки в insert_invoice
+    let totals = pricing::price_invoice(&pricing_lines(&invoice, &items)).map_err(|e| e.to_string())?;
+    for (item, line) in items.iter_mut().zip(&totals.lines) {
+        item.total = line.net;
+    }
+    invoice.total = totals.total;
+    
+    insert_invoice(tx, &invoice, &items)
+}
+
+/// Выставленный фискальный документ — всё, кроме предрачуна и черновика.
+/// Он исправляется только кредит-нотой: не удаляется, позиции и шапка не меняются
+/// (кроме примечания и отметки о доставке), иначе запрет удаления обходится
+/// переводом в черновик или в предрачун.
+fn issued_invoice(conn: &Connection, id: &str) -> Result<Option<Invoice>, String> {
+    Ok(load_invoice(conn, id)?.filter(|invoice| is_issued(&invoice.document_type, &invoice.status)))
+}
+
+pub(crate) fn is_issued(document_type: &str, status: &str) -> bool {
+    numbering::sequence_key(document_type) != numbering::PREDRACUN && status != "draft"
+}
+
+/// Поля шапки выданного документа, которые правка пытается изменить.
+fn issued_header_changes(conn: &Connection, issued: &Invoice, invoice: &Invoice) -> Result<Vec<&'static str>, String> {
+    let client_id = resolve_client_id(conn, invoice.client_id.as_deref())?;
+    let currency = invoice.currency.as_deref().unwrap_or("RSD");
+    let changes = [
+        ("status", issued.status != invoice.status),
+        (
+            "document type",
+            numbering::sequence_key(&issued.document_type) != numbering::sequence_key(&invoice.document_type),
+        ),
+        ("number", issued.invoice_number != invoice.invoice_number.trim()),
+        ("client", issued.client_id != client_id || issued.client_name != invoice.client_name),
+        ("date", issued.date != invoice.date || issued.due_date != invoice.due_date),
+        (
+            "currency",
+            issued.currency.as_deref().unwrap_or("RSD") != currency
+                || issued.exchange_rate != invoice.exchange_rate
+                || issued.exchange_rate_date != invoice.exchange_rate_date,
+        ),
+    ];
+    Ok(changes.into_iter().filter(|(_, changed)| *changed).map(|(what, _)| what).collect())
+}
+
+fn issued_error(invoice: &Invoice, what: &str) -> String {
+    format!(
+        "Invoice {} is issued and its {} cannot be changed; create a credit note instead",
+        invoice.invoice_number, what
+    )
+}
+
+fn ensure_deletable(conn: &Connection, id: &str) -> Result<(), String> {
+    if let Some(invoice) = issued_invoice(conn, id)? {
+        return Err(format!(
+            "Invoice {} is issued and cannot be deleted; create a credit note instead",
+            invoice.invoice_number
+        ));
+    }
+    Ok(())
+}
+
+struct StoredItem {
+    product_id: String,
+    product_name: String,
+    quantity: f64,
+    price: f64,
+    total: f64,
+    unit_weight_g: Option<f64>,
+    discount_pct: Option<f64>,
+    vat_rate: Option<f64>,
+    vat_exemption_code: Option<String>,
+    vat_amount: Option<f64>,
+}
+
+/// Приводит позиции документа в БД к списку `items` с посчитанными `totals`.
+/// Снимок `unit_weight_g` сохраняется, пока у строки не меняется товар.
+fn sync_invoice_items(
+    tx: &Transaction,
+    invoice_id: &str,
+    items: &[InvoiceItem],
+    totals: &InvoiceTotals,
+) -> Result<(), String> {
+    let mut stored: HashMap<String, StoredItem> = HashMap::new();
+    {
+        let mut stmt = tx
+            .prepare("SELECT id, product_id, product_name, quantity, price, total, unit_weight_g, discount_pct, vat_rate, vat_exemption_code, vat_amount FROM invoice_items WHERE invoice_id = ?1")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([invoice_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    StoredItem {
+                        product_id: row.get(1)?,
+                        product_name: row.get(2)?,
+                        quantity: row.get(3)?,
+                        price: row.get(4)?,
+                        total: row.get(5)?,
+                        unit_weight_g: row.get(6)?,
+                        discount_pct: row.get(7)?,
+                        vat_rate: row.get(8)?,
+                        vat_exemption_code: row.get(9)?,
+                        vat_amount: row.get(10)?,
+                    },
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+        for row in rows {
+            let (item_id, item) = row.map_err(|e| e.to_string())?;
+            stored.insert(item_id, item);
+        }
+    }
+    
+    let mut kept: HashSet<String> = HashSet::new();
+    for (idx, (item, line)) in items.iter().zip(&totals.lines).enumerate() {
+        let discount_pct = item.discount_pct.unwrap_or(0.0);
+        let product_id = resolve_product_id(tx, &item.product_id, &item.product_name).map_err(|e| item_error(idx, &item.product_name, e))?;
+        let existing = item
+            .id
+            .as_ref()
+            .and_then(|item_id| stored.get(item_id).map(|old| (item_id, old)));
+        match existing {
+            Some((item_id, old)) => {
+                kept.insert(item_id.clone());
+                let unchanged = old.product_id == product_id
+                    && old.product_name == item.product_name
+                    && old.quantity == item.quantity
+                    && old.price == item.price
+                    && old.total == line.net
+                    && old.discount_pct == Some(discount_pct)
+                    && old.vat_rate == Some(line.vat_rate)
+                    && old.vat_exemption_code == line.vat_exemption_code
+                    && old.vat_amount == Some(line.vat_amount);
+                if unchanged {
+                    continue;
+                }
+                let unit_weight_g = if old.product_id == product_id {
+                    old.unit_weight_g
+                } else {
+                    lookup_unit_weight_g(tx, &product_id)
+                };
+                tx.execute(
+                    "UPDATE invoice_items SET product_id = ?1, product_name = ?2, quantity = ?3, unit_weight_g = ?4, price = ?5, total = ?6, discount_pct = ?7, vat_rate = ?8, vat_exemption_code = ?9, vat_amount = ?10 WHERE id = ?11",
+                    params![
+                        product_id,
+                        item.product_name,
+                        item.quantity,
+                        unit_weight_g,
+                        item.price,
+                        line.net,
+                        discount_pct,
+                        line.vat_rate,
+                        line.vat_exemption_code,
+                        line.vat_amount,
+                        item_id,
+                    ],
+                )
+                .map_err(|e| item_error(idx, &item.product_name, e))?;
+            }
+            None => {
+                let item_id = uuid::Uuid::new_v4().to_string();
+                kept.insert(item_id.clone());
+                tx.execute(
+                    "INSERT INTO invoice_items (id, invoice_id, product_id, product_name, quantity, unit_weight_g, price, total, discount_pct, vat_rate, vat_exemption_code, vat_amount) 
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    params![
+                        item_id,
+                        invoice_id,
+                        product_id,
+                        item.product_name,
+                        item.quantity,
+                        lookup_unit_weight_g(tx, &product_id),
+                        item.price,
+                        line.net,
+                        discount_pct,
+                        line.vat_rate,
+                        line.vat_exemption_code,
+                        line.vat_amount,
+                    ],
+                )
+                .map_err(|e| item_error(idx, &item.product_name, e))?;
+            }
+        }
+    }
+    
+    for item_id in stored.keys().filter(|item_id| !kept.contains(*item_id)) {
+        tx.execute("DELETE FROM invoice_items WHERE id = ?1", params![item_id])
+            .map_err(|e| e.to_string())?;
+    }
+    
+    write_tax_breakdown(tx, invoice_id, &totals.breakdown)
+}
+
+#[tauri::command]
+pub fn delete_invoice(id: String, db: State<Database>, session: State<Session>) -> Result<(), CommandError> {
+    let actor = session.require(Permission::InvoicesDelete)?;
+    println!("🗑️ delete_invoice: Deleting invoice {}", id);
+    
+    db.try_transaction(|tx| {
+        // Выданный документ и документ, из которого сделаны другие (рачун из предрачуна), удалять нельзя
+        ensure_deletable(tx, &id)?;
+        let derived = count_usages(
+            tx,
+            &[("invoices", "SELECT COUNT(*) FROM invoices WHERE source_document_id = ?1")],
+            &id,
+        )?;
+        if !derived.is_empty() {
+            return Err(CommandError::in_use("invoice", &id, derived));
+        }
+        
+        let before = audit::invoice(tx, &id)?;
+        
+        // Сначала удаляем items и разбивку ПДВ
+        tx.execute("DELETE FROM invoice_items WHERE invoice_id = ?1", params![id])
+            .map_err(|e| {
+                println!("❌ delete_invoice: Failed to delete items: {}", e);
+                e.to_string()
+            })?;
+        tx.execute("DELETE FROM invoice_tax_breakdown WHERE invoice_id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM payments WHERE invoice_id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        
+        // Затем удаляем сам invoice
+        tx.execute("DELETE FROM invoices WHERE id = ?1", params![id])
+            .map_err(|e| {
+                println!("❌ delete_invoice: Failed to delete invoice: {}", e);
+                e.to_string()
+            })?;
+        audit::record(tx, &actor, audit::DELETE, "invoice", &id, before, None)?;
+        Ok(())
+    })?;
     
     println!("✅ delete_invoice: Successfully deleted invoice {}", id);
     Ok(())
 }
 
 #[tauri::command]
-pub fn get_client_history(client_id: String, db: State<Database>) -> Result<Vec<Invoice>, String> {
-    let mut stmt = db.conn()
-        .prepare("SELECT id, invoice_number, document_type, client_id, client_name, date, due_date, total, status, notes, created_at, paid, delivered, currency, exchange_rate, exchange_rate_date FROM invoices WHERE client_id = ?1 ORDER BY created_at DESC")
+pub fn get_client_history(client_id: String, db: State<Database>, session: State<Session>) -> Result<Vec<Invoice>, String> {
+    let scope = session.require_scoped(Permission::InvoicesView)?;
+    if scope.is_some_and(|own| own.to_string() != client_id) {
+        return Err(format!("Client {} not found", client_id));
+    }
+    let conn = db.conn()?;
+    let mut stmt = conn
+        .prepare(&format!("{} WHERE client_id = ?1 ORDER BY created_at DESC", invoice_select()))
         .map_err(|e| e.to_string())?;
     
-    let invoices = stmt.query_map([&client_id], |row| {
-        let paid_int: Option<i32> = row.get(11).ok();
-        let delivered_int: Option<i32> = row.get(12).ok();
-        
-        Ok(Invoice {
-            id: Some(row.get(0)?),
-            invoice_number: row.get(1)?,
-            document_type: row.get(2)?,
-            client_id: row.get(3)?,
-            client_name: row.get(4)?,
-            date: row.get(5)?,
-            due_date: row.get(6)?,
-            total: row.get(7)?,
-            status: row.get(8)?,
-            notes: row.get(9)?,
-            created_at: Some(row.get(10)?),
-            paid: paid_int.map(|v| v != 0),
-            delivered: delivered_int.map(|v| v != 0),
-            currency: row.get(13).ok(),
-            exchange_rate: row.get(14).ok(),
-            exchange_rate_date: row.get(15).ok(),
-        })
-    })
+    let invoices = stmt.query_map([&client_id], invoice_from_row)
     .map_err(|e| e.to_string())?
     .collect::<Result<Vec<_>, _>>()
     .map_err(|e| e.to_string())?;
@@ -911,134 +2163,357 @@ pub fn get_client_history(client_id: String, db: State<Database>) -> Result<Vec<
 }
 
 #[tauri::command]
-/// Номер в UI (`number`) часто без префикса, а в SQLite `invoices.invoice_number` иногда хранится с префиксом.
-/// Предрачун/отпремница могут иметь префиксы, поэтому пробуем несколько кандидатов.
-fn invoice_number_db_candidates(raw: &str, document_type: Option<&str>) -> Vec<String> {
-    let raw = raw.trim();
-    if raw.is_empty() {
-        return vec![];
+pub fn update_invoice_payment_status(
+    // JS может прислать как invoiceNumber, так и invoice_number (в зависимости от адаптера)
+    #[allow(non_snake_case)]
+    invoiceNumber: Option<String>,
+    invoice_number: Option<String>,
+    paid: bool,
+    delivered: bool,
+    document_type: Option<String>,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<(), String> {
+    // Доставка — флаг в шапке, оплата — журнал payments
+    let actor = session.require(Permission::PaymentsEdit)?;
+    println!("═══════════════════════════════════════════════════════════");
+    println!("🔄 update_invoice_payment_status ВЫЗВАН:");
+    let invoice_number = invoice_number
+        .or(invoiceNumber)
+        .unwrap_or_default();
+    println!("   invoice_number: {}", invoice_number);
+    println!("   paid: {}", paid);
+    println!("   delivered: {}", delivered);
+    println!("   document_type: {:?}", document_type);
+    
+    if invoice_number.trim().is_empty() {
+        return Err("invoice_number is empty".to_string());
     }
-    let mut out: Vec<String> = Vec::new();
-    let mut push = |s: String| {
-        if !s.is_empty() && !out.contains(&s) {
-            out.push(s);
-        }
-    };
-
-    match document_type {
-        Some("predracun") => {
-            if raw.starts_with('p') {
-                push(raw.to_string());
-            } else {
-                push(format!("p{}", raw));
-            }
-            push(raw.to_string());
-        }
-        Some("delivery") => {
-            if raw.starts_with('o') {
-                push(raw.to_string());
-            } else {
-                push(format!("o{}", raw));
+    let (number, rows_affected) = db.with_transaction(|tx| {
+        // Номера в БД хранятся в каноническом виде; без типа документа номер должен совпадать точно
+        let number = match document_type.as_deref() {
+            Some(document_type) => {
+                let seq = numbering::load_sequence(tx, document_type)?;
+                numbering::canonical_number(&seq, &invoice_number, "")
             }
-            push(raw.to_string());
-        }
-        Some("racun") => {
-            let no_prefix = raw.trim_start_matches('p').trim_start_matches('o');
-            push(no_prefix.to_string());
-            push(raw.to_string());
-        }
-        _ => {
-            push(raw.to_string());
-            if !raw.starts_with('p') {
-                push(format!("p{}", raw));
-            }
-            if !raw.starts_with('o') {
-                push(format!("o{}", raw));
-            }
-            let stripped = raw.trim_start_matches('p').trim_start_matches('o');
-            if stripped != raw {
-                push(stripped.to_string());
-            }
-        }
+            None => invoice_number.trim().to_string(),
+        };
+        let found: Option<(String, String)> = tx
+            .query_row(
+                "SELECT id, date FROM invoices WHERE invoice_number = ?1",
+                params![number],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        let Some((id, date)) = found else {
+            return Ok((number, 0));
+        };
+        let before = audit::invoice(tx, &id)?;
+        tx.execute(
+            "UPDATE invoices SET delivered = ?1 WHERE id = ?2",
+            params![delivered as i32, id],
+        )
+        .map_err(|e| e.to_string())?;
+        payments::set_paid_flag(tx, &id, paid, &date)?;
+        audit::stamp(tx, "invoices", "id", &id, &actor, audit::UPDATE)?;
+        let after = audit::invoice(tx, &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "invoice", &id, before, after)?;
+        Ok((number, 1))
+    })
+    .map_err(|e| {
+        println!("❌ update_invoice_payment_status: Failed: {}", e);
+        e
+    })?;
+    
+    println!("   canonical_invoice_number: {}", number);
+    println!("   rows_affected: {}", rows_affected);
+    
+    if rows_affected == 0 {
+        println!(
+            "⚠️ ВНИМАНИЕ: Ни одна запись не обновлена! Инвойс {:?} (тип {:?}) не найден в базе.",
+            number, document_type
+        );
+    } else {
+        println!("✅ update_invoice_payment_status: Успешно обновлено {} записей", rows_affected);
     }
+    println!("═══════════════════════════════════════════════════════════");
+    
+    Ok(())
+}
 
-    out
+// ==================== КОМАНДЫ: НУМЕРАЦИЯ ====================
+
+#[tauri::command]
+pub fn get_document_sequences(db: State<Database>, session: State<Session>) -> Result<Vec<DocumentSequence>, String> {
+    session.require(Permission::InvoicesView)?;
+    let conn = db.conn()?;
+    numbering::list_sequences(&conn)
+}
+
+#[tauri::command]
+pub fn update_document_sequence(
+    sequence: DocumentSequence,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<DocumentSequence, String> {
+    let actor = session.require(Permission::SettingsEdit)?;
+    println!("🔄 update_document_sequence: {} -> {}{}", sequence.document_type, sequence.prefix, sequence.template);
+    let key = numbering::sequence_key(&sequence.document_type);
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "document_sequences", "document_type", &key)?;
+        let saved = numbering::save_sequence(tx, &sequence)?;
+        let after = audit::row(tx, "document_sequences", "document_type", &key)?;
+        audit::record(tx, &actor, audit::UPDATE, "document_sequence", key, before, after)?;
+        Ok(saved)
+    })
+}
+
+/// Следующий номер для подсказки в форме. Окончательно номер резервируется в create_invoice.
+#[tauri::command]
+pub fn get_next_document_number(document_type: String, date: Option<String>, db: State<Database>, session: State<Session>) -> Result<String, String> {
+    session.require(Permission::InvoicesView)?;
+    let conn = db.conn()?;
+    numbering::peek_next(&conn, &document_type, date.as_deref().unwrap_or(""))
+}
+
+// ==================== КОМАНДЫ: ОПЛАТЫ ====================
+
+#[tauri::command]
+pub fn record_payment(payment: NewPayment, db: State<Database>, session: State<Session>) -> Result<Payment, String> {
+    let actor = session.require(Permission::PaymentsEdit)?;
+    println!("💰 record_payment: {} {} for invoice {}", payment.amount, payment.currency.as_deref().unwrap_or(""), payment.invoice_id);
+    db.with_transaction(|tx| {
+        let recorded = payments::record_payment(tx, &payment)?;
+        audit::stamp(tx, "payments", "id", &recorded.id, &actor, audit::CREATE)?;
+        let after = audit::row(tx, "payments", "id", &recorded.id)?;
+        audit::record(tx, &actor, audit::CREATE, "payment", &recorded.id, None, after)?;
+        Ok(recorded)
+    })
+    .map_err(|e| {
+        println!("❌ record_payment: {}", e);
+        e
+    })
+}
+
+#[tauri::command]
+pub fn get_payments(invoice_id: String, db: State<Database>, session: State<Session>) -> Result<Vec<Payment>, String> {
+    let scope = session.require_scoped(Permission::InvoicesView)?;
+    let conn = db.conn()?;
+    ensure_in_scope(&conn, &invoice_id, scope)?;
+    payments::list_payments(&conn, &invoice_id)
+}
+
+#[tauri::command]
+pub fn reverse_payment(
+    id: String,
+    reason: Option<String>,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<Payment, String> {
+    let actor = session.require(Permission::PaymentsEdit)?;
+    println!("↩️ reverse_payment: {}", id);
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "payments", "id", &id)?;
+        let reversed = payments::reverse_payment(tx, &id, reason.as_deref())?;
+        audit::stamp(tx, "payments", "id", &id, &actor, audit::UPDATE)?;
+        let after = audit::row(tx, "payments", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "payment", &id, before, after)?;
+        Ok(reversed)
+    })
+}
+
+// ==================== КОМАНДЫ: ПРОФИЛЬ ФИРМЫ ====================
+
+#[tauri::command]
+pub fn get_company_profile(db: State<Database>, session: State<Session>) -> Result<CompanyProfile, String> {
+    session.signed_in()?;
+    let conn = db.conn()?;
+    company::load_profile(&conn)
+}
+
+#[tauri::command]
+pub fn update_company_profile(
+    profile: CompanyProfile,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<CompanyProfile, String> {
+    let actor = session.require(Permission::SettingsEdit)?;
+    println!("🏢 update_company_profile: {} ({} bank accounts)", profile.name, profile.bank_accounts.len());
+    db.with_transaction(|tx| {
+        let before = audit::company_profile(tx)?;
+        let saved = company::save_profile(tx, &profile)?;
+        let after = audit::company_profile(tx)?;
+        audit::record(tx, &actor, audit::UPDATE, "company_profile", "1", before, after)?;
+        Ok(saved)
+    })
+    .map_err(|e| {
+        println!("❌ update_company_profile: {}", e);
+        e
+    })
+}
+
+// ==================== КОМАНДЫ: EFAKTURA ====================
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EfakturaSendRequest {
+    pub invoice_id: String,
+    pub otpremnica: Option<OtpremnicaAttachment>,
+}
+
+/// UBL сохранённого рачуна или кредит-ноты: покупатель — из clients, продавец — профиль фирмы (company_profile).
+fn invoice_ubl(conn: &Connection, id: &str, otpremnica: Option<&OtpremnicaAttachment>) -> Result<String, String> {
+    let document = load_invoice_with_items(conn, id)?.ok_or_else(|| format!("Invoice {} not found", id))?;
+    let sequence = numbering::sequence_key(&document.invoice.document_type);
+    if sequence != numbering::RACUN && sequence != numbering::KNJIZNO_ODOBRENJE {
+        return Err(format!(
+            "UBL export is only available for racun and credit notes, not {}",
+            document.invoice.document_type
+        ));
+    }
+    let client_id = document
+        .invoice
+        .client_id
+        .as_deref()
+        .ok_or_else(|| format!("Invoice {} has no client", document.invoice.invoice_number))?;
+    let customer = ubl::load_customer(conn, client_id)?.ok_or_else(|| format!("Client {} not found", client_id))?;
+    let profile = company::load_profile(conn)?;
+    let bank_account = profile
+        .bank_account_for(document.invoice.currency.as_deref().unwrap_or("RSD"))
+        .ok_or_else(|| "Company profile has no bank account".to_string())?;
+
+    ubl::build_invoice_xml(&UblInput {
+        document: &document,
+        supplier: &profile.to_ubl_party(),
+        customer: &customer,
+        bank_account: &bank_account.account_number,
+        otpremnica,
+    })
+}
+
+#[tauri::command]
+pub fn export_invoice_ubl(id: String, otpremnica: Option<OtpremnicaAttachment>, db: State<Database>, session: State<Session>) -> Result<String, String> {
+    let scope = session.require_scoped(Permission::InvoicesView)?;
+    let conn = db.conn()?;
+    ensure_in_scope(&conn, &id, scope)?;
+    println!("📄 export_invoice_ubl: {}", id);
+    invoice_ubl(&conn, &id, otpremnica.as_ref())
+}
+
+#[tauri::command]
+pub fn get_efaktura_settings(db: State<Database>, session: State<Session>) -> Result<EfakturaSettings, String> {
+    session.require(Permission::SettingsEdit)?;
+    let conn = db.conn()?;
+    efaktura::load_settings(&conn).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn update_efaktura_settings(
+    settings: EfakturaSettings,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<EfakturaSettings, String> {
+    let actor = session.require(Permission::SettingsEdit)?;
+    println!("🔄 update_efaktura_settings: {}", settings.base_url);
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "efaktura_settings", "id", &1)?;
+        let saved = efaktura::save_settings(tx, &settings)?;
+        let after = audit::row(tx, "efaktura_settings", "id", &1)?;
+        audit::record(tx, &actor, audit::UPDATE, "efaktura_settings", "1", before, after)?;
+        Ok(saved)
+    })
 }
 
+/// Отправляет рачун в SEF и сохраняет SalesInvoiceId/статус на документе.
+/// UBL всегда формируется из базы (как export_invoice_ubl), а не принимается от UI.
+/// Ошибка отправки тоже сохраняется (invoices.sef_error), чтобы её было видно в списке.
 #[tauri::command]
-pub fn update_invoice_payment_status(
-    // JS может прислать как invoiceNumber, так и invoice_number (в зависимости от адаптера)
-    #[allow(non_snake_case)]
-    invoiceNumber: Option<String>,
-    invoice_number: Option<String>,
-    paid: bool,
-    delivered: bool,
-    document_type: Option<String>,
-    db: State<Database>,
-) -> Result<(), String> {
-    println!("═══════════════════════════════════════════════════════════");
-    println!("🔄 update_invoice_payment_status ВЫЗВАН:");
-    let invoice_number = invoice_number
-        .or(invoiceNumber)
-        .unwrap_or_default();
-    println!("   invoice_number: {}", invoice_number);
-    println!("   paid: {}", paid);
-    println!("   delivered: {}", delivered);
-    println!("   document_type: {:?}", document_type);
-    
-    let candidates = invoice_number_db_candidates(&invoice_number, document_type.as_deref());
-    if candidates.is_empty() {
-        return Err("invoice_number is empty".to_string());
-    }
+pub async fn send_invoice_to_efaktura(request: EfakturaSendRequest, db: State<'_, Database>, session: State<'_, Session>) -> Result<Invoice, String> {
+    let actor = session.require(Permission::InvoicesEdit)?;
+    println!("📤 send_invoice_to_efaktura: {}", request.invoice_id);
+    // Соединение не держим через .await
+    let (config, sales_request) = {
+        let conn = db.conn()?;
+        let config = efaktura::load_config(&conn).map_err(|e| e.to_string())?;
+        let ubl_xml = invoice_ubl(&conn, &request.invoice_id, request.otpremnica.as_ref())?;
+        let sales_request =
+            efaktura::prepare_sales_invoice(&conn, &request.invoice_id, &ubl_xml).map_err(|e| e.to_string())?;
+        (config, sales_request)
+    };
 
-    let mut rows_affected: usize = 0;
-    let mut matched: Option<String> = None;
-    for cand in &candidates {
-        let n = db
-            .conn()
-            .execute(
-                "UPDATE invoices SET paid = ?1, delivered = ?2 WHERE invoice_number = ?3",
-                params![paid as i32, delivered as i32, cand],
-            )
-            .map_err(|e| {
-                println!("❌ update_invoice_payment_status: Failed: {}", e);
-                e.to_string()
-            })?;
-        if n > 0 {
-            rows_affected = n;
-            matched = Some(cand.clone());
-            break;
-        }
-    }
-    
-    println!("   tried_candidates: {:?}", candidates);
-    println!("   rows_affected: {}", rows_affected);
-    if let Some(m) = &matched {
-        println!("   matched_invoice_number: {}", m);
+    let client = EfakturaClient::new(config).map_err(|e| e.to_string())?;
+    let sent = client.send_sales_invoice(&sales_request).await;
+
+    let failure = db.with_transaction(|tx| store_sef_result(tx, &actor, &sales_request, sent))?;
+    if let Some(e) = failure {
+        println!("❌ send_invoice_to_efaktura {}: {}", sales_request.invoice_number, e);
+        return Err(e.to_string());
     }
-    
-    if rows_affected == 0 {
-        println!(
-            "⚠️ ВНИМАНИЕ: Ни одна запись не обновлена! Инвойс {:?} (тип {:?}) не найден в базе по номерам {:?}.",
-            invoice_number, document_type, candidates
-        );
-    } else {
-        println!("✅ update_invoice_payment_status: Успешно обновлено {} записей", rows_affected);
+    println!("✅ Рачун {} отправлен в eFaktura", sales_request.invoice_number);
+    let conn = db.conn()?;
+    load_invoice(&conn, &request.invoice_id)?.ok_or_else(|| format!("Invoice {} not found", request.invoice_id))
+}
+
+/// Сохраняет результат отправки (sef_* или sef_error) и пишет его в журнал аудита.
+/// Возвращает ошибку отправки, если она была.
+fn store_sef_result(
+    tx: &Transaction,
+    actor: &audit::Actor,
+    request: &SalesInvoiceRequest,
+    sent: Result<SalesInvoiceResponse, EfakturaError>,
+) -> Result<Option<EfakturaError>, String> {
+    let before = audit::invoice(tx, &request.invoice_id)?;
+    let failure = sent.and_then(|response| efaktura::mark_sent(tx, request, &response)).err();
+    if let Some(e) = &failure {
+        efaktura::mark_failed(tx, &request.invoice_id, e).map_err(|e| e.to_string())?;
     }
-    println!("═══════════════════════════════════════════════════════════");
-    
-    Ok(())
+    audit::stamp(tx, "invoices", "id", &request.invoice_id, actor, audit::UPDATE)?;
+    let after = audit::invoice(tx, &request.invoice_id)?;
+    audit::record(tx, actor, audit::UPDATE, "invoice", &request.invoice_id, before, after)?;
+    Ok(failure)
+}
+
+/// Внеочередной опрос SEF (тот же, что выполняет фоновая задача)
+#[tauri::command]
+pub async fn sync_efaktura(db: State<'_, Database>, session: State<'_, Session>) -> Result<PollSummary, String> {
+    session.require(Permission::InvoicesEdit)?;
+    println!("🔄 sync_efaktura");
+    efaktura_poller::poll_once(&db).await.map_err(|e| e.to_string())
+}
+
+/// Входящие рачуни поставщиков из SEF
+#[tauri::command]
+pub fn get_purchase_invoices(supplier_id: Option<i64>, db: State<Database>, session: State<Session>) -> Result<Vec<PurchaseInvoice>, String> {
+    session.require(Permission::SuppliersView)?;
+    let conn = db.conn()?;
+    efaktura_poller::list_purchase_invoices(&conn, supplier_id)
+}
+
+/// Повторная загрузка входящего рачуна, сохранённого с ошибкой (битый UBL, 4xx SEF)
+#[tauri::command]
+pub async fn retry_purchase_invoice(id: String, db: State<'_, Database>, session: State<'_, Session>) -> Result<PurchaseInvoice, String> {
+    session.require(Permission::InvoicesEdit)?;
+    println!("🔄 retry_purchase_invoice: {}", id);
+    efaktura_poller::retry_purchase_invoice(&db, &id).await.map_err(|e| e.to_string())
 }
 
 // ==================== КОМАНДЫ: ДОСТАВКИ ====================
 
 #[tauri::command]
-pub fn get_deliveries(db: State<Database>) -> Result<Vec<Delivery>, String> {
-    let mut stmt = db.conn()
-        .prepare("SELECT id, delivery_number, client_id, client_name, date, status, notes, created_at FROM deliveries ORDER BY created_at DESC")
+pub fn get_deliveries(db: State<Database>, session: State<Session>) -> Result<Vec<Delivery>, String> {
+    let scope = session.require_scoped(Permission::WarehouseView)?;
+    let conn = db.conn()?;
+    // Отпремнице ссылаются на клиента по id или по МБ (синхронизация)
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, delivery_number, client_id, client_name, date, status, notes, created_at FROM deliveries
+             WHERE ?1 IS NULL OR client_id = ?1 OR client_id = (SELECT mb FROM clients WHERE id = ?1)
+             ORDER BY created_at DESC",
+        )
         .map_err(|e| e.to_string())?;
     
-    let deliveries = stmt.query_map([], |row| {
+    let deliveries = stmt.query_map([scope.map(|id| id.to_string())], |row| {
         Ok(Delivery {
             id: Some(row.get(0)?),
             delivery_number: row.get(1)?,
@@ -1058,41 +2533,56 @@ pub fn get_deliveries(db: State<Database>) -> Result<Vec<Delivery>, String> {
 }
 
 #[tauri::command]
-pub fn create_delivery(delivery: Delivery, items: Vec<DeliveryItem>, db: State<Database>) -> Result<String, String> {
+pub fn create_delivery(
+    delivery: Delivery,
+    items: Vec<DeliveryItem>,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<String, String> {
+    let actor = session.require(Permission::WarehouseEdit)?;
     let id = uuid::Uuid::new_v4().to_string();
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "INSERT INTO deliveries (id, delivery_number, client_id, client_name, date, status, notes, created_at) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-        params![
-            id,
-            delivery.delivery_number,
-            delivery.client_id,
-            delivery.client_name,
-            delivery.date,
-            delivery.status,
-            delivery.notes,
-            created_at,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
-    
-    for item in items {
-        let item_id = uuid::Uuid::new_v4().to_string();
-        db.conn().execute(
-            "INSERT INTO delivery_items (id, delivery_id, product_id, product_name, quantity) 
-             VALUES (?1, ?2, ?3, ?4, ?5)",
+    db.with_transaction(|tx| {
+        tx.execute(
+            "INSERT INTO deliveries (id, delivery_number, client_id, client_name, date, status, notes, created_at) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
             params![
-                item_id,
                 id,
-                item.product_id,
-                item.product_name,
-                item.quantity,
+                delivery.delivery_number,
+                delivery.client_id,
+                delivery.client_name,
+                delivery.date,
+                delivery.status,
+                delivery.notes,
+                created_at,
             ],
         )
         .map_err(|e| e.to_string())?;
-    }
+        
+        for (idx, item) in items.iter().enumerate() {
+            let item_id = uuid::Uuid::new_v4().to_string();
+            tx.execute(
+                "INSERT INTO delivery_items (id, delivery_id, product_id, product_name, quantity) 
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    item_id,
+                    id,
+                    item.product_id,
+                    item.product_name,
+                    item.quantity,
+                ],
+            )
+            .map_err(|e| item_error(idx, &item.product_name, e))?;
+        }
+        
+        audit::stamp(tx, "deliveries", "id", &id, &actor, audit::CREATE)?;
+        let mut after = audit::row(tx, "deliveries", "id", &id)?;
+        if let Some(after) = after.as_mut() {
+            after["items"] = audit::rows(tx, "SELECT * FROM delivery_items WHERE delivery_id = ?1 ORDER BY rowid", &[&id])?.into();
+        }
+        audit::record(tx, &actor, audit::CREATE, "delivery", &id, None, after)
+    })?;
     
     Ok(id)
 }
@@ -1100,8 +2590,10 @@ pub fn create_delivery(delivery: Delivery, items: Vec<DeliveryItem>, db: State<D
 // ==================== КОМАНДЫ: СКЛАД ====================
 
 #[tauri::command]
-pub fn get_warehouse_groups(db: State<Database>) -> Result<Vec<WarehouseGroup>, String> {
-    let mut stmt = db.conn()
+pub fn get_warehouse_groups(db: State<Database>, session: State<Session>) -> Result<Vec<WarehouseGroup>, String> {
+    session.require(Permission::WarehouseView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, description, created_at FROM warehouse_groups ORDER BY created_at DESC")
         .map_err(|e| e.to_string())?;
     
@@ -1121,66 +2613,106 @@ pub fn get_warehouse_groups(db: State<Database>) -> Result<Vec<WarehouseGroup>,
 }
 
 #[tauri::command]
-pub fn create_warehouse_group(group: WarehouseGroup, db: State<Database>) -> Result<String, String> {
+pub fn create_warehouse_group(group: WarehouseGroup, db: State<Database>, session: State<Session>) -> Result<String, String> {
+    let actor = session.require(Permission::WarehouseEdit)?;
     let id = uuid::Uuid::new_v4().to_string();
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "INSERT INTO warehouse_groups (id, name, description, created_at) 
-         VALUES (?1, ?2, ?3, ?4)",
-        params![
-            id,
-            group.name,
-            group.description,
-            created_at,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
+    db.with_transaction(|tx| {
+        tx.execute(
+            "INSERT INTO warehouse_groups (id, name, description, created_at) 
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                id,
+                group.name,
+                group.description,
+                created_at,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "warehouse_groups", "id", &id, &actor, audit::CREATE)?;
+        let after = audit::row(tx, "warehouse_groups", "id", &id)?;
+        audit::record(tx, &actor, audit::CREATE, "warehouse_group", &id, None, after)
+    })?;
     
     Ok(id)
 }
 
 #[tauri::command]
-pub fn update_warehouse_group(id: String, group: WarehouseGroup, db: State<Database>) -> Result<(), String> {
-    db.conn().execute(
-        "UPDATE warehouse_groups SET name = ?1, description = ?2 WHERE id = ?3",
-        params![
-            group.name,
-            group.description,
-            id,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
-    Ok(())
+pub fn update_warehouse_group(
+    id: String,
+    group: WarehouseGroup,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<(), String> {
+    let actor = session.require(Permission::WarehouseEdit)?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "warehouse_groups", "id", &id)?;
+        tx.execute(
+            "UPDATE warehouse_groups SET name = ?1, description = ?2 WHERE id = ?3",
+            params![
+                group.name,
+                group.description,
+                id,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "warehouse_groups", "id", &id, &actor, audit::UPDATE)?;
+        let after = audit::row(tx, "warehouse_groups", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "warehouse_group", &id, before, after)
+    })
 }
 
 #[tauri::command]
-pub fn delete_warehouse_group_item(group_id: String, product_id: String, db: State<Database>) -> Result<(), String> {
-    db.conn()
-        .execute("DELETE FROM warehouse_items WHERE group_id = ?1 AND product_id = ?2", params![group_id, product_id])
-        .map_err(|e| e.to_string())?;
-    Ok(())
+pub fn delete_warehouse_group_item(
+    group_id: String,
+    product_id: String,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<(), String> {
+    let actor = session.require(Permission::WarehouseEdit)?;
+    db.with_transaction(|tx| {
+        let items = audit::rows(
+            tx,
+            "SELECT * FROM warehouse_items WHERE group_id = ?1 AND product_id = ?2",
+            &[&group_id, &product_id],
+        )?;
+        tx.execute("DELETE FROM warehouse_items WHERE group_id = ?1 AND product_id = ?2", params![group_id, product_id])
+            .map_err(|e| e.to_string())?;
+        for before in items {
+            let item_id = before["id"].as_str().unwrap_or_default().to_string();
+            audit::record(tx, &actor, audit::DELETE, "warehouse_item", &item_id, Some(before), None)?;
+        }
+        Ok(())
+    })
 }
 
 #[tauri::command]
-pub fn delete_warehouse_group(id: String, db: State<Database>) -> Result<(), String> {
+pub fn delete_warehouse_group(id: String, db: State<Database>, session: State<Session>) -> Result<(), String> {
+    let actor = session.require(Permission::WarehouseEdit)?;
     println!("🗑️ delete_warehouse_group: Deleting group {}", id);
     
-    // Сначала удаляем все items в группе
-    db.conn()
-        .execute("DELETE FROM warehouse_items WHERE group_id = ?1", params![id])
-        .map_err(|e| {
-            println!("❌ delete_warehouse_group: Failed to delete items: {}", e);
-            e.to_string()
-        })?;
-    
-    // Затем удаляем саму группу
-    db.conn()
-        .execute("DELETE FROM warehouse_groups WHERE id = ?1", params![id])
-        .map_err(|e| {
-            println!("❌ delete_warehouse_group: Failed to delete group: {}", e);
-            e.to_string()
-        })?;
+    db.with_transaction(|tx| {
+        let mut before = audit::row(tx, "warehouse_groups", "id", &id)?;
+        if let Some(before) = before.as_mut() {
+            before["items"] = audit::rows(tx, "SELECT * FROM warehouse_items WHERE group_id = ?1 ORDER BY rowid", &[&id])?.into();
+        }
+        
+        // Сначала удаляем все items в группе
+        tx.execute("DELETE FROM warehouse_items WHERE group_id = ?1", params![id])
+            .map_err(|e| {
+                println!("❌ delete_warehouse_group: Failed to delete items: {}", e);
+                e.to_string()
+            })?;
+        
+        // Затем удаляем саму группу
+        tx.execute("DELETE FROM warehouse_groups WHERE id = ?1", params![id])
+            .map_err(|e| {
+                println!("❌ delete_warehouse_group: Failed to delete group: {}", e);
+                e.to_string()
+            })?;
+        audit::record(tx, &actor, audit::DELETE, "warehouse_group", &id, before, None)
+    })?;
     
     println!("✅ delete_warehouse_group: Successfully deleted group {}", id);
     Ok(())
@@ -1196,9 +2728,10 @@ pub fn save_invoice_html(
     month: String,
     html_content: String,
     app_handle: tauri::AppHandle,
+    session: State<Session>,
 ) -> Result<String, String> {
+    session.require(Permission::InvoicesEdit)?;
     use std::fs;
-    use std::path::PathBuf;
 
     println!("💾 save_invoice_html: Saving {} #{}", document_type, invoice_number);
 
@@ -1239,83 +2772,410 @@ pub fn load_invoice_html(
     year: String,
     month: String,
     app_handle: tauri::AppHandle,
+    db: State<Database>,
+    session: State<Session>,
 ) -> Result<String, String> {
+    let scope = session.require_scoped(Permission::InvoicesView)?;
     use std::fs;
 
-    println!("📂 load_invoice_html: Loading {} #{}", document_type, invoice_number);
+    if let Some(client_id) = scope {
+        let own: bool = db
+            .conn()?
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM invoices WHERE invoice_number = ?1 AND client_id = ?2)",
+                params![invoice_number.trim(), client_id.to_string()],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if !own {
+            return Err(format!("Invoice {} not found", invoice_number));
+        }
+    }
+
+    println!("📂 load_invoice_html: Loading {} #{}", document_type, invoice_number);
+
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+
+    let safe_filename = invoice_number.replace("/", "-").replace("\\", "-");
+    let file_path = app_data_dir
+        .join("invoices")
+        .join(&document_type)
+        .join(&year)
+        .join(&month)
+        .join(format!("{}.html", safe_filename));
+
+    if !file_path.exists() {
+        return Err(format!("HTML file not found: {}", file_path.to_string_lossy()));
+    }
+
+    let html_content = fs::read_to_string(&file_path)
+        .map_err(|e| format!("Failed to read HTML file: {}", e))?;
+
+    println!("✅ load_invoice_html: Loaded from {}", file_path.to_string_lossy());
+    Ok(html_content)
+}
+
+#[tauri::command]
+pub fn delete_invoice_html(
+    invoice_number: String,
+    document_type: String,
+    year: String,
+    month: String,
+    app_handle: tauri::AppHandle,
+    session: State<Session>,
+) -> Result<(), String> {
+    session.require(Permission::InvoicesEdit)?;
+    use std::fs;
+
+    println!("🗑️ delete_invoice_html: Deleting {} #{}", document_type, invoice_number);
+
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+
+    let safe_filename = invoice_number.replace("/", "-").replace("\\", "-");
+    let file_path = app_data_dir
+        .join("invoices")
+        .join(&document_type)
+        .join(&year)
+        .join(&month)
+        .join(format!("{}.html", safe_filename));
+
+    if file_path.exists() {
+        fs::remove_file(&file_path)
+            .map_err(|e| format!("Failed to delete HTML file: {}", e))?;
+        println!("✅ delete_invoice_html: Deleted {}", file_path.to_string_lossy());
+    } else {
+        println!("⚠️ delete_invoice_html: File not found, skipping");
+    }
+
+    Ok(())
+}
+
+// ==================== PDF ИНВОЙСОВ ====================
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PdfBatchRequest {
+    /// Конкретные документы; если не заданы — все по фильтру ниже
+    pub ids: Option<Vec<String>>,
+    pub document_type: Option<String>,
+    /// YYYY-MM-DD включительно
+    pub date_from: Option<String>,
+    pub date_to: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PdfBatchFailure {
+    pub invoice_id: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PdfBatchResult {
+    pub generated: Vec<String>,
+    pub failed: Vec<PdfBatchFailure>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpsQr {
+    pub payload: String,
+    pub svg: String,
+    /// data:image/png;base64,... — для вставки в HTML-шаблоны
+    pub png_data_url: String,
+}
+
+/// NBS IPS QR для рачуна/предрачуна в динарах: получатель — наша фирма, плательщик — клиент,
+/// сумма — неоплаченный остаток по журналу оплат.
+fn invoice_ips_qr(conn: &Connection, id: &str) -> Result<IpsQr, String> {
+    use base64::Engine;
+
+    let document = load_invoice_with_items(conn, id)?.ok_or_else(|| format!("Invoice {} not found", id))?;
+    let company = company::load_profile(conn)?;
+    let customer = match document.invoice.client_id.as_deref() {
+        Some(client_id) => ubl::load_customer(conn, client_id)?,
+        None => None,
+    };
+    let payer = customer
+        .as_ref()
+        .map(|c| c.name.as_str())
+        .or(document.invoice.client_name.as_deref());
+    let amount = pricing::round_money(ips::amount_due(&document.invoice, document.invoice.total));
+    if amount <= 0.0 {
+        return Err(format!("Invoice {} is fully paid", document.invoice.invoice_number));
+    }
+    let payment = ips::invoice_payment(&document.invoice, amount, &company, payer).map_err(|e| e.to_string())?;
+    let payload = ips::build_payload(&payment).map_err(|e| e.to_string())?;
+    let svg = ips::render_svg(&payload, 200).map_err(|e| e.to_string())?;
+    let png = ips::render_png(&payload, 6).map_err(|e| e.to_string())?;
+    Ok(IpsQr {
+        png_data_url: format!(
+            "data:image/png;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(png)
+        ),
+        payload,
+        svg,
+    })
+}
+
+#[tauri::command]
+pub fn get_invoice_ips_qr(id: String, db: State<Database>, session: State<Session>) -> Result<IpsQr, String> {
+    let scope = session.require_scoped(Permission::InvoicesView)?;
+    let conn = db.conn()?;
+    ensure_in_scope(&conn, &id, scope)?;
+    invoice_ips_qr(&conn, &id)
+}
+
+/// PDF сохранённого документа из данных базы: реквизиты — из профиля фирмы, покупатель — из clients.
+fn invoice_pdf(conn: &Connection, id: &str, base_dir: &std::path::Path) -> Result<String, String> {
+    let document = load_invoice_with_items(conn, id)?.ok_or_else(|| format!("Invoice {} not found", id))?;
+    let company = company::load_profile(conn)?;
+    let customer = match document.invoice.client_id.as_deref() {
+        Some(client_id) => ubl::load_customer(conn, client_id)?,
+        None => None,
+    };
+    let path = pdf::write_archive(
+        base_dir,
+        &PdfInput {
+            document: &document,
+            company: &company,
+            customer: customer.as_ref(),
+        },
+    )?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Документы для пакетной генерации: тип сравниваем по ключу нумерации (delivery == otpremnica).
+fn pdf_batch_ids(conn: &Connection, request: &PdfBatchRequest) -> Result<Vec<String>, String> {
+    if let Some(ids) = &request.ids {
+        return Ok(ids.clone());
+    }
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, document_type FROM invoices
+             WHERE (?1 IS NULL OR substr(date, 1, 10) >= ?1) AND (?2 IS NULL OR substr(date, 1, 10) <= ?2)
+             ORDER BY date, invoice_number",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![request.date_from, request.date_to], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let wanted = request.document_type.as_deref().map(numbering::sequence_key);
+    Ok(rows
+        .into_iter()
+        .filter(|(_, document_type)| wanted.is_none_or(|w| numbering::sequence_key(document_type) == w))
+        .map(|(id, _)| id)
+        .collect())
+}
+
+#[tauri::command]
+pub fn generate_invoice_pdf(id: String, app_handle: tauri::AppHandle, db: State<Database>, session: State<Session>) -> Result<String, String> {
+    session.require(Permission::InvoicesEdit)?;
+    println!("📄 generate_invoice_pdf: {}", id);
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+    let conn = db.conn()?;
+    let path = invoice_pdf(&conn, &id, &app_data_dir)?;
+    println!("✅ generate_invoice_pdf: Saved to {}", path);
+    Ok(path)
+}
+
+/// Пакетная генерация: ошибка одного документа не останавливает остальные.
+#[tauri::command]
+pub fn generate_invoice_pdfs(
+    request: PdfBatchRequest,
+    app_handle: tauri::AppHandle,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<PdfBatchResult, String> {
+    session.require(Permission::InvoicesEdit)?;
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+    let conn = db.conn()?;
+    let ids = pdf_batch_ids(&conn, &request)?;
+    println!("📄 generate_invoice_pdfs: {} documents", ids.len());
+
+    let mut result = PdfBatchResult {
+        generated: Vec::new(),
+        failed: Vec::new(),
+    };
+    for id in ids {
+        match invoice_pdf(&conn, &id, &app_data_dir) {
+            Ok(path) => result.generated.push(path),
+            Err(error) => {
+                println!("⚠️ generate_invoice_pdfs: {} failed: {}", id, error);
+                result.failed.push(PdfBatchFailure { invoice_id: id, error });
+            }
+        }
+    }
+    println!(
+        "✅ generate_invoice_pdfs: {} generated, {} failed",
+        result.generated.len(),
+        result.failed.len()
+    );
+    Ok(result)
+}
+
+// ==================== ПРОГНОЗ: ПОТРЕБЛЕНИЕ И ВЫРУЧКА ====================
+
+#[tauri::command]
+pub fn get_forecast_report(req: ForecastRequest, db: State<Database>, session: State<Session>) -> Result<ForecastReport, String> {
+    session.require(Permission::ReportsView)?;
+    ForecastService::generate(&db, req)
+}
+
+#[tauri::command]
+pub fn get_receivables_report(req: ReceivablesRequest, db: State<Database>, session: State<Session>) -> Result<ReceivablesReport, String> {
+    session.require(Permission::ReportsView)?;
+    ReceivablesService::generate(&db, req)
+}
 
-    let app_data_dir = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+// ==================== СХЕМА БД ====================
 
-    let safe_filename = invoice_number.replace("/", "-").replace("\\", "-");
-    let file_path = app_data_dir
-        .join("invoices")
-        .join(&document_type)
-        .join(&year)
-        .join(&month)
-        .join(format!("{}.html", safe_filename));
+#[tauri::command]
+pub fn get_schema_version(db: State<Database>, session: State<Session>) -> Result<SchemaVersion, String> {
+    session.signed_in()?;
+    let conn = db.conn()?;
+    migrations::schema_version(&conn).map_err(|e| e.to_string())
+}
 
-    if !file_path.exists() {
-        return Err(format!("HTML file not found: {}", file_path.to_string_lossy()));
-    }
+// ==================== ШИФРОВАНИЕ БАЗЫ ====================
 
-    let html_content = fs::read_to_string(&file_path)
-        .map_err(|e| format!("Failed to read HTML file: {}", e))?;
+/// Доступна до входа: по ней UI решает, показать ли запрос пароля базы.
+#[tauri::command]
+pub fn get_database_encryption(db: State<Database>) -> Result<EncryptionStatus, String> {
+    db.encryption_status()
+}
 
-    println!("✅ load_invoice_html: Loaded from {}", file_path.to_string_lossy());
-    Ok(html_content)
+/// Пароль владельца при запуске: без него зашифрованная база закрыта, в том числе
+/// таблица users, поэтому команда работает без сессии.
+#[tauri::command]
+pub fn unlock_database(passphrase: String, db: State<Database>) -> Result<EncryptionStatus, String> {
+    db.unlock(&passphrase)?;
+    db.encryption_status()
 }
 
 #[tauri::command]
-pub fn delete_invoice_html(
-    invoice_number: String,
-    document_type: String,
-    year: String,
-    month: String,
-    app_handle: tauri::AppHandle,
-) -> Result<(), String> {
-    use std::fs;
+pub fn encrypt_database(passphrase: String, db: State<Database>, session: State<Session>) -> Result<EncryptionStatus, String> {
+    let actor = session.require(Permission::DatabaseManage)?;
+    db.encrypt(&passphrase)?;
+    record_encryption_change(&db, &actor, "encryptedAt")?;
+    db.encryption_status()
+}
 
-    println!("🗑️ delete_invoice_html: Deleting {} #{}", document_type, invoice_number);
+#[tauri::command]
+pub fn rotate_database_key(
+    current_passphrase: String,
+    new_passphrase: String,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<EncryptionStatus, String> {
+    let actor = session.require(Permission::DatabaseManage)?;
+    if current_passphrase == new_passphrase {
+        return Err("New passphrase must differ from the current one".to_string());
+    }
+    db.rotate_key(&current_passphrase, &new_passphrase)?;
+    record_encryption_change(&db, &actor, "keyRotatedAt")?;
+    db.encryption_status()
+}
 
-    let app_data_dir = app_handle
+/// В журнал — только факт и время, пароль туда не попадает
+fn record_encryption_change(db: &Database, actor: &audit::Actor, field: &str) -> Result<(), String> {
+    let after = serde_json::json!({ field: Utc::now().to_rfc3339() });
+    db.with_transaction(|tx| audit::record(tx, actor, audit::UPDATE, "database", "encryption", None, Some(after)))
+}
+
+// ==================== РЕЗЕРВНЫЕ КОПИИ ====================
+
+fn app_data_dir(app_handle: &tauri::AppHandle) -> Result<std::path::PathBuf, String> {
+    app_handle
         .path()
         .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+        .map_err(|e| format!("Failed to get app data dir: {}", e))
+}
 
-    let safe_filename = invoice_number.replace("/", "-").replace("\\", "-");
-    let file_path = app_data_dir
-        .join("invoices")
-        .join(&document_type)
-        .join(&year)
-        .join(&month)
-        .join(format!("{}.html", safe_filename));
+#[tauri::command]
+pub fn get_backup_settings(db: State<Database>, session: State<Session>) -> Result<BackupSettings, String> {
+    session.require(Permission::DatabaseManage)?;
+    let conn = db.conn()?;
+    backup::load_settings(&conn).map_err(|e| e.to_string())
+}
 
-    if file_path.exists() {
-        fs::remove_file(&file_path)
-            .map_err(|e| format!("Failed to delete HTML file: {}", e))?;
-        println!("✅ delete_invoice_html: Deleted {}", file_path.to_string_lossy());
-    } else {
-        println!("⚠️ delete_invoice_html: File not found, skipping");
-    }
+#[tauri::command]
+pub fn save_backup_settings(settings: BackupSettings, db: State<Database>, session: State<Session>) -> Result<BackupSettings, String> {
+    let actor = session.require(Permission::DatabaseManage)?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "backup_settings", "id", &1)?;
+        let saved = backup::save_settings(tx, &settings)?;
+        let after = audit::row(tx, "backup_settings", "id", &1)?;
+        audit::record(tx, &actor, audit::UPDATE, "backup_settings", "1", before, after)?;
+        Ok(saved)
+    })
+}
 
-    Ok(())
+#[tauri::command]
+pub fn list_backups(app_handle: tauri::AppHandle, db: State<Database>, session: State<Session>) -> Result<Vec<BackupInfo>, String> {
+    session.require(Permission::DatabaseManage)?;
+    backup::list(&db, &app_data_dir(&app_handle)?)
 }
 
-// ==================== ПРОГНОЗ: ПОТРЕБЛЕНИЕ И ВЫРУЧКА ====================
+#[tauri::command]
+pub fn create_backup(app_handle: tauri::AppHandle, db: State<Database>, session: State<Session>) -> Result<BackupInfo, String> {
+    let actor = session.require(Permission::DatabaseManage)?;
+    let created = backup::create(&db, &app_data_dir(&app_handle)?, BackupKind::Manual)?;
+    let after = serde_json::json!({ "name": created.name, "sizeBytes": created.size_bytes });
+    db.with_transaction(|tx| audit::record(tx, &actor, audit::CREATE, "backup", &created.name, None, Some(after)))?;
+    Ok(created)
+}
 
+/// После восстановления сессия закрывается: пользователи и права теперь из копии.
 #[tauri::command]
-pub fn get_forecast_report(req: ForecastRequest, db: State<Database>) -> Result<ForecastReport, String> {
-    ForecastService::generate(&db, req)
+pub fn restore_backup(
+    name: String,
+    app_handle: tauri::AppHandle,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<RestoreResult, String> {
+    let actor = session.require(Permission::DatabaseManage)?;
+    let restored = backup::restore(&db, &app_data_dir(&app_handle)?, &name)?;
+    let after = serde_json::json!({ "safetyBackup": restored.safety_backup.name });
+    db.with_transaction(|tx| audit::record(tx, &actor, audit::RESTORE, "backup", &name, None, Some(after)))?;
+    session.sign_out();
+    Ok(restored)
+}
+
+// ==================== ЖУРНАЛ ИЗМЕНЕНИЙ ====================
+
+/// Кто и что менял: фильтр по сущности (client, invoice, ...), записи или пользователю.
+#[tauri::command]
+pub fn get_audit_log(filter: Option<AuditFilter>, db: State<Database>, session: State<Session>) -> Result<Vec<AuditEntry>, String> {
+    session.require(Permission::AuditView)?;
+    let conn = db.conn()?;
+    audit::load(&conn, &filter.unwrap_or_default())
 }
 
 // ==================== КОМАНДЫ: КАТЕГОРИИ ====================
 
 #[tauri::command]
-pub fn get_categories(db: State<Database>) -> Result<Vec<Category>, String> {
-    let mut stmt = db.conn()
+pub fn get_categories(db: State<Database>, session: State<Session>) -> Result<Vec<Category>, String> {
+    session.require(Permission::ProductsView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, created_at FROM categories ORDER BY name ASC")
         .map_err(|e| e.to_string())?;
     
@@ -1334,15 +3194,20 @@ pub fn get_categories(db: State<Database>) -> Result<Vec<Category>, String> {
 }
 
 #[tauri::command]
-pub fn create_category(category: Category, db: State<Database>) -> Result<Category, String> {
+pub fn create_category(category: Category, db: State<Database>, session: State<Session>) -> Result<Category, String> {
+    let actor = session.require(Permission::ProductsEdit)?;
     let id = uuid::Uuid::new_v4().to_string();
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "INSERT INTO categories (id, name, created_at) VALUES (?1, ?2, ?3)",
-        params![id, category.name, created_at],
-    )
-    .map_err(|e| e.to_string())?;
+    db.with_transaction(|tx| {
+        tx.execute(
+            "INSERT INTO categories (id, name, created_at) VALUES (?1, ?2, ?3)",
+            params![id, category.name, created_at],
+        )
+        .map_err(|e| e.to_string())?;
+        let after = audit::row(tx, "categories", "id", &id)?;
+        audit::record(tx, &actor, audit::CREATE, "category", &id, None, after)
+    })?;
     
     Ok(Category {
         id: Some(id),
@@ -1352,25 +3217,32 @@ pub fn create_category(category: Category, db: State<Database>) -> Result<Catego
 }
 
 #[tauri::command]
-pub fn delete_category(id: String, db: State<Database>) -> Result<(), String> {
-    // Сначала удаляем все субкатегории этой категории
-    db.conn()
-        .execute("DELETE FROM subcategories WHERE category_id = ?1", params![id])
-        .map_err(|e| e.to_string())?;
-    
-    // Затем удаляем саму категорию
-    db.conn()
-        .execute("DELETE FROM categories WHERE id = ?1", params![id])
-        .map_err(|e| e.to_string())?;
-    
-    Ok(())
+pub fn delete_category(id: String, db: State<Database>, session: State<Session>) -> Result<(), String> {
+    let actor = session.require(Permission::ProductsEdit)?;
+    db.with_transaction(|tx| {
+        let mut before = audit::row(tx, "categories", "id", &id)?;
+        if let Some(before) = before.as_mut() {
+            before["subcategories"] = audit::rows(tx, "SELECT * FROM subcategories WHERE category_id = ?1 ORDER BY name", &[&id])?.into();
+        }
+        
+        // Сначала удаляем все субкатегории этой категории
+        tx.execute("DELETE FROM subcategories WHERE category_id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        
+        // Затем удаляем саму категорию
+        tx.execute("DELETE FROM categories WHERE id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        audit::record(tx, &actor, audit::DELETE, "category", &id, before, None)
+    })
 }
 
 // ==================== КОМАНДЫ: СУБКАТЕГОРИИ ====================
 
 #[tauri::command]
-pub fn get_subcategories(db: State<Database>) -> Result<Vec<Subcategory>, String> {
-    let mut stmt = db.conn()
+pub fn get_subcategories(db: State<Database>, session: State<Session>) -> Result<Vec<Subcategory>, String> {
+    session.require(Permission::ProductsView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, category_id, created_at FROM subcategories ORDER BY name ASC")
         .map_err(|e| e.to_string())?;
     
@@ -1390,8 +3262,10 @@ pub fn get_subcategories(db: State<Database>) -> Result<Vec<Subcategory>, String
 }
 
 #[tauri::command]
-pub fn get_subcategories_by_category(category_id: String, db: State<Database>) -> Result<Vec<Subcategory>, String> {
-    let mut stmt = db.conn()
+pub fn get_subcategories_by_category(category_id: String, db: State<Database>, session: State<Session>) -> Result<Vec<Subcategory>, String> {
+    session.require(Permission::ProductsView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, category_id, created_at FROM subcategories WHERE category_id = ?1 ORDER BY name ASC")
         .map_err(|e| e.to_string())?;
     
@@ -1411,15 +3285,20 @@ pub fn get_subcategories_by_category(category_id: String, db: State<Database>) -
 }
 
 #[tauri::command]
-pub fn create_subcategory(subcategory: Subcategory, db: State<Database>) -> Result<Subcategory, String> {
+pub fn create_subcategory(subcategory: Subcategory, db: State<Database>, session: State<Session>) -> Result<Subcategory, String> {
+    let actor = session.require(Permission::ProductsEdit)?;
     let id = uuid::Uuid::new_v4().to_string();
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "INSERT INTO subcategories (id, name, category_id, created_at) VALUES (?1, ?2, ?3, ?4)",
-        params![id, subcategory.name, subcategory.category_id, created_at],
-    )
-    .map_err(|e| e.to_string())?;
+    db.with_transaction(|tx| {
+        tx.execute(
+            "INSERT INTO subcategories (id, name, category_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![id, subcategory.name, subcategory.category_id, created_at],
+        )
+        .map_err(|e| e.to_string())?;
+        let after = audit::row(tx, "subcategories", "id", &id)?;
+        audit::record(tx, &actor, audit::CREATE, "subcategory", &id, None, after)
+    })?;
     
     Ok(Subcategory {
         id: Some(id),
@@ -1430,12 +3309,14 @@ pub fn create_subcategory(subcategory: Subcategory, db: State<Database>) -> Resu
 }
 
 #[tauri::command]
-pub fn delete_subcategory(id: String, db: State<Database>) -> Result<(), String> {
-    db.conn()
-        .execute("DELETE FROM subcategories WHERE id = ?1", params![id])
-        .map_err(|e| e.to_string())?;
-    
-    Ok(())
+pub fn delete_subcategory(id: String, db: State<Database>, session: State<Session>) -> Result<(), String> {
+    let actor = session.require(Permission::ProductsEdit)?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "subcategories", "id", &id)?;
+        tx.execute("DELETE FROM subcategories WHERE id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        audit::record(tx, &actor, audit::DELETE, "subcategory", &id, before, None)
+    })
 }
 
 // ==================== СТРУКТУРЫ: СТРАНЫ ====================
@@ -1500,8 +3381,10 @@ pub struct Supplier {
 // ==================== КОМАНДЫ: СТРАНЫ ====================
 
 #[tauri::command]
-pub fn get_countries(db: State<Database>) -> Result<Vec<Country>, String> {
-    let mut stmt = db.conn()
+pub fn get_countries(db: State<Database>, session: State<Session>) -> Result<Vec<Country>, String> {
+    session.signed_in()?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, code, created_at FROM countries ORDER BY name ASC")
         .map_err(|e| e.to_string())?;
     
@@ -1523,8 +3406,10 @@ pub fn get_countries(db: State<Database>) -> Result<Vec<Country>, String> {
 // ==================== КОМАНДЫ: СЕКТОРЫ ПОСТАВЩИКОВ ====================
 
 #[tauri::command]
-pub fn get_supplier_sectors(db: State<Database>) -> Result<Vec<SupplierSector>, String> {
-    let mut stmt = db.conn()
+pub fn get_supplier_sectors(db: State<Database>, session: State<Session>) -> Result<Vec<SupplierSector>, String> {
+    session.require(Permission::SuppliersView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, created_at FROM supplier_sectors ORDER BY name ASC")
         .map_err(|e| e.to_string())?;
     
@@ -1543,15 +3428,20 @@ pub fn get_supplier_sectors(db: State<Database>) -> Result<Vec<SupplierSector>,
 }
 
 #[tauri::command]
-pub fn create_supplier_sector(sector: SupplierSector, db: State<Database>) -> Result<SupplierSector, String> {
+pub fn create_supplier_sector(sector: SupplierSector, db: State<Database>, session: State<Session>) -> Result<SupplierSector, String> {
+    let actor = session.require(Permission::SuppliersEdit)?;
     let id = uuid::Uuid::new_v4().to_string();
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "INSERT INTO supplier_sectors (id, name, created_at) VALUES (?1, ?2, ?3)",
-        params![id, sector.name, created_at],
-    )
-    .map_err(|e| e.to_string())?;
+    db.with_transaction(|tx| {
+        tx.execute(
+            "INSERT INTO supplier_sectors (id, name, created_at) VALUES (?1, ?2, ?3)",
+            params![id, sector.name, created_at],
+        )
+        .map_err(|e| e.to_string())?;
+        let after = audit::row(tx, "supplier_sectors", "id", &id)?;
+        audit::record(tx, &actor, audit::CREATE, "supplier_sector", &id, None, after)
+    })?;
     
     Ok(SupplierSector {
         id: Some(id),
@@ -1561,25 +3451,58 @@ pub fn create_supplier_sector(sector: SupplierSector, db: State<Database>) -> Re
 }
 
 #[tauri::command]
-pub fn delete_supplier_sector(id: String, db: State<Database>) -> Result<(), String> {
-    // Сначала удаляем все продукции этого сектора
-    db.conn()
-        .execute("DELETE FROM supplier_products WHERE sector_id = ?1", params![id])
+pub fn delete_supplier_sector(id: String, db: State<Database>, session: State<Session>) -> Result<(), CommandError> {
+    let actor = session.require(Permission::SuppliersEdit)?;
+    db.try_transaction(|tx| {
+        let usages = count_usages(
+            tx,
+            &[(
+                "suppliers",
+                "SELECT COUNT(*) FROM suppliers WHERE is_active = 1
+                 AND (sector_id = ?1 OR product_id IN (SELECT id FROM supplier_products WHERE sector_id = ?1))",
+            )],
+            &id,
+        )?;
+        if !usages.is_empty() {
+            return Err(CommandError::in_use("supplier_sector", &id, usages));
+        }
+        
+        let mut before = audit::row(tx, "supplier_sectors", "id", &id)?;
+        if let Some(before) = before.as_mut() {
+            before["products"] = audit::rows(tx, "SELECT * FROM supplier_products WHERE sector_id = ?1 ORDER BY name", &[&id])?.into();
+        }
+        
+        // У удалённых (неактивных) поставщиков ссылки обнуляем
+        tx.execute(
+            "UPDATE suppliers SET sector_id = NULL WHERE sector_id = ?1",
+            params![id],
+        )
         .map_err(|e| e.to_string())?;
-    
-    // Затем удаляем сам сектор
-    db.conn()
-        .execute("DELETE FROM supplier_sectors WHERE id = ?1", params![id])
+        tx.execute(
+            "UPDATE suppliers SET product_id = NULL WHERE product_id IN (SELECT id FROM supplier_products WHERE sector_id = ?1)",
+            params![id],
+        )
         .map_err(|e| e.to_string())?;
-    
-    Ok(())
+        
+        // Сначала удаляем все продукции этого сектора
+        tx.execute("DELETE FROM supplier_products WHERE sector_id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        
+        // Затем удаляем сам сектор
+        tx.execute("DELETE FROM supplier_sectors WHERE id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        audit::record(tx, &actor, audit::DELETE, "supplier_sector", &id, before, None)?;
+        Ok(())
+    })
 }
 
 // ==================== КОМАНДЫ: ПРОДУКЦИЯ ПОСТАВЩИКОВ ====================
 
 #[tauri::command]
-pub fn get_supplier_products(db: State<Database>) -> Result<Vec<SupplierProduct>, String> {
-    let mut stmt = db.conn()
+pub fn get_supplier_products(db: State<Database>, session: State<Session>) -> Result<Vec<SupplierProduct>, String> {
+    session.require(Permission::SuppliersView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, sector_id, created_at FROM supplier_products ORDER BY name ASC")
         .map_err(|e| e.to_string())?;
     
@@ -1599,8 +3522,10 @@ pub fn get_supplier_products(db: State<Database>) -> Result<Vec<SupplierProduct>
 }
 
 #[tauri::command]
-pub fn get_supplier_products_by_sector(sector_id: String, db: State<Database>) -> Result<Vec<SupplierProduct>, String> {
-    let mut stmt = db.conn()
+pub fn get_supplier_products_by_sector(sector_id: String, db: State<Database>, session: State<Session>) -> Result<Vec<SupplierProduct>, String> {
+    session.require(Permission::SuppliersView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, sector_id, created_at FROM supplier_products WHERE sector_id = ?1 ORDER BY name ASC")
         .map_err(|e| e.to_string())?;
     
@@ -1620,15 +3545,24 @@ pub fn get_supplier_products_by_sector(sector_id: String, db: State<Database>) -
 }
 
 #[tauri::command]
-pub fn create_supplier_product(product: SupplierProduct, db: State<Database>) -> Result<SupplierProduct, String> {
+pub fn create_supplier_product(
+    product: SupplierProduct,
+    db: State<Database>,
+    session: State<Session>,
+) -> Result<SupplierProduct, String> {
+    let actor = session.require(Permission::SuppliersEdit)?;
     let id = uuid::Uuid::new_v4().to_string();
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "INSERT INTO supplier_products (id, name, sector_id, created_at) VALUES (?1, ?2, ?3, ?4)",
-        params![id, product.name, product.sector_id, created_at],
-    )
-    .map_err(|e| e.to_string())?;
+    db.with_transaction(|tx| {
+        tx.execute(
+            "INSERT INTO supplier_products (id, name, sector_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![id, product.name, product.sector_id, created_at],
+        )
+        .map_err(|e| e.to_string())?;
+        let after = audit::row(tx, "supplier_products", "id", &id)?;
+        audit::record(tx, &actor, audit::CREATE, "supplier_product", &id, None, after)
+    })?;
     
     Ok(SupplierProduct {
         id: Some(id),
@@ -1639,19 +3573,35 @@ pub fn create_supplier_product(product: SupplierProduct, db: State<Database>) ->
 }
 
 #[tauri::command]
-pub fn delete_supplier_product(id: String, db: State<Database>) -> Result<(), String> {
-    db.conn()
-        .execute("DELETE FROM supplier_products WHERE id = ?1", params![id])
-        .map_err(|e| e.to_string())?;
-    
-    Ok(())
+pub fn delete_supplier_product(id: String, db: State<Database>, session: State<Session>) -> Result<(), CommandError> {
+    let actor = session.require(Permission::SuppliersEdit)?;
+    db.try_transaction(|tx| {
+        let usages = count_usages(
+            tx,
+            &[("suppliers", "SELECT COUNT(*) FROM suppliers WHERE is_active = 1 AND product_id = ?1")],
+            &id,
+        )?;
+        if !usages.is_empty() {
+            return Err(CommandError::in_use("supplier_product", &id, usages));
+        }
+        
+        let before = audit::row(tx, "supplier_products", "id", &id)?;
+        tx.execute("UPDATE suppliers SET product_id = NULL WHERE product_id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM supplier_products WHERE id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        audit::record(tx, &actor, audit::DELETE, "supplier_product", &id, before, None)?;
+        Ok(())
+    })
 }
 
 // ==================== КОМАНДЫ: ПОСТАВЩИКИ ====================
 
 #[tauri::command]
-pub fn get_suppliers(db: State<Database>) -> Result<Vec<Supplier>, String> {
-    let mut stmt = db.conn()
+pub fn get_suppliers(db: State<Database>, session: State<Session>) -> Result<Vec<Supplier>, String> {
+    session.require(Permission::SuppliersView)?;
+    let conn = db.conn()?;
+    let mut stmt = conn
         .prepare("SELECT id, name, legal_name, mb, pib, reg_number, address, city, country, phone, email, telegram, instagram, wechat, website, bank, sector_id, product_id, contact_person, contact_person_status, google_maps, notes, is_active, created_at FROM suppliers WHERE is_active = 1 ORDER BY created_at DESC")
         .map_err(|e| e.to_string())?;
     
@@ -1691,41 +3641,49 @@ pub fn get_suppliers(db: State<Database>) -> Result<Vec<Supplier>, String> {
 }
 
 #[tauri::command]
-pub fn create_supplier(supplier: Supplier, db: State<Database>) -> Result<Supplier, String> {
+pub fn create_supplier(supplier: Supplier, db: State<Database>, session: State<Session>) -> Result<Supplier, CommandError> {
+    let actor = session.require(Permission::SuppliersEdit)?;
+    validation::validate_supplier(&supplier).into_result("create_supplier")?;
     let created_at = Utc::now().to_rfc3339();
     
-    db.conn().execute(
-        "INSERT INTO suppliers (name, legal_name, mb, pib, reg_number, address, city, country, phone, email, telegram, instagram, wechat, website, bank, sector_id, product_id, contact_person, contact_person_status, google_maps, notes, is_active, created_at) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23)",
-        params![
-            supplier.name,
-            supplier.legal_name,
-            supplier.mb,
-            supplier.pib,
-            supplier.reg_number,
-            supplier.address,
-            supplier.city,
-            supplier.country,
-            supplier.phone,
-            supplier.email,
-            supplier.telegram,
-            supplier.instagram,
-            supplier.wechat,
-            supplier.website,
-            supplier.bank,
-            supplier.sector_id,
-            supplier.product_id,
-            supplier.contact_person,
-            supplier.contact_person_status,
-            supplier.google_maps,
-            supplier.notes,
-            supplier.is_active.unwrap_or(1),
-            created_at,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
-    
-    let id = db.conn().last_insert_rowid();
+    let id = db.with_transaction(|tx| {
+        tx.execute(
+            "INSERT INTO suppliers (name, legal_name, mb, pib, reg_number, address, city, country, phone, email, telegram, instagram, wechat, website, bank, sector_id, product_id, contact_person, contact_person_status, google_maps, notes, is_active, created_at) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23)",
+            params![
+                supplier.name,
+                supplier.legal_name,
+                supplier.mb,
+                supplier.pib,
+                supplier.reg_number,
+                supplier.address,
+                supplier.city,
+                supplier.country,
+                supplier.phone,
+                supplier.email,
+                supplier.telegram,
+                supplier.instagram,
+                supplier.wechat,
+                supplier.website,
+                supplier.bank,
+                supplier.sector_id,
+                supplier.product_id,
+                supplier.contact_person,
+                supplier.contact_person_status,
+                supplier.google_maps,
+                supplier.notes,
+                supplier.is_active.unwrap_or(1),
+                created_at,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        
+        let id = tx.last_insert_rowid();
+        audit::stamp(tx, "suppliers", "id", &id, &actor, audit::CREATE)?;
+        let after = audit::row(tx, "suppliers", "id", &id)?;
+        audit::record(tx, &actor, audit::CREATE, "supplier", &id.to_string(), None, after)?;
+        Ok(id)
+    })?;
     
     Ok(Supplier {
         id: Some(id),
@@ -1735,48 +3693,67 @@ pub fn create_supplier(supplier: Supplier, db: State<Database>) -> Result<Suppli
 }
 
 #[tauri::command]
-pub fn update_supplier(supplier: Supplier, db: State<Database>) -> Result<Supplier, String> {
+pub fn update_supplier(supplier: Supplier, db: State<Database>, session: State<Session>) -> Result<Supplier, CommandError> {
+    let actor = session.require(Permission::SuppliersEdit)?;
+    validation::validate_supplier(&supplier).into_result("update_supplier")?;
     let id = supplier.id.ok_or("Supplier ID is required")?;
     
-    db.conn().execute(
-        "UPDATE suppliers SET name = ?1, legal_name = ?2, mb = ?3, pib = ?4, reg_number = ?5, address = ?6, city = ?7, country = ?8, phone = ?9, email = ?10, telegram = ?11, instagram = ?12, wechat = ?13, website = ?14, bank = ?15, sector_id = ?16, product_id = ?17, contact_person = ?18, contact_person_status = ?19, google_maps = ?20, notes = ?21 WHERE id = ?22",
-        params![
-            supplier.name,
-            supplier.legal_name,
-            supplier.mb,
-            supplier.pib,
-            supplier.reg_number,
-            supplier.address,
-            supplier.city,
-            supplier.country,
-            supplier.phone,
-            supplier.email,
-            supplier.telegram,
-            supplier.instagram,
-            supplier.wechat,
-            supplier.website,
-            supplier.bank,
-            supplier.sector_id,
-            supplier.product_id,
-            supplier.contact_person,
-            supplier.contact_person_status,
-            supplier.google_maps,
-            supplier.notes,
-            id,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "suppliers", "id", &id)?;
+        tx.execute(
+            "UPDATE suppliers SET name = ?1, legal_name = ?2, mb = ?3, pib = ?4, reg_number = ?5, address = ?6, city = ?7, country = ?8, phone = ?9, email = ?10, telegram = ?11, instagram = ?12, wechat = ?13, website = ?14, bank = ?15, sector_id = ?16, product_id = ?17, contact_person = ?18, contact_person_status = ?19, google_maps = ?20, notes = ?21 WHERE id = ?22",
+            params![
+                supplier.name,
+                supplier.legal_name,
+                supplier.mb,
+                supplier.pib,
+                supplier.reg_number,
+                supplier.address,
+                supplier.city,
+                supplier.country,
+                supplier.phone,
+                supplier.email,
+                supplier.telegram,
+                supplier.instagram,
+                supplier.wechat,
+                supplier.website,
+                supplier.bank,
+                supplier.sector_id,
+                supplier.product_id,
+                supplier.contact_person,
+                supplier.contact_person_status,
+                supplier.google_maps,
+                supplier.notes,
+                id,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "suppliers", "id", &id, &actor, audit::UPDATE)?;
+        let after = audit::row(tx, "suppliers", "id", &id)?;
+        audit::record(tx, &actor, audit::UPDATE, "supplier", &id.to_string(), before, after)
+    })?;
     
     Ok(supplier)
 }
 
 #[tauri::command]
-pub fn delete_supplier(id: i64, db: State<Database>) -> Result<(), String> {
-    // Soft delete - помечаем как неактивного
-    db.conn()
-        .execute("UPDATE suppliers SET is_active = 0 WHERE id = ?1", [id])
-        .map_err(|e| e.to_string())?;
-    Ok(())
+pub fn validate_supplier(supplier: Supplier, session: State<Session>) -> Result<ValidationReport, String> {
+    session.require(Permission::SuppliersEdit)?;
+    Ok(validation::validate_supplier(&supplier))
+}
+
+#[tauri::command]
+pub fn delete_supplier(id: i64, db: State<Database>, session: State<Session>) -> Result<(), String> {
+    let actor = session.require(Permission::SuppliersEdit)?;
+    db.with_transaction(|tx| {
+        let before = audit::row(tx, "suppliers", "id", &id)?;
+        // Soft delete - помечаем как неактивного
+        tx.execute("UPDATE suppliers SET is_active = 0 WHERE id = ?1", [id])
+            .map_err(|e| e.to_string())?;
+        audit::stamp(tx, "suppliers", "id", &id, &actor, audit::UPDATE)?;
+        let after = audit::row(tx, "suppliers", "id", &id)?;
+        audit::record(tx, &actor, audit::DELETE, "supplier", &id.to_string(), before, after)
+    })
 }
 
 // ==================== HTTP ЗАПРОСЫ ДЛЯ СИНХРОНИЗАЦИИ ====================
@@ -1794,7 +3771,9 @@ pub async fn http_request(
     method: String,
     headers: std::collections::HashMap<String, String>,
     body: Option<String>,
+    session: State<'_, Session>,
 ) -> Result<HttpResponse, String> {
+    session.signed_in()?;
     // Создаем клиент с настройками таймаута и принятием всех сертификатов
     let client = reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(30))
@@ -1850,3 +3829,554 @@ pub async fn http_request(
     
     Ok(HttpResponse { status, body, ok })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Клиент 1 и товары A, B, C, BAD (id = код), на которые ссылаются документы тестов
+    fn test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO clients (id, name, mb, created_at) VALUES (1, 'Klijent', '21000001', '');
+             INSERT INTO products (id, code, name, created_at, updated_at) VALUES
+                ('A', 'A', 'A', '', ''), ('B', 'B', 'B', '', ''), ('C', 'C', 'C', '', ''), ('BAD', 'BAD', 'BAD', '', '');",
+        )
+        .unwrap();
+        conn
+    }
+
+    /// Документ на 300.00 без ПДВ по общей ставке 20%
+    fn invoice(number: &str) -> Invoice {
+        Invoice {
+            id: None,
+            invoice_number: number.to_string(),
+            document_type: "racun".to_string(),
+            client_id: Some("1".to_string()),
+            client_name: Some("Klijent".to_string()),
+            date: "2026-03-01".to_string(),
+            due_date: None,
+            total: 360.0,
+            status: "draft".to_string(),
+            notes: None,
+            created_at: None,
+            paid: None,
+            delivered: None,
+            currency: None,
+            exchange_rate: None,
+            exchange_rate_date: None,
+            subtotal: None,
+            vat_total: None,
+            vat_rate: None,
+            vat_exemption_code: None,
+            source_document_id: None,
+            amount_paid: None,
+            balance: None,
+            payment_status: None,
+            sef_invoice_id: None,
+            sef_status: None,
+            sef_sent_at: None,
+            sef_error: None,
+            created_by: None,
+            modified_by: None,
+        }
+    }
+
+    fn item(product_id: &str, quantity: f64, price: f64) -> InvoiceItem {
+        InvoiceItem {
+            id: None,
+            invoice_id: String::new(),
+            product_id: product_id.to_string(),
+            product_name: format!("Product {}", product_id),
+            quantity,
+            unit_weight_g: None,
+            price,
+            total: quantity * price,
+            discount_pct: None,
+            vat_rate: None,
+            vat_exemption_code: None,
+            vat_amount: None,
+            credited_item_id: None,
+        }
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_insert_invoice_rolls_back_on_failed_item() {
+        let conn = test_conn();
+        conn.execute_batch(
+            "CREATE TRIGGER reject_bad BEFORE INSERT ON invoice_items
+             WHEN NEW.product_id = 'BAD' BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+        )
+        .unwrap();
+
+        let tx = conn.unchecked_transaction().unwrap();
+        let err = insert_invoice(&tx, &invoice("1/2026"), &[item("A", 1.0, 100.0), item("BAD", 2.0, 100.0)])
+            .unwrap_err();
+        drop(tx);
+
+        assert!(err.starts_with("Item 2 (Product BAD)"), "{}", err);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM invoices"), 0);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM invoice_items"), 0);
+    }
+
+    #[test]
+    fn test_insert_invoice_resolves_client_and_product_refs() {
+        let conn = test_conn();
+        conn.execute("UPDATE products SET internal_code = 'INT-A' WHERE id = 'A'", []).unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        // UI присылает МБ клиента и внутренний код товара
+        let mut header = invoice("1/2026");
+        header.client_id = Some("21000001".to_string());
+        let id = insert_invoice(&tx, &header, &[item("INT-A", 3.0, 100.0)]).unwrap();
+
+        let stored: (String, String) = tx
+            .query_row(
+                "SELECT i.client_id, it.product_id FROM invoices i JOIN invoice_items it ON it.invoice_id = i.id WHERE i.id = ?1",
+                [&id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(stored, ("1".to_string(), "A".to_string()));
+        // Наружу по-прежнему отдаётся код товара
+        let items = load_invoice_with_items(&tx, &id).unwrap().unwrap().items;
+        assert_eq!(items[0].product_id, "INT-A");
+
+        let usages = count_usages(
+            &tx,
+            &[
+                ("invoices", "SELECT COUNT(*) FROM invoices WHERE client_id = CAST(?1 AS TEXT)"),
+                ("deliveries", "SELECT COUNT(*) FROM deliveries WHERE client_id = CAST(?1 AS TEXT)"),
+            ],
+            &1,
+        )
+        .unwrap();
+        let in_use = CommandError::in_use("client", 1, usages);
+        assert_eq!(in_use.to_string(), "client 1 is in use by 1 invoices");
+
+        // Неизвестные ссылки — по правилам m012: товар-заглушка и пустой клиент
+        let mut unknown = invoice("2/2026");
+        unknown.client_id = Some("99999999".to_string());
+        let id = insert_invoice(&tx, &unknown, &[item("NOPE", 3.0, 100.0)]).unwrap();
+        let stored: (Option<String>, String, i64) = tx
+            .query_row(
+                "SELECT i.client_id, p.name, p.is_active FROM invoices i
+                 JOIN invoice_items it ON it.invoice_id = i.id JOIN products p ON p.id = it.product_id
+                 WHERE i.id = ?1",
+                [&id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(stored, (None, "Product NOPE".to_string(), 0));
+        let items = load_invoice_with_items(&tx, &id).unwrap().unwrap().items;
+        assert_eq!(items[0].product_id, "NOPE");
+    }
+
+    #[test]
+    fn test_pdf_batch_filters_and_archives() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        let racun_id = insert_invoice(&tx, &invoice("1/2026"), &[item("A", 3.0, 100.0)]).unwrap();
+        let mut delivery = invoice("o1/2026");
+        delivery.document_type = "delivery".to_string();
+        delivery.date = "2026-04-02".to_string();
+        let delivery_id = insert_invoice(&tx, &delivery, &[item("A", 3.0, 100.0)]).unwrap();
+        tx.commit().unwrap();
+
+        let by_type = PdfBatchRequest {
+            document_type: Some("otpremnica".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(pdf_batch_ids(&conn, &by_type).unwrap(), vec![delivery_id.clone()]);
+        let by_date = PdfBatchRequest {
+            date_to: Some("2026-03-31".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(pdf_batch_ids(&conn, &by_date).unwrap(), vec![racun_id.clone()]);
+
+        let dir = std::env::temp_dir().join(format!("srecha-pdf-{}", uuid::Uuid::new_v4()));
+        let path = invoice_pdf(&conn, &racun_id, &dir).unwrap();
+        assert!(path.ends_with("invoices/racun/2026/03/1-2026.pdf"), "{}", path);
+        assert!(std::fs::read(&path).unwrap().starts_with(b"%PDF"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invoice_ips_qr() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        let id = insert_invoice(&tx, &invoice("1/2026"), &[item("A", 3.0, 100.0)]).unwrap();
+        tx.commit().unwrap();
+
+        let qr = invoice_ips_qr(&conn, &id).unwrap();
+        assert!(qr.payload.starts_with("K:PR|V:01|C:1|R:190000000008554029|N:"), "{}", qr.payload);
+        assert!(qr.payload.contains("|I:RSD360,00|P:Klijent|SF:221|"), "{}", qr.payload);
+        assert!(qr.png_data_url.starts_with("data:image/png;base64,"));
+
+        // После частичной оплаты QR — на остаток, после полной — не выдаётся
+        let payment = |invoice_id: &str, amount: f64| NewPayment {
+            invoice_id: invoice_id.to_string(),
+            date: "2026-03-05".to_string(),
+            amount,
+            currency: None,
+            method: None,
+            reference: None,
+            note: None,
+        };
+        let tx = conn.unchecked_transaction().unwrap();
+        payments::record_payment(&tx, &payment(&id, 100.0)).unwrap();
+        tx.commit().unwrap();
+        let qr = invoice_ips_qr(&conn, &id).unwrap();
+        assert!(qr.payload.contains("|I:RSD260,00|"), "{}", qr.payload);
+        let tx = conn.unchecked_transaction().unwrap();
+        payments::record_payment(&tx, &payment(&id, 260.0)).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(invoice_ips_qr(&conn, &id).unwrap_err(), "Invoice 1/2026 is fully paid");
+    }
+
+    #[test]
+    fn test_sync_invoice_items_diffs_against_stored() {
+        let conn = test_conn();
+        conn.execute_batch("UPDATE products SET weight = 100.0 WHERE id = 'A'; UPDATE products SET weight = 50.0 WHERE id = 'C';")
+            .unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        let id = insert_invoice(&tx, &invoice("1/2026"), &[item("A", 1.0, 100.0), item("B", 2.0, 100.0)]).unwrap();
+        // Вес товара изменился после выставления документа — снимок в строке должен остаться прежним
+        tx.execute("UPDATE products SET weight = 250.0 WHERE id = 'A'", []).unwrap();
+
+        let stored = load_invoice_with_items(&tx, &id).unwrap().unwrap().items;
+        let line_a = stored.iter().find(|i| i.product_id == "A").unwrap();
+        let line_b = stored.iter().find(|i| i.product_id == "B").unwrap();
+
+        let mut changed_a = item("A", 3.0, 100.0);
+        changed_a.id = line_a.id.clone();
+        let items = [changed_a, item("C", 1.0, 40.0)];
+        let mut header = invoice("1/2026");
+        header.total = 408.0;
+        let totals = price_document(&header, &items).unwrap();
+        sync_invoice_items(&tx, &id, &items, &totals).unwrap();
+
+        let after = load_invoice_with_items(&tx, &id).unwrap().unwrap().items;
+        assert_eq!(after.len(), 2);
+        assert!(after.iter().all(|i| i.id != line_b.id));
+        let a = after.iter().find(|i| i.product_id == "A").unwrap();
+        assert_eq!(a.id, line_a.id);
+        assert_eq!(a.quantity, 3.0);
+        assert_eq!(a.total, 300.0);
+        assert_eq!(a.unit_weight_g, Some(100.0));
+        let c = after.iter().find(|i| i.product_id == "C").unwrap();
+        assert_eq!(c.unit_weight_g, Some(50.0));
+        assert_eq!(load_tax_breakdown(&tx, &id).unwrap()[0].taxable_amount, 340.0);
+    }
+
+    #[test]
+    fn test_insert_invoice_writes_header_and_items() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        let id = insert_invoice(&tx, &invoice("1/2026"), &[item("A", 1.0, 100.0), item("B", 2.0, 100.0)]).unwrap();
+        tx.commit().unwrap();
+
+        let items: i64 = conn
+            .query_row("SELECT COUNT(*) FROM invoice_items WHERE invoice_id = ?1", [&id], |row| row.get(0))
+            .unwrap();
+        assert_eq!(items, 2);
+    }
+
+    #[test]
+    fn test_insert_invoice_stores_server_totals_and_breakdown() {
+        let conn = test_conn();
+        let mut header = invoice("1/2026");
+        header.vat_rate = Some(pricing::VAT_REDUCED);
+        header.total = 238.0;
+        let mut discounted = item("A", 2.0, 100.0);
+        discounted.discount_pct = Some(10.0);
+        discounted.total = 180.0;
+        let mut standard = item("B", 1.0, 20.0);
+        standard.vat_rate = Some(pricing::VAT_STANDARD);
+        // 180 * 1.1 + 20 * 1.2 = 222.00
+        let tx = conn.unchecked_transaction().unwrap();
+        let err = insert_invoice(&tx, &header, &[discounted, standard]).unwrap_err();
+        assert!(err.starts_with("Invoice total 238.00"), "{}", err);
+        drop(tx);
+
+        let mut discounted = item("A", 2.0, 100.0);
+        discounted.discount_pct = Some(10.0);
+        discounted.total = 180.0;
+        let mut standard = item("B", 1.0, 20.0);
+        standard.vat_rate = Some(pricing::VAT_STANDARD);
+        header.total = 222.0;
+        let tx = conn.unchecked_transaction().unwrap();
+        let id = insert_invoice(&tx, &header, &[discounted, standard]).unwrap();
+        tx.commit().unwrap();
+
+        let stored = load_invoice_with_items(&conn, &id).unwrap().unwrap();
+        assert_eq!(stored.invoice.subtotal, Some(200.0));
+        assert_eq!(stored.invoice.vat_total, Some(22.0));
+        assert_eq!(stored.invoice.total, 222.0);
+        let rates: Vec<f64> = stored.tax_breakdown.iter().map(|b| b.vat_rate).collect();
+        assert_eq!(rates, vec![20.0, 10.0]);
+        assert_eq!(stored.tax_breakdown[1].vat_amount, 18.0);
+    }
+
+    #[test]
+    fn test_price_document_accepts_ui_payload() {
+        // Так sync-service.js сохраняет предрачун с формой по умолчанию (ПДВ 0%)
+        let invoice: Invoice = serde_json::from_value(serde_json::json!({
+            "invoiceNumber": "p3/2026",
+            "documentType": "predracun",
+            "clientId": "21000001",
+            "clientName": "Klijent",
+            "date": "2026-03-01",
+            "dueDate": null,
+            "total": 449.5,
+            "status": "confirmed",
+            "notes": null,
+            "paid": false,
+            "delivered": false,
+            "vatRate": 0,
+            "vatExemptionCode": "PDV-RS-33"
+        }))
+        .unwrap();
+        let items: Vec<InvoiceItem> = serde_json::from_value(serde_json::json!([
+            { "invoiceId": "", "productId": "A", "productName": "A", "quantity": 3, "price": 99.9, "discountPct": 0, "total": 299.70000000000005 },
+            { "invoiceId": "", "productId": "", "productName": "Usluga", "quantity": 1, "price": 166.44, "discountPct": 10, "total": 149.796 }
+        ]))
+        .unwrap();
+
+        let totals = price_document(&invoice, &items).unwrap();
+        assert_eq!(totals.total, 449.5);
+        assert_eq!(totals.breakdown.len(), 1);
+        assert_eq!(totals.breakdown[0].vat_exemption_code.as_deref(), Some("PDV-RS-33"));
+    }
+
+    #[test]
+    fn test_convert_predracun_copies_items_and_links_documents() {
+        let conn = test_conn();
+        let mut proforma = invoice("");
+        proforma.document_type = "predracun".to_string();
+        let tx = conn.unchecked_transaction().unwrap();
+        let source_id = insert_invoice(&tx, &proforma, &[item("A", 1.0, 100.0), item("B", 2.0, 100.0)]).unwrap();
+
+        let racun_id = convert_predracun(&tx, &source_id, "racun", Some("2026-03-05"), None).unwrap();
+        let otpremnica_id = convert_predracun(&tx, &source_id, "otpremnica", Some("2026-03-05"), None).unwrap();
+        assert!(convert_predracun(&tx, &source_id, "racun", None, None)
+            .unwrap_err()
+            .contains("already converted"));
+        assert!(convert_predracun(&tx, &racun_id, "delivery", None, None).is_err());
+        tx.commit().unwrap();
+
+        let racun = load_invoice_with_items(&conn, &racun_id).unwrap().unwrap();
+        assert_eq!(racun.invoice.invoice_number, "1/2026");
+        assert_eq!(racun.invoice.client_name.as_deref(), Some("Klijent"));
+        assert_eq!(racun.invoice.total, 360.0);
+        assert_eq!(racun.items.len(), 2);
+        assert_eq!(racun.source_documents.len(), 1);
+        assert_eq!(racun.source_documents[0].invoice_number, "p1/2026");
+
+        let otpremnica = load_invoice_with_items(&conn, &otpremnica_id).unwrap().unwrap();
+        assert_eq!(otpremnica.invoice.document_type, "delivery");
+        assert_eq!(otpremnica.invoice.invoice_number, "o1/2026");
+
+        let source = load_invoice_with_items(&conn, &source_id).unwrap().unwrap();
+        let derived: Vec<&str> = source.derived_documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(derived, vec![racun_id.as_str(), otpremnica_id.as_str()]);
+    }
+
+    #[test]
+    fn test_credit_note_partial_and_storno() {
+        let conn = test_conn();
+        let mut racun = invoice("");
+        racun.status = "confirmed".to_string();
+        let tx = conn.unchecked_transaction().unwrap();
+        let racun_id = insert_invoice(&tx, &racun, &[item("A", 1.0, 100.0), item("B", 2.0, 100.0)]).unwrap();
+        let lines = load_invoice_with_items(&tx, &racun_id).unwrap().unwrap().items;
+        let line_b = lines.iter().find(|i| i.product_id == "B").unwrap().id.clone().unwrap();
+
+        let partial = CreditNoteRequest {
+            original_invoice_id: racun_id.clone(),
+            lines: vec![CreditNoteLine { item_id: line_b.clone(), quantity: 1.5 }],
+            reason: Some("Povrat robe".to_string()),
+            date: Some("2026-03-10".to_string()),
+        };
+        let partial_id = insert_credit_note(&tx, &partial).unwrap();
+        let note = load_invoice_with_items(&tx, &partial_id).unwrap().unwrap();
+        assert_eq!(note.invoice.invoice_number, "KO-1/2026");
+        assert_eq!(note.invoice.total, -180.0);
+        assert_eq!(note.items[0].quantity, -1.5);
+        assert_eq!(note.items[0].credited_item_id.as_deref(), Some(line_b.as_str()));
+        assert_eq!(note.source_documents[0].id, racun_id);
+
+        let too_much = CreditNoteRequest {
+            original_invoice_id: racun_id.clone(),
+            lines: vec![CreditNoteLine { item_id: line_b, quantity: 1.0 }],
+            reason: None,
+            date: None,
+        };
+        assert!(insert_credit_note(&tx, &too_much).unwrap_err().contains("remaining 0.5"));
+
+        // Сторно: кредитуется весь остаток
+        let storno = CreditNoteRequest {
+            original_invoice_id: racun_id.clone(),
+            lines: vec![],
+            reason: None,
+            date: Some("2026-03-11".to_string()),
+        };
+        let storno_id = insert_credit_note(&tx, &storno).unwrap();
+        let storno_note = load_invoice_with_items(&tx, &storno_id).unwrap().unwrap();
+        assert_eq!(storno_note.invoice.total, -180.0);
+        assert!(insert_credit_note(&tx, &storno).unwrap_err().contains("fully credited"));
+
+        let net: f64 = tx
+            .query_row("SELECT SUM(total) FROM invoices", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(net, 0.0);
+
+        assert!(ensure_deletable(&tx, &racun_id).unwrap_err().contains("credit note"));
+        assert!(ensure_deletable(&tx, &storno_id).is_err());
+    }
+
+    #[test]
+    fn test_sef_result_is_audited() {
+        let conn = test_conn();
+        let actor = audit::Actor { user_id: None, username: "prodaja".to_string() };
+        let mut racun = invoice("");
+        racun.status = "confirmed".to_string();
+        let tx = conn.unchecked_transaction().unwrap();
+        let id = insert_invoice(&tx, &racun, &[item("A", 3.0, 100.0)]).unwrap();
+        let request = SalesInvoiceRequest {
+            invoice_id: id.clone(),
+            invoice_number: "1/2026".to_string(),
+            request_id: "req-1".to_string(),
+            ubl_xml: String::new(),
+        };
+
+        let rejected = EfakturaError::Rejected { status: 400, message: "Neispravan PIB".to_string() };
+        let failure = store_sef_result(&tx, &actor, &request, Err(rejected)).unwrap();
+        assert!(failure.is_some());
+        let response = SalesInvoiceResponse { invoice_id: None, purchase_invoice_id: None, sales_invoice_id: Some(77) };
+        assert!(store_sef_result(&tx, &actor, &request, Ok(response)).unwrap().is_none());
+
+        let filter = AuditFilter { entity: Some("invoice".to_string()), entity_id: Some(id), ..Default::default() };
+        let entries = audit::load(&tx, &filter).unwrap();
+        let sef: Vec<&AuditEntry> = entries.iter().filter(|e| e.username == "prodaja").collect();
+        assert_eq!(sef.len(), 2);
+        assert!(sef.iter().any(|e| e.diff["sef_error"]["new"].as_str().is_some_and(|s| s.contains("Neispravan PIB"))));
+        assert!(sef.iter().any(|e| e.diff["sef_invoice_id"]["new"] == 77));
+    }
+
+    #[test]
+    fn test_client_profile_reads_only_own_invoices() {
+        let conn = test_conn();
+        conn.execute("INSERT INTO clients (id, name, mb, created_at) VALUES (2, 'Drugi', '21000002', '')", [])
+            .unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        let own = insert_invoice(&tx, &invoice("1/2026"), &[item("A", 3.0, 100.0)]).unwrap();
+        let mut foreign = invoice("2/2026");
+        foreign.client_id = Some("2".to_string());
+        foreign.client_name = Some("Drugi".to_string());
+        let foreign = insert_invoice(&tx, &foreign, &[item("A", 3.0, 100.0)]).unwrap();
+        tx.commit().unwrap();
+
+        let session = Session::default();
+        session.sign_in(audit::Actor { user_id: None, username: "kupac".to_string() }, Role::Client, Some(1));
+        let scope = session.require_scoped(Permission::InvoicesView).unwrap();
+
+        let visible: Vec<String> = list_invoices(&conn, scope).unwrap().into_iter().filter_map(|i| i.id).collect();
+        assert_eq!(visible, vec![own.clone()]);
+        assert!(ensure_in_scope(&conn, &own, scope).is_ok());
+        assert_eq!(
+            ensure_in_scope(&conn, &foreign, scope).unwrap_err(),
+            format!("Invoice {} not found", foreign)
+        );
+        // Остальные роли видят всех клиентов
+        assert_eq!(list_invoices(&conn, None).unwrap().len(), 2);
+        assert!(ensure_in_scope(&conn, &foreign, None).is_ok());
+    }
+
+    #[test]
+    fn test_credit_note_requires_valid_original() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        for status in ["draft", "cancelled", "rejected"] {
+            let mut racun = invoice("");
+            racun.status = status.to_string();
+            let racun_id = insert_invoice(&tx, &racun, &[item("A", 3.0, 100.0)]).unwrap();
+            let request = CreditNoteRequest {
+                original_invoice_id: racun_id,
+                lines: vec![],
+                reason: None,
+                date: None,
+            };
+            let err = insert_credit_note(&tx, &request).unwrap_err();
+            assert!(err.ends_with(&format!("with status {}", status)), "{}", err);
+        }
+        let credit_notes: i64 = tx
+            .query_row("SELECT COUNT(*) FROM invoices WHERE document_type = 'knjizno_odobrenje'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(credit_notes, 0);
+    }
+
+    #[test]
+    fn test_issued_racun_cannot_be_turned_deletable() {
+        let conn = test_conn();
+        let actor = audit::Actor { user_id: None, username: "test".to_string() };
+        let mut racun = invoice("");
+        racun.status = "confirmed".to_string();
+        let tx = conn.unchecked_transaction().unwrap();
+        let id = insert_invoice(&tx, &racun, &[item("A", 3.0, 100.0)]).unwrap();
+
+        // Обход запрета удаления: перевести в черновик или в предрачун, затем удалить
+        let err = set_invoice_status(&tx, &actor, &id, "draft").unwrap_err();
+        assert!(err.contains("status cannot be changed"), "{}", err);
+        assert!(ensure_deletable(&tx, &id).is_err());
+
+        let mut as_predracun = invoice("1/2026");
+        as_predracun.status = "confirmed".to_string();
+        as_predracun.document_type = "predracun".to_string();
+        let err = update_invoice_header(&tx, &actor, &id, &as_predracun).unwrap_err();
+        assert!(err.contains("document type cannot be changed"), "{}", err);
+        assert!(ensure_deletable(&tx, &id).is_err());
+
+        // Примечание выданного документа по-прежнему правится
+        let mut with_notes = invoice("1/2026");
+        with_notes.status = "confirmed".to_string();
+        with_notes.notes = Some("Dostava petkom".to_string());
+        update_invoice_header(&tx, &actor, &id, &with_notes).unwrap();
+        let stored = load_invoice(&tx, &id).unwrap().unwrap();
+        assert_eq!((stored.document_type.as_str(), stored.status.as_str()), ("racun", "confirmed"));
+        assert_eq!(stored.notes.as_deref(), Some("Dostava petkom"));
+    }
+
+    #[test]
+    fn test_issued_racun_header_is_locked() {
+        let conn = test_conn();
+        let actor = audit::Actor { user_id: None, username: "test".to_string() };
+        let mut racun = invoice("");
+        racun.status = "confirmed".to_string();
+        let tx = conn.unchecked_transaction().unwrap();
+        let id = insert_invoice(&tx, &racun, &[item("A", 3.0, 100.0)]).unwrap();
+
+        let mut edited = invoice("1/2026");
+        edited.status = "confirmed".to_string();
+        edited.client_id = None;
+        edited.client_name = Some("Drugi kupac".to_string());
+        edited.date = "2026-04-15".to_string();
+        let err = update_invoice_header(&tx, &actor, &id, &edited).unwrap_err();
+        assert_eq!(
+            err,
+            "Invoice 1/2026 is issued and its client, date cannot be changed; create a credit note instead"
+        );
+        let mut renumbered = invoice("2/2026");
+        renumbered.status = "confirmed".to_string();
+        renumbered.currency = Some("EUR".to_string());
+        let err = update_invoice_header(&tx, &actor, &id, &renumbered).unwrap_err();
+        assert!(err.contains("its number, currency cannot"), "{}", err);
+
+        let stored = load_invoice(&tx, &id).unwrap().unwrap();
+        assert_eq!((stored.client_name, stored.date), (racun.client_name, racun.date));
+    }
+}
@@ -0,0 +1,387 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::ubl::UblParty;
+use crate::validation;
+
+/// Реквизиты нашей фирмы (издавалац рачуна). Одна строка `company_profile` (id = 1)
+/// плюс текущие рачуни в `company_bank_accounts`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompanyProfile {
+    pub name: String,
+    /// Полное юридическое имя из АПР; если нет — `name`
+    pub legal_name: Option<String>,
+    pub pib: String,
+    pub mb: String,
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub postal_code: Option<String>,
+    /// ISO 3166-1 alpha-2
+    pub country_code: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub website: Option<String>,
+    /// Логотип как data URL (data:image/png;base64,...)
+    pub logo: Option<String>,
+    /// Текст в подвале документов
+    pub invoice_footer: Option<String>,
+    #[serde(default)]
+    pub bank_accounts: Vec<BankAccount>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BankAccount {
+    /// None — новый счёт
+    pub id: Option<i64>,
+    /// Формат 3-13-2: 190-0000000085540-29
+    pub account_number: String,
+    pub bank_name: Option<String>,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+fn default_currency() -> String {
+    "RSD".to_string()
+}
+
+impl CompanyProfile {
+    pub fn default_bank_account(&self) -> Option<&BankAccount> {
+        self.bank_accounts
+            .iter()
+            .find(|a| a.is_default)
+            .or_else(|| self.bank_accounts.first())
+    }
+
+    /// Счёт для документа в валюте `currency`: основной, если валюта совпадает,
+    /// иначе первый счёт в этой валюте, иначе всё равно основной.
+    pub fn bank_account_for(&self, currency: &str) -> Option<&BankAccount> {
+        let default = self.default_bank_account()?;
+        if default.currency.eq_ignore_ascii_case(currency) {
+            return Some(default);
+        }
+        Some(
+            self.bank_accounts
+                .iter()
+                .find(|a| a.currency.eq_ignore_ascii_case(currency))
+                .unwrap_or(default),
+        )
+    }
+
+    pub fn to_ubl_party(&self) -> UblParty {
+        UblParty {
+            name: self.name.clone(),
+            registration_name: self.legal_name.clone(),
+            pib: Some(self.pib.clone()),
+            mb: Some(self.mb.clone()),
+            street: self.street.clone(),
+            city: self.city.clone(),
+            postal_code: self.postal_code.clone(),
+            country_code: self.country_code.clone(),
+        }
+    }
+}
+
+/// Приводит номер текущего рачуна к виду 3-13-2 (средняя часть дополняется нулями слева)
+/// и проверяет контрольный номер по ISO 7064 MOD 97-10. Принимает и 18 цифр подряд.
+pub fn normalize_account_number(value: &str) -> Option<String> {
+    let value = value.trim();
+    let (bank, middle, control) = if value.contains('-') {
+        let parts: Vec<&str> = value.split('-').map(str::trim).collect();
+        match parts.as_slice() {
+            [bank, middle, control] => (bank.to_string(), middle.to_string(), control.to_string()),
+            _ => return None,
+        }
+    } else {
+        let digits: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.len() != 18 {
+            return None;
+        }
+        (digits[..3].to_string(), digits[3..16].to_string(), digits[16..].to_string())
+    };
+    let all_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if bank.len() != 3 || control.len() != 2 || middle.len() > 13 || !all_digits(&bank) || !all_digits(&middle) || !all_digits(&control) {
+        return None;
+    }
+    let middle = format!("{:0>13}", middle);
+    let remainder = format!("{}{}{}", bank, middle, control)
+        .chars()
+        .fold(0u32, |acc, c| (acc * 10 + c.to_digit(10).unwrap_or(0)) % 97);
+    if remainder != 1 {
+        return None;
+    }
+    Some(format!("{}-{}-{}", bank, middle, control))
+}
+
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(str::to_string)
+}
+
+/// Проверяет и нормализует профиль перед сохранением.
+pub fn validate_profile(profile: &CompanyProfile) -> Result<CompanyProfile, String> {
+    let name = profile.name.trim();
+    if name.is_empty() {
+        return Err("Company name is required".to_string());
+    }
+    let pib = profile.pib.trim();
+    if pib.len() != 9 || !pib.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid company PIB {}: expected 9 digits", profile.pib));
+    }
+    if !validation::pib_checksum_ok(pib) {
+        return Err(format!("Invalid company PIB {}: check digit mismatch", profile.pib));
+    }
+    let mb = profile.mb.trim();
+    if mb.len() != 8 || !mb.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid company MB {}: expected 8 digits", profile.mb));
+    }
+    let country_code = profile.country_code.trim().to_uppercase();
+    if country_code.len() != 2 || !country_code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("Invalid country code {}", profile.country_code));
+    }
+    if let Some(logo) = non_empty(&profile.logo) {
+        if !logo.starts_with("data:image/") {
+            return Err("Logo must be an image data URL".to_string());
+        }
+    }
+
+    let mut bank_accounts = Vec::with_capacity(profile.bank_accounts.len());
+    for account in &profile.bank_accounts {
+        let account_number = normalize_account_number(&account.account_number)
+            .ok_or_else(|| format!("Invalid bank account number {}", account.account_number))?;
+        if bank_accounts.iter().any(|a: &BankAccount| a.account_number == account_number) {
+            return Err(format!("Bank account {} is listed twice", account_number));
+        }
+        let currency = account.currency.trim().to_uppercase();
+        if currency.len() != 3 {
+            return Err(format!("Invalid currency {} for bank account {}", account.currency, account_number));
+        }
+        bank_accounts.push(BankAccount {
+            id: account.id,
+            account_number,
+            bank_name: non_empty(&account.bank_name),
+            currency,
+            is_default: account.is_default,
+        });
+    }
+    match bank_accounts.iter().filter(|a| a.is_default).count() {
+        0 => {
+            if let Some(first) = bank_accounts.first_mut() {
+                first.is_default = true;
+            }
+        }
+        1 => {}
+        _ => return Err("Only one bank account can be the default".to_string()),
+    }
+
+    Ok(CompanyProfile {
+        name: name.to_string(),
+        legal_name: non_empty(&profile.legal_name),
+        pib: pib.to_string(),
+        mb: mb.to_string(),
+        street: non_empty(&profile.street),
+        city: non_empty(&profile.city),
+        postal_code: non_empty(&profile.postal_code),
+        country_code,
+        email: non_empty(&profile.email),
+        phone: non_empty(&profile.phone),
+        website: non_empty(&profile.website),
+        logo: non_empty(&profile.logo),
+        invoice_footer: non_empty(&profile.invoice_footer),
+        bank_accounts,
+        updated_at: profile.updated_at.clone(),
+    })
+}
+
+pub fn load_profile(conn: &Connection) -> Result<CompanyProfile, String> {
+    let profile = conn
+        .query_row(
+            "SELECT name, legal_name, pib, mb, street, city, postal_code, country_code,
+                    email, phone, website, logo, invoice_footer, updated_at
+             FROM company_profile WHERE id = 1",
+            [],
+            |row| {
+                Ok(CompanyProfile {
+                    name: row.get(0)?,
+                    legal_name: row.get(1)?,
+                    pib: row.get(2)?,
+                    mb: row.get(3)?,
+                    street: row.get(4)?,
+                    city: row.get(5)?,
+                    postal_code: row.get(6)?,
+                    country_code: row.get(7)?,
+                    email: row.get(8)?,
+                    phone: row.get(9)?,
+                    website: row.get(10)?,
+                    logo: row.get(11)?,
+                    invoice_footer: row.get(12)?,
+                    bank_accounts: Vec::new(),
+                    updated_at: row.get(13)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Company profile is not set up".to_string())?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, account_number, bank_name, currency, is_default
+             FROM company_bank_accounts ORDER BY is_default DESC, id",
+        )
+        .map_err(|e| e.to_string())?;
+    let bank_accounts = stmt
+        .query_map([], |row| {
+            Ok(BankAccount {
+                id: Some(row.get(0)?),
+                account_number: row.get(1)?,
+                bank_name: row.get(2)?,
+                currency: row.get(3)?,
+                is_default: row.get::<_, i64>(4)? != 0,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(CompanyProfile { bank_accounts, ..profile })
+}
+
+/// Сохраняет профиль целиком: счета, которых нет в списке, удаляются.
+pub fn save_profile(tx: &Transaction, profile: &CompanyProfile) -> Result<CompanyProfile, String> {
+    let profile = validate_profile(profile)?;
+    tx.execute(
+        "INSERT INTO company_profile (id, name, legal_name, pib, mb, street, city, postal_code, country_code,
+                                      email, phone, website, logo, invoice_footer, updated_at)
+         VALUES (1, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+         ON CONFLICT(id) DO UPDATE SET
+            name = excluded.name, legal_name = excluded.legal_name, pib = excluded.pib, mb = excluded.mb,
+            street = excluded.street, city = excluded.city, postal_code = excluded.postal_code,
+            country_code = excluded.country_code, email = excluded.email, phone = excluded.phone,
+            website = excluded.website, logo = excluded.logo, invoice_footer = excluded.invoice_footer,
+            updated_at = excluded.updated_at",
+        params![
+            profile.name,
+            profile.legal_name,
+            profile.pib,
+            profile.mb,
+            profile.street,
+            profile.city,
+            profile.postal_code,
+            profile.country_code,
+            profile.email,
+            profile.phone,
+            profile.website,
+            profile.logo,
+            profile.invoice_footer,
+            Utc::now().to_rfc3339(),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    tx.execute("DELETE FROM company_bank_accounts", []).map_err(|e| e.to_string())?;
+    for account in &profile.bank_accounts {
+        tx.execute(
+            "INSERT INTO company_bank_accounts (id, account_number, bank_name, currency, is_default)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                account.id,
+                account.account_number,
+                account.bank_name,
+                account.currency,
+                account.is_default as i64
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    load_profile(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        conn
+    }
+
+    fn account(number: &str, currency: &str, is_default: bool) -> BankAccount {
+        BankAccount {
+            id: None,
+            account_number: number.to_string(),
+            bank_name: None,
+            currency: currency.to_string(),
+            is_default,
+        }
+    }
+
+    #[test]
+    fn test_normalize_account_number() {
+        assert_eq!(
+            normalize_account_number("190-85540-29").as_deref(),
+            Some("190-0000000085540-29")
+        );
+        assert_eq!(
+            normalize_account_number("190000000008554029").as_deref(),
+            Some("190-0000000085540-29")
+        );
+        // неверный контрольный номер
+        assert_eq!(normalize_account_number("190-0000000085540-28"), None);
+        assert_eq!(normalize_account_number("190-85540"), None);
+    }
+
+    #[test]
+    fn test_migration_seeds_current_company() {
+        let conn = test_conn();
+        let profile = load_profile(&conn).unwrap();
+        assert_eq!(profile.pib, "114407658");
+        assert_eq!(profile.mb, "22019309");
+        let account = profile.default_bank_account().unwrap();
+        assert_eq!(account.account_number, "190-0000000085540-29");
+        assert!(account.is_default);
+    }
+
+    #[test]
+    fn test_save_profile_replaces_bank_accounts() {
+        let mut conn = test_conn();
+        let mut profile = load_profile(&conn).unwrap();
+        profile.invoice_footer = Some("  Hvala na saradnji  ".to_string());
+        profile.bank_accounts = vec![
+            account("190-85540-29", "rsd", false),
+            account("160000000001234595", "EUR", false),
+        ];
+
+        let tx = conn.transaction().unwrap();
+        let saved = save_profile(&tx, &profile).unwrap();
+        tx.commit().unwrap();
+
+        assert_eq!(saved.invoice_footer.as_deref(), Some("Hvala na saradnji"));
+        assert_eq!(saved.bank_accounts.len(), 2);
+        // без явного основного счёта основным становится первый
+        assert_eq!(saved.default_bank_account().unwrap().account_number, "190-0000000085540-29");
+        assert_eq!(saved.bank_account_for("EUR").unwrap().account_number, "160-0000000012345-95");
+        assert_eq!(saved.bank_account_for("USD").unwrap().currency, "RSD");
+    }
+
+    #[test]
+    fn test_two_default_accounts_are_rejected() {
+        let conn = test_conn();
+        let mut profile = load_profile(&conn).unwrap();
+        profile.bank_accounts = vec![
+            account("190-85540-29", "RSD", true),
+            account("160-12345-95", "EUR", true),
+        ];
+        assert_eq!(
+            validate_profile(&profile).unwrap_err(),
+            "Only one bank account can be the default"
+        );
+    }
+}
@@ -1,424 +1,240 @@
-use rusqlite::{Connection, Result};
-use std::path::PathBuf;
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, Result, Transaction, TransactionBehavior};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use crate::encryption::{self, EncryptionStatus};
+use crate::migrations;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
 use std::fs;
 
+/// Соединение, взятое из пула. Разыменовывается в `rusqlite::Connection`.
+pub type DbConn = PooledConnection<SqliteConnectionManager>;
+
+// Сколько ждать снятия блокировки другим соединением, прежде чем вернуть SQLITE_BUSY
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const POOL_MAX_SIZE: u32 = 8;
+// Online backup API копирует базу порциями, не блокируя запись надолго
+const BACKUP_PAGES_PER_STEP: std::os::raw::c_int = 256;
+const BACKUP_STEP_PAUSE: Duration = Duration::from_millis(10);
+
+/// Открытый пул и пароль, которым он открыт (None — база не зашифрована).
+/// Пароль нужен соединениям к снимкам базы (backup.rs).
+struct KeyedPool {
+    pool: Pool<SqliteConnectionManager>,
+    passphrase: Option<String>,
+}
+
+/// Пул соединений к SQLite в режиме WAL: читатели не блокируют писателя,
+/// конкурентные записи ждут друг друга до `BUSY_TIMEOUT`.
+/// Tauri вызывает команды (в т.ч. async, например `fetch_nbs_rate`) из разных потоков,
+/// поэтому каждая команда берет собственное соединение через `conn()`.
+/// Зашифрованная база (см. encryption.rs) открывается без пула — он появляется
+/// после `unlock` с паролем владельца.
 pub struct Database {
-    conn: Connection,
+    pool: RwLock<Option<KeyedPool>>,
     db_path: PathBuf,
 }
 
-// ВАЖНО: Database НЕ использует Mutex, потому что Tauri автоматически
-// синхронизирует доступ через State<Database>
-unsafe impl Send for Database {}
-unsafe impl Sync for Database {}
-
 impl Database {
-    pub fn new(app_data_dir: PathBuf) -> Result<Self> {
+    pub fn new(app_data_dir: PathBuf) -> std::result::Result<Self, String> {
         // Создаем директорию если не существует
         fs::create_dir_all(&app_data_dir).ok();
         
         let db_path = app_data_dir.join("srecha-invoice.db");
-        let conn = Connection::open(&db_path)?;
-        
-        Ok(Database { conn, db_path })
+        let encrypted = encryption::is_encrypted(&db_path)
+            .map_err(|e| format!("Failed to read database file: {}", e))?;
+        let pool = if encrypted {
+            println!("🔒 База зашифрована — ждём пароль владельца");
+            None
+        } else {
+            Some(KeyedPool::open(&db_path, None)?)
+        };
+        
+        Ok(Database { pool: RwLock::new(pool), db_path })
+    }
+    
+    /// Берет соединение из пула. Соединение возвращается в пул при drop,
+    /// поэтому его не стоит держать через `.await`.
+    pub fn conn(&self) -> std::result::Result<DbConn, String> {
+        let pool = self.pool.read().unwrap_or_else(|e| e.into_inner());
+        pool.as_ref()
+            .ok_or("Database is locked")?
+            .pool
+            .get()
+            .map_err(|e| format!("Database connection unavailable: {}", e))
+    }
+    
+    /// Выполняет `f` в одной транзакции на одном соединении пула:
+    /// Ok — commit, Err — rollback, ошибка `f` возвращается без изменений.
+    /// BEGIN IMMEDIATE сразу берет блокировку записи, поэтому конкурентный
+    /// писатель ждет `BUSY_TIMEOUT`, а не получает SQLITE_BUSY посреди транзакции.
+    pub fn with_transaction<T, F>(&self, f: F) -> std::result::Result<T, String>
+    where
+        F: FnOnce(&Transaction) -> std::result::Result<T, String>,
+    {
+        self.try_transaction(f)
     }
     
-    pub fn conn(&self) -> &Connection {
-        &self.conn
+    /// То же, что `with_transaction`, но со своим типом ошибки (например CommandError),
+    /// чтобы проверки внутри транзакции могли вернуть структурированную ошибку.
+    pub fn try_transaction<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        E: From<String>,
+        F: FnOnce(&Transaction) -> std::result::Result<T, E>,
+    {
+        let mut conn = self.conn()?;
+        let tx = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+        let value = f(&tx)?;
+        tx.commit()
+            .map_err(|e| format!("Failed to commit transaction: {}", e))?;
+        Ok(value)
     }
     
+    /// Файл базы и его -wal / -shm доступны только владельцу (0600)
     pub fn set_permissions(&self) -> std::io::Result<()> {
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = fs::metadata(&self.db_path)?.permissions();
-            perms.set_mode(0o666);
-            fs::set_permissions(&self.db_path, perms)?;
+        encryption::restrict_permissions(&self.db_path)
+    }
+    
+    pub fn is_locked(&self) -> bool {
+        self.pool.read().unwrap_or_else(|e| e.into_inner()).is_none()
+    }
+    
+    pub fn encryption_status(&self) -> std::result::Result<EncryptionStatus, String> {
+        Ok(EncryptionStatus {
+            supported: encryption::SUPPORTED,
+            encrypted: encryption::is_encrypted(&self.db_path).map_err(|e| e.to_string())?,
+            locked: self.is_locked(),
+        })
+    }
+    
+    /// Открывает зашифрованную базу паролем владельца и доводит её схему
+    /// до текущей версии — то же, что `init` при запуске с открытой базой.
+    pub fn unlock(&self, passphrase: &str) -> std::result::Result<(), String> {
+        let mut pool = self.pool.write().unwrap_or_else(|e| e.into_inner());
+        if pool.is_some() {
+            return Err("Database is already unlocked".to_string());
         }
+        encryption::verify(&self.db_path, passphrase)?;
+        *pool = Some(KeyedPool::open(&self.db_path, Some(passphrase.to_string()))?);
+        drop(pool);
+        
+        self.init()?;
+        self.set_permissions().ok();
+        println!("🔓 База открыта");
         Ok(())
     }
     
-    pub fn init(&self) -> Result<()> {
-        // 1. Таблица пользователей
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                id TEXT PRIMARY KEY,
-                username TEXT UNIQUE NOT NULL,
-                password TEXT NOT NULL,
-                role TEXT NOT NULL,
-                created_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // 2. Таблица клиентов (24 поля + updated_at для синхронизации)
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS clients (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                legal_name TEXT,
-                mb TEXT NOT NULL,
-                pib TEXT,
-                address TEXT,
-                city TEXT,
-                postal_code TEXT,
-                country TEXT,
-                phone TEXT,
-                email TEXT,
-                tax_id TEXT,
-                bank TEXT,
-                client_type TEXT,
-                municipality TEXT,
-                street TEXT,
-                house_number TEXT,
-                is_manual_address INTEGER DEFAULT 0,
-                google_maps TEXT,
-                contact_person TEXT,
-                telegram TEXT,
-                instagram TEXT,
-                installment INTEGER DEFAULT 0,
-                installment_term INTEGER,
-                showcase INTEGER DEFAULT 0,
-                bar INTEGER DEFAULT 0,
-                notes TEXT,
-                contact TEXT,
-                created_at TEXT NOT NULL,
-                updated_at TEXT
-            )",
-            [],
-        )?;
-        
-        // Миграция: добавляем колонку updated_at в clients если её нет
-        let _ = self.conn.execute(
-            "ALTER TABLE clients ADD COLUMN updated_at TEXT",
-            [],
-        );
-        
-        // 3. Таблица товаров
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS products (
-                id TEXT PRIMARY KEY,
-                code TEXT UNIQUE NOT NULL,
-                name TEXT NOT NULL,
-                description TEXT,
-                price REAL,
-                category TEXT,
-                weight REAL,
-                supplier TEXT,
-                is_active INTEGER DEFAULT 1,
-                created_at TEXT NOT NULL,
-                updated_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // Миграция: добавляем колонку supplier если её нет
-        self.conn.execute(
-            "ALTER TABLE products ADD COLUMN supplier TEXT",
-            [],
-        ).ok(); // Игнорируем ошибку если колонка уже существует
-        
-        // 4. Таблица инвойсов
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS invoices (
-                id TEXT PRIMARY KEY,
-                invoice_number TEXT UNIQUE NOT NULL,
-                document_type TEXT NOT NULL,
-                client_id TEXT,
-                client_name TEXT,
-                date TEXT NOT NULL,
-                due_date TEXT,
-                total REAL NOT NULL,
-                status TEXT NOT NULL,
-                notes TEXT,
-                created_at TEXT NOT NULL,
-                paid INTEGER DEFAULT 0,
-                delivered INTEGER DEFAULT 0,
-                currency TEXT DEFAULT 'RSD',
-                exchange_rate REAL,
-                exchange_rate_date TEXT
-            )",
-            [],
-        )?;
-        
-        // Миграция: добавляем колонки paid и delivered если их нет
-        let _ = self.conn.execute("ALTER TABLE invoices ADD COLUMN paid INTEGER DEFAULT 0", []);
-        let _ = self.conn.execute("ALTER TABLE invoices ADD COLUMN delivered INTEGER DEFAULT 0", []);
-        // Миграция: мультивалютность
-        let _ = self.conn.execute("ALTER TABLE invoices ADD COLUMN currency TEXT DEFAULT 'RSD'", []);
-        let _ = self.conn.execute("ALTER TABLE invoices ADD COLUMN exchange_rate REAL", []);
-        let _ = self.conn.execute("ALTER TABLE invoices ADD COLUMN exchange_rate_date TEXT", []);
-
-        // Кэш курсов НБС (date,currency -> rate)
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS nbs_rates (
-                date TEXT NOT NULL,
-                currency TEXT NOT NULL,
-                rate REAL NOT NULL,
-                fetched_at TEXT NOT NULL,
-                PRIMARY KEY (date, currency)
-            )",
-            [],
-        )?;
-        
-        // 5. Таблица позиций инвойса
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS invoice_items (
-                id TEXT PRIMARY KEY,
-                invoice_id TEXT NOT NULL,
-                product_id TEXT NOT NULL,
-                product_name TEXT NOT NULL,
-                quantity REAL NOT NULL,
-                unit_weight_g REAL,
-                price REAL NOT NULL,
-                total REAL NOT NULL,
-                FOREIGN KEY (invoice_id) REFERENCES invoices(id)
-            )",
-            [],
-        )?;
-
-        // Миграция: добавляем unit_weight_g в invoice_items если её нет
-        let _ = self
-            .conn
-            .execute("ALTER TABLE invoice_items ADD COLUMN unit_weight_g REAL", []);
-        
-        // 6. Таблица доставок
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS deliveries (
-                id TEXT PRIMARY KEY,
-                delivery_number TEXT UNIQUE NOT NULL,
-                client_id TEXT,
-                client_name TEXT,
-                date TEXT NOT NULL,
-                status TEXT NOT NULL,
-                notes TEXT,
-                created_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // 7. Таблица позиций доставки
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS delivery_items (
-                id TEXT PRIMARY KEY,
-                delivery_id TEXT NOT NULL,
-                product_id TEXT NOT NULL,
-                product_name TEXT NOT NULL,
-                quantity REAL NOT NULL,
-                FOREIGN KEY (delivery_id) REFERENCES deliveries(id)
-            )",
-            [],
-        )?;
-        
-        // 8. Таблица групп склада
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS warehouse_groups (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                description TEXT,
-                created_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // 9. Таблица товаров на складе
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS warehouse_items (
-                id TEXT PRIMARY KEY,
-                group_id TEXT NOT NULL,
-                product_id TEXT NOT NULL,
-                product_code TEXT NOT NULL,
-                product_name TEXT NOT NULL,
-                quantity REAL NOT NULL,
-                notes TEXT,
-                created_at TEXT NOT NULL,
-                FOREIGN KEY (group_id) REFERENCES warehouse_groups(id),
-                FOREIGN KEY (product_id) REFERENCES products(id)
-            )",
-            [],
-        )?;
-        
-        // 10. Таблица статистики
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS statistics (
-                id TEXT PRIMARY KEY,
-                date TEXT NOT NULL,
-                type TEXT NOT NULL,
-                value REAL NOT NULL,
-                metadata TEXT,
-                created_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // 11. Таблица категорий
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS categories (
-                id TEXT PRIMARY KEY,
-                name TEXT UNIQUE NOT NULL,
-                created_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // 12. Таблица субкатегорий
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS subcategories (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                category_id TEXT NOT NULL,
-                created_at TEXT NOT NULL,
-                FOREIGN KEY (category_id) REFERENCES categories(id)
-            )",
-            [],
-        )?;
-        
-        // 13. Таблица секторов поставщиков (аналог категорий)
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS supplier_sectors (
-                id TEXT PRIMARY KEY,
-                name TEXT UNIQUE NOT NULL,
-                created_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // 14. Таблица продукции поставщиков (аналог субкатегорий)
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS supplier_products (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                sector_id TEXT NOT NULL,
-                created_at TEXT NOT NULL,
-                FOREIGN KEY (sector_id) REFERENCES supplier_sectors(id)
-            )",
-            [],
-        )?;
-        
-        // 15. Таблица поставщиков
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS suppliers (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL,
-                legal_name TEXT,
-                mb TEXT,
-                pib TEXT,
-                address TEXT,
-                city TEXT,
-                phone TEXT,
-                email TEXT,
-                telegram TEXT,
-                instagram TEXT,
-                website TEXT,
-                bank TEXT,
-                sector_id TEXT,
-                product_id TEXT,
-                contact_person TEXT,
-                contact_person_status TEXT,
-                google_maps TEXT,
-                notes TEXT,
-                is_active INTEGER DEFAULT 1,
-                created_at TEXT NOT NULL,
-                FOREIGN KEY (sector_id) REFERENCES supplier_sectors(id),
-                FOREIGN KEY (product_id) REFERENCES supplier_products(id)
-            )",
-            [],
-        )?;
-        
-        // 16. Таблица стран (члены ООН)
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS countries (
-                id TEXT PRIMARY KEY,
-                name TEXT UNIQUE NOT NULL,
-                code TEXT,
-                created_at TEXT NOT NULL
-            )",
-            [],
-        )?;
-        
-        // Миграция: добавляем колонку contact_person_status если её нет
-        let _ = self.conn.execute(
-            "ALTER TABLE clients ADD COLUMN contact_person_status TEXT",
-            [],
-        );
-        
-        // Миграция: добавляем колонку abbreviation в clients если её нет
-        let _ = self.conn.execute(
-            "ALTER TABLE clients ADD COLUMN abbreviation TEXT",
-            [],
-        );
-        
-        // Миграция: добавляем колонку subcategory в products если её нет
-        let _ = self.conn.execute(
-            "ALTER TABLE products ADD COLUMN subcategory TEXT",
-            [],
-        );
-        
-        // Миграция: добавляем колонку internal_code в products если её нет
-        let _ = self.conn.execute(
-            "ALTER TABLE products ADD COLUMN internal_code TEXT",
-            [],
-        );
-        
-        // Миграция: добавляем колонку country в suppliers если её нет
-        let _ = self.conn.execute(
-            "ALTER TABLE suppliers ADD COLUMN country TEXT",
-            [],
-        );
-        
-        // Миграция: добавляем колонку reg_number в suppliers если её нет (для не-сербских компаний)
-        let _ = self.conn.execute(
-            "ALTER TABLE suppliers ADD COLUMN reg_number TEXT",
-            [],
-        );
+    /// Шифрует открытую базу на месте.
+    pub fn encrypt(&self, passphrase: &str) -> std::result::Result<(), String> {
+        if encryption::is_encrypted(&self.db_path).map_err(|e| e.to_string())? {
+            return Err("Database is already encrypted".to_string());
+        }
+        self.rewrite(None, passphrase)?;
+        println!("🔒 База зашифрована");
+        Ok(())
+    }
+    
+    /// Перешифровывает базу новым паролем; `current` проверяется по файлу.
+    pub fn rotate_key(&self, current: &str, new_passphrase: &str) -> std::result::Result<(), String> {
+        if !encryption::is_encrypted(&self.db_path).map_err(|e| e.to_string())? {
+            return Err("Database is not encrypted".to_string());
+        }
+        // Проверяем до закрытия пула: при сбое rewrite он откроется этим же ключом
+        encryption::verify(&self.db_path, current)?;
+        self.rewrite(Some(current), new_passphrase)?;
+        println!("🔑 Ключ базы заменён");
+        Ok(())
+    }
+    
+    /// Закрывает пул, переписывает файл и открывает пул с новым ключом.
+    /// Пока держим блокировку пула, новые соединения не выдаются; занятые — значит,
+    /// другая команда ещё работает с базой, и файл подменять нельзя.
+    fn rewrite(&self, current: Option<&str>, new_passphrase: &str) -> std::result::Result<(), String> {
+        encryption::check_passphrase(new_passphrase)?;
+        let mut pool = self.pool.write().unwrap_or_else(|e| e.into_inner());
+        let open = pool.take().ok_or("Database is locked")?;
+        let state = open.pool.state();
+        if state.connections != state.idle_connections {
+            *pool = Some(open);
+            return Err("Database is busy, try again".to_string());
+        }
+        drop(open);
         
-        // Миграция: добавляем колонку wechat в suppliers если её нет
-        let _ = self.conn.execute(
-            "ALTER TABLE suppliers ADD COLUMN wechat TEXT",
-            [],
-        );
+        let result = encryption::rewrite(&self.db_path, current, new_passphrase);
+        // При ошибке файл не тронут — открываем его прежним ключом
+        let key = if result.is_ok() { Some(new_passphrase) } else { current };
+        *pool = Some(KeyedPool::open(&self.db_path, key.map(str::to_string))?);
+        result
+    }
+    
+    /// Открывает отдельный файл базы (снимок) тем же ключом, что и рабочую базу.
+    pub fn open_snapshot(&self, path: &Path) -> std::result::Result<Connection, String> {
+        encryption::open_with_key(path, self.passphrase()?.as_deref())
+    }
+    
+    /// Копирует рабочую базу в `target` через online backup API, не останавливая
+    /// остальные команды. Зашифрованная база копируется с тем же ключом.
+    pub fn backup_into(&self, target: &Path) -> std::result::Result<(), String> {
+        let conn = self.conn()?;
+        let mut snapshot = self.open_snapshot_target(target)?;
+        Backup::new(&conn, &mut snapshot)
+            .and_then(|backup| backup.run_to_completion(BACKUP_PAGES_PER_STEP, BACKUP_STEP_PAUSE, None))
+            .map_err(|e| format!("Failed to back up database: {}", e))
+    }
+    
+    /// Заменяет содержимое рабочей базы проверенным снимком (тем же online backup API
+    /// в обратную сторону) и доводит схему снимка до текущей версии.
+    pub fn restore_from(&self, snapshot: &Connection) -> std::result::Result<(), String> {
+        let mut conn = self.conn()?;
+        Backup::new(snapshot, &mut conn)
+            .and_then(|backup| backup.run_to_completion(BACKUP_PAGES_PER_STEP, BACKUP_STEP_PAUSE, None))
+            .map_err(|e| format!("Failed to restore database: {}", e))?;
+        drop(conn);
+        self.init()?;
+        self.set_permissions().ok();
+        Ok(())
+    }
+    
+    fn passphrase(&self) -> std::result::Result<Option<String>, String> {
+        let pool = self.pool.read().unwrap_or_else(|e| e.into_inner());
+        Ok(pool.as_ref().ok_or("Database is locked")?.passphrase.clone())
+    }
+    
+    /// Новый файл для снимка: ключ задаётся до первой записи
+    fn open_snapshot_target(&self, target: &Path) -> std::result::Result<Connection, String> {
+        let passphrase = self.passphrase()?;
+        let conn = Connection::open(target).map_err(|e| format!("Failed to create snapshot: {}", e))?;
+        if let Some(passphrase) = &passphrase {
+            encryption::apply_key(&conn, passphrase).map_err(|e| e.to_string())?;
+        }
+        Ok(conn)
+    }
+    
+    pub fn init(&self) -> std::result::Result<(), String> {
+        // Схема БД: версионированные миграции (см. migrations.rs)
+        let conn = self.conn()?;
+        migrations::run(&conn).map_err(|e| e.to_string())?;
         
         // Создаем дефолтные категории если их нет
-        self.seed_default_categories()?;
+        Self::seed_default_categories(&conn).map_err(|e| e.to_string())?;
         
         // Создаем дефолтные секторы поставщиков если их нет
-        self.seed_default_supplier_sectors()?;
+        Self::seed_default_supplier_sectors(&conn).map_err(|e| e.to_string())?;
         
         // Создаем страны если их нет
-        self.seed_countries()?;
-        
-        // Создаем дефолтного пользователя если его нет
-        self.seed_default_user()?;
-        
-        Ok(())
-    }
-    
-    fn seed_default_user(&self) -> Result<()> {
-        let count: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM users",
-            [],
-            |row| row.get(0),
-        )?;
+        Self::seed_countries(&conn).map_err(|e| e.to_string())?;
         
-        if count == 0 {
-            let id = uuid::Uuid::new_v4().to_string();
-            let password_hash = bcrypt::hash("MoskvaSlezamNeVeryt2024", bcrypt::DEFAULT_COST)
-                .expect("Failed to hash password");
-            let created_at = chrono::Utc::now().to_rfc3339();
-            
-            self.conn.execute(
-                "INSERT INTO users (id, username, password, role, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-                [&id, "BrankoFND", &password_hash, "admin", &created_at],
-            )?;
-            
-            println!("✅ Создан дефолтный пользователь: BrankoFND");
-        }
+        // Пользователей не создаём: первого администратора заводит create_initial_admin
         
         Ok(())
     }
     
-    fn seed_default_categories(&self) -> Result<()> {
-        let count: i64 = self.conn.query_row(
+    fn seed_default_categories(conn: &Connection) -> Result<()> {
+        let count: i64 = conn.query_row(
             "SELECT COUNT(*) FROM categories",
             [],
             |row| row.get(0),
@@ -436,7 +252,7 @@ impl Database {
             
             for (name, _display_name) in default_categories {
                 let id = uuid::Uuid::new_v4().to_string();
-                self.conn.execute(
+                conn.execute(
                     "INSERT INTO categories (id, name, created_at) VALUES (?1, ?2, ?3)",
                     [&id, name, &created_at],
                 )?;
@@ -448,8 +264,8 @@ impl Database {
         Ok(())
     }
     
-    fn seed_default_supplier_sectors(&self) -> Result<()> {
-        let count: i64 = self.conn.query_row(
+    fn seed_default_supplier_sectors(conn: &Connection) -> Result<()> {
+        let count: i64 = conn.query_row(
             "SELECT COUNT(*) FROM supplier_sectors",
             [],
             |row| row.get(0),
@@ -467,7 +283,7 @@ impl Database {
             
             for name in default_sectors {
                 let id = uuid::Uuid::new_v4().to_string();
-                self.conn.execute(
+                conn.execute(
                     "INSERT INTO supplier_sectors (id, name, created_at) VALUES (?1, ?2, ?3)",
                     [&id, name, &created_at],
                 )?;
@@ -479,8 +295,8 @@ impl Database {
         Ok(())
     }
     
-    fn seed_countries(&self) -> Result<()> {
-        let count: i64 = self.conn.query_row(
+    fn seed_countries(conn: &Connection) -> Result<()> {
+        let count: i64 = conn.query_row(
             "SELECT COUNT(*) FROM countries",
             [],
             |row| row.get(0),
@@ -561,7 +377,7 @@ impl Database {
             let count = countries.len();
             for (name, code) in countries {
                 let id = uuid::Uuid::new_v4().to_string();
-                self.conn.execute(
+                conn.execute(
                     "INSERT INTO countries (id, name, code, created_at) VALUES (?1, ?2, ?3, ?4)",
                     [&id, name, code, &created_at],
                 )?;
@@ -573,3 +389,112 @@ impl Database {
         Ok(())
     }
 }
+
+impl KeyedPool {
+    fn open(db_path: &Path, passphrase: Option<String>) -> std::result::Result<Self, String> {
+        let key = passphrase.clone();
+        let manager = SqliteConnectionManager::file(db_path).with_init(move |conn| {
+            // Ключ — первой командой, до любого чтения файла
+            if let Some(key) = &key {
+                encryption::apply_key(conn, key)?;
+            }
+            configure_connection(conn)
+        });
+        let pool = Pool::builder()
+            .max_size(POOL_MAX_SIZE)
+            .build(manager)
+            .map_err(|e| format!("Failed to open database pool: {}", e))?;
+        Ok(KeyedPool { pool, passphrase })
+    }
+}
+
+/// Настройки, которые применяются к каждому новому соединению пула.
+fn configure_connection(conn: &mut Connection) -> Result<()> {
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    // journal_mode возвращает строку с новым режимом, поэтому pragma_update не подходит
+    let mode: String = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    if !mode.eq_ignore_ascii_case("wal") {
+        println!("⚠️ SQLite не переключился в WAL (journal_mode = {})", mode);
+    }
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    // В SQLite внешние ключи выключены по умолчанию для каждого соединения
+    conn.pragma_update(None, "foreign_keys", true)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_writers_do_not_fail_with_locked() {
+        let dir = std::env::temp_dir().join(format!("srecha-db-test-{}", uuid::Uuid::new_v4()));
+        let db = std::sync::Arc::new(Database::new(dir.clone()).unwrap());
+        db.init().unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        db.conn()
+                            .unwrap()
+                            .execute(
+                                "INSERT INTO categories (id, name, created_at) VALUES (?1, ?2, '')",
+                                [uuid::Uuid::new_v4().to_string(), format!("t{}-{}", t, i)],
+                            )
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let count: i64 = db
+            .conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM categories WHERE name LIKE 't%'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 200);
+
+        let mode: String = db
+            .conn()
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode.to_lowercase(), "wal");
+
+        drop(db);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_encrypted_database_opens_locked() {
+        let dir = std::env::temp_dir().join(format!("srecha-db-test-{}", uuid::Uuid::new_v4()));
+        let db = Database::new(dir.clone()).unwrap();
+        db.init().unwrap();
+        db.encrypt("kljuc-vlasnika-1").unwrap();
+        assert!(db.conn().is_ok());
+        drop(db);
+
+        let db = Database::new(dir.clone()).unwrap();
+        assert!(db.is_locked());
+        assert_eq!(db.conn().unwrap_err(), "Database is locked");
+        assert_eq!(db.unlock("pogresan-kljuc").unwrap_err(), "Wrong database passphrase");
+        db.unlock("kljuc-vlasnika-1").unwrap();
+
+        db.rotate_key("kljuc-vlasnika-1", "kljuc-vlasnika-2").unwrap();
+        let count: i64 = db
+            .conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM countries", [], |row| row.get(0))
+            .unwrap();
+        assert!(count > 0);
+
+        drop(db);
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,562 @@
+use crate::numbering;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// Продуктивный SEF; демо-окружение — https://demoefaktura.mfin.gov.rs
+pub const DEFAULT_BASE_URL: &str = "https://efaktura.mfin.gov.rs";
+const SALES_INVOICE_UBL_PATH: &str = "/api/publicApi/sales-invoice/ubl";
+const SALES_INVOICE_PATH: &str = "/api/publicApi/sales-invoice";
+const PURCHASE_INVOICE_PATH: &str = "/api/publicApi/purchase-invoice";
+const PURCHASE_INVOICE_IDS_PATH: &str = "/api/publicApi/purchase-invoice/ids";
+const PURCHASE_INVOICE_XML_PATH: &str = "/api/publicApi/purchase-invoice/xml";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Сколько символов тела ответа SEF сохраняем в тексте ошибки
+const MAX_ERROR_BODY: usize = 500;
+
+/// Статус, который ставим сразу после успешного импорта; дальше его обновляет SEF
+pub const SEF_STATUS_SENT: &str = "Sent";
+/// Статусы SEF, после которых документ больше не меняется — такие не опрашиваем
+pub const SEF_FINAL_STATUSES: [&str; 5] = ["Approved", "Rejected", "Cancelled", "Storno", "Deleted"];
+
+/// Статус документа (invoices.status) по финальному статусу SEF. Промежуточные статусы
+/// (Sent, Seen, ...) и Deleted (удалён черновик в SEF) видны только в sef_status.
+pub fn invoice_status_for(sef_status: &str) -> Option<&'static str> {
+    match sef_status {
+        "Approved" => Some("accepted"),
+        "Rejected" => Some("rejected"),
+        "Cancelled" | "Storno" => Some("cancelled"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EfakturaSettings {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EfakturaConfig {
+    pub base_url: String,
+    pub api_key: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EfakturaError {
+    NotConfigured,
+    InvoiceNotFound(String),
+    NotSendable { invoice_number: String, reason: String },
+    AlreadySent { invoice_number: String, sef_invoice_id: i64 },
+    Network(String),
+    Unauthorized,
+    Rejected { status: u16, message: String },
+    Server { status: u16, message: String },
+    InvalidResponse(String),
+    Database(String),
+}
+
+impl fmt::Display for EfakturaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EfakturaError::NotConfigured => write!(f, "eFaktura ApiKey is not configured"),
+            EfakturaError::InvoiceNotFound(id) => write!(f, "Invoice {} not found", id),
+            EfakturaError::NotSendable { invoice_number, reason } => {
+                write!(f, "Invoice {} cannot be sent to eFaktura: {}", invoice_number, reason)
+            }
+            EfakturaError::AlreadySent { invoice_number, sef_invoice_id } => write!(
+                f,
+                "Invoice {} is already sent to eFaktura (SalesInvoiceId {})",
+                invoice_number, sef_invoice_id
+            ),
+            EfakturaError::Network(e) => write!(f, "eFaktura is unreachable: {}", e),
+            EfakturaError::Unauthorized => write!(f, "eFaktura rejected the ApiKey (401/403)"),
+            EfakturaError::Rejected { status, message } => {
+                write!(f, "eFaktura rejected the request ({}): {}", status, message)
+            }
+            EfakturaError::Server { status, message } => write!(f, "eFaktura server error ({}): {}", status, message),
+            EfakturaError::InvalidResponse(e) => write!(f, "Unexpected eFaktura response: {}", e),
+            EfakturaError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EfakturaError {}
+
+impl From<rusqlite::Error> for EfakturaError {
+    fn from(e: rusqlite::Error) -> Self {
+        EfakturaError::Database(e.to_string())
+    }
+}
+
+/// Ответ POST /api/publicApi/sales-invoice/ubl
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SalesInvoiceResponse {
+    #[serde(alias = "invoiceId")]
+    pub invoice_id: Option<i64>,
+    #[serde(alias = "purchaseInvoiceId")]
+    pub purchase_invoice_id: Option<i64>,
+    #[serde(alias = "salesInvoiceId")]
+    pub sales_invoice_id: Option<i64>,
+}
+
+/// Статус документа в SEF: GET sales-invoice / purchase-invoice
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SefInvoiceStatus {
+    #[serde(alias = "invoiceId")]
+    pub invoice_id: Option<i64>,
+    #[serde(alias = "status")]
+    pub status: String,
+    #[serde(alias = "comment", default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PurchaseInvoiceIds {
+    #[serde(alias = "purchaseInvoiceIds", default)]
+    purchase_invoice_ids: Vec<i64>,
+}
+
+/// Готовый к отправке документ: UBL и requestId, по которому SEF отсекает повторы.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SalesInvoiceRequest {
+    pub invoice_id: String,
+    pub invoice_number: String,
+    pub request_id: String,
+    pub ubl_xml: String,
+}
+
+// ==================== НАСТРОЙКИ ====================
+
+pub fn load_settings(conn: &Connection) -> rusqlite::Result<EfakturaSettings> {
+    let settings = conn
+        .query_row(
+            "SELECT base_url, api_key, updated_at FROM efaktura_settings WHERE id = 1",
+            [],
+            |row| {
+                Ok(EfakturaSettings {
+                    base_url: row.get(0)?,
+                    api_key: row.get(1)?,
+                    updated_at: row.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(settings.unwrap_or(EfakturaSettings {
+        base_url: DEFAULT_BASE_URL.to_string(),
+        api_key: None,
+        updated_at: None,
+    }))
+}
+
+pub fn save_settings(conn: &Connection, settings: &EfakturaSettings) -> Result<EfakturaSettings, String> {
+    let base_url = settings.base_url.trim().trim_end_matches('/');
+    if !(base_url.starts_with("https://") || base_url.starts_with("http://")) {
+        return Err(format!("Invalid eFaktura base URL: {}", settings.base_url));
+    }
+    let api_key = settings
+        .api_key
+        .as_deref()
+        .map(str::trim)
+        .filter(|k| !k.is_empty());
+    conn.execute(
+        "INSERT INTO efaktura_settings (id, base_url, api_key, updated_at) VALUES (1, ?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET base_url = excluded.base_url, api_key = excluded.api_key, updated_at = excluded.updated_at",
+        params![base_url, api_key, Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| e.to_string())?;
+    load_settings(conn).map_err(|e| e.to_string())
+}
+
+pub fn load_config(conn: &Connection) -> Result<EfakturaConfig, EfakturaError> {
+    let settings = load_settings(conn)?;
+    let api_key = settings
+        .api_key
+        .filter(|k| !k.trim().is_empty())
+        .ok_or(EfakturaError::NotConfigured)?;
+    Ok(EfakturaConfig {
+        base_url: settings.base_url.trim_end_matches('/').to_string(),
+        api_key,
+    })
+}
+
+// ==================== ПОДГОТОВКА ДОКУМЕНТА ====================
+
+/// Проверяет, что сохранённый документ можно отправить, и сверяет UBL с ним:
+/// номер (cbc:ID) и дата (cbc:IssueDate) должны совпадать с базой, иначе SEF
+/// может перезаписать чужой документ. requestId сохраняется до отправки —
+/// повтор после обрыва связи уходит с тем же requestId и SEF не создаёт дубль.
+pub fn prepare_sales_invoice(
+    conn: &Connection,
+    invoice_id: &str,
+    ubl_xml: &str,
+) -> Result<SalesInvoiceRequest, EfakturaError> {
+    let row = conn
+        .query_row(
+            "SELECT invoice_number, document_type, date, status, sef_invoice_id, sef_request_id
+             FROM invoices WHERE id = ?1",
+            [invoice_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                ))
+            },
+        )
+        .optional()?;
+    let (invoice_number, document_type, date, status, sef_invoice_id, request_id) =
+        row.ok_or_else(|| EfakturaError::InvoiceNotFound(invoice_id.to_string()))?;
+
+    let not_sendable = |reason: String| EfakturaError::NotSendable {
+        invoice_number: invoice_number.clone(),
+        reason,
+    };
+    let sequence = numbering::sequence_key(&document_type);
+    if sequence != numbering::RACUN && sequence != numbering::KNJIZNO_ODOBRENJE {
+        return Err(not_sendable(format!("document type {} is not a racun or credit note", document_type)));
+    }
+    if status == "draft" {
+        return Err(not_sendable("invoice is a draft".to_string()));
+    }
+    if let Some(sef_invoice_id) = sef_invoice_id {
+        return Err(EfakturaError::AlreadySent {
+            invoice_number,
+            sef_invoice_id,
+        });
+    }
+    match xml_element_text(ubl_xml, "cbc:ID") {
+        Some(id) if id == invoice_number => {}
+        other => {
+            return Err(not_sendable(format!(
+                "UBL cbc:ID {} does not match invoice number",
+                other.unwrap_or_default()
+            )))
+        }
+    }
+    let issue_date = date.get(..10).unwrap_or(&date);
+    match xml_element_text(ubl_xml, "cbc:IssueDate") {
+        Some(d) if d == issue_date => {}
+        other => {
+            return Err(not_sendable(format!(
+                "UBL cbc:IssueDate {} does not match invoice date {}",
+                other.unwrap_or_default(),
+                issue_date
+            )))
+        }
+    }
+
+    let request_id = match request_id {
+        Some(existing) => existing,
+        None => {
+            let new_id = uuid::Uuid::new_v4().to_string();
+            conn.execute(
+                "UPDATE invoices SET sef_request_id = ?1 WHERE id = ?2",
+                params![new_id, invoice_id],
+            )?;
+            new_id
+        }
+    };
+
+    Ok(SalesInvoiceRequest {
+        invoice_id: invoice_id.to_string(),
+        invoice_number,
+        request_id,
+        ubl_xml: ubl_xml.to_string(),
+    })
+}
+
+/// Текст первого элемента `<tag>...</tag>` (без вложенных элементов).
+fn xml_element_text(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(xml[start..end].trim().to_string())
+}
+
+pub fn mark_sent(conn: &Connection, request: &SalesInvoiceRequest, response: &SalesInvoiceResponse) -> Result<(), EfakturaError> {
+    let sef_invoice_id = response
+        .sales_invoice_id
+        .or(response.invoice_id)
+        .ok_or_else(|| EfakturaError::InvalidResponse("SalesInvoiceId is missing".to_string()))?;
+    conn.execute(
+        "UPDATE invoices SET sef_invoice_id = ?1, sef_status = ?2, sef_sent_at = ?3, sef_error = NULL WHERE id = ?4",
+        params![sef_invoice_id, SEF_STATUS_SENT, Utc::now().to_rfc3339(), request.invoice_id],
+    )?;
+    Ok(())
+}
+
+pub fn mark_failed(conn: &Connection, invoice_id: &str, error: &EfakturaError) -> Result<(), EfakturaError> {
+    conn.execute(
+        "UPDATE invoices SET sef_error = ?1 WHERE id = ?2",
+        params![error.to_string(), invoice_id],
+    )?;
+    Ok(())
+}
+
+// ==================== HTTP КЛИЕНТ ====================
+
+pub struct EfakturaClient {
+    http: reqwest::Client,
+    config: EfakturaConfig,
+}
+
+impl EfakturaClient {
+    pub fn new(config: EfakturaConfig) -> Result<Self, EfakturaError> {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| EfakturaError::Network(e.to_string()))?;
+        Ok(EfakturaClient { http, config })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.base_url.trim_end_matches('/'), path)
+    }
+
+    /// Выполняет запрос с ApiKey и возвращает тело успешного ответа
+    async fn execute(&self, request: reqwest::RequestBuilder) -> Result<String, EfakturaError> {
+        let response = request
+            .header("ApiKey", &self.config.api_key)
+            .send()
+            .await
+            .map_err(|e| EfakturaError::Network(e.to_string()))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| EfakturaError::Network(e.to_string()))?;
+        check_status(status.as_u16(), &body)?;
+        Ok(body)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T, EfakturaError> {
+        let body = self
+            .execute(self.http.get(self.url(path)).query(query).header("Accept", "application/json"))
+            .await?;
+        parse_json(&body)
+    }
+
+    /// Импорт исходящего документа: POST /api/publicApi/sales-invoice/ubl
+    pub async fn send_sales_invoice(&self, request: &SalesInvoiceRequest) -> Result<SalesInvoiceResponse, EfakturaError> {
+        let body = self
+            .execute(
+                self.http
+                    .post(self.url(SALES_INVOICE_UBL_PATH))
+                    .query(&[("requestId", request.request_id.as_str()), ("executeValidation", "true")])
+                    .header("Content-Type", "application/xml")
+                    .header("Accept", "application/json")
+                    .body(request.ubl_xml.clone()),
+            )
+            .await?;
+        parse_json(&body)
+    }
+
+    pub async fn get_sales_invoice(&self, sef_invoice_id: i64) -> Result<SefInvoiceStatus, EfakturaError> {
+        self.get_json(SALES_INVOICE_PATH, &[("invoiceId", sef_invoice_id.to_string())]).await
+    }
+
+    pub async fn get_purchase_invoice(&self, sef_invoice_id: i64) -> Result<SefInvoiceStatus, EfakturaError> {
+        self.get_json(PURCHASE_INVOICE_PATH, &[("invoiceId", sef_invoice_id.to_string())]).await
+    }
+
+    /// Входящие документы за период (даты YYYY-MM-DD включительно)
+    pub async fn purchase_invoice_ids(&self, date_from: &str, date_to: &str) -> Result<Vec<i64>, EfakturaError> {
+        let body = self
+            .execute(
+                self.http
+                    .post(self.url(PURCHASE_INVOICE_IDS_PATH))
+                    .query(&[("dateFrom", date_from), ("dateTo", date_to)])
+                    .header("Accept", "application/json"),
+            )
+            .await?;
+        let ids: PurchaseInvoiceIds = parse_json(&body)?;
+        Ok(ids.purchase_invoice_ids)
+    }
+
+    /// UBL входящего документа (в конверте SEF)
+    pub async fn get_purchase_invoice_xml(&self, sef_invoice_id: i64) -> Result<String, EfakturaError> {
+        self.execute(
+            self.http
+                .get(self.url(PURCHASE_INVOICE_XML_PATH))
+                .query(&[("invoiceId", sef_invoice_id.to_string())])
+                .header("Accept", "application/xml"),
+        )
+        .await
+    }
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, EfakturaError> {
+    serde_json::from_str(body).map_err(|e| EfakturaError::InvalidResponse(format!("{}: {}", e, body)))
+}
+
+fn check_status(status: u16, body: &str) -> Result<(), EfakturaError> {
+    match status {
+        200..=299 => Ok(()),
+        401 | 403 => Err(EfakturaError::Unauthorized),
+        400..=499 => Err(EfakturaError::Rejected {
+            status,
+            message: error_message(body),
+        }),
+        _ => Err(EfakturaError::Server {
+            status,
+            message: error_message(body),
+        }),
+    }
+}
+
+/// SEF отдаёт ошибки как JSON с полем Message (иногда message); иначе — сырой текст.
+fn error_message(body: &str) -> String {
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| {
+            v.get("Message")
+                .or_else(|| v.get("message"))
+                .and_then(|m| m.as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| body.trim().to_string());
+    if message.is_empty() {
+        return "Request failed".to_string();
+    }
+    message.chars().take(MAX_ERROR_BODY).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    const UBL: &str = "<Invoice><cbc:CustomizationID>urn:cen.eu:en16931:2017</cbc:CustomizationID>\
+                       <cbc:ID>12/2026</cbc:ID><cbc:IssueDate>2026-03-01</cbc:IssueDate></Invoice>";
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        conn.execute("INSERT INTO clients (id, name, mb, created_at) VALUES (1, 'Klijent', '21000001', '')", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO invoices (id, invoice_number, document_type, client_id, date, total, status, created_at)
+             VALUES ('inv', '12/2026', 'racun', '1', '2026-03-01', 120.0, 'confirmed', '')",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn client(base_url: String) -> EfakturaClient {
+        EfakturaClient::new(EfakturaConfig {
+            base_url,
+            api_key: "secret".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_prepare_checks_invoice_and_reuses_request_id() {
+        let conn = setup();
+
+        let wrong = UBL.replace("12/2026", "13/2026");
+        assert!(matches!(
+            prepare_sales_invoice(&conn, "inv", &wrong),
+            Err(EfakturaError::NotSendable { .. })
+        ));
+
+        let first = prepare_sales_invoice(&conn, "inv", UBL).unwrap();
+        let retry = prepare_sales_invoice(&conn, "inv", UBL).unwrap();
+        assert_eq!(first.request_id, retry.request_id);
+
+        mark_sent(
+            &conn,
+            &first,
+            &SalesInvoiceResponse {
+                invoice_id: Some(1),
+                purchase_invoice_id: Some(2),
+                sales_invoice_id: Some(3),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            prepare_sales_invoice(&conn, "inv", UBL),
+            Err(EfakturaError::AlreadySent {
+                invoice_number: "12/2026".to_string(),
+                sef_invoice_id: 3
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_sales_invoice_against_mock_server() {
+        let conn = setup();
+        let request = prepare_sales_invoice(&conn, "inv", UBL).unwrap();
+
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", SALES_INVOICE_UBL_PATH)
+            .match_query(mockito::Matcher::UrlEncoded("requestId".into(), request.request_id.clone()))
+            .match_header("ApiKey", "secret")
+            .match_body(UBL)
+            .with_status(200)
+            .with_body(r#"{"InvoiceId":101,"PurchaseInvoiceId":102,"SalesInvoiceId":103}"#)
+            .create_async()
+            .await;
+
+        let response = client(server.url()).send_sales_invoice(&request).await.unwrap();
+        mock.assert_async().await;
+        assert_eq!(response.sales_invoice_id, Some(103));
+
+        mark_sent(&conn, &request, &response).unwrap();
+        let (sef_id, sef_status): (i64, String) = conn
+            .query_row("SELECT sef_invoice_id, sef_status FROM invoices WHERE id = 'inv'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(sef_id, 103);
+        assert_eq!(sef_status, SEF_STATUS_SENT);
+    }
+
+    #[tokio::test]
+    async fn test_send_sales_invoice_maps_errors() {
+        let conn = setup();
+        let request = prepare_sales_invoice(&conn, "inv", UBL).unwrap();
+        let mut server = mockito::Server::new_async().await;
+
+        let unauthorized = server
+            .mock("POST", SALES_INVOICE_UBL_PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(401)
+            .create_async()
+            .await;
+        let err = client(server.url()).send_sales_invoice(&request).await.unwrap_err();
+        assert_eq!(err, EfakturaError::Unauthorized);
+        unauthorized.remove_async().await;
+
+        server
+            .mock("POST", SALES_INVOICE_UBL_PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(400)
+            .with_body(r#"{"Message":"Buyer is not registered in SEF"}"#)
+            .create_async()
+            .await;
+        let err = client(server.url()).send_sales_invoice(&request).await.unwrap_err();
+        assert_eq!(
+            err,
+            EfakturaError::Rejected {
+                status: 400,
+                message: "Buyer is not registered in SEF".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,717 @@
+use crate::audit;
+use crate::database::Database;
+use crate::efaktura::{self, EfakturaClient, EfakturaError, SefInvoiceStatus};
+use crate::ubl::{self, PurchaseInvoiceHeader};
+use chrono::{Days, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Интервал фонового опроса SEF
+pub const POLL_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Первый опрос — после загрузки главного окна, а не в момент старта
+pub const FIRST_POLL_DELAY: Duration = Duration::from_secs(60);
+/// При первом опросе входящие документы берём за последние N дней
+const INITIAL_PURCHASE_DAYS: u64 = 30;
+/// Автор изменений статуса в журнале (audit_log)
+const SEF_ACTOR: &str = "eFaktura";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PollSummary {
+    pub sales_checked: u32,
+    pub sales_changed: u32,
+    pub purchases_received: u32,
+    pub purchases_changed: u32,
+    /// Входящие, которые не удалось загрузить: удалённые в SEF и с битым UBL сохраняются
+    /// с ошибкой (load_error), после сбоя связи повторяются при следующем опросе
+    pub purchases_skipped: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurchaseInvoice {
+    pub id: String,
+    pub sef_invoice_id: i64,
+    pub sef_status: Option<String>,
+    pub sef_comment: Option<String>,
+    /// Поставщик из справочника (по PIB, затем по МБ); None — не найден
+    pub supplier_id: Option<i64>,
+    pub supplier_name: Option<String>,
+    pub supplier_pib: Option<String>,
+    pub supplier_mb: Option<String>,
+    pub invoice_number: String,
+    pub invoice_type_code: Option<String>,
+    pub issue_date: Option<String>,
+    pub due_date: Option<String>,
+    pub currency: String,
+    pub total: Option<f64>,
+    pub vat_total: Option<f64>,
+    pub received_at: String,
+    pub updated_at: Option<String>,
+    /// Почему документ не загрузился; None — загружен
+    pub load_error: Option<String>,
+}
+
+/// Входящий документ, который не удалось загрузить: то, что SEF успел отдать, и ошибка
+struct FailedPurchase {
+    status: Option<SefInvoiceStatus>,
+    xml: Option<String>,
+    error: EfakturaError,
+}
+
+/// Документ, статус которого ещё может измениться в SEF
+struct PendingDocument {
+    id: String,
+    sef_invoice_id: i64,
+}
+
+/// Один проход опроса: статусы отправленных рачуна, статусы известных входящих,
+/// затем новые входящие документы с даты прошлого опроса.
+pub async fn poll_once(db: &Database) -> Result<PollSummary, EfakturaError> {
+    // Соединения берём короткими блоками — не держим их через .await
+    let (config, sales, purchases, synced_until) = {
+        let conn = db.conn().map_err(EfakturaError::Database)?;
+        (
+            efaktura::load_config(&conn)?,
+            pending_documents(&conn, "invoices")?,
+            pending_documents(&conn, "purchase_invoices")?,
+            purchase_synced_until(&conn)?,
+        )
+    };
+    let client = EfakturaClient::new(config)?;
+    let mut summary = PollSummary::default();
+
+    for doc in sales {
+        let Some(status) = skip_missing(client.get_sales_invoice(doc.sef_invoice_id).await, doc.sef_invoice_id)? else {
+            continue;
+        };
+        summary.sales_checked += 1;
+        let changed = db
+            .with_transaction(|tx| update_status(tx, "invoices", &doc.id, &status).map_err(|e| e.to_string()))
+            .map_err(EfakturaError::Database)?;
+        if changed {
+            summary.sales_changed += 1;
+        }
+    }
+
+    for doc in purchases {
+        let Some(status) = skip_missing(client.get_purchase_invoice(doc.sef_invoice_id).await, doc.sef_invoice_id)? else {
+            continue;
+        };
+        let conn = db.conn().map_err(EfakturaError::Database)?;
+        if update_status(&conn, "purchase_invoices", &doc.id, &status)? {
+            summary.purchases_changed += 1;
+        }
+    }
+
+    let today = Utc::now().date_naive();
+    let date_to = today.format("%Y-%m-%d").to_string();
+    let date_from = synced_until.unwrap_or_else(|| {
+        (today - Days::new(INITIAL_PURCHASE_DAYS))
+            .format("%Y-%m-%d")
+            .to_string()
+    });
+    let ids = client.purchase_invoice_ids(&date_from, &date_to).await?;
+    let known = {
+        let conn = db.conn().map_err(EfakturaError::Database)?;
+        known_purchase_ids(&conn)?
+    };
+    // Один недоступный или битый документ не должен останавливать весь ящик входящих
+    let mut retry_later = false;
+    for sef_invoice_id in ids.into_iter().filter(|id| !known.contains(id)) {
+        let (status, xml, header) = match fetch_purchase_invoice(&client, sef_invoice_id).await {
+            Ok(fetched) => fetched,
+            Err(failed) => {
+                summary.purchases_skipped += 1;
+                if is_permanent(&failed.error) {
+                    println!("⚠️ Входящий рачун SEF {} сохранён с ошибкой: {}", sef_invoice_id, failed.error);
+                    let conn = db.conn().map_err(EfakturaError::Database)?;
+                    insert_failed_purchase_invoice(&conn, sef_invoice_id, &failed)?;
+                } else {
+                    println!(
+                        "⚠️ Входящий рачун SEF {} не загружен, повторим при следующем опросе: {}",
+                        sef_invoice_id, failed.error
+                    );
+                    retry_later = true;
+                }
+                continue;
+            }
+        };
+        let conn = db.conn().map_err(EfakturaError::Database)?;
+        insert_purchase_invoice(&conn, sef_invoice_id, &status, &header, &xml)?;
+        println!(
+            "📥 Входящий рачун {} от {} (SEF {})",
+            header.invoice_number,
+            header.supplier_name.as_deref().unwrap_or("?"),
+            sef_invoice_id
+        );
+        summary.purchases_received += 1;
+    }
+
+    // Следующий опрос начинаем с сегодняшнего дня: документы дня придут повторно и отсеются по sef_invoice_id.
+    // После временного сбоя окно не сдвигаем — пропущенные документы запросим ещё раз.
+    if !retry_later {
+        let conn = db.conn().map_err(EfakturaError::Database)?;
+        conn.execute(
+            "UPDATE efaktura_settings SET purchase_synced_until = ?1 WHERE id = 1",
+            params![date_to],
+        )?;
+    }
+
+    Ok(summary)
+}
+
+/// Повторная загрузка входящего, сохранённого с ошибкой (load_error)
+pub async fn retry_purchase_invoice(db: &Database, id: &str) -> Result<PurchaseInvoice, EfakturaError> {
+    let (config, sef_invoice_id) = {
+        let conn = db.conn().map_err(EfakturaError::Database)?;
+        let (sef_invoice_id, load_error): (i64, Option<String>) = conn
+            .query_row(
+                "SELECT sef_invoice_id, load_error FROM purchase_invoices WHERE id = ?1",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| EfakturaError::InvoiceNotFound(id.to_string()))?;
+        if load_error.is_none() {
+            return load_purchase_invoice(&conn, id);
+        }
+        (efaktura::load_config(&conn)?, sef_invoice_id)
+    };
+    let client = EfakturaClient::new(config)?;
+    let fetched = fetch_purchase_invoice(&client, sef_invoice_id).await;
+
+    let conn = db.conn().map_err(EfakturaError::Database)?;
+    match fetched {
+        Ok((status, xml, header)) => insert_purchase_invoice(&conn, sef_invoice_id, &status, &header, &xml)?,
+        Err(failed) => {
+            if is_permanent(&failed.error) {
+                insert_failed_purchase_invoice(&conn, sef_invoice_id, &failed)?;
+            }
+            return Err(failed.error);
+        }
+    }
+    println!("📥 Входящий рачун SEF {} загружен повторно", sef_invoice_id);
+    load_purchase_invoice(&conn, id)
+}
+
+async fn fetch_purchase_invoice(
+    client: &EfakturaClient,
+    sef_invoice_id: i64,
+) -> Result<(SefInvoiceStatus, String, PurchaseInvoiceHeader), FailedPurchase> {
+    let status = client
+        .get_purchase_invoice(sef_invoice_id)
+        .await
+        .map_err(|error| FailedPurchase { status: None, xml: None, error })?;
+    let xml = match client.get_purchase_invoice_xml(sef_invoice_id).await {
+        Ok(xml) => xml,
+        Err(error) => return Err(FailedPurchase { status: Some(status), xml: None, error }),
+    };
+    match ubl::parse_purchase_invoice(&xml) {
+        Ok(header) => Ok((status, xml, header)),
+        Err(e) => Err(FailedPurchase {
+            status: Some(status),
+            xml: Some(xml),
+            error: EfakturaError::InvalidResponse(format!("purchase invoice {}: {}", sef_invoice_id, e)),
+        }),
+    }
+}
+
+/// Повтор не поможет: документ удалён/недоступен в SEF (4xx) или его UBL не разбирается
+fn is_permanent(e: &EfakturaError) -> bool {
+    matches!(e, EfakturaError::Rejected { .. } | EfakturaError::InvalidResponse(_))
+}
+
+/// Документ удалён в SEF или недоступен (404 и т.п.) — пропускаем его, а не весь опрос.
+fn skip_missing(
+    result: Result<SefInvoiceStatus, EfakturaError>,
+    sef_invoice_id: i64,
+) -> Result<Option<SefInvoiceStatus>, EfakturaError> {
+    match result {
+        Ok(status) => Ok(Some(status)),
+        Err(EfakturaError::Rejected { status, message }) => {
+            println!("⚠️ SEF документ {} пропущен ({}): {}", sef_invoice_id, status, message);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Документы с SEF id в нефинальном статусе (`table` — invoices или purchase_invoices)
+fn pending_documents(conn: &Connection, table: &str) -> Result<Vec<PendingDocument>, EfakturaError> {
+    let placeholders = vec!["?"; efaktura::SEF_FINAL_STATUSES.len()].join(", ");
+    let sql = format!(
+        "SELECT id, sef_invoice_id FROM {} WHERE sef_invoice_id IS NOT NULL AND COALESCE(sef_status, '') NOT IN ({})",
+        table, placeholders
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let docs = stmt
+        .query_map(rusqlite::params_from_iter(efaktura::SEF_FINAL_STATUSES), |row| {
+            Ok(PendingDocument {
+                id: row.get(0)?,
+                sef_invoice_id: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(docs)
+}
+
+/// Сохраняет статус SEF; true — если статус изменился. Финальный статус SEF рачуна
+/// переводит и сам документ (invoices.status, см. efaktura::invoice_status_for) с записью в журнал.
+fn update_status(conn: &Connection, table: &str, id: &str, status: &SefInvoiceStatus) -> Result<bool, EfakturaError> {
+    let previous: Option<String> = conn
+        .query_row(&format!("SELECT sef_status FROM {} WHERE id = ?1", table), [id], |row| row.get(0))
+        .optional()?
+        .flatten();
+    let now = Utc::now().to_rfc3339();
+    let comment = status.comment.as_deref().map(str::trim).filter(|c| !c.is_empty());
+    if table == "invoices" {
+        conn.execute(
+            "UPDATE invoices SET sef_status = ?1, sef_comment = ?2, sef_checked_at = ?3 WHERE id = ?4",
+            params![status.status, comment, now, id],
+        )?;
+        if let Some(invoice_status) = efaktura::invoice_status_for(&status.status) {
+            let before = audit::invoice(conn, id).map_err(EfakturaError::Database)?;
+            conn.execute("UPDATE invoices SET status = ?1 WHERE id = ?2", params![invoice_status, id])?;
+            let after = audit::invoice(conn, id).map_err(EfakturaError::Database)?;
+            let actor = audit::Actor {
+                user_id: None,
+                username: SEF_ACTOR.to_string(),
+            };
+            audit::record(conn, &actor, audit::UPDATE, "invoice", id, before, after).map_err(EfakturaError::Database)?;
+        }
+    } else {
+        conn.execute(
+            "UPDATE purchase_invoices SET sef_status = ?1, sef_comment = ?2, updated_at = ?3 WHERE id = ?4",
+            params![status.status, comment, now, id],
+        )?;
+    }
+
+    let changed = previous.as_deref() != Some(status.status.as_str());
+    if changed {
+        println!(
+            "🔔 SEF статус {} {}: {} -> {}",
+            table,
+            id,
+            previous.as_deref().unwrap_or("-"),
+            status.status
+        );
+    }
+    Ok(changed)
+}
+
+fn purchase_synced_until(conn: &Connection) -> Result<Option<String>, EfakturaError> {
+    let value = conn
+        .query_row("SELECT purchase_synced_until FROM efaktura_settings WHERE id = 1", [], |row| {
+            row.get::<_, Option<String>>(0)
+        })
+        .optional()?
+        .flatten();
+    Ok(value)
+}
+
+fn known_purchase_ids(conn: &Connection) -> Result<HashSet<i64>, EfakturaError> {
+    let mut stmt = conn.prepare("SELECT sef_invoice_id FROM purchase_invoices")?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<HashSet<i64>, _>>()?;
+    Ok(ids)
+}
+
+/// Поставщик из справочника: сначала по PIB, затем по МБ
+fn find_supplier(conn: &Connection, header: &PurchaseInvoiceHeader) -> Result<Option<i64>, EfakturaError> {
+    let candidates = [("pib", header.supplier_pib.as_deref()), ("mb", header.supplier_mb.as_deref())];
+    for (column, value) in candidates {
+        let Some(value) = value.filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        let id = conn
+            .query_row(
+                &format!("SELECT id FROM suppliers WHERE {} = ?1 ORDER BY is_active DESC LIMIT 1", column),
+                [value],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if id.is_some() {
+            return Ok(id);
+        }
+    }
+    Ok(None)
+}
+
+/// Новый входящий документ. Строку, сохранённую с ошибкой загрузки, заменяет; загруженную не трогает.
+fn insert_purchase_invoice(
+    conn: &Connection,
+    sef_invoice_id: i64,
+    status: &SefInvoiceStatus,
+    header: &PurchaseInvoiceHeader,
+    xml: &str,
+) -> Result<(), EfakturaError> {
+    let supplier_id = find_supplier(conn, header)?;
+    conn.execute(
+        "INSERT INTO purchase_invoices (
+            id, sef_invoice_id, sef_status, sef_comment, supplier_id, supplier_name, supplier_pib, supplier_mb,
+            invoice_number, invoice_type_code, issue_date, due_date, currency, total, vat_total, ubl_xml, received_at
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)
+         ON CONFLICT(sef_invoice_id) DO UPDATE SET
+            sef_status = excluded.sef_status, sef_comment = excluded.sef_comment, supplier_id = excluded.supplier_id,
+            supplier_name = excluded.supplier_name, supplier_pib = excluded.supplier_pib, supplier_mb = excluded.supplier_mb,
+            invoice_number = excluded.invoice_number, invoice_type_code = excluded.invoice_type_code,
+            issue_date = excluded.issue_date, due_date = excluded.due_date, currency = excluded.currency,
+            total = excluded.total, vat_total = excluded.vat_total, ubl_xml = excluded.ubl_xml,
+            load_error = NULL, updated_at = excluded.received_at
+         WHERE purchase_invoices.load_error IS NOT NULL",
+        params![
+            uuid::Uuid::new_v4().to_string(),
+            sef_invoice_id,
+            status.status,
+            status.comment,
+            supplier_id,
+            header.supplier_name,
+            header.supplier_pib,
+            header.supplier_mb,
+            header.invoice_number,
+            header.invoice_type_code,
+            header.issue_date,
+            header.due_date,
+            header.currency.as_deref().unwrap_or("RSD"),
+            header.total,
+            header.vat_total,
+            xml,
+            Utc::now().to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Документ, который не удалось загрузить: номер неизвестен, UBL — если SEF успел его отдать.
+/// Повторная ошибка обновляет текст; загруженную строку не трогает.
+fn insert_failed_purchase_invoice(conn: &Connection, sef_invoice_id: i64, failed: &FailedPurchase) -> Result<(), EfakturaError> {
+    conn.execute(
+        "INSERT INTO purchase_invoices (id, sef_invoice_id, sef_status, sef_comment, invoice_number, ubl_xml, load_error, received_at)
+         VALUES (?1, ?2, ?3, ?4, '', ?5, ?6, ?7)
+         ON CONFLICT(sef_invoice_id) DO UPDATE SET
+            sef_status = COALESCE(excluded.sef_status, purchase_invoices.sef_status),
+            sef_comment = COALESCE(excluded.sef_comment, purchase_invoices.sef_comment),
+            ubl_xml = excluded.ubl_xml, load_error = excluded.load_error, updated_at = excluded.received_at
+         WHERE purchase_invoices.load_error IS NOT NULL",
+        params![
+            uuid::Uuid::new_v4().to_string(),
+            sef_invoice_id,
+            failed.status.as_ref().map(|s| s.status.as_str()),
+            failed.status.as_ref().and_then(|s| s.comment.as_deref()),
+            failed.xml,
+            failed.error.to_string(),
+            Utc::now().to_rfc3339(),
+        ],
+    )?;
+    Ok(())
+}
+
+const PURCHASE_COLUMNS: &str = "id, sef_invoice_id, sef_status, sef_comment, supplier_id, supplier_name, supplier_pib, supplier_mb,
+    invoice_number, invoice_type_code, issue_date, due_date, currency, total, vat_total, received_at, updated_at, load_error";
+
+fn purchase_from_row(row: &Row) -> rusqlite::Result<PurchaseInvoice> {
+    Ok(PurchaseInvoice {
+        id: row.get(0)?,
+        sef_invoice_id: row.get(1)?,
+        sef_status: row.get(2)?,
+        sef_comment: row.get(3)?,
+        supplier_id: row.get(4)?,
+        supplier_name: row.get(5)?,
+        supplier_pib: row.get(6)?,
+        supplier_mb: row.get(7)?,
+        invoice_number: row.get(8)?,
+        invoice_type_code: row.get(9)?,
+        issue_date: row.get(10)?,
+        due_date: row.get(11)?,
+        currency: row.get(12)?,
+        total: row.get(13)?,
+        vat_total: row.get(14)?,
+        received_at: row.get(15)?,
+        updated_at: row.get(16)?,
+        load_error: row.get(17)?,
+    })
+}
+
+fn load_purchase_invoice(conn: &Connection, id: &str) -> Result<PurchaseInvoice, EfakturaError> {
+    conn.query_row(
+        &format!("SELECT {} FROM purchase_invoices WHERE id = ?1", PURCHASE_COLUMNS),
+        [id],
+        purchase_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| EfakturaError::InvoiceNotFound(id.to_string()))
+}
+
+pub fn list_purchase_invoices(conn: &Connection, supplier_id: Option<i64>) -> Result<Vec<PurchaseInvoice>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM purchase_invoices
+             WHERE (?1 IS NULL OR supplier_id = ?1)
+             ORDER BY issue_date DESC, received_at DESC",
+            PURCHASE_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let invoices = stmt
+        .query_map([supplier_id], purchase_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(invoices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+
+    const PURCHASE_XML: &str = r#"<env:DocumentEnvelope xmlns:env="urn:eFaktura:MinFinrs:envelop:schema"><env:DocumentBody>
+        <Invoice xmlns="urn:oasis:names:specification:ubl:schema:xsd:Invoice-2"
+                 xmlns:cac="urn:oasis:names:specification:ubl:schema:xsd:CommonAggregateComponents-2"
+                 xmlns:cbc="urn:oasis:names:specification:ubl:schema:xsd:CommonBasicComponents-2">
+          <cbc:ID>R-44/2026</cbc:ID>
+          <cbc:IssueDate>2026-03-02</cbc:IssueDate>
+          <cbc:DocumentCurrencyCode>RSD</cbc:DocumentCurrencyCode>
+          <cac:AccountingSupplierParty><cac:Party>
+            <cbc:EndpointID schemeID="9948">100000024</cbc:EndpointID>
+            <cac:PartyName><cbc:Name>Ambalaža DOO</cbc:Name></cac:PartyName>
+          </cac:Party></cac:AccountingSupplierParty>
+          <cac:LegalMonetaryTotal><cbc:PayableAmount currencyID="RSD">1200.00</cbc:PayableAmount></cac:LegalMonetaryTotal>
+        </Invoice>
+    </env:DocumentBody></env:DocumentEnvelope>"#;
+
+    #[tokio::test]
+    async fn test_poll_updates_sales_status_and_receives_purchase_invoices() {
+        let dir = std::env::temp_dir().join(format!("srecha-poll-{}", uuid::Uuid::new_v4()));
+        let db = Database::new(dir.clone()).unwrap();
+        db.init().unwrap();
+
+        let mut server = mockito::Server::new_async().await;
+        {
+            let conn = db.conn().unwrap();
+            conn.execute_batch(&format!(
+                "UPDATE efaktura_settings SET base_url = '{}', api_key = 'secret' WHERE id = 1;
+                 INSERT INTO suppliers (name, pib, created_at) VALUES ('Ambalaža', '100000024', '');
+                 INSERT INTO invoices (id, invoice_number, document_type, date, total, status, created_at, sef_invoice_id, sef_status)
+                 VALUES ('sent', '1/2026', 'racun', '2026-03-01', 120.0, 'confirmed', '', 103, 'Sent'),
+                        ('done', '2/2026', 'racun', '2026-03-01', 120.0, 'confirmed', '', 104, 'Approved');",
+                server.url()
+            ))
+            .unwrap();
+        }
+
+        server
+            .mock("GET", "/api/publicApi/sales-invoice")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "103".into()))
+            .with_body(r#"{"InvoiceId":103,"Status":"Rejected","Comment":"Pogrešna cena"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/publicApi/purchase-invoice/ids")
+            .match_query(Matcher::Any)
+            .with_body(r#"{"PurchaseInvoiceIds":[501]}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/publicApi/purchase-invoice")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "501".into()))
+            .with_body(r#"{"InvoiceId":501,"Status":"New"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/publicApi/purchase-invoice/xml")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "501".into()))
+            .with_body(PURCHASE_XML)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let summary = poll_once(&db).await.unwrap();
+        assert_eq!(
+            summary,
+            PollSummary {
+                sales_checked: 1,
+                sales_changed: 1,
+                purchases_received: 1,
+                purchases_changed: 0,
+                purchases_skipped: 0,
+            }
+        );
+
+        let conn = db.conn().unwrap();
+        let (sef_status, comment, status): (String, String, String) = conn
+            .query_row("SELECT sef_status, sef_comment, status FROM invoices WHERE id = 'sent'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(
+            (sef_status.as_str(), comment.as_str(), status.as_str()),
+            ("Rejected", "Pogrešna cena", "rejected")
+        );
+        let logged: String = conn
+            .query_row("SELECT username FROM audit_log WHERE entity = 'invoice' AND entity_id = 'sent'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(logged, SEF_ACTOR);
+
+        let inbox = list_purchase_invoices(&conn, None).unwrap();
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox[0].invoice_number, "R-44/2026");
+        assert_eq!(inbox[0].supplier_id, Some(1));
+        assert_eq!(inbox[0].total, Some(1200.0));
+        drop(conn);
+
+        // Повторный опрос: рачун уже в финальном статусе, входящий не дублируется
+        let again = poll_once(&db).await.unwrap();
+        assert_eq!(again.sales_checked, 0);
+        assert_eq!(again.purchases_received, 0);
+
+        drop(db);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_poll_skips_broken_purchase_invoices() {
+        let dir = std::env::temp_dir().join(format!("srecha-poll-{}", uuid::Uuid::new_v4()));
+        let db = Database::new(dir.clone()).unwrap();
+        db.init().unwrap();
+
+        let mut server = mockito::Server::new_async().await;
+        db.conn()
+            .unwrap()
+            .execute(
+                "UPDATE efaktura_settings SET base_url = ?1, api_key = 'secret' WHERE id = 1",
+                [server.url()],
+            )
+            .unwrap();
+        server
+            .mock("POST", "/api/publicApi/purchase-invoice/ids")
+            .match_query(Matcher::Any)
+            .with_body(r#"{"PurchaseInvoiceIds":[501,502,503]}"#)
+            .create_async()
+            .await;
+        for id in ["501", "502", "503"] {
+            server
+                .mock("GET", "/api/publicApi/purchase-invoice")
+                .match_query(Matcher::UrlEncoded("invoiceId".into(), id.into()))
+                .with_body(format!(r#"{{"InvoiceId":{},"Status":"New"}}"#, id))
+                .create_async()
+                .await;
+        }
+        // 501 — битый UBL, 502 — временный сбой SEF, 503 — нормальный документ
+        server
+            .mock("GET", "/api/publicApi/purchase-invoice/xml")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "501".into()))
+            .with_body("<Invoice>")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/publicApi/purchase-invoice/xml")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "502".into()))
+            .with_status(503)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/publicApi/purchase-invoice/xml")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "503".into()))
+            .with_body(PURCHASE_XML)
+            .create_async()
+            .await;
+
+        let summary = poll_once(&db).await.unwrap();
+        assert_eq!((summary.purchases_received, summary.purchases_skipped), (1, 2));
+        let conn = db.conn().unwrap();
+        let inbox = list_purchase_invoices(&conn, None).unwrap();
+        // 501 сохранён с ошибкой, 502 запросим ещё раз
+        let ids: Vec<(i64, bool)> = inbox.iter().map(|p| (p.sef_invoice_id, p.load_error.is_some())).collect();
+        assert_eq!(ids, vec![(503, false), (501, true)]);
+        // Из-за временного сбоя окно опроса не сдвигается
+        assert_eq!(purchase_synced_until(&conn).unwrap(), None);
+
+        drop(conn);
+        drop(db);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_malformed_purchase_invoice_is_kept_for_retry() {
+        let dir = std::env::temp_dir().join(format!("srecha-poll-{}", uuid::Uuid::new_v4()));
+        let db = Database::new(dir.clone()).unwrap();
+        db.init().unwrap();
+
+        let mut server = mockito::Server::new_async().await;
+        db.conn()
+            .unwrap()
+            .execute(
+                "UPDATE efaktura_settings SET base_url = ?1, api_key = 'secret' WHERE id = 1",
+                [server.url()],
+            )
+            .unwrap();
+        server
+            .mock("POST", "/api/publicApi/purchase-invoice/ids")
+            .match_query(Matcher::Any)
+            .with_body(r#"{"PurchaseInvoiceIds":[601,602]}"#)
+            .create_async()
+            .await;
+        for id in ["601", "602"] {
+            server
+                .mock("GET", "/api/publicApi/purchase-invoice")
+                .match_query(Matcher::UrlEncoded("invoiceId".into(), id.into()))
+                .with_body(format!(r#"{{"InvoiceId":{},"Status":"New"}}"#, id))
+                .create_async()
+                .await;
+        }
+        let malformed = server
+            .mock("GET", "/api/publicApi/purchase-invoice/xml")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "601".into()))
+            .with_body("<Invoice><cbc:ID>R-45")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/publicApi/purchase-invoice/xml")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "602".into()))
+            .with_body(PURCHASE_XML)
+            .create_async()
+            .await;
+
+        let summary = poll_once(&db).await.unwrap();
+        assert_eq!((summary.purchases_received, summary.purchases_skipped), (1, 1));
+        let failed = {
+            let conn = db.conn().unwrap();
+            // Битый документ не задерживает окно опроса: он уже сохранён с ошибкой
+            assert!(purchase_synced_until(&conn).unwrap().is_some());
+            let inbox = list_purchase_invoices(&conn, None).unwrap();
+            let failed = inbox.iter().find(|p| p.sef_invoice_id == 601).unwrap().clone();
+            let raw: String = conn
+                .query_row("SELECT ubl_xml FROM purchase_invoices WHERE id = ?1", [&failed.id], |row| row.get(0))
+                .unwrap();
+            assert_eq!(raw, "<Invoice><cbc:ID>R-45");
+            failed
+        };
+        assert_eq!(failed.sef_status.as_deref(), Some("New"));
+        assert!(failed.load_error.as_deref().unwrap().contains("purchase invoice 601"));
+
+        // Следующий опрос его не запрашивает и не дублирует
+        assert_eq!(poll_once(&db).await.unwrap(), PollSummary::default());
+
+        // Повторная загрузка: битый UBL — ошибка остаётся, исправленный — документ загружен
+        assert!(retry_purchase_invoice(&db, &failed.id).await.is_err());
+        malformed.remove_async().await;
+        server
+            .mock("GET", "/api/publicApi/purchase-invoice/xml")
+            .match_query(Matcher::UrlEncoded("invoiceId".into(), "601".into()))
+            .with_body(PURCHASE_XML)
+            .create_async()
+            .await;
+        let loaded = retry_purchase_invoice(&db, &failed.id).await.unwrap();
+        assert_eq!(loaded.invoice_number, "R-44/2026");
+        assert_eq!(loaded.total, Some(1200.0));
+        assert_eq!(loaded.load_error, None);
+
+        drop(db);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+/// Собрано ли приложение с SQLCipher (cargo feature `sqlcipher`). Без него
+/// PRAGMA key молча игнорируется, поэтому все операции ниже сначала проверяют флаг.
+pub const SUPPORTED: bool = cfg!(feature = "sqlcipher");
+
+/// Минимальная длина пароля базы: ключ из него выводит сам SQLCipher
+/// (PBKDF2-HMAC-SHA512, 256 000 итераций, соль в заголовке файла)
+pub const MIN_PASSPHRASE_LEN: usize = 12;
+
+/// Первые 16 байт открытого файла SQLite; у зашифрованного там соль
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptionStatus {
+    /// Приложение собрано с SQLCipher
+    pub supported: bool,
+    pub encrypted: bool,
+    /// Файл зашифрован, пароль ещё не введён — команды получают "Database is locked"
+    pub locked: bool,
+}
+
+fn ensure_supported() -> Result<(), String> {
+    if SUPPORTED {
+        Ok(())
+    } else {
+        Err("Database encryption is not available in this build (cargo feature sqlcipher)".to_string())
+    }
+}
+
+pub fn check_passphrase(passphrase: &str) -> Result<(), String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!("Passphrase must be at least {} characters", MIN_PASSPHRASE_LEN));
+    }
+    Ok(())
+}
+
+/// Зашифрован ли файл. Отсутствующий или пустой файл (новая установка) — открытый.
+pub fn is_encrypted(path: &Path) -> std::io::Result<bool> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let mut header = Vec::with_capacity(SQLITE_HEADER.len());
+    file.by_ref().take(SQLITE_HEADER.len() as u64).read_to_end(&mut header)?;
+    Ok(!header.is_empty() && header.as_slice() != SQLITE_HEADER)
+}
+
+/// Задаёт ключ соединению. Должно быть первой командой после открытия.
+pub fn apply_key(conn: &Connection, passphrase: &str) -> rusqlite::Result<()> {
+    conn.pragma_update(None, "key", passphrase)
+}
+
+/// Открывает файл с паролем и проверяет, что он подходит: SQLCipher
+/// расшифровывает первую страницу только при чтении схемы.
+pub fn open_with_key(path: &Path, passphrase: Option<&str>) -> Result<Connection, String> {
+    let conn = Connection::open(path).map_err(|e| format!("Failed to open database: {}", e))?;
+    if let Some(passphrase) = passphrase {
+        apply_key(&conn, passphrase).map_err(|e| e.to_string())?;
+    }
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0))
+        .map_err(|_| "Wrong database passphrase".to_string())?;
+    Ok(conn)
+}
+
+/// Проверяет пароль зашифрованного файла.
+pub fn verify(path: &Path, passphrase: &str) -> Result<(), String> {
+    ensure_supported()?;
+    open_with_key(path, Some(passphrase)).map(drop)
+}
+
+/// Переписывает файл копией, зашифрованной `new_passphrase`: шифрование открытой базы
+/// (`current` = None) и смена ключа. Копия собирается рядом через sqlcipher_export
+/// и атомарно подменяет исходный файл, так что сбой посередине оставляет старый файл целым.
+/// Пул соединений к файлу на это время должен быть закрыт.
+pub fn rewrite(path: &Path, current: Option<&str>, new_passphrase: &str) -> Result<(), String> {
+    ensure_supported()?;
+    check_passphrase(new_passphrase)?;
+
+    let target = sibling(path, "rekey");
+    remove_if_exists(&target)?;
+
+    let conn = open_with_key(path, current)?;
+    // Всё из WAL переносим в основной файл: после подмены старый -wal не нужен
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .map_err(|e| format!("Failed to checkpoint database: {}", e))?;
+    let export = (|| -> rusqlite::Result<()> {
+        conn.execute(
+            "ATTACH DATABASE ?1 AS rekeyed KEY ?2",
+            params![target.to_string_lossy(), new_passphrase],
+        )?;
+        conn.query_row("SELECT sqlcipher_export('rekeyed')", [], |_| Ok(()))?;
+        conn.execute("DETACH DATABASE rekeyed", [])?;
+        Ok(())
+    })();
+    drop(conn);
+    if let Err(e) = export {
+        remove_if_exists(&target).ok();
+        return Err(format!("Failed to encrypt database: {}", e));
+    }
+
+    if let Err(e) = open_with_key(&target, Some(new_passphrase)) {
+        remove_if_exists(&target).ok();
+        return Err(format!("Encrypted copy is unreadable: {}", e));
+    }
+    restrict_permissions(&target).map_err(|e| e.to_string())?;
+    fs::rename(&target, path).map_err(|e| format!("Failed to replace database file: {}", e))?;
+    for suffix in ["wal", "shm"] {
+        remove_if_exists(&sibling(path, suffix))?;
+    }
+    Ok(())
+}
+
+/// Права 0600 на файл базы и его -wal / -shm. Новые -wal / -shm SQLite создаёт
+/// с правами самого файла базы.
+pub fn restrict_permissions(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        for file in [path.to_path_buf(), sibling(path, "wal"), sibling(path, "shm")] {
+            if file.exists() {
+                fs::set_permissions(&file, fs::Permissions::from_mode(0o600))?;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// `srecha-invoice.db` → `srecha-invoice.db-<suffix>`, как называет свои файлы SQLite
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push("-");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to remove {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db() -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("srecha-crypt-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.db");
+        (dir, path)
+    }
+
+    #[test]
+    fn test_plaintext_detection() {
+        let (dir, path) = temp_db();
+        assert!(!is_encrypted(&path).unwrap());
+        fs::write(&path, b"").unwrap();
+        assert!(!is_encrypted(&path).unwrap());
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute("CREATE TABLE t (x INTEGER)", []).unwrap();
+        drop(conn);
+        assert!(!is_encrypted(&path).unwrap());
+
+        fs::write(&path, [0x5a_u8; 64]).unwrap();
+        assert!(is_encrypted(&path).unwrap());
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    #[test]
+    fn test_rewrite_needs_sqlcipher() {
+        let (dir, path) = temp_db();
+        let err = rewrite(&path, None, "очень-длинный-пароль").unwrap_err();
+        assert!(err.contains("sqlcipher"), "{}", err);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_encrypt_and_rotate_key() {
+        let (dir, path) = temp_db();
+        let conn = Connection::open(&path).unwrap();
+        conn.pragma_update(None, "journal_mode", "WAL").unwrap();
+        conn.execute("CREATE TABLE clients (pib TEXT)", []).unwrap();
+        conn.execute("INSERT INTO clients VALUES ('114407658')", []).unwrap();
+        drop(conn);
+
+        assert!(check_passphrase("short").is_err());
+        rewrite(&path, None, "prvi-kljuc-firme").unwrap();
+        assert!(is_encrypted(&path).unwrap());
+        assert!(!fs::read(&path).unwrap().windows(9).any(|w| w == b"114407658"));
+        assert_eq!(verify(&path, "pogresan-kljuc").unwrap_err(), "Wrong database passphrase");
+
+        rewrite(&path, Some("prvi-kljuc-firme"), "drugi-kljuc-firme").unwrap();
+        assert!(verify(&path, "prvi-kljuc-firme").is_err());
+        let conn = open_with_key(&path, Some("drugi-kljuc-firme")).unwrap();
+        let pib: String = conn.query_row("SELECT pib FROM clients", [], |row| row.get(0)).unwrap();
+        assert_eq!(pib, "114407658");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_dir_all(dir).ok();
+    }
+}
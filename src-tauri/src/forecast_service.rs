@@ -1,6 +1,6 @@
 use crate::database::Database;
 use chrono::{Datelike, NaiveDate};
-use rusqlite::Row;
+use rusqlite::{Connection, Row};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 
@@ -471,14 +471,23 @@ fn next_months(end_month: &str, n: usize) -> Vec<String> {
 }
 
 fn load_transactions(db: &Database, req: &ForecastRequest) -> Result<Vec<Txn>, String> {
+    let conn = db.conn()?;
+    query_transactions(&conn, req)
+}
+
+/// Продажи — только рачуны и кредит-ноты (строки со знаком минус), как в receivables_service.
+/// Предрачун и отпремница того же заказа (convert_document) продажу не дублируют,
+/// черновики, отклонённые и отменённые документы не считаются.
+fn query_transactions(conn: &Connection, req: &ForecastRequest) -> Result<Vec<Txn>, String> {
     // NOTE: пока канал b2b/b2c не храним; req.categories поддерживаем.
     let mut sql = String::from(
-        "SELECT i.date, it.product_id, it.product_name, p.category, it.quantity, \
+        "SELECT i.date, COALESCE(NULLIF(p.internal_code, ''), p.code, it.product_id), it.product_name, p.category, it.quantity, \
                 COALESCE(it.unit_weight_g, p.weight) AS unit_weight_g, it.total \
          FROM invoices i \
          JOIN invoice_items it ON it.invoice_id = i.id \
-         LEFT JOIN products p ON p.internal_code = it.product_id OR p.code = it.product_id \
-         WHERE 1=1",
+         LEFT JOIN products p ON p.id = it.product_id \
+         WHERE i.status NOT IN ('draft', 'rejected', 'cancelled') \
+           AND TRIM(i.document_type) NOT IN ('predracun', 'delivery', 'otpremnica')",
     );
     let mut params_vec: Vec<String> = Vec::new();
 
@@ -505,18 +514,12 @@ fn load_transactions(db: &Database, req: &ForecastRequest) -> Result<Vec<Txn>, S
         }
     }
 
-    let mut stmt = db.conn().prepare(&sql).map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
     let rows = stmt
-        .query_map(rusqlite::params_from_iter(params_vec.iter()), |row| txn_from_row(row))
+        .query_map(rusqlite::params_from_iter(params_vec.iter()), txn_from_row)
         .map_err(|e| e.to_string())?;
 
-    let mut out: Vec<Txn> = Vec::new();
-    for r in rows {
-        if let Ok(txn) = r {
-            out.push(txn);
-        }
-    }
-    Ok(out)
+    Ok(rows.flatten().collect())
 }
 
 fn txn_from_row(row: &Row<'_>) -> rusqlite::Result<Txn> {
@@ -858,6 +861,43 @@ mod tests {
         assert!(m.r2 >= 0.0);
     }
 
+    #[test]
+    fn test_converted_chain_is_counted_once() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::migrations::run(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO products (id, code, name, weight, created_at, updated_at) VALUES ('A', 'A', 'Ajvar', 500, '', '');
+             INSERT INTO invoices (id, invoice_number, document_type, date, total, status, source_document_id, created_at) VALUES
+                 ('p', 'p1/2026', 'predracun', '2026-03-01', 1000, 'confirmed', NULL, ''),
+                 ('r', '1/2026', 'racun', '2026-03-02', 1000, 'confirmed', 'p', ''),
+                 ('o', 'o1/2026', 'delivery', '2026-03-02', 1000, 'confirmed', 'p', ''),
+                 ('k', 'k1/2026', 'knjizno_odobrenje', '2026-03-10', -300, 'confirmed', 'r', ''),
+                 ('d', '2/2026', 'racun', '2026-03-11', 500, 'draft', NULL, '');
+             INSERT INTO invoice_items (id, invoice_id, product_id, product_name, quantity, price, total) VALUES
+                 ('pi', 'p', 'A', 'Ajvar', 10, 100, 1000),
+                 ('ri', 'r', 'A', 'Ajvar', 10, 100, 1000),
+                 ('oi', 'o', 'A', 'Ajvar', 10, 100, 1000),
+                 ('ki', 'k', 'A', 'Ajvar', -3, 100, -300),
+                 ('di', 'd', 'A', 'Ajvar', 5, 100, 500);",
+        )
+        .unwrap();
+        let req = ForecastRequest {
+            start_date: None,
+            end_date: None,
+            categories: None,
+            horizons: None,
+            mode: ForecastMode::NoGrowth,
+            growth_pct_3: None,
+            growth_pct_6: None,
+            growth_pct_12: None,
+        };
+
+        let txns = query_transactions(&conn, &req).unwrap();
+        let units: f64 = txns.iter().map(|t| t.quantity).sum();
+        let revenue: f64 = txns.iter().map(|t| t.total_amount_rsd).sum();
+        assert_eq!((txns.len(), units, revenue), (2, 7.0, 700.0));
+    }
+
     #[test]
     fn test_stability() {
         let v = vec![0.0, 1.0, 0.0, 2.0];
@@ -0,0 +1,437 @@
+use std::fmt;
+
+use qrcode::{render::svg, Color, EcLevel, QrCode};
+
+use crate::commands::Invoice;
+use crate::company::{self, CompanyProfile};
+use crate::numbering;
+
+/// NBS IPS QR: «PR» — налог за пренос, версия 01, кодировка 1 = UTF-8
+const TAG_KIND: &str = "PR";
+const VERSION: &str = "01";
+const CHARSET_UTF8: &str = "1";
+/// IPS принимает только динары
+pub const CURRENCY: &str = "RSD";
+/// Шифра плаћања по умолчанию: промет робе и услуга — финална потрошња
+pub const DEFAULT_PAYMENT_CODE: &str = "221";
+/// Модел 97: контролный номер по ISO 7064 MOD 97-10
+pub const MODEL_97: &str = "97";
+
+const MAX_NAME: usize = 70;
+const MAX_PURPOSE: usize = 35;
+const MAX_REFERENCE: usize = 35;
+const MAX_AMOUNT: f64 = 999_999_999_999.99;
+/// Порядок тегов по спецификации NBS; обязательные — K, V, C, R, N, I
+const TAG_ORDER: [&str; 11] = ["K", "V", "C", "R", "N", "I", "P", "SF", "S", "M", "RO"];
+const REQUIRED_TAGS: [&str; 6] = ["K", "V", "C", "R", "N", "I"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IpsError {
+    /// Тег не по спецификации, повтор или нарушен порядок
+    MalformedPayload(String),
+    MissingTag(&'static str),
+    InvalidField { tag: String, reason: String },
+    UnsupportedCurrency(String),
+    InvalidAmount(f64),
+    Render(String),
+}
+
+impl fmt::Display for IpsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpsError::MalformedPayload(e) => write!(f, "Malformed IPS payload: {}", e),
+            IpsError::MissingTag(tag) => write!(f, "IPS payload has no {} field", tag),
+            IpsError::InvalidField { tag, reason } => write!(f, "Invalid IPS field {}: {}", tag, reason),
+            IpsError::UnsupportedCurrency(c) => write!(f, "IPS QR supports only RSD, not {}", c),
+            IpsError::InvalidAmount(a) => write!(f, "Invalid IPS amount {:.2}", a),
+            IpsError::Render(e) => write!(f, "Failed to render IPS QR: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for IpsError {}
+
+/// Данные для налога: получатель — наша фирма, плательщик — клиент.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpsPayment {
+    /// Текущий рачун получателя (в любом формате, который принимает company::normalize_account_number)
+    pub account: String,
+    /// Получатель: название и адрес, строки через \n
+    pub recipient: String,
+    pub amount: f64,
+    pub currency: String,
+    /// Плательщик (необязательно)
+    pub payer: Option<String>,
+    pub payment_code: String,
+    pub purpose: Option<String>,
+    /// Поле RO целиком: модель + позив на број (например 97 + контрольный номер + номер)
+    pub reference: Option<String>,
+}
+
+/// Позив на број по модели 97 из номера документа: группы цифр через дефис,
+/// впереди два контрольных знака. "12/2026" -> "97" + "98" + "12-2026".
+pub fn model97_reference(invoice_number: &str) -> Option<String> {
+    let groups: Vec<&str> = invoice_number
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|g| !g.is_empty())
+        .collect();
+    if groups.is_empty() {
+        return None;
+    }
+    let number = groups.join("-");
+    let control = model97_control(&number)?;
+    Some(format!("{}{:02}{}", MODEL_97, control, number))
+}
+
+/// 98 - (число * 100 mod 97); дефисы не участвуют в расчёте
+fn model97_control(number: &str) -> Option<u32> {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    if digits.is_empty() {
+        return None;
+    }
+    let remainder = digits
+        .iter()
+        .chain([0, 0].iter())
+        .fold(0u32, |acc, d| (acc * 10 + d) % 97);
+    Some(98 - remainder)
+}
+
+/// Сумма в формате IPS: "1234,50" — десятичная запятая, без разделителя тысяч.
+fn format_amount(amount: f64) -> String {
+    format!("{:.2}", amount).replace('.', ",")
+}
+
+/// Символы | и перевод строки в значениях запрещены; длинные строки обрезаем по лимиту поля.
+fn clean(value: &str, max: usize) -> String {
+    let lines: Vec<String> = value
+        .lines()
+        .map(|l| l.replace('|', " ").trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    let joined = lines.join("\r\n");
+    joined.chars().take(max).collect::<String>().trim_end().to_string()
+}
+
+/// Налог по документу: QR печатается только на рачуне и предрачуне.
+/// `amount` — итог документа, посчитанный pricing; счёт — основной динарский из профиля фирмы.
+pub fn invoice_payment(
+    invoice: &Invoice,
+    amount: f64,
+    company: &CompanyProfile,
+    payer: Option<&str>,
+) -> Result<IpsPayment, IpsError> {
+    let purpose = match numbering::sequence_key(&invoice.document_type) {
+        numbering::RACUN => format!("Plaćanje po računu {}", invoice.invoice_number),
+        numbering::PREDRACUN => format!("Plaćanje po predračunu {}", invoice.invoice_number),
+        other => {
+            return Err(IpsError::MalformedPayload(format!(
+                "IPS QR is only printed on racun and predracun, not {}",
+                other
+            )))
+        }
+    };
+    let currency = invoice.currency.as_deref().unwrap_or(CURRENCY).trim().to_uppercase();
+    let account = company
+        .bank_account_for(CURRENCY)
+        .filter(|a| a.currency == CURRENCY)
+        .ok_or_else(|| IpsError::InvalidField {
+            tag: "R".to_string(),
+            reason: "company profile has no RSD bank account".to_string(),
+        })?;
+    let address = [company.street.as_deref(), company.city.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(IpsPayment {
+        account: account.account_number.clone(),
+        recipient: format!("{}\n{}", company.name, address),
+        amount,
+        currency,
+        payer: payer.map(str::to_string),
+        payment_code: DEFAULT_PAYMENT_CODE.to_string(),
+        purpose: Some(purpose),
+        reference: model97_reference(&invoice.invoice_number),
+    })
+}
+
+/// Сумма к оплате: остаток по журналу оплат и кредит-нотам (`Invoice::balance`),
+/// без него — итог документа. Ноль и меньше — оплачено, QR не нужен.
+pub fn amount_due(invoice: &Invoice, total: f64) -> f64 {
+    invoice.balance.unwrap_or(total)
+}
+
+/// Строит payload IPS QR и сразу проверяет его `validate_payload`.
+pub fn build_payload(payment: &IpsPayment) -> Result<String, IpsError> {
+    let currency = payment.currency.trim().to_uppercase();
+    if currency != CURRENCY {
+        return Err(IpsError::UnsupportedCurrency(currency));
+    }
+    if !payment.amount.is_finite() || payment.amount <= 0.0 || payment.amount > MAX_AMOUNT {
+        return Err(IpsError::InvalidAmount(payment.amount));
+    }
+    let account = company::normalize_account_number(&payment.account).ok_or_else(|| IpsError::InvalidField {
+        tag: "R".to_string(),
+        reason: format!("invalid bank account {}", payment.account),
+    })?;
+
+    let mut fields = vec![
+        format!("K:{}", TAG_KIND),
+        format!("V:{}", VERSION),
+        format!("C:{}", CHARSET_UTF8),
+        format!("R:{}", account.replace('-', "")),
+        format!("N:{}", clean(&payment.recipient, MAX_NAME)),
+        format!("I:{}{}", CURRENCY, format_amount(payment.amount)),
+    ];
+    if let Some(payer) = payment.payer.as_deref().map(|p| clean(p, MAX_NAME)).filter(|p| !p.is_empty()) {
+        fields.push(format!("P:{}", payer));
+    }
+    fields.push(format!("SF:{}", payment.payment_code.trim()));
+    if let Some(purpose) = payment.purpose.as_deref().map(|p| clean(p, MAX_PURPOSE)).filter(|p| !p.is_empty()) {
+        fields.push(format!("S:{}", purpose));
+    }
+    if let Some(reference) = payment.reference.as_deref().map(str::trim).filter(|r| !r.is_empty()) {
+        fields.push(format!("RO:{}", reference));
+    }
+
+    let payload = fields.join("|");
+    validate_payload(&payload)?;
+    Ok(payload)
+}
+
+/// Проверка payload по спецификации NBS IPS: порядок и повторы тегов, обязательные поля,
+/// формат счёта (18 цифр, MOD 97), суммы, шифры плаћања и позива на број по модели 97.
+pub fn validate_payload(payload: &str) -> Result<(), IpsError> {
+    let invalid = |tag: &str, reason: &str| IpsError::InvalidField {
+        tag: tag.to_string(),
+        reason: reason.to_string(),
+    };
+    let mut last_position = None;
+    let mut seen = Vec::new();
+    for field in payload.split('|') {
+        let (tag, value) = field
+            .split_once(':')
+            .ok_or_else(|| IpsError::MalformedPayload(format!("field without tag: {}", field)))?;
+        let position = TAG_ORDER
+            .iter()
+            .position(|t| *t == tag)
+            .ok_or_else(|| IpsError::MalformedPayload(format!("unknown tag {}", tag)))?;
+        if last_position.is_some_and(|last| position <= last) {
+            return Err(IpsError::MalformedPayload(format!("tag {} is out of order or repeated", tag)));
+        }
+        last_position = Some(position);
+        seen.push(tag);
+
+        match tag {
+            "K" if value != TAG_KIND => return Err(invalid(tag, "expected PR")),
+            "V" if value != VERSION => return Err(invalid(tag, "expected 01")),
+            "C" if value != CHARSET_UTF8 => return Err(invalid(tag, "expected 1 (UTF-8)")),
+            "R" => {
+                if value.len() != 18 || !value.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(invalid(tag, "expected 18 digits"));
+                }
+                if company::normalize_account_number(value).is_none() {
+                    return Err(invalid(tag, "control number mismatch"));
+                }
+            }
+            "N" | "P" => {
+                if value.trim().is_empty() || value.chars().count() > MAX_NAME {
+                    return Err(invalid(tag, "expected 1 to 70 characters"));
+                }
+                if value.split("\r\n").count() > 3 {
+                    return Err(invalid(tag, "at most 3 lines"));
+                }
+            }
+            "I" => validate_amount(value).map_err(|reason| invalid(tag, &reason))?,
+            "SF" => {
+                let valid = value.len() == 3
+                    && value.chars().all(|c| c.is_ascii_digit())
+                    && (value.starts_with('1') || value.starts_with('2'));
+                if !valid {
+                    return Err(invalid(tag, "expected 3 digits starting with 1 or 2"));
+                }
+            }
+            "S" if value.chars().count() > MAX_PURPOSE => return Err(invalid(tag, "at most 35 characters")),
+            "M" if value.len() != 4 || !value.chars().all(|c| c.is_ascii_digit()) => {
+                return Err(invalid(tag, "expected 4 digits"))
+            }
+            "RO" => validate_reference(value).map_err(|reason| invalid(tag, &reason))?,
+            _ => {}
+        }
+    }
+    for tag in REQUIRED_TAGS {
+        if !seen.contains(&tag) {
+            return Err(IpsError::MissingTag(tag));
+        }
+    }
+    Ok(())
+}
+
+fn validate_amount(value: &str) -> Result<(), String> {
+    let amount = value
+        .strip_prefix(CURRENCY)
+        .ok_or_else(|| "expected RSD amount".to_string())?;
+    let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    let valid = match amount.split_once(',') {
+        Some((int_part, frac_part)) => {
+            !int_part.is_empty() && int_part.len() <= 12 && digits(int_part) && frac_part.len() <= 2 && digits(frac_part)
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(format!("invalid amount {}, expected RSD1234,56", amount));
+    }
+    Ok(())
+}
+
+fn validate_reference(value: &str) -> Result<(), String> {
+    if value.len() < 3 || value.len() > MAX_REFERENCE {
+        return Err("expected model and reference, at most 35 characters".to_string());
+    }
+    let (model, reference) = value.split_at(2);
+    if !model.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("invalid model {}", model));
+    }
+    if model == MODEL_97 {
+        if reference.len() < 3 || !reference.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            return Err("model 97 reference must contain digits and dashes".to_string());
+        }
+        let (control, number) = reference.split_at(2);
+        let expected = model97_control(number).ok_or_else(|| "model 97 reference has no digits".to_string())?;
+        if control != format!("{:02}", expected) {
+            return Err(format!("model 97 control number {} does not match {:02}", control, expected));
+        }
+    }
+    Ok(())
+}
+
+/// Матрица QR: true — тёмный модуль. Уровень коррекции M, как требует NBS.
+pub fn qr_modules(payload: &str) -> Result<(usize, Vec<bool>), IpsError> {
+    let code = QrCode::with_error_correction_level(payload.as_bytes(), EcLevel::M)
+        .map_err(|e| IpsError::Render(e.to_string()))?;
+    let modules = code.to_colors().into_iter().map(|c| c == Color::Dark).collect();
+    Ok((code.width(), modules))
+}
+
+pub fn render_svg(payload: &str, size_px: u32) -> Result<String, IpsError> {
+    let code = QrCode::with_error_correction_level(payload.as_bytes(), EcLevel::M)
+        .map_err(|e| IpsError::Render(e.to_string()))?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(size_px, size_px)
+        .quiet_zone(true)
+        .build())
+}
+
+/// PNG в оттенках серого: `scale` пикселей на модуль и 4 модуля тихой зоны.
+pub fn render_png(payload: &str, scale: u32) -> Result<Vec<u8>, IpsError> {
+    const QUIET_ZONE: usize = 4;
+    let (width, modules) = qr_modules(payload)?;
+    let scale = scale.max(1) as usize;
+    let side = (width + 2 * QUIET_ZONE) * scale;
+    let mut pixels = vec![255u8; side * side];
+    for (index, dark) in modules.iter().enumerate() {
+        if !dark {
+            continue;
+        }
+        let (row, col) = (index / width + QUIET_ZONE, index % width + QUIET_ZONE);
+        for y in row * scale..(row + 1) * scale {
+            pixels[y * side + col * scale..y * side + (col + 1) * scale].fill(0);
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| IpsError::Render(e.to_string()))?;
+    writer.write_image_data(&pixels).map_err(|e| IpsError::Render(e.to_string()))?;
+    writer.finish().map_err(|e| IpsError::Render(e.to_string()))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment() -> IpsPayment {
+        IpsPayment {
+            account: "190-0000000085540-29".to_string(),
+            recipient: "Среħа 2024 ДОО\nМајке Јевросиме 35, Београд".to_string(),
+            amount: 1234.5,
+            currency: "RSD".to_string(),
+            payer: Some("Кафана Ђурђевак".to_string()),
+            payment_code: DEFAULT_PAYMENT_CODE.to_string(),
+            purpose: Some("Plaćanje po računu 12/2026".to_string()),
+            reference: model97_reference("12/2026"),
+        }
+    }
+
+    #[test]
+    fn test_model97_reference() {
+        // 122026 * 100 mod 97 = 0 -> 98 - 0 = 98
+        assert_eq!(model97_reference("12/2026").as_deref(), Some("979812-2026"));
+        assert_eq!(model97_reference("o1/2026").as_deref(), Some("97071-2026"));
+        assert_eq!(model97_reference("bez broja"), None);
+        assert!(validate_reference("979812-2026").is_ok());
+        assert!(validate_reference("972212-2026").is_err());
+    }
+
+    #[test]
+    fn test_build_payload() {
+        let payload = build_payload(&payment()).unwrap();
+        assert_eq!(
+            payload,
+            "K:PR|V:01|C:1|R:190000000008554029|N:Среħа 2024 ДОО\r\nМајке Јевросиме 35, Београд|I:RSD1234,50|P:Кафана Ђурђевак|SF:221|S:Plaćanje po računu 12/2026|RO:979812-2026"
+        );
+    }
+
+    #[test]
+    fn test_build_payload_rejects_foreign_currency_and_zero_amount() {
+        let mut eur = payment();
+        eur.currency = "EUR".to_string();
+        assert_eq!(build_payload(&eur).unwrap_err(), IpsError::UnsupportedCurrency("EUR".to_string()));
+        let mut zero = payment();
+        zero.amount = 0.0;
+        assert_eq!(build_payload(&zero).unwrap_err(), IpsError::InvalidAmount(0.0));
+    }
+
+    #[test]
+    fn test_validate_payload_format() {
+        let valid = "K:PR|V:01|C:1|R:190000000008554029|N:Srecha|I:RSD100,00|SF:221";
+        assert!(validate_payload(valid).is_ok());
+
+        let cases = [
+            ("K:PR|V:01|C:1|R:190000000008554028|N:Srecha|I:RSD100,00|SF:221", "R"),
+            ("K:PR|V:01|C:1|R:190000000008554029|N:Srecha|I:RSD100.00|SF:221", "I"),
+            ("K:PR|V:01|C:1|R:190000000008554029|N:Srecha|I:RSD100,00|SF:321", "SF"),
+            ("K:PR|V:01|C:1|R:190000000008554029|N:Srecha|I:RSD100,00|SF:221|RO:971112-2026", "RO"),
+        ];
+        for (payload, tag) in cases {
+            match validate_payload(payload) {
+                Err(IpsError::InvalidField { tag: t, .. }) => assert_eq!(t, tag, "{}", payload),
+                other => panic!("{}: {:?}", payload, other),
+            }
+        }
+        assert_eq!(
+            validate_payload("K:PR|V:01|C:1|R:190000000008554029|I:RSD100,00"),
+            Err(IpsError::MissingTag("N"))
+        );
+        assert!(matches!(
+            validate_payload("K:PR|V:01|C:1|N:Srecha|R:190000000008554029|I:RSD100,00"),
+            Err(IpsError::MalformedPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_render_png_and_svg() {
+        let payload = build_payload(&payment()).unwrap();
+        let png = render_png(&payload, 4).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        let svg = render_svg(&payload, 200).unwrap();
+        assert!(svg.contains("<svg"));
+        let (width, modules) = qr_modules(&payload).unwrap();
+        assert_eq!(modules.len(), width * width);
+    }
+}
@@ -1,9 +1,28 @@
 mod database;
+mod migrations;
+mod pricing;
+mod numbering;
+mod company;
+mod validation;
+mod audit;
+mod encryption;
+mod backup;
+mod session;
+mod users;
+mod client_merge;
+mod payments;
+mod ubl;
+mod ips;
+mod pdf;
+mod efaktura;
+mod efaktura_poller;
 mod commands;
 mod forecast_service;
+mod receivables_service;
 
 use tauri::Manager;
 use database::Database;
+use session::Session;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -21,11 +40,56 @@ pub fn run() {
             let db = Database::new(app_data_dir)
                 .expect("Failed to initialize database");
             
-            db.init().expect("Failed to initialize database tables");
-            db.set_permissions().ok(); // Устанавливаем права доступа
+            // Ошибка миграции останавливает запуск, а не оставляет полуобновлённую базу.
+            // Зашифрованную базу мигрирует unlock_database после ввода пароля.
+            if !db.is_locked() {
+                db.init()
+                    .map_err(|e| format!("Failed to migrate database: {}", e))?;
+            }
+            db.set_permissions().ok(); // Только владелец файла (0600)
             
             // Сохраняем базу данных в состоянии приложения
             app.manage(db);
+            // Текущий пользователь — автор записей журнала изменений
+            app.manage(Session::default());
+            
+            // Фоновый опрос eFaktura: статусы отправленных рачуна и входящие рачуни поставщиков
+            let poll_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(efaktura_poller::FIRST_POLL_DELAY).await;
+                loop {
+                    let db = poll_handle.state::<Database>();
+                    if db.is_locked() {
+                        tokio::time::sleep(efaktura_poller::POLL_INTERVAL).await;
+                        continue;
+                    }
+                    match efaktura_poller::poll_once(&db).await {
+                        Ok(summary) => println!("✅ eFaktura опрос: {:?}", summary),
+                        // ApiKey не задан — eFaktura просто не используется
+                        Err(efaktura::EfakturaError::NotConfigured) => {}
+                        Err(e) => println!("⚠️ eFaktura опрос: {}", e),
+                    }
+                    tokio::time::sleep(efaktura_poller::POLL_INTERVAL).await;
+                }
+            });
+            
+            // Резервные копии по расписанию (настройки — backup_settings)
+            let backup_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(backup::FIRST_CHECK_DELAY).await;
+                loop {
+                    let db = backup_handle.state::<Database>();
+                    match backup_handle.path().app_data_dir() {
+                        Ok(dir) => match backup::run_scheduled(&db, &dir) {
+                            Ok(Some(created)) => println!("✅ Резервная копия по расписанию: {}", created.name),
+                            Ok(None) => {}
+                            Err(e) => println!("⚠️ Резервная копия: {}", e),
+                        },
+                        Err(e) => println!("⚠️ Резервная копия: {}", e),
+                    }
+                    tokio::time::sleep(backup::SCHEDULE_CHECK_INTERVAL).await;
+                }
+            });
             
             // Создаем Splash Screen окно
             let splash_window = tauri::WebviewWindowBuilder::new(
@@ -73,11 +137,29 @@ pub fn run() {
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
+            commands::get_setup_status,
+            commands::get_database_encryption,
+            commands::unlock_database,
+            commands::create_initial_admin,
             commands::login,
+            commands::logout,
+            commands::get_session,
+            // Пользователи
+            commands::get_users,
+            commands::get_roles,
+            commands::create_user,
+            commands::update_user,
+            commands::set_user_active,
+            commands::reset_user_password,
+            commands::change_password,
             commands::get_clients,
             commands::create_client,
             commands::update_client,
             commands::delete_client,
+            commands::validate_client,
+            commands::find_duplicate_clients,
+            commands::merge_clients,
+            commands::get_client_merges,
             commands::get_products,
             commands::create_product,
             commands::get_product_by_code,
@@ -88,9 +170,31 @@ pub fn run() {
             commands::create_invoice,
             commands::update_invoice_status,
             commands::update_invoice,
+            commands::update_invoice_with_items,
+            commands::convert_document,
+            commands::create_credit_note,
             commands::update_invoice_payment_status,
+            // Оплаты
+            commands::record_payment,
+            commands::get_payments,
+            commands::reverse_payment,
+            // Профиль фирмы
+            commands::get_company_profile,
+            commands::update_company_profile,
+            // eFaktura (SEF)
+            commands::get_efaktura_settings,
+            commands::update_efaktura_settings,
+            commands::send_invoice_to_efaktura,
+            commands::export_invoice_ubl,
+            commands::sync_efaktura,
+            commands::get_purchase_invoices,
+            commands::retry_purchase_invoice,
             commands::delete_invoice,
             commands::get_client_history,
+            // Нумерация документов
+            commands::get_document_sequences,
+            commands::update_document_sequence,
+            commands::get_next_document_number,
             commands::get_deliveries,
             commands::create_delivery,
             commands::get_warehouse_groups,
@@ -101,6 +205,9 @@ pub fn run() {
             commands::save_invoice_html,
             commands::load_invoice_html,
             commands::delete_invoice_html,
+            commands::generate_invoice_pdf,
+            commands::generate_invoice_pdfs,
+            commands::get_invoice_ips_qr,
             commands::get_categories,
             commands::create_category,
             commands::delete_category,
@@ -122,11 +229,24 @@ pub fn run() {
             commands::create_supplier,
             commands::update_supplier,
             commands::delete_supplier,
+            commands::validate_supplier,
             // HTTP для синхронизации
             commands::http_request,
             // НБС курс (для мультивалютности)
             commands::fetch_nbs_rate,
             commands::get_forecast_report,
+            commands::get_receivables_report,
+            // Схема БД
+            commands::get_schema_version,
+            commands::encrypt_database,
+            commands::rotate_database_key,
+            commands::get_backup_settings,
+            commands::save_backup_settings,
+            commands::list_backups,
+            commands::create_backup,
+            commands::restore_backup,
+            // Журнал изменений
+            commands::get_audit_log,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,1258 @@
+use rusqlite::{ffi, params, Connection, Transaction};
+use serde::Serialize;
+use std::fmt;
+
+use crate::numbering;
+
+/// Одна версия схемы. Миграции применяются строго по возрастанию `version`,
+/// каждая — в собственной транзакции вместе с записью в `schema_migrations`.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+/// Реестр миграций. Новые миграции добавляются ТОЛЬКО в конец списка,
+/// уже выпущенные миграции не редактируются.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "initial_schema", up: m001_initial_schema },
+    Migration { version: 2, name: "legacy_columns", up: m002_legacy_columns },
+    Migration { version: 3, name: "pricing", up: m003_pricing },
+    Migration { version: 4, name: "document_numbering", up: m004_document_numbering },
+    Migration { version: 5, name: "document_lineage", up: m005_document_lineage },
+    Migration { version: 6, name: "credit_notes", up: m006_credit_notes },
+    Migration { version: 7, name: "payments", up: m007_payments },
+    Migration { version: 8, name: "efaktura", up: m008_efaktura },
+    Migration { version: 9, name: "efaktura_inbox", up: m009_efaktura_inbox },
+    Migration { version: 10, name: "company_profile", up: m010_company_profile },
+    Migration { version: 11, name: "client_merges", up: m011_client_merges },
+    Migration { version: 12, name: "foreign_keys", up: m012_foreign_keys },
+    Migration { version: 13, name: "audit_log", up: m013_audit_log },
+    Migration { version: 14, name: "user_accounts", up: m014_user_accounts },
+    Migration { version: 15, name: "ownership", up: m015_ownership },
+    Migration { version: 16, name: "login_throttling", up: m016_login_throttling },
+    Migration { version: 17, name: "backup_settings", up: m017_backup_settings },
+    Migration { version: 18, name: "purchase_load_errors", up: m018_purchase_load_errors },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// Не удалось прочитать или создать таблицу `schema_migrations`
+    Bookkeeping(rusqlite::Error),
+    /// Миграция упала, её транзакция откатена
+    Failed {
+        version: i64,
+        name: &'static str,
+        source: rusqlite::Error,
+    },
+    /// База создана более новой версией приложения
+    NewerSchema { db_version: i64, app_version: i64 },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Bookkeeping(e) => write!(f, "schema_migrations недоступна: {}", e),
+            MigrationError::Failed { version, name, source } => {
+                write!(f, "миграция {} ({}) не применена: {}", version, name, source)
+            }
+            MigrationError::NewerSchema { db_version, app_version } => write!(
+                f,
+                "версия схемы базы ({}) новее, чем поддерживает приложение ({})",
+                db_version, app_version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaVersion {
+    pub current: i64,
+    pub latest: i64,
+    pub applied: Vec<AppliedMigration>,
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Применяет все ещё не применённые миграции. Возвращает количество применённых.
+pub fn run(conn: &Connection) -> Result<usize, MigrationError> {
+    run_migrations(conn, MIGRATIONS)
+}
+
+fn run_migrations(conn: &Connection, migrations: &[Migration]) -> Result<usize, MigrationError> {
+    ensure_bookkeeping_table(conn).map_err(MigrationError::Bookkeeping)?;
+    let current = current_version(conn).map_err(MigrationError::Bookkeeping)?;
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+    if current > latest {
+        return Err(MigrationError::NewerSchema {
+            db_version: current,
+            app_version: latest,
+        });
+    }
+
+    // PRAGMA foreign_keys внутри транзакции не действует, а пересборка таблиц
+    // (m012) должна идти с выключенными ключами — выключаем на время миграций
+    let foreign_keys: bool = conn
+        .pragma_query_value(None, "foreign_keys", |row| row.get(0))
+        .map_err(MigrationError::Bookkeeping)?;
+    if foreign_keys {
+        conn.pragma_update(None, "foreign_keys", false)
+            .map_err(MigrationError::Bookkeeping)?;
+    }
+    let applied = apply_pending(conn, migrations, current);
+    if foreign_keys {
+        conn.pragma_update(None, "foreign_keys", true)
+            .map_err(MigrationError::Bookkeeping)?;
+    }
+    applied
+}
+
+fn apply_pending(conn: &Connection, migrations: &[Migration], current: i64) -> Result<usize, MigrationError> {
+    let mut applied = 0;
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        let fail = |source| MigrationError::Failed {
+            version: migration.version,
+            name: migration.name,
+            source,
+        };
+        let tx = conn.unchecked_transaction().map_err(fail)?;
+        (migration.up)(&tx).map_err(fail)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.name, chrono::Utc::now().to_rfc3339()],
+        )
+        .map_err(fail)?;
+        tx.commit().map_err(fail)?;
+
+        println!("✅ Миграция {} ({}) применена", migration.version, migration.name);
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+fn ensure_bookkeeping_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+pub fn current_version(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", [], |row| row.get(0))
+}
+
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<SchemaVersion> {
+    let mut stmt = conn.prepare("SELECT version, name, applied_at FROM schema_migrations ORDER BY version ASC")?;
+    let applied = stmt
+        .query_map([], |row| {
+            Ok(AppliedMigration {
+                version: row.get(0)?,
+                name: row.get(1)?,
+                applied_at: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(SchemaVersion {
+        current: applied.last().map(|m| m.version).unwrap_or(0),
+        latest: latest_version(),
+        applied,
+    })
+}
+
+fn column_exists(tx: &Transaction, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = tx.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(names.iter().any(|n| n == column))
+}
+
+/// ALTER TABLE ... ADD COLUMN, но только если колонки ещё нет.
+/// В отличие от старого `let _ = execute(...)` любая другая ошибка пробрасывается.
+fn add_column_if_missing(tx: &Transaction, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    if !column_exists(tx, table, column)? {
+        tx.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+/// SQLite не умеет добавлять ограничения к существующей таблице: создаём копию с
+/// дополнительными `constraints`, переносим строки и индексы, старую удаляем
+/// (порядок из https://www.sqlite.org/lang_altertable.html#otheralter).
+/// Вызывать при выключенных foreign_keys, иначе DROP TABLE проверит ссылки на таблицу.
+fn rebuild_with_constraints(tx: &Transaction, table: &str, constraints: &[&str]) -> rusqlite::Result<()> {
+    let sql: String = tx.query_row(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [table],
+        |row| row.get(0),
+    )?;
+    let indexes: Vec<String> = {
+        let mut stmt = tx.prepare("SELECT sql FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1 AND sql IS NOT NULL")?;
+        let rows = stmt.query_map([table], |row| row.get(0))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    // Тело CREATE TABLE между первой «(» и последней «)» — колонки, в т.ч. добавленные ALTER TABLE
+    let (Some(open), Some(close)) = (sql.find('('), sql.rfind(')')) else {
+        return Err(rusqlite::Error::InvalidParameterName(sql));
+    };
+
+    let rebuilt = format!("{}_rebuild", table);
+    tx.execute(
+        &format!("CREATE TABLE {} {}, {})", rebuilt, &sql[open..close], constraints.join(", ")),
+        [],
+    )?;
+    tx.execute(&format!("INSERT INTO {} SELECT * FROM {}", rebuilt, table), [])?;
+    tx.execute(&format!("DROP TABLE {}", table), [])?;
+    tx.execute(&format!("ALTER TABLE {} RENAME TO {}", rebuilt, table), [])?;
+    for index in indexes {
+        tx.execute(&index, [])?;
+    }
+    Ok(())
+}
+
+/// Ошибка, если в базе есть ссылки на несуществующие записи.
+fn check_foreign_keys(tx: &Transaction) -> rusqlite::Result<()> {
+    let mut stmt = tx.prepare("PRAGMA foreign_key_check")?;
+    let violations = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    match violations.first() {
+        None => Ok(()),
+        Some((table, rowid, parent)) => Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_CONSTRAINT_FOREIGNKEY),
+            Some(format!(
+                "{} foreign key violations, first: {} rowid {:?} -> {}",
+                violations.len(),
+                table,
+                rowid,
+                parent
+            )),
+        )),
+    }
+}
+
+// ==================== МИГРАЦИИ ====================
+
+/// Исходная схема. Для баз, созданных до появления `schema_migrations`,
+/// все CREATE TABLE IF NOT EXISTS — no-op.
+fn m001_initial_schema(tx: &Transaction) -> rusqlite::Result<()> {
+    // 1. Таблица пользователей
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+            id TEXT PRIMARY KEY,
+            username TEXT UNIQUE NOT NULL,
+            password TEXT NOT NULL,
+            role TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    
+    // 2. Таблица клиентов (24 поля + updated_at для синхронизации)
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS clients (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            legal_name TEXT,
+            mb TEXT NOT NULL,
+            pib TEXT,
+            address TEXT,
+            city TEXT,
+            postal_code TEXT,
+            country TEXT,
+            phone TEXT,
+            email TEXT,
+            tax_id TEXT,
+            bank TEXT,
+            client_type TEXT,
+            municipality TEXT,
+            street TEXT,
+            house_number TEXT,
+            is_manual_address INTEGER DEFAULT 0,
+            google_maps TEXT,
+            contact_person TEXT,
+            telegram TEXT,
+            instagram TEXT,
+            installment INTEGER DEFAULT 0,
+            installment_term INTEGER,
+            showcase INTEGER DEFAULT 0,
+            bar INTEGER DEFAULT 0,
+            notes TEXT,
+            contact TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT
+        )",
+        [],
+    )?;
+    
+    
+    // 3. Таблица товаров
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS products (
+            id TEXT PRIMARY KEY,
+            code TEXT UNIQUE NOT NULL,
+            name TEXT NOT NULL,
+            description TEXT,
+            price REAL,
+            category TEXT,
+            weight REAL,
+            supplier TEXT,
+            is_active INTEGER DEFAULT 1,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    
+    
+    // 4. Таблица инвойсов
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS invoices (
+            id TEXT PRIMARY KEY,
+            invoice_number TEXT UNIQUE NOT NULL,
+            document_type TEXT NOT NULL,
+            client_id TEXT,
+            client_name TEXT,
+            date TEXT NOT NULL,
+            due_date TEXT,
+            total REAL NOT NULL,
+            status TEXT NOT NULL,
+            notes TEXT,
+            created_at TEXT NOT NULL,
+            paid INTEGER DEFAULT 0,
+            delivered INTEGER DEFAULT 0,
+            currency TEXT DEFAULT 'RSD',
+            exchange_rate REAL,
+            exchange_rate_date TEXT
+        )",
+        [],
+    )?;
+    
+    // Кэш курсов НБС (date,currency -> rate)
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS nbs_rates (
+            date TEXT NOT NULL,
+            currency TEXT NOT NULL,
+            rate REAL NOT NULL,
+            fetched_at TEXT NOT NULL,
+            PRIMARY KEY (date, currency)
+        )",
+        [],
+    )?;
+    
+    // 5. Таблица позиций инвойса
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS invoice_items (
+            id TEXT PRIMARY KEY,
+            invoice_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            product_name TEXT NOT NULL,
+            quantity REAL NOT NULL,
+            unit_weight_g REAL,
+            price REAL NOT NULL,
+            total REAL NOT NULL,
+            FOREIGN KEY (invoice_id) REFERENCES invoices(id)
+        )",
+        [],
+    )?;
+    
+    // 6. Таблица доставок
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS deliveries (
+            id TEXT PRIMARY KEY,
+            delivery_number TEXT UNIQUE NOT NULL,
+            client_id TEXT,
+            client_name TEXT,
+            date TEXT NOT NULL,
+            status TEXT NOT NULL,
+            notes TEXT,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    
+    // 7. Таблица позиций доставки
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS delivery_items (
+            id TEXT PRIMARY KEY,
+            delivery_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            product_name TEXT NOT NULL,
+            quantity REAL NOT NULL,
+            FOREIGN KEY (delivery_id) REFERENCES deliveries(id)
+        )",
+        [],
+    )?;
+    
+    // 8. Таблица групп склада
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS warehouse_groups (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    
+    // 9. Таблица товаров на складе
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS warehouse_items (
+            id TEXT PRIMARY KEY,
+            group_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            product_code TEXT NOT NULL,
+            product_name TEXT NOT NULL,
+            quantity REAL NOT NULL,
+            notes TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (group_id) REFERENCES warehouse_groups(id),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )",
+        [],
+    )?;
+    
+    // 10. Таблица статистики
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS statistics (
+            id TEXT PRIMARY KEY,
+            date TEXT NOT NULL,
+            type TEXT NOT NULL,
+            value REAL NOT NULL,
+            metadata TEXT,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    
+    // 11. Таблица категорий
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS categories (
+            id TEXT PRIMARY KEY,
+            name TEXT UNIQUE NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    
+    // 12. Таблица субкатегорий
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS subcategories (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            category_id TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (category_id) REFERENCES categories(id)
+        )",
+        [],
+    )?;
+    
+    // 13. Таблица секторов поставщиков (аналог категорий)
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS supplier_sectors (
+            id TEXT PRIMARY KEY,
+            name TEXT UNIQUE NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    
+    // 14. Таблица продукции поставщиков (аналог субкатегорий)
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS supplier_products (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            sector_id TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (sector_id) REFERENCES supplier_sectors(id)
+        )",
+        [],
+    )?;
+    
+    // 15. Таблица поставщиков
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS suppliers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            legal_name TEXT,
+            mb TEXT,
+            pib TEXT,
+            address TEXT,
+            city TEXT,
+            phone TEXT,
+            email TEXT,
+            telegram TEXT,
+            instagram TEXT,
+            website TEXT,
+            bank TEXT,
+            sector_id TEXT,
+            product_id TEXT,
+            contact_person TEXT,
+            contact_person_status TEXT,
+            google_maps TEXT,
+            notes TEXT,
+            is_active INTEGER DEFAULT 1,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (sector_id) REFERENCES supplier_sectors(id),
+            FOREIGN KEY (product_id) REFERENCES supplier_products(id)
+        )",
+        [],
+    )?;
+    
+    // 16. Таблица стран (члены ООН)
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS countries (
+            id TEXT PRIMARY KEY,
+            name TEXT UNIQUE NOT NULL,
+            code TEXT,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Колонки, которые раньше добавлялись ad-hoc через ALTER TABLE в `Database::init`.
+/// Старые базы могут не иметь любой из них.
+fn m002_legacy_columns(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "clients", "updated_at", "TEXT")?;
+    add_column_if_missing(tx, "clients", "contact_person_status", "TEXT")?;
+    add_column_if_missing(tx, "clients", "abbreviation", "TEXT")?;
+
+    add_column_if_missing(tx, "products", "supplier", "TEXT")?;
+    add_column_if_missing(tx, "products", "subcategory", "TEXT")?;
+    add_column_if_missing(tx, "products", "internal_code", "TEXT")?;
+
+    add_column_if_missing(tx, "invoices", "paid", "INTEGER DEFAULT 0")?;
+    add_column_if_missing(tx, "invoices", "delivered", "INTEGER DEFAULT 0")?;
+    // Мультивалютность
+    add_column_if_missing(tx, "invoices", "currency", "TEXT DEFAULT 'RSD'")?;
+    add_column_if_missing(tx, "invoices", "exchange_rate", "REAL")?;
+    add_column_if_missing(tx, "invoices", "exchange_rate_date", "TEXT")?;
+
+    add_column_if_missing(tx, "invoice_items", "unit_weight_g", "REAL")?;
+
+    add_column_if_missing(tx, "suppliers", "country", "TEXT")?;
+    // Для не-сербских компаний
+    add_column_if_missing(tx, "suppliers", "reg_number", "TEXT")?;
+    add_column_if_missing(tx, "suppliers", "wechat", "TEXT")?;
+
+    Ok(())
+}
+
+/// Рабат, ставки ПДВ и суммы, посчитанные на сервере (модуль `pricing`).
+fn m003_pricing(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "invoice_items", "discount_pct", "REAL DEFAULT 0")?;
+    add_column_if_missing(tx, "invoice_items", "vat_rate", "REAL")?;
+    add_column_if_missing(tx, "invoice_items", "vat_exemption_code", "TEXT")?;
+    add_column_if_missing(tx, "invoice_items", "vat_amount", "REAL")?;
+
+    add_column_if_missing(tx, "invoices", "subtotal", "REAL")?;
+    add_column_if_missing(tx, "invoices", "vat_total", "REAL")?;
+    add_column_if_missing(tx, "invoices", "vat_rate", "REAL")?;
+    add_column_if_missing(tx, "invoices", "vat_exemption_code", "TEXT")?;
+
+    // Разбивка ПДВ по ставкам (одна строка на ставку/код освобождения)
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS invoice_tax_breakdown (
+            id TEXT PRIMARY KEY,
+            invoice_id TEXT NOT NULL,
+            vat_rate REAL NOT NULL,
+            vat_exemption_code TEXT,
+            taxable_amount REAL NOT NULL,
+            vat_amount REAL NOT NULL,
+            FOREIGN KEY (invoice_id) REFERENCES invoices(id)
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_invoice_tax_breakdown_invoice ON invoice_tax_breakdown(invoice_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Последовательности номеров по типам документов (модуль `numbering`).
+/// Существующие номера приводятся к каноническому виду, счётчики — к максимуму по году.
+fn m004_document_numbering(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS document_sequences (
+            document_type TEXT PRIMARY KEY,
+            prefix TEXT NOT NULL DEFAULT '',
+            template TEXT NOT NULL,
+            reset_yearly INTEGER NOT NULL DEFAULT 1,
+            updated_at TEXT
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS document_sequence_counters (
+            document_type TEXT NOT NULL,
+            year INTEGER NOT NULL,
+            last_number INTEGER NOT NULL,
+            PRIMARY KEY (document_type, year),
+            FOREIGN KEY (document_type) REFERENCES document_sequences(document_type)
+        )",
+        [],
+    )?;
+
+    let sequences: Vec<numbering::DocumentSequence> = [
+        (numbering::RACUN, ""),
+        (numbering::PREDRACUN, "p"),
+        (numbering::OTPREMNICA, "o"),
+    ]
+    .into_iter()
+    .map(|(document_type, prefix)| numbering::DocumentSequence {
+        document_type: document_type.to_string(),
+        prefix: prefix.to_string(),
+        template: numbering::DEFAULT_TEMPLATE.to_string(),
+        reset_yearly: true,
+        updated_at: None,
+    })
+    .collect();
+    for seq in &sequences {
+        tx.execute(
+            "INSERT OR IGNORE INTO document_sequences (document_type, prefix, template, reset_yearly) VALUES (?1, ?2, ?3, 1)",
+            params![seq.document_type, seq.prefix, seq.template],
+        )?;
+    }
+
+    let mut stmt = tx.prepare("SELECT id, invoice_number, COALESCE(document_type, ''), COALESCE(date, '') FROM invoices")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    drop(stmt);
+    for (id, number, document_type, date) in rows {
+        let key = numbering::sequence_key(&document_type);
+        let Some(seq) = sequences.iter().find(|seq| seq.document_type == key) else {
+            continue;
+        };
+        let canonical = numbering::canonical_number(seq, &number, &date);
+        if canonical != number {
+            // При совпадении с уже существующим номером оставляем как есть
+            tx.execute(
+                "UPDATE invoices SET invoice_number = ?1 WHERE id = ?2
+                 AND NOT EXISTS (SELECT 1 FROM invoices WHERE invoice_number = ?1)",
+                params![canonical, id],
+            )?;
+        }
+        if let Some((n, year)) = numbering::parse_number(&number) {
+            let year = year.unwrap_or_else(|| numbering::document_year(&date));
+            tx.execute(
+                "INSERT INTO document_sequence_counters (document_type, year, last_number) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(document_type, year) DO UPDATE SET last_number = MAX(last_number, excluded.last_number)",
+                params![seq.document_type, year, n],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Связь документов: из какого предрачуна создан рачун/отпремница.
+fn m005_document_lineage(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "invoices", "source_document_id", "TEXT REFERENCES invoices(id)")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_invoices_source_document ON invoices(source_document_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Кредит-ноты: ссылка строки на сторнируемую строку рачуна и отдельная нумерация.
+fn m006_credit_notes(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "invoice_items", "credited_item_id", "TEXT REFERENCES invoice_items(id)")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_invoice_items_credited ON invoice_items(credited_item_id)",
+        [],
+    )?;
+    tx.execute(
+        "INSERT OR IGNORE INTO document_sequences (document_type, prefix, template, reset_yearly) VALUES (?1, 'KO-', ?2, 1)",
+        params![numbering::KNJIZNO_ODOBRENJE, numbering::DEFAULT_TEMPLATE],
+    )?;
+
+    Ok(())
+}
+
+/// Журнал оплат вместо флага `invoices.paid`. Документы, отмеченные оплаченными,
+/// получают одну оплату на всю сумму датой документа.
+fn m007_payments(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS payments (
+            id TEXT PRIMARY KEY,
+            invoice_id TEXT NOT NULL,
+            date TEXT NOT NULL,
+            amount REAL NOT NULL,
+            currency TEXT NOT NULL DEFAULT 'RSD',
+            method TEXT NOT NULL,
+            reference TEXT,
+            note TEXT,
+            created_at TEXT NOT NULL,
+            reversed_at TEXT,
+            reversal_reason TEXT,
+            FOREIGN KEY (invoice_id) REFERENCES invoices(id)
+        )",
+        [],
+    )?;
+    tx.execute("CREATE INDEX IF NOT EXISTS idx_payments_invoice ON payments(invoice_id)", [])?;
+    tx.execute(
+        "INSERT INTO payments (id, invoice_id, date, amount, currency, method, note, created_at)
+         SELECT lower(hex(randomblob(16))), id, substr(date, 1, 10), total, COALESCE(currency, 'RSD'), 'other',
+                'Перенесено из флага paid', datetime('now')
+         FROM invoices
+         WHERE paid = 1 AND total > 0
+           AND NOT EXISTS (SELECT 1 FROM payments p WHERE p.invoice_id = invoices.id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// eFaktura (SEF): идентификатор и статус документа в SEF, настройки подключения.
+fn m008_efaktura(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "invoices", "sef_invoice_id", "INTEGER")?;
+    add_column_if_missing(tx, "invoices", "sef_request_id", "TEXT")?;
+    add_column_if_missing(tx, "invoices", "sef_status", "TEXT")?;
+    add_column_if_missing(tx, "invoices", "sef_sent_at", "TEXT")?;
+    add_column_if_missing(tx, "invoices", "sef_error", "TEXT")?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_invoices_sef_invoice ON invoices(sef_invoice_id)",
+        [],
+    )?;
+
+    // Одна строка (id = 1): ApiKey раньше хранился в localStorage фронтенда
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS efaktura_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            base_url TEXT NOT NULL,
+            api_key TEXT,
+            updated_at TEXT
+        )",
+        [],
+    )?;
+    tx.execute(
+        "INSERT OR IGNORE INTO efaktura_settings (id, base_url) VALUES (1, 'https://efaktura.mfin.gov.rs')",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Опрос SEF: комментарий покупателя к статусу, время последней проверки,
+/// входящие рачуни поставщиков.
+fn m009_efaktura_inbox(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "invoices", "sef_comment", "TEXT")?;
+    add_column_if_missing(tx, "invoices", "sef_checked_at", "TEXT")?;
+    // Дата, с которой запрашивать входящие при следующем опросе
+    add_column_if_missing(tx, "efaktura_settings", "purchase_synced_until", "TEXT")?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS purchase_invoices (
+            id TEXT PRIMARY KEY,
+            sef_invoice_id INTEGER NOT NULL UNIQUE,
+            sef_status TEXT,
+            sef_comment TEXT,
+            supplier_id INTEGER,
+            supplier_name TEXT,
+            supplier_pib TEXT,
+            supplier_mb TEXT,
+            invoice_number TEXT NOT NULL,
+            invoice_type_code TEXT,
+            issue_date TEXT,
+            due_date TEXT,
+            currency TEXT NOT NULL DEFAULT 'RSD',
+            total REAL,
+            vat_total REAL,
+            ubl_xml TEXT,
+            received_at TEXT NOT NULL,
+            updated_at TEXT,
+            FOREIGN KEY (supplier_id) REFERENCES suppliers(id)
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_purchase_invoices_supplier ON purchase_invoices(supplier_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Реквизиты фирмы и текущие рачуни — раньше были зашиты в шаблоны UI и ubl.rs.
+/// Заполняем теми же значениями, чтобы документы после обновления не поменялись.
+fn m010_company_profile(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS company_profile (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            name TEXT NOT NULL,
+            legal_name TEXT,
+            pib TEXT NOT NULL,
+            mb TEXT NOT NULL,
+            street TEXT,
+            city TEXT,
+            postal_code TEXT,
+            country_code TEXT NOT NULL DEFAULT 'RS',
+            email TEXT,
+            phone TEXT,
+            website TEXT,
+            logo TEXT,
+            invoice_footer TEXT,
+            updated_at TEXT
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS company_bank_accounts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_number TEXT NOT NULL UNIQUE,
+            bank_name TEXT,
+            currency TEXT NOT NULL DEFAULT 'RSD',
+            is_default INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "INSERT OR IGNORE INTO company_profile (id, name, pib, mb, street, city, country_code)
+         VALUES (1, 'Среħа 2024 ДОО Београд (Стари Град)', '114407658', '22019309', 'Мајке Јевросиме 35', 'Београд', 'RS')",
+        [],
+    )?;
+    tx.execute(
+        "INSERT OR IGNORE INTO company_bank_accounts (account_number, bank_name, currency, is_default)
+         VALUES ('190-0000000085540-29', 'Alta Banka', 'RSD', 1)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Журнал объединения дублей клиентов: снимок удалённой записи и сколько документов
+/// перенесено на оставшуюся.
+fn m011_client_merges(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS client_merges (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            surviving_id INTEGER NOT NULL,
+            merged_id INTEGER NOT NULL,
+            merged_snapshot TEXT NOT NULL,
+            invoices_moved INTEGER NOT NULL DEFAULT 0,
+            deliveries_moved INTEGER NOT NULL DEFAULT 0,
+            merged_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_client_merges_surviving ON client_merges(surviving_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Внешние ключи включены (`configure_connection`), поэтому:
+/// - чистим строки, которые ссылаются на давно удалённые записи;
+/// - invoices.client_id хранил id или МБ клиента — приводим к clients.id;
+/// - invoice_items.product_id хранил внутренний код или код товара — приводим к products.id.
+///   Коды, которых уже нет в справочнике, становятся неактивными товарами, чтобы документы
+///   не потеряли код (при чтении product_id отдаётся как код товара).
+fn m012_foreign_keys(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "DELETE FROM invoice_items WHERE invoice_id NOT IN (SELECT id FROM invoices);
+         DELETE FROM invoice_tax_breakdown WHERE invoice_id NOT IN (SELECT id FROM invoices);
+         DELETE FROM payments WHERE invoice_id NOT IN (SELECT id FROM invoices);
+         UPDATE invoices SET source_document_id = NULL
+          WHERE source_document_id IS NOT NULL AND source_document_id NOT IN (SELECT id FROM invoices);
+         UPDATE invoice_items SET credited_item_id = NULL
+          WHERE credited_item_id IS NOT NULL AND credited_item_id NOT IN (SELECT id FROM invoice_items);
+         DELETE FROM delivery_items WHERE delivery_id NOT IN (SELECT id FROM deliveries);
+         UPDATE warehouse_items SET product_id = COALESCE(
+             (SELECT p.id FROM products p
+               WHERE p.internal_code = warehouse_items.product_id OR p.code = warehouse_items.product_id
+               ORDER BY p.is_active DESC LIMIT 1),
+             product_id)
+          WHERE product_id NOT IN (SELECT id FROM products);
+         DELETE FROM warehouse_items
+          WHERE group_id NOT IN (SELECT id FROM warehouse_groups) OR product_id NOT IN (SELECT id FROM products);
+         DELETE FROM subcategories WHERE category_id NOT IN (SELECT id FROM categories);
+         DELETE FROM supplier_products WHERE sector_id NOT IN (SELECT id FROM supplier_sectors);
+         UPDATE purchase_invoices SET supplier_id = NULL
+          WHERE supplier_id IS NOT NULL AND supplier_id NOT IN (SELECT id FROM suppliers);
+         DELETE FROM document_sequence_counters
+          WHERE document_type NOT IN (SELECT document_type FROM document_sequences);",
+    )?;
+
+    // В старых базах suppliers мог быть создан без этих колонок
+    if column_exists(tx, "suppliers", "sector_id")? {
+        tx.execute(
+            "UPDATE suppliers SET sector_id = NULL
+             WHERE sector_id IS NOT NULL AND sector_id NOT IN (SELECT id FROM supplier_sectors)",
+            [],
+        )?;
+    }
+    if column_exists(tx, "suppliers", "product_id")? {
+        tx.execute(
+            "UPDATE suppliers SET product_id = NULL
+             WHERE product_id IS NOT NULL AND product_id NOT IN (SELECT id FROM supplier_products)",
+            [],
+        )?;
+    }
+
+    // Клиент: id как есть, МБ -> id, неизвестные ссылки обнуляем (client_name остаётся)
+    tx.execute(
+        "UPDATE invoices SET client_id = (
+             SELECT CAST(c.id AS TEXT) FROM clients c WHERE c.mb = invoices.client_id ORDER BY c.id LIMIT 1)
+         WHERE client_id IS NOT NULL
+           AND NOT EXISTS (SELECT 1 FROM clients c WHERE CAST(c.id AS TEXT) = invoices.client_id)",
+        [],
+    )?;
+
+    tx.execute(
+        "UPDATE invoice_items SET product_id = (
+             SELECT p.id FROM products p
+              WHERE p.internal_code = invoice_items.product_id OR p.code = invoice_items.product_id
+              ORDER BY p.is_active DESC LIMIT 1)
+         WHERE product_id NOT IN (SELECT id FROM products)
+           AND EXISTS (SELECT 1 FROM products p
+                        WHERE p.internal_code = invoice_items.product_id OR p.code = invoice_items.product_id)",
+        [],
+    )?;
+    let unknown: Vec<(String, String)> = {
+        let mut stmt = tx.prepare(
+            "SELECT product_id, MIN(product_name) FROM invoice_items
+             WHERE product_id NOT IN (SELECT id FROM products) GROUP BY product_id",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    for (code, name) in unknown {
+        let id = insert_placeholder_product(tx, &code, &name)?;
+        tx.execute("UPDATE invoice_items SET product_id = ?1 WHERE product_id = ?2", params![id, code])?;
+    }
+
+    rebuild_with_constraints(tx, "invoices", &["FOREIGN KEY (client_id) REFERENCES clients(id)"])?;
+    rebuild_with_constraints(tx, "invoice_items", &["FOREIGN KEY (product_id) REFERENCES products(id)"])?;
+    check_foreign_keys(tx)
+}
+
+/// Неактивный товар-заглушка для кода, которого нет в справочнике: документ сохраняет код
+/// и название позиции. Если код пуст или уже занят, code получает `ARCHIVED-…`.
+pub(crate) fn insert_placeholder_product(conn: &Connection, code: &str, name: &str) -> rusqlite::Result<String> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let code_taken: bool = conn.query_row("SELECT EXISTS(SELECT 1 FROM products WHERE code = ?1)", [code], |row| row.get(0))?;
+    let product_code = if code.trim().is_empty() || code_taken {
+        format!("ARCHIVED-{}", &id[..8])
+    } else {
+        code.to_string()
+    };
+    let now = chrono::Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO products (id, code, name, internal_code, is_active, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, 0, ?5, ?5)",
+        params![id, product_code, name, code, now],
+    )?;
+    Ok(id)
+}
+
+/// Журнал изменений (audit.rs): кто, когда и что поменял в записи. Пользователь хранится
+/// и как id, и как имя — запись журнала переживает удаление пользователя.
+fn m013_audit_log(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id TEXT,
+            username TEXT NOT NULL,
+            action TEXT NOT NULL,
+            entity TEXT NOT NULL,
+            entity_id TEXT NOT NULL,
+            diff TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log(entity, entity_id)",
+        [],
+    )?;
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_audit_log_username ON audit_log(username)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Учётные записи (users.rs): отключение вместо удаления, имя для UI и привязка
+/// профиля клиента к записи в clients.
+fn m014_user_accounts(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "users", "is_active", "INTEGER NOT NULL DEFAULT 1")?;
+    add_column_if_missing(tx, "users", "full_name", "TEXT")?;
+    add_column_if_missing(tx, "users", "client_id", "INTEGER REFERENCES clients(id)")?;
+    add_column_if_missing(tx, "users", "updated_at", "TEXT")?;
+
+    Ok(())
+}
+
+/// Кто создал и кто последним изменил запись (audit::stamp). Для старых записей пусто.
+fn m015_ownership(tx: &Transaction) -> rusqlite::Result<()> {
+    for table in ["clients", "products", "invoices", "deliveries", "warehouse_groups", "suppliers", "payments"] {
+        add_column_if_missing(tx, table, "created_by", "TEXT")?;
+        add_column_if_missing(tx, table, "modified_by", "TEXT")?;
+    }
+
+    Ok(())
+}
+
+/// Неудачные входы и блокировка учётной записи (users::check_throttle).
+fn m016_login_throttling(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "users", "failed_logins", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "users", "last_failed_login", "TEXT")?;
+    add_column_if_missing(tx, "users", "locked_until", "TEXT")?;
+    add_column_if_missing(tx, "users", "last_login_at", "TEXT")?;
+
+    Ok(())
+}
+
+/// Расписание и хранение резервных копий (backup.rs). Одна строка (id = 1);
+/// directory NULL — папка backups рядом с базой.
+fn m017_backup_settings(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS backup_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            directory TEXT,
+            interval_hours INTEGER NOT NULL DEFAULT 24,
+            keep_last INTEGER NOT NULL DEFAULT 7,
+            keep_days INTEGER NOT NULL DEFAULT 30,
+            updated_at TEXT
+        )",
+        [],
+    )?;
+    tx.execute("INSERT OR IGNORE INTO backup_settings (id) VALUES (1)", [])?;
+
+    Ok(())
+}
+
+/// Входящие, которые не удалось загрузить (4xx SEF, битый UBL), хранятся строкой с текстом ошибки,
+/// чтобы их можно было увидеть и загрузить повторно; NULL — документ загружен.
+fn m018_purchase_load_errors(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "purchase_invoices", "load_error", "TEXT")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+        stmt.query_map([], |row| row.get::<_, String>(1))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fresh_database_reaches_latest_version() {
+        let conn = Connection::open_in_memory().unwrap();
+        let applied = run(&conn).unwrap();
+        assert_eq!(applied, MIGRATIONS.len());
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+
+        // Повторный запуск ничего не делает
+        assert_eq!(run(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_legacy_database_gets_missing_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        // Таблица в том виде, в каком её создавали первые версии приложения
+        conn.execute(
+            "CREATE TABLE suppliers (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, created_at TEXT NOT NULL)",
+            [],
+        )
+        .unwrap();
+
+        run(&conn).unwrap();
+
+        let cols = columns(&conn, "suppliers");
+        assert!(cols.contains(&"country".to_string()));
+        assert!(cols.contains(&"reg_number".to_string()));
+        assert!(cols.contains(&"wechat".to_string()));
+    }
+
+    #[test]
+    fn test_existing_invoice_numbers_become_canonical() {
+        let conn = Connection::open_in_memory().unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        m001_initial_schema(&tx).unwrap();
+        m002_legacy_columns(&tx).unwrap();
+        tx.execute_batch(
+            "INSERT INTO invoices (id, invoice_number, document_type, date, total, status, created_at) VALUES
+                ('a', '7/2026', 'predracun', '2026-02-01', 0, 'confirmed', ''),
+                ('b', 'p5/2026', 'racun', '2026-02-01', 0, 'confirmed', ''),
+                ('c', '1-08052026', 'delivery', '2026-05-08', 0, 'confirmed', '');",
+        )
+        .unwrap();
+        tx.commit().unwrap();
+
+        run(&conn).unwrap();
+
+        let number = |id: &str| -> String {
+            conn.query_row("SELECT invoice_number FROM invoices WHERE id = ?1", [id], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(number("a"), "p7/2026");
+        assert_eq!(number("b"), "5/2026");
+        assert_eq!(number("c"), "1-08052026");
+        let last: i64 = conn
+            .query_row(
+                "SELECT last_number FROM document_sequence_counters WHERE document_type = 'predracun' AND year = 2026",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(last, 7);
+    }
+
+    fn m_create_probe(tx: &Transaction) -> rusqlite::Result<()> {
+        tx.execute("CREATE TABLE probe (id INTEGER)", [])?;
+        Ok(())
+    }
+
+    fn m_broken(tx: &Transaction) -> rusqlite::Result<()> {
+        tx.execute("CREATE TABLE half_done (id INTEGER)", [])?;
+        tx.execute("ALTER TABLE no_such_table ADD COLUMN x TEXT", [])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_migration_is_rolled_back_and_reported() {
+        let conn = Connection::open_in_memory().unwrap();
+        let migrations = [
+            Migration { version: 1, name: "probe", up: m_create_probe },
+            Migration { version: 2, name: "broken", up: m_broken },
+        ];
+
+        let err = run_migrations(&conn, &migrations).unwrap_err();
+        match err {
+            MigrationError::Failed { version, name, .. } => {
+                assert_eq!(version, 2);
+                assert_eq!(name, "broken");
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        // Первая миграция осталась, вторая откатилась целиком
+        assert_eq!(current_version(&conn).unwrap(), 1);
+        let half_done: i64 = conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = 'half_done'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(half_done, 0);
+    }
+
+    #[test]
+    fn test_foreign_keys_migration_normalizes_references() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn, &MIGRATIONS[..11]).unwrap();
+        // Данные, накопленные без внешних ключей: ссылки по МБ и коду, висячие строки
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             INSERT INTO clients (id, name, mb, created_at) VALUES (7, 'Kafana', '20000007', '');
+             INSERT INTO products (id, code, name, internal_code, created_at, updated_at) VALUES
+                ('p-tea', 'T-100', 'Čaj', 'CAJ-1', '', '');
+             INSERT INTO invoices (id, invoice_number, document_type, client_id, date, total, status, created_at) VALUES
+                ('a', '1/2026', 'racun', '20000007', '2026-01-01', 10, 'confirmed', ''),
+                ('b', '2/2026', 'racun', '7', '2026-01-02', 10, 'confirmed', ''),
+                ('c', '3/2026', 'racun', '99999999', '2026-01-03', 10, 'confirmed', '');
+             INSERT INTO invoice_items (id, invoice_id, product_id, product_name, quantity, price, total) VALUES
+                ('a1', 'a', 'CAJ-1', 'Čaj', 1, 10, 10),
+                ('b1', 'b', 'T-100', 'Čaj', 1, 10, 10),
+                ('c1', 'c', 'OLD-5', 'Stara šolja', 1, 10, 10),
+                ('x1', 'gone', 'CAJ-1', 'Čaj', 1, 10, 10);
+             PRAGMA foreign_keys = ON;",
+        )
+        .unwrap();
+
+        run(&conn).unwrap();
+
+        let client_ids: Vec<Option<String>> = conn
+            .prepare("SELECT client_id FROM invoices ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(client_ids, vec![Some("7".to_string()), Some("7".to_string()), None]);
+
+        let items: Vec<(String, String)> = conn
+            .prepare("SELECT i.id, p.internal_code FROM invoice_items i JOIN products p ON p.id = i.product_id ORDER BY i.id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            items,
+            vec![
+                ("a1".to_string(), "CAJ-1".to_string()),
+                ("b1".to_string(), "CAJ-1".to_string()),
+                ("c1".to_string(), "OLD-5".to_string()),
+            ]
+        );
+        // Неизвестный код стал неактивным товаром, а не потерялся
+        let archived: (String, i64) = conn
+            .query_row("SELECT name, is_active FROM products WHERE code = 'OLD-5'", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(archived, ("Stara šolja".to_string(), 0));
+
+        // Ограничения действуют
+        assert!(conn.execute("DELETE FROM clients WHERE id = 7", []).is_err());
+        assert!(conn.execute("DELETE FROM products WHERE id = 'p-tea'", []).is_err());
+        assert!(conn
+            .execute("UPDATE invoices SET client_id = '12345' WHERE id = 'c'", [])
+            .is_err());
+    }
+
+    #[test]
+    fn test_newer_schema_is_rejected() {
+        let conn = Connection::open_in_memory().unwrap();
+        run(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, 'future', '')",
+            [latest_version() + 1],
+        )
+        .unwrap();
+
+        assert!(matches!(run(&conn), Err(MigrationError::NewerSchema { .. })));
+    }
+}
@@ -0,0 +1,388 @@
+use chrono::{Datelike, NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+
+/// Последовательности номеров. Номер резервируется внутри транзакции создания документа,
+/// поэтому отменённое создание не оставляет дыр в нумерации.
+pub const RACUN: &str = "racun";
+pub const PREDRACUN: &str = "predracun";
+pub const OTPREMNICA: &str = "otpremnica";
+/// Кредит-нота (knjižno odobrenje)
+pub const KNJIZNO_ODOBRENJE: &str = "knjizno_odobrenje";
+
+pub const DEFAULT_TEMPLATE: &str = "{N}/{YEAR}";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSequence {
+    pub document_type: String,
+    /// Префикс канонического номера: "" для рачуна, "p" для предрачуна, "o" для отпремницы
+    pub prefix: String,
+    /// Шаблон номера: {N} — порядковый номер, {YEAR} — год документа
+    pub template: String,
+    /// Сбрасывать счётчик 1 января
+    pub reset_yearly: bool,
+    pub updated_at: Option<String>,
+}
+
+/// Тип документа из UI -> ключ последовательности.
+/// UI исторически называет отпремницу "delivery", а рачун иногда "invoice" или пустой строкой.
+pub fn sequence_key(document_type: &str) -> &'static str {
+    match document_type.trim() {
+        "predracun" => PREDRACUN,
+        "delivery" | "otpremnica" => OTPREMNICA,
+        "knjizno_odobrenje" | "credit_note" => KNJIZNO_ODOBRENJE,
+        _ => RACUN,
+    }
+}
+
+/// Год документа из `invoices.date` (YYYY-MM-DD, RFC 3339 или DD.MM.YYYY); иначе текущий.
+pub fn document_year(date: &str) -> i32 {
+    let s = date.trim();
+    if let Ok(d) = NaiveDate::parse_from_str(s.get(..10).unwrap_or(s), "%Y-%m-%d") {
+        return d.year();
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%d.%m.%Y") {
+        return d.year();
+    }
+    Utc::now().year()
+}
+
+/// Разбирает номер вида "[префикс]N/YEAR" или "[префикс]N". Возвращает (N, YEAR).
+pub fn parse_number(raw: &str) -> Option<(i64, Option<i32>)> {
+    let s = raw.trim();
+    let (head, year) = match s.rsplit_once('/') {
+        Some((head, tail)) => {
+            let tail = tail.trim();
+            if tail.len() != 4 || !tail.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            (head.trim_end(), Some(tail.parse().ok()?))
+        }
+        None => (s, None),
+    };
+    let digits_start = head
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_ascii_digit())
+        .last()
+        .map(|(idx, _)| idx)?;
+    // Цифры до номера означают другой формат (например "1-08052026") — не угадываем
+    if head[..digits_start].chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let n: i64 = head[digits_start..].parse().ok()?;
+    if n <= 0 {
+        return None;
+    }
+    Some((n, year))
+}
+
+pub fn format_number(seq: &DocumentSequence, n: i64, year: i32) -> String {
+    let body = seq
+        .template
+        .replace("{N}", &n.to_string())
+        .replace("{YEAR}", &year.to_string());
+    format!("{}{}", seq.prefix, body)
+}
+
+/// Канонический вид номера для последовательности: "p7/2026", "o12/2026", "42/2026".
+/// Номера старого формата (например "1-08052026") хранятся как есть.
+pub fn canonical_number(seq: &DocumentSequence, raw: &str, date: &str) -> String {
+    match parse_number(raw) {
+        Some((n, year)) => format_number(seq, n, year.unwrap_or_else(|| document_year(date))),
+        None => raw.trim().to_string(),
+    }
+}
+
+pub fn load_sequence(conn: &Connection, document_type: &str) -> Result<DocumentSequence, String> {
+    let key = sequence_key(document_type);
+    conn.query_row(
+        "SELECT document_type, prefix, template, reset_yearly, updated_at FROM document_sequences WHERE document_type = ?1",
+        params![key],
+        |row| {
+            Ok(DocumentSequence {
+                document_type: row.get(0)?,
+                prefix: row.get(1)?,
+                template: row.get(2)?,
+                reset_yearly: row.get::<_, i32>(3)? != 0,
+                updated_at: row.get(4)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Document sequence {} not found", key))
+}
+
+pub fn list_sequences(conn: &Connection) -> Result<Vec<DocumentSequence>, String> {
+    let mut stmt = conn
+        .prepare("SELECT document_type FROM document_sequences ORDER BY document_type")
+        .map_err(|e| e.to_string())?;
+    let keys = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    keys.iter().map(|key| load_sequence(conn, key)).collect()
+}
+
+pub fn save_sequence(conn: &Connection, seq: &DocumentSequence) -> Result<DocumentSequence, String> {
+    let key = sequence_key(&seq.document_type);
+    if !seq.template.contains("{N}") {
+        return Err("Number template must contain {N}".to_string());
+    }
+    if seq.reset_yearly && !seq.template.contains("{YEAR}") {
+        return Err("Number template must contain {YEAR} when the sequence resets yearly".to_string());
+    }
+    conn.execute(
+        "UPDATE document_sequences SET prefix = ?1, template = ?2, reset_yearly = ?3, updated_at = ?4 WHERE document_type = ?5",
+        params![seq.prefix.trim(), seq.template.trim(), seq.reset_yearly, Utc::now().to_rfc3339(), key],
+    )
+    .map_err(|e| e.to_string())?;
+    load_sequence(conn, key)
+}
+
+fn counter_year(seq: &DocumentSequence, year: i32) -> i32 {
+    // Без ежегодного сброса весь счётчик живёт в строке year = 0
+    if seq.reset_yearly {
+        year
+    } else {
+        0
+    }
+}
+
+fn last_number(conn: &Connection, key: &str, year: i32) -> Result<i64, String> {
+    conn.query_row(
+        "SELECT last_number FROM document_sequence_counters WHERE document_type = ?1 AND year = ?2",
+        params![key, year],
+        |row| row.get(0),
+    )
+    .optional()
+    .map(|n| n.unwrap_or(0))
+    .map_err(|e| e.to_string())
+}
+
+/// Номер, который получит следующий документ (без резервирования — для подсказки в UI).
+pub fn peek_next(conn: &Connection, document_type: &str, date: &str) -> Result<String, String> {
+    let seq = load_sequence(conn, document_type)?;
+    let year = document_year(date);
+    let last = last_number(conn, &seq.document_type, counter_year(&seq, year))?;
+    Ok(format_number(&seq, last + 1, year))
+}
+
+fn number_exists(tx: &Transaction, number: &str, exclude_id: Option<&str>) -> Result<bool, String> {
+    tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM invoices WHERE invoice_number = ?1 AND id IS NOT ?2)",
+        params![number, exclude_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Назначает номер новому документу внутри транзакции создания.
+/// Пустой номер — резервируем следующий в последовательности. Явный номер (импорт,
+/// синхронизация с другого устройства) приводится к каноническому виду и допускается
+/// только следующим по счётчику или на место пропуска — дыр в нумерации он не оставляет.
+pub fn assign_number(tx: &Transaction, document_type: &str, raw: &str, date: &str) -> Result<String, String> {
+    let seq = load_sequence(tx, document_type)?;
+    let year = document_year(date);
+
+    if raw.trim().is_empty() {
+        let counter = counter_year(&seq, year);
+        let n: i64 = tx
+            .query_row(
+                "INSERT INTO document_sequence_counters (document_type, year, last_number) VALUES (?1, ?2, 1)
+                 ON CONFLICT(document_type, year) DO UPDATE SET last_number = last_number + 1
+                 RETURNING last_number",
+                params![seq.document_type, counter],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        let number = format_number(&seq, n, year);
+        if number_exists(tx, &number, None)? {
+            return Err(format!("Invoice number {} already exists", number));
+        }
+        return Ok(number);
+    }
+
+    let number = canonical_number(&seq, raw, date);
+    if number_exists(tx, &number, None)? {
+        return Err(format!("Invoice number {} already exists", number));
+    }
+    claim_explicit(tx, &seq, raw, &number, year)?;
+    Ok(number)
+}
+
+/// Явный номер допускается только следующим по счётчику (счётчик сдвигается)
+/// или на место пропуска — дыр в нумерации он не оставляет.
+fn claim_explicit(tx: &Transaction, seq: &DocumentSequence, raw: &str, number: &str, year: i32) -> Result<(), String> {
+    let Some((n, parsed_year)) = parse_number(raw) else {
+        return Ok(());
+    };
+    let year = parsed_year.unwrap_or(year);
+    let counter = counter_year(seq, year);
+    let last = last_number(tx, &seq.document_type, counter)?;
+    if n > last + 1 {
+        return Err(format!(
+            "Invoice number {} is out of sequence, next is {}",
+            number,
+            format_number(seq, last + 1, year)
+        ));
+    }
+    if n == last + 1 {
+        tx.execute(
+            "INSERT INTO document_sequence_counters (document_type, year, last_number) VALUES (?1, ?2, ?3)
+             ON CONFLICT(document_type, year) DO UPDATE SET last_number = excluded.last_number",
+            params![seq.document_type, counter, n],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Канонический номер при редактировании существующего документа. Прежний номер
+/// остаётся как есть; новый проходит ту же проверку последовательности, что и в assign_number.
+pub fn renumber(tx: &Transaction, invoice_id: &str, document_type: &str, raw: &str, date: &str) -> Result<String, String> {
+    if raw.trim().is_empty() {
+        return Err("invoice_number is empty".to_string());
+    }
+    let seq = load_sequence(tx, document_type)?;
+    let number = canonical_number(&seq, raw, date);
+    let current: Option<String> = tx
+        .query_row("SELECT invoice_number FROM invoices WHERE id = ?1", [invoice_id], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if current.as_deref() == Some(number.as_str()) {
+        return Ok(number);
+    }
+    if number_exists(tx, &number, Some(invoice_id))? {
+        return Err(format!("Invoice number {} already exists", number));
+    }
+    claim_explicit(tx, &seq, raw, &number, document_year(date))?;
+    Ok(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        conn
+    }
+
+    fn insert(tx: &Transaction, number: &str) {
+        tx.execute(
+            "INSERT INTO invoices (id, invoice_number, document_type, date, total, status, created_at) VALUES (?1, ?2, 'racun', '2026-01-01', 0, 'draft', '')",
+            params![uuid::Uuid::new_v4().to_string(), number],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_parse_and_canonical_form() {
+        assert_eq!(parse_number("p42/2026"), Some((42, Some(2026))));
+        assert_eq!(parse_number("R/ 7 / 2026"), Some((7, Some(2026))));
+        assert_eq!(parse_number("15"), Some((15, None)));
+        assert_eq!(parse_number("1-08052026"), None);
+
+        let conn = test_conn();
+        let predracun = load_sequence(&conn, "predracun").unwrap();
+        let racun = load_sequence(&conn, "invoice").unwrap();
+        let otpremnica = load_sequence(&conn, "delivery").unwrap();
+        assert_eq!(canonical_number(&predracun, "42/2026", ""), "p42/2026");
+        assert_eq!(canonical_number(&predracun, "p042/2026", ""), "p42/2026");
+        assert_eq!(canonical_number(&racun, "p42/2026", ""), "42/2026");
+        assert_eq!(canonical_number(&racun, "42", "2025-12-31"), "42/2025");
+        assert_eq!(canonical_number(&otpremnica, "1-08052026", ""), "1-08052026");
+        assert_eq!(canonical_number(&otpremnica, "o1-08052026", ""), "o1-08052026");
+    }
+
+    #[test]
+    fn test_reserve_is_sequential_per_type_and_resets_yearly() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        let a = assign_number(&tx, "racun", "", "2026-03-01").unwrap();
+        insert(&tx, &a);
+        let b = assign_number(&tx, "racun", "", "2026-03-02").unwrap();
+        insert(&tx, &b);
+        assert_eq!((a.as_str(), b.as_str()), ("1/2026", "2/2026"));
+        assert_eq!(assign_number(&tx, "predracun", "", "2026-03-02").unwrap(), "p1/2026");
+        assert_eq!(assign_number(&tx, "racun", "", "2027-01-02").unwrap(), "1/2027");
+        tx.commit().unwrap();
+
+        // Откат транзакции возвращает номер в последовательность
+        let tx = conn.unchecked_transaction().unwrap();
+        assert_eq!(assign_number(&tx, "racun", "", "2026-04-01").unwrap(), "3/2026");
+        drop(tx);
+        assert_eq!(peek_next(&conn, "racun", "2026-04-01").unwrap(), "3/2026");
+    }
+
+    #[test]
+    fn test_explicit_numbers_stay_in_sequence_and_must_be_unique() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        let imported = assign_number(&tx, "predracun", "1/2026", "2026-05-01").unwrap();
+        assert_eq!(imported, "p1/2026");
+        insert(&tx, &imported);
+        assert_eq!(
+            assign_number(&tx, "predracun", "p1/2026", "2026-05-01").unwrap_err(),
+            "Invoice number p1/2026 already exists"
+        );
+        assert_eq!(
+            assign_number(&tx, "predracun", "500/2026", "2026-05-01").unwrap_err(),
+            "Invoice number p500/2026 is out of sequence, next is p2/2026"
+        );
+        assert_eq!(assign_number(&tx, "predracun", "", "2026-05-02").unwrap(), "p2/2026");
+
+        // Зарезервированный, но не сохранённый здесь номер (документ с другого устройства)
+        // занимает своё место, счётчик не двигается
+        let third = assign_number(&tx, "predracun", "", "2026-05-02").unwrap();
+        insert(&tx, &third);
+        assert_eq!(assign_number(&tx, "predracun", "p2/2026", "2026-05-02").unwrap(), "p2/2026");
+        assert_eq!(peek_next(&tx, "predracun", "2026-05-02").unwrap(), "p4/2026");
+    }
+
+    #[test]
+    fn test_renumber_stays_in_sequence() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        for _ in 0..5 {
+            let number = assign_number(&tx, "racun", "", "2026-05-01").unwrap();
+            insert(&tx, &number);
+        }
+        let id: String = tx
+            .query_row("SELECT id FROM invoices WHERE invoice_number = '5/2026'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(renumber(&tx, &id, "racun", "5/2026", "2026-05-01").unwrap(), "5/2026");
+        assert_eq!(
+            renumber(&tx, &id, "racun", "50/2026", "2026-05-01").unwrap_err(),
+            "Invoice number 50/2026 is out of sequence, next is 6/2026"
+        );
+        assert_eq!(
+            renumber(&tx, &id, "racun", "4/2026", "2026-05-01").unwrap_err(),
+            "Invoice number 4/2026 already exists"
+        );
+        assert_eq!(renumber(&tx, &id, "racun", "6/2026", "2026-05-01").unwrap(), "6/2026");
+        assert_eq!(peek_next(&tx, "racun", "2026-05-01").unwrap(), "7/2026");
+    }
+
+    #[test]
+    fn test_template_without_yearly_reset() {
+        let conn = test_conn();
+        let mut seq = load_sequence(&conn, "racun").unwrap();
+        seq.reset_yearly = true;
+        seq.template = "{N}".to_string();
+        assert!(save_sequence(&conn, &seq).is_err());
+        seq.reset_yearly = false;
+        seq.prefix = "R-".to_string();
+        save_sequence(&conn, &seq).unwrap();
+
+        let tx = conn.unchecked_transaction().unwrap();
+        assert_eq!(assign_number(&tx, "racun", "", "2026-12-31").unwrap(), "R-1");
+        assert_eq!(assign_number(&tx, "racun", "", "2027-01-01").unwrap(), "R-2");
+    }
+}
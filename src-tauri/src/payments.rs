@@ -0,0 +1,341 @@
+use chrono::{Local, NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::numbering;
+
+/// Способы оплаты, которые принимает журнал
+pub const METHODS: &[&str] = &["bank_transfer", "cash", "card", "compensation", "other"];
+
+pub const STATUS_PAID: &str = "paid";
+pub const STATUS_PARTIAL: &str = "partial";
+pub const STATUS_UNPAID: &str = "unpaid";
+pub const STATUS_OVERDUE: &str = "overdue";
+
+/// Остаток меньше полпары считаем закрытым
+const BALANCE_EPSILON: f64 = 0.005;
+
+/// Подзапросы для `SELECT ... FROM invoices`: сумма действующих оплат и сумма кредит-нот
+/// (кредит-ноты хранятся с отрицательным total).
+pub const AMOUNT_PAID_SQL: &str = "(SELECT COALESCE(SUM(amount), 0) FROM payments WHERE payments.invoice_id = invoices.id AND payments.reversed_at IS NULL)";
+pub const CREDITED_TOTAL_SQL: &str = "(SELECT COALESCE(SUM(c.total), 0) FROM invoices c WHERE c.source_document_id = invoices.id AND c.document_type = 'knjizno_odobrenje')";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Payment {
+    pub id: String,
+    pub invoice_id: String,
+    pub date: String,
+    pub amount: f64,
+    pub currency: String,
+    pub method: String,
+    pub reference: Option<String>,
+    pub note: Option<String>,
+    pub created_at: String,
+    pub reversed_at: Option<String>,
+    pub reversal_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewPayment {
+    pub invoice_id: String,
+    pub date: String,
+    pub amount: f64,
+    /// По умолчанию — валюта документа
+    pub currency: Option<String>,
+    pub method: Option<String>,
+    /// Позив на број / номер выписки
+    pub reference: Option<String>,
+    pub note: Option<String>,
+}
+
+/// Состояние оплаты, вычисленное по журналу.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentState {
+    pub amount_paid: f64,
+    pub balance: f64,
+    /// None — документ не является требованием (кредит-нота)
+    pub status: Option<&'static str>,
+}
+
+pub fn parse_date(s: &str) -> Option<NaiveDate> {
+    let s = s.trim();
+    NaiveDate::parse_from_str(s.get(..10).unwrap_or(s), "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%d.%m.%Y"))
+        .ok()
+}
+
+pub fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+pub fn payment_state(
+    document_type: &str,
+    total: f64,
+    credited_total: f64,
+    amount_paid: f64,
+    due_date: Option<&str>,
+    today: NaiveDate,
+) -> PaymentState {
+    if numbering::sequence_key(document_type) == numbering::KNJIZNO_ODOBRENJE {
+        return PaymentState { amount_paid, balance: 0.0, status: None };
+    }
+    let balance = ((total + credited_total - amount_paid) * 100.0).round() / 100.0;
+    let status = if balance <= BALANCE_EPSILON {
+        STATUS_PAID
+    } else if due_date.and_then(parse_date).is_some_and(|due| due < today) {
+        STATUS_OVERDUE
+    } else if amount_paid > 0.0 {
+        STATUS_PARTIAL
+    } else {
+        STATUS_UNPAID
+    };
+    PaymentState { amount_paid, balance: balance.max(0.0), status: Some(status) }
+}
+
+struct InvoiceBalance {
+    invoice_number: String,
+    currency: String,
+    state: PaymentState,
+}
+
+fn load_balance(conn: &Connection, invoice_id: &str) -> Result<InvoiceBalance, String> {
+    conn.query_row(
+        &format!(
+            "SELECT invoice_number, document_type, total, COALESCE(currency, 'RSD'), due_date, {}, {} FROM invoices WHERE id = ?1",
+            AMOUNT_PAID_SQL, CREDITED_TOTAL_SQL
+        ),
+        params![invoice_id],
+        |row| {
+            let document_type: String = row.get(1)?;
+            let due_date: Option<String> = row.get(4)?;
+            Ok(InvoiceBalance {
+                invoice_number: row.get(0)?,
+                currency: row.get(3)?,
+                state: payment_state(&document_type, row.get(2)?, row.get(6)?, row.get(5)?, due_date.as_deref(), today()),
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Invoice {} not found", invoice_id))
+}
+
+fn payment_from_row(row: &rusqlite::Row) -> rusqlite::Result<Payment> {
+    Ok(Payment {
+        id: row.get(0)?,
+        invoice_id: row.get(1)?,
+        date: row.get(2)?,
+        amount: row.get(3)?,
+        currency: row.get(4)?,
+        method: row.get(5)?,
+        reference: row.get(6)?,
+        note: row.get(7)?,
+        created_at: row.get(8)?,
+        reversed_at: row.get(9)?,
+        reversal_reason: row.get(10)?,
+    })
+}
+
+const PAYMENT_COLUMNS: &str = "id, invoice_id, date, amount, currency, method, reference, note, created_at, reversed_at, reversal_reason";
+
+fn load_payment(conn: &Connection, id: &str) -> Result<Payment, String> {
+    conn.query_row(
+        &format!("SELECT {} FROM payments WHERE id = ?1", PAYMENT_COLUMNS),
+        params![id],
+        payment_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Payment {} not found", id))
+}
+
+/// Все оплаты документа, включая сторнированные (для истории).
+pub fn list_payments(conn: &Connection, invoice_id: &str) -> Result<Vec<Payment>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM payments WHERE invoice_id = ?1 ORDER BY date, created_at",
+            PAYMENT_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let payments = stmt
+        .query_map([invoice_id], payment_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(payments)
+}
+
+pub fn record_payment(tx: &Transaction, payment: &NewPayment) -> Result<Payment, String> {
+    let invoice = load_balance(tx, &payment.invoice_id)?;
+    if invoice.state.status.is_none() {
+        return Err(format!("Invoice {} is a credit note and cannot be paid", invoice.invoice_number));
+    }
+    if !payment.amount.is_finite() || payment.amount <= 0.0 {
+        return Err("Payment amount must be positive".to_string());
+    }
+    if payment.amount > invoice.state.balance + BALANCE_EPSILON {
+        return Err(format!(
+            "Payment {:.2} exceeds outstanding balance {:.2} of invoice {}",
+            payment.amount, invoice.state.balance, invoice.invoice_number
+        ));
+    }
+    let currency = payment.currency.clone().unwrap_or_else(|| invoice.currency.clone());
+    if currency != invoice.currency {
+        return Err(format!(
+            "Payment currency {} does not match invoice currency {}",
+            currency, invoice.currency
+        ));
+    }
+    let method = payment.method.clone().unwrap_or_else(|| METHODS[0].to_string());
+    if !METHODS.contains(&method.as_str()) {
+        return Err(format!("Unknown payment method {}", method));
+    }
+    let date = parse_date(&payment.date)
+        .ok_or_else(|| format!("Invalid payment date {}", payment.date))?
+        .format("%Y-%m-%d")
+        .to_string();
+
+    let id = uuid::Uuid::new_v4().to_string();
+    tx.execute(
+        "INSERT INTO payments (id, invoice_id, date, amount, currency, method, reference, note, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            id,
+            payment.invoice_id,
+            date,
+            (payment.amount * 100.0).round() / 100.0,
+            currency,
+            method,
+            payment.reference,
+            payment.note,
+            Utc::now().to_rfc3339(),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    load_payment(tx, &id)
+}
+
+/// Сторно оплаты: запись остаётся в журнале с отметкой, в балансе не учитывается.
+pub fn reverse_payment(tx: &Transaction, id: &str, reason: Option<&str>) -> Result<Payment, String> {
+    let payment = load_payment(tx, id)?;
+    if payment.reversed_at.is_some() {
+        return Err(format!("Payment {} is already reversed", id));
+    }
+    tx.execute(
+        "UPDATE payments SET reversed_at = ?1, reversal_reason = ?2 WHERE id = ?3",
+        params![Utc::now().to_rfc3339(), reason, id],
+    )
+    .map_err(|e| e.to_string())?;
+    load_payment(tx, id)
+}
+
+/// Совместимость с чекбоксом «оплачено» в UI: отметка проводит оплату на весь остаток,
+/// снятие отметки сторнирует действующие оплаты.
+pub fn set_paid_flag(tx: &Transaction, invoice_id: &str, paid: bool, date: &str) -> Result<(), String> {
+    let invoice = load_balance(tx, invoice_id)?;
+    if invoice.state.status.is_none() {
+        return Ok(());
+    }
+    if paid && invoice.state.balance > BALANCE_EPSILON {
+        record_payment(
+            tx,
+            &NewPayment {
+                invoice_id: invoice_id.to_string(),
+                date: parse_date(date)
+                    .unwrap_or_else(today)
+                    .format("%Y-%m-%d")
+                    .to_string(),
+                amount: invoice.state.balance,
+                currency: None,
+                method: Some("other".to_string()),
+                reference: None,
+                note: Some("Отмечено как оплаченное".to_string()),
+            },
+        )?;
+    } else if !paid && invoice.state.amount_paid > 0.0 {
+        tx.execute(
+            "UPDATE payments SET reversed_at = ?1, reversal_reason = ?2 WHERE invoice_id = ?3 AND reversed_at IS NULL",
+            params![Utc::now().to_rfc3339(), "Отметка об оплате снята", invoice_id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO invoices (id, invoice_number, document_type, date, due_date, total, status, created_at) VALUES ('inv', '1/2026', 'racun', '2026-03-01', '2026-03-31', 1000.0, 'confirmed', '')",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn new_payment(amount: f64) -> NewPayment {
+        NewPayment {
+            invoice_id: "inv".to_string(),
+            date: "2026-03-10".to_string(),
+            amount,
+            currency: None,
+            method: None,
+            reference: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn test_payment_state_derivation() {
+        let d = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        let state = |paid: f64, today: &str| payment_state("racun", 1000.0, 0.0, paid, Some("2026-03-31"), d(today)).status;
+        assert_eq!(state(0.0, "2026-03-15"), Some(STATUS_UNPAID));
+        assert_eq!(state(400.0, "2026-03-15"), Some(STATUS_PARTIAL));
+        assert_eq!(state(400.0, "2026-04-01"), Some(STATUS_OVERDUE));
+        assert_eq!(state(1000.0, "2026-04-01"), Some(STATUS_PAID));
+        // Кредит-нота уменьшает остаток
+        let credited = payment_state("racun", 1000.0, -300.0, 700.0, None, d("2026-04-01"));
+        assert_eq!((credited.balance, credited.status), (0.0, Some(STATUS_PAID)));
+        assert_eq!(payment_state("knjizno_odobrenje", -300.0, 0.0, 0.0, None, d("2026-04-01")).status, None);
+    }
+
+    #[test]
+    fn test_record_and_reverse_payments() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        let first = record_payment(&tx, &new_payment(400.0)).unwrap();
+        record_payment(&tx, &new_payment(350.0)).unwrap();
+        assert_eq!(load_balance(&tx, "inv").unwrap().state.balance, 250.0);
+        assert!(record_payment(&tx, &new_payment(300.0)).unwrap_err().contains("exceeds outstanding balance"));
+
+        let mut wrong_currency = new_payment(10.0);
+        wrong_currency.currency = Some("EUR".to_string());
+        assert!(record_payment(&tx, &wrong_currency).is_err());
+
+        let reversed = reverse_payment(&tx, &first.id, Some("Vraćeno")).unwrap();
+        assert!(reversed.reversed_at.is_some());
+        assert!(reverse_payment(&tx, &first.id, None).is_err());
+        assert_eq!(load_balance(&tx, "inv").unwrap().state.amount_paid, 350.0);
+        assert_eq!(list_payments(&tx, "inv").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_paid_flag_maps_to_ledger() {
+        let conn = test_conn();
+        let tx = conn.unchecked_transaction().unwrap();
+        record_payment(&tx, &new_payment(400.0)).unwrap();
+        set_paid_flag(&tx, "inv", true, "2026-03-20").unwrap();
+        let state = load_balance(&tx, "inv").unwrap().state;
+        assert_eq!((state.amount_paid, state.status), (1000.0, Some(STATUS_PAID)));
+
+        set_paid_flag(&tx, "inv", false, "").unwrap();
+        assert_eq!(load_balance(&tx, "inv").unwrap().state.amount_paid, 0.0);
+    }
+}
@@ -0,0 +1,783 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use chrono::Datelike;
+use printpdf::path::PaintMode;
+use printpdf::{
+    Color, IndirectFontRef, Line, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Point, Rect, Rgb,
+};
+
+use crate::commands::{pricing_lines, InvoiceWithItems};
+use crate::company::CompanyProfile;
+use crate::ips::{self, IpsError};
+use crate::numbering;
+use crate::payments;
+use crate::pricing::{self, InvoiceTotals};
+use crate::ubl::UblParty;
+
+/// DejaVu Sans покрывает и ћирилицу, и latinicu (čćžšđ); лицензия — fonts/LICENSE-DejaVu.txt
+const FONT_REGULAR: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+const FONT_BOLD: &[u8] = include_bytes!("../fonts/DejaVuSans-Bold.ttf");
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 15.0;
+const RIGHT: f32 = PAGE_WIDTH - MARGIN;
+/// Высота подвала: текст из профиля фирмы и номер страницы
+const FOOTER_HEIGHT: f32 = 20.0;
+const LINE_HEIGHT: f32 = 4.5;
+const PT_TO_MM: f32 = 25.4 / 72.0;
+
+const SIZE_TEXT: f32 = 9.0;
+const SIZE_SMALL: f32 = 7.5;
+const SIZE_COMPANY: f32 = 13.0;
+const SIZE_TITLE: f32 = 15.0;
+/// Сторона IPS QR в мм
+const QR_SIZE: f32 = 32.0;
+
+pub struct PdfInput<'a> {
+    pub document: &'a InvoiceWithItems,
+    pub company: &'a CompanyProfile,
+    /// None — клиента нет в базе: печатаем client_name из документа
+    pub customer: Option<&'a UblParty>,
+}
+
+/// Заголовок документа по типу (типы из UI: racun / predracun / delivery / knjizno_odobrenje)
+fn document_title(document_type: &str) -> &'static str {
+    match numbering::sequence_key(document_type) {
+        numbering::PREDRACUN => "PREDRAČUN",
+        numbering::OTPREMNICA => "OTPREMNICA",
+        numbering::KNJIZNO_ODOBRENJE => "KNJIŽNO ODOBRENJE",
+        _ => "RAČUN",
+    }
+}
+
+/// invoices/{document_type}/{year}/{month}/{number}.pdf — рядом с HTML из save_invoice_html
+pub fn archive_path(base_dir: &Path, document_type: &str, date: &str, invoice_number: &str) -> PathBuf {
+    let date = payments::parse_date(date).unwrap_or_else(payments::today);
+    let safe_filename = invoice_number.replace(['/', '\\'], "-");
+    base_dir
+        .join("invoices")
+        .join(document_type)
+        .join(date.year().to_string())
+        .join(format!("{:02}", date.month()))
+        .join(format!("{}.pdf", safe_filename))
+}
+
+/// Рендерит документ и кладёт PDF в архив. Возвращает путь к файлу.
+pub fn write_archive(base_dir: &Path, input: &PdfInput) -> Result<PathBuf, String> {
+    let invoice = &input.document.invoice;
+    let bytes = render_document_pdf(input)?;
+    let path = archive_path(base_dir, &invoice.document_type, &invoice.date, &invoice.invoice_number);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create directories: {}", e))?;
+    }
+    std::fs::write(&path, bytes).map_err(|e| format!("Failed to write PDF file: {}", e))?;
+    Ok(path)
+}
+
+/// 1234.5 -> "1.234,50"
+fn format_amount(value: f64) -> String {
+    let value = pricing::round_money(value);
+    let formatted = format!("{:.2}", value.abs());
+    let (int_part, frac_part) = formatted.split_once('.').unwrap_or((&formatted, "00"));
+    let mut grouped = String::new();
+    for (i, ch) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            grouped.push('.');
+        }
+        grouped.push(ch);
+    }
+    let sign = if value < 0.0 { "-" } else { "" };
+    format!("{}{},{}", sign, grouped, frac_part)
+}
+
+/// Количество и проценты без хвостовых нулей: 3, 2,5, 0,125
+fn format_decimal(value: f64) -> String {
+    let s = format!("{:.3}", value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.replace('.', ",") }
+}
+
+fn format_date(value: &str) -> String {
+    payments::parse_date(value)
+        .map(|d| d.format("%d.%m.%Y").to_string())
+        .unwrap_or_else(|| value.to_string())
+}
+
+struct Font {
+    pdf: IndirectFontRef,
+    face: ttf_parser::Face<'static>,
+}
+
+impl Font {
+    fn load(doc: &PdfDocumentReference, data: &'static [u8]) -> Result<Self, String> {
+        let pdf = doc
+            .add_external_font(Cursor::new(data))
+            .map_err(|e| format!("Failed to load PDF font: {}", e))?;
+        let face = ttf_parser::Face::parse(data, 0).map_err(|e| format!("Failed to parse PDF font: {}", e))?;
+        Ok(Font { pdf, face })
+    }
+
+    /// Ширина строки в мм
+    fn width(&self, text: &str, size: f32) -> f32 {
+        let units_per_em = self.face.units_per_em() as f32;
+        let units: f32 = text
+            .chars()
+            .map(|c| {
+                self.face
+                    .glyph_index(c)
+                    .and_then(|g| self.face.glyph_hor_advance(g))
+                    .map(f32::from)
+                    .unwrap_or(units_per_em / 2.0)
+            })
+            .sum();
+        units / units_per_em * size * PT_TO_MM
+    }
+
+    /// Перенос по словам; слово длиннее строки режется по символам.
+    fn wrap(&self, text: &str, size: f32, max_width: f32) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.lines() {
+            let mut current = String::new();
+            for word in paragraph.split_whitespace() {
+                let candidate = if current.is_empty() {
+                    word.to_string()
+                } else {
+                    format!("{} {}", current, word)
+                };
+                if self.width(&candidate, size) <= max_width {
+                    current = candidate;
+                    continue;
+                }
+                if !current.is_empty() {
+                    lines.push(std::mem::take(&mut current));
+                }
+                for ch in word.chars() {
+                    current.push(ch);
+                    if self.width(&current, size) > max_width && current.chars().count() > 1 {
+                        current.pop();
+                        lines.push(std::mem::take(&mut current));
+                        current.push(ch);
+                    }
+                }
+            }
+            lines.push(current);
+        }
+        while lines.len() > 1 && lines.last().is_some_and(|l| l.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+}
+
+/// Колонка таблицы позиций: правый край для чисел, левый — для текста.
+struct Column {
+    title: &'static str,
+    x: f32,
+    right_aligned: bool,
+}
+
+/// Постраничный вывод: `y` — отступ от верхнего края страницы в мм.
+struct Writer {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    regular: Font,
+    bold: Font,
+    y: f32,
+    page: usize,
+    footer: Vec<String>,
+}
+
+impl Writer {
+    fn font(&self, bold: bool) -> &Font {
+        if bold {
+            &self.bold
+        } else {
+            &self.regular
+        }
+    }
+
+    fn text(&self, x: f32, text: &str, size: f32, bold: bool) {
+        let font = &self.font(bold).pdf;
+        self.layer.use_text(text, size, Mm(x), Mm(PAGE_HEIGHT - self.y), font);
+    }
+
+    fn text_right(&self, right: f32, text: &str, size: f32, bold: bool) {
+        let x = right - self.font(bold).width(text, size);
+        self.text(x, text, size, bold);
+    }
+
+    fn hline(&self, from: f32, to: f32) {
+        let y = Mm(PAGE_HEIGHT - self.y);
+        self.layer.set_outline_thickness(0.5);
+        self.layer.add_line(Line {
+            points: vec![(Point::new(Mm(from), y), false), (Point::new(Mm(to), y), false)],
+            is_closed: false,
+        });
+    }
+
+    /// QR из векторных квадратов: `y` — верхний край, `size` — сторона в мм.
+    fn qr(&self, payload: &str, x: f32, size: f32) -> Result<(), IpsError> {
+        let (width, modules) = ips::qr_modules(payload)?;
+        let module = size / width as f32;
+        let top = PAGE_HEIGHT - self.y;
+        self.layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+        for (index, _) in modules.iter().enumerate().filter(|(_, dark)| **dark) {
+            let left = x + (index % width) as f32 * module;
+            let upper = top - (index / width) as f32 * module;
+            self.layer.add_rect(
+                Rect::new(Mm(left), Mm(upper - module), Mm(left + module), Mm(upper)).with_mode(PaintMode::Fill),
+            );
+        }
+        Ok(())
+    }
+
+    fn bottom(&self) -> f32 {
+        PAGE_HEIGHT - MARGIN - FOOTER_HEIGHT
+    }
+
+    fn draw_footer(&mut self) {
+        let saved = self.y;
+        self.y = PAGE_HEIGHT - MARGIN - FOOTER_HEIGHT + 4.0;
+        self.layer.set_outline_color(Color::Rgb(Rgb::new(0.6, 0.6, 0.6, None)));
+        self.hline(MARGIN, RIGHT);
+        self.y += LINE_HEIGHT;
+        for line in self.footer.clone() {
+            self.text(MARGIN, &line, SIZE_SMALL, false);
+            self.y += LINE_HEIGHT - 1.0;
+        }
+        self.y = PAGE_HEIGHT - MARGIN;
+        self.text_right(RIGHT, &format!("Strana {}", self.page), SIZE_SMALL, false);
+        self.y = saved;
+    }
+
+    fn new_page(&mut self) {
+        self.draw_footer();
+        let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        self.layer = self.doc.get_page(page).get_layer(layer);
+        self.page += 1;
+        self.y = MARGIN + 5.0;
+    }
+
+    /// Переходит на новую страницу, если `height` мм не помещается. true — страница сменилась.
+    fn ensure_space(&mut self, height: f32) -> bool {
+        if self.y + height > self.bottom() {
+            self.new_page();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub fn render_document_pdf(input: &PdfInput) -> Result<Vec<u8>, String> {
+    let invoice = &input.document.invoice;
+    let is_delivery = numbering::sequence_key(&invoice.document_type) == numbering::OTPREMNICA;
+    let totals = if is_delivery {
+        None
+    } else {
+        Some(pricing::price_invoice(&pricing_lines(invoice, &input.document.items)).map_err(|e| e.to_string())?)
+    };
+    let currency = invoice.currency.as_deref().unwrap_or("RSD").trim().to_uppercase();
+    let title = format!("{} br. {}", document_title(&invoice.document_type), invoice.invoice_number);
+
+    let (doc, page, layer) = PdfDocument::new(title.as_str(), Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+    let regular = Font::load(&doc, FONT_REGULAR)?;
+    let bold = Font::load(&doc, FONT_BOLD)?;
+    let footer = match input.company.invoice_footer.as_deref() {
+        Some(text) => regular.wrap(text, SIZE_SMALL, RIGHT - MARGIN - 20.0),
+        None => Vec::new(),
+    };
+    let layer = doc.get_page(page).get_layer(layer);
+    let mut w = Writer {
+        doc,
+        layer,
+        regular,
+        bold,
+        y: MARGIN + 5.0,
+        page: 1,
+        footer,
+    };
+
+    write_header(&mut w, input, &title, &currency);
+    write_customer(&mut w, input);
+    write_items(&mut w, input, totals.as_ref(), is_delivery);
+    if let Some(totals) = &totals {
+        write_totals(&mut w, input, totals, &currency)?;
+    }
+    if let Some(notes) = invoice.notes.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+        w.y += LINE_HEIGHT;
+        for line in w.regular.wrap(&format!("Napomena: {}", notes), SIZE_TEXT, RIGHT - MARGIN) {
+            w.ensure_space(LINE_HEIGHT);
+            w.text(MARGIN, &line, SIZE_TEXT, false);
+            w.y += LINE_HEIGHT;
+        }
+    }
+    w.draw_footer();
+
+    w.doc.save_to_bytes().map_err(|e| format!("Failed to build PDF: {}", e))
+}
+
+/// Шапка: реквизиты фирмы слева, название и даты документа справа.
+fn write_header(w: &mut Writer, input: &PdfInput, title: &str, currency: &str) {
+    let company = input.company;
+    let invoice = &input.document.invoice;
+    let top = w.y;
+
+    w.text(MARGIN, &company.name, SIZE_COMPANY, true);
+    w.y += 6.0;
+    let mut lines = Vec::new();
+    if let Some(legal_name) = company.legal_name.as_deref().filter(|n| *n != company.name) {
+        lines.push(legal_name.to_string());
+    }
+    let city = [company.postal_code.as_deref(), company.city.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let address = [company.street.as_deref(), Some(city.as_str())]
+        .into_iter()
+        .flatten()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !address.is_empty() {
+        lines.push(address);
+    }
+    lines.push(format!("PIB: {}   MB: {}", company.pib, company.mb));
+    if let Some(account) = company.bank_account_for(currency) {
+        match account.bank_name.as_deref() {
+            Some(bank) => lines.push(format!("Tekući račun: {} ({})", account.account_number, bank)),
+            None => lines.push(format!("Tekući račun: {}", account.account_number)),
+        }
+    }
+    let contacts = [company.phone.as_deref(), company.email.as_deref(), company.website.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("   ");
+    if !contacts.is_empty() {
+        lines.push(contacts);
+    }
+    for line in &lines {
+        w.text(MARGIN, line, SIZE_TEXT, false);
+        w.y += LINE_HEIGHT;
+    }
+    let left_bottom = w.y;
+
+    w.y = top;
+    w.text_right(RIGHT, title, SIZE_TITLE, true);
+    w.y += 7.0;
+    w.text_right(RIGHT, &format!("Datum: {}", format_date(&invoice.date)), SIZE_TEXT, false);
+    w.y += LINE_HEIGHT;
+    if let Some(due) = invoice.due_date.as_deref().filter(|d| !d.trim().is_empty()) {
+        w.text_right(RIGHT, &format!("Rok plaćanja: {}", format_date(due)), SIZE_TEXT, false);
+        w.y += LINE_HEIGHT;
+    }
+    if let Some(city) = company.city.as_deref() {
+        w.text_right(RIGHT, &format!("Mesto izdavanja: {}", city), SIZE_TEXT, false);
+        w.y += LINE_HEIGHT;
+    }
+    w.text_right(RIGHT, &format!("Valuta: {}", currency), SIZE_TEXT, false);
+    w.y += LINE_HEIGHT;
+
+    w.y = w.y.max(left_bottom) + 3.0;
+    w.layer.set_outline_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+    w.hline(MARGIN, RIGHT);
+    w.y += 6.0;
+}
+
+fn write_customer(w: &mut Writer, input: &PdfInput) {
+    w.text(MARGIN, "Kupac:", SIZE_TEXT, true);
+    w.y += LINE_HEIGHT;
+    let mut lines = Vec::new();
+    match input.customer {
+        Some(customer) => {
+            lines.push(customer.registration_name.clone().unwrap_or_else(|| customer.name.clone()));
+            if let Some(street) = &customer.street {
+                lines.push(street.clone());
+            }
+            let city = [customer.postal_code.as_deref(), customer.city.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !city.is_empty() {
+                lines.push(city);
+            }
+            let ids = [
+                customer.pib.as_deref().map(|p| format!("PIB: {}", p)),
+                customer.mb.as_deref().map(|m| format!("MB: {}", m)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("   ");
+            if !ids.is_empty() {
+                lines.push(ids);
+            }
+        }
+        None => lines.push(input.document.invoice.client_name.clone().unwrap_or_default()),
+    }
+    for line in &lines {
+        w.text(MARGIN, line, SIZE_TEXT, false);
+        w.y += LINE_HEIGHT;
+    }
+    w.y += 5.0;
+}
+
+fn columns(is_delivery: bool) -> Vec<Column> {
+    let column = |title, x, right_aligned| Column { title, x, right_aligned };
+    if is_delivery {
+        vec![
+            column("R.br.", MARGIN, false),
+            column("Naziv", MARGIN + 10.0, false),
+            column("Količina", RIGHT, true),
+        ]
+    } else {
+        vec![
+            column("R.br.", MARGIN, false),
+            column("Naziv", MARGIN + 10.0, false),
+            column("Kol.", 110.0, true),
+            column("Cena", 130.0, true),
+            column("Rabat %", 146.0, true),
+            column("PDV %", 160.0, true),
+            column("Iznos bez PDV", RIGHT, true),
+        ]
+    }
+}
+
+fn write_table_header(w: &mut Writer, columns: &[Column]) {
+    for column in columns {
+        if column.right_aligned {
+            w.text_right(column.x, column.title, SIZE_SMALL, true);
+        } else {
+            w.text(column.x, column.title, SIZE_SMALL, true);
+        }
+    }
+    w.y += 1.5;
+    w.hline(MARGIN, RIGHT);
+    w.y += LINE_HEIGHT;
+}
+
+fn write_items(w: &mut Writer, input: &PdfInput, totals: Option<&InvoiceTotals>, is_delivery: bool) {
+    let columns = columns(is_delivery);
+    // Название занимает место до первой числовой колонки (с запасом под её ширину)
+    let name_width = if is_delivery { RIGHT - 25.0 - columns[1].x } else { 95.0 - columns[1].x };
+    write_table_header(w, &columns);
+
+    for (index, item) in input.document.items.iter().enumerate() {
+        let name_lines = w.regular.wrap(&item.product_name, SIZE_TEXT, name_width);
+        let height = LINE_HEIGHT * name_lines.len() as f32 + 1.0;
+        if w.ensure_space(height) {
+            write_table_header(w, &columns);
+        }
+
+        w.text(columns[0].x, &format!("{}.", index + 1), SIZE_TEXT, false);
+        if is_delivery {
+            w.text_right(columns[2].x, &format_decimal(item.quantity), SIZE_TEXT, false);
+        } else {
+            let priced = totals.and_then(|t| t.lines.get(index));
+            w.text_right(columns[2].x, &format_decimal(item.quantity), SIZE_TEXT, false);
+            w.text_right(columns[3].x, &format_amount(item.price), SIZE_TEXT, false);
+            w.text_right(columns[4].x, &format_decimal(item.discount_pct.unwrap_or(0.0)), SIZE_TEXT, false);
+            if let Some(line) = priced {
+                w.text_right(columns[5].x, &format_decimal(line.vat_rate), SIZE_TEXT, false);
+                w.text_right(columns[6].x, &format_amount(line.net), SIZE_TEXT, false);
+            }
+        }
+        for line in &name_lines {
+            w.text(columns[1].x, line, SIZE_TEXT, false);
+            w.y += LINE_HEIGHT;
+        }
+        w.y += 1.0;
+    }
+    w.hline(MARGIN, RIGHT);
+    w.y += 6.0;
+}
+
+/// IPS QR на неоплаченный остаток рачуна и предрачуна в динарах; для прочих и оплаченных — None.
+/// Ошибка проверки payload (счёт фирмы, сумма, позив на број) возвращается, а не теряется.
+fn ips_payload(input: &PdfInput, total: f64) -> Result<Option<String>, String> {
+    let invoice = &input.document.invoice;
+    let sequence = numbering::sequence_key(&invoice.document_type);
+    let currency = invoice.currency.as_deref().unwrap_or(ips::CURRENCY).trim().to_uppercase();
+    let amount = pricing::round_money(ips::amount_due(invoice, total));
+    if (sequence != numbering::RACUN && sequence != numbering::PREDRACUN) || currency != ips::CURRENCY || amount <= 0.0 {
+        return Ok(None);
+    }
+    let payer = input
+        .customer
+        .map(|c| c.name.as_str())
+        .or(invoice.client_name.as_deref());
+    ips::invoice_payment(invoice, amount, input.company, payer)
+        .and_then(|payment| ips::build_payload(&payment))
+        .map(Some)
+        .map_err(|e| format!("IPS QR for {}: {}", invoice.invoice_number, e))
+}
+
+fn write_totals(w: &mut Writer, input: &PdfInput, totals: &InvoiceTotals, currency: &str) -> Result<(), String> {
+    let label_right = 160.0;
+    let rows = 2 + totals.breakdown.len();
+    let payload = ips_payload(input, totals.total)?;
+    let qr_height = if payload.is_some() { QR_SIZE + 6.0 } else { 0.0 };
+    w.ensure_space((LINE_HEIGHT * rows as f32 + 10.0).max(qr_height));
+    let top = w.y;
+    if let Some(payload) = &payload {
+        if let Err(e) = w.qr(payload, MARGIN, QR_SIZE) {
+            println!("⚠️ IPS QR for {} skipped: {}", input.document.invoice.invoice_number, e);
+        }
+    }
+
+    w.text_right(label_right, "Osnovica:", SIZE_TEXT, false);
+    w.text_right(RIGHT, &format_amount(totals.subtotal), SIZE_TEXT, false);
+    w.y += LINE_HEIGHT;
+    for tax in &totals.breakdown {
+        let label = if tax.vat_rate > 0.0 {
+            format!("PDV {}% na {}:", format_decimal(tax.vat_rate), format_amount(tax.taxable_amount))
+        } else {
+            format!("Oslobođeno PDV-a ({}):", format_amount(tax.taxable_amount))
+        };
+        w.text_right(label_right, &label, SIZE_TEXT, false);
+        w.text_right(RIGHT, &format_amount(tax.vat_amount), SIZE_TEXT, false);
+        w.y += LINE_HEIGHT;
+    }
+    w.y += 1.0;
+    w.hline(110.0, RIGHT);
+    w.y += LINE_HEIGHT;
+    let total_label = if numbering::sequence_key(&input.document.invoice.document_type) == numbering::KNJIZNO_ODOBRENJE {
+        format!("UKUPNO ODOBRENJE ({}):", currency)
+    } else {
+        format!("UKUPNO ZA PLAĆANJE ({}):", currency)
+    };
+    w.text_right(label_right, &total_label, SIZE_TEXT, true);
+    w.text_right(RIGHT, &format_amount(totals.total), SIZE_TEXT, true);
+    w.y += LINE_HEIGHT + 2.0;
+    w.y = w.y.max(top + qr_height);
+
+    let mut exemptions: Vec<&str> = totals
+        .breakdown
+        .iter()
+        .filter_map(|t| t.vat_exemption_code.as_deref())
+        .collect();
+    exemptions.dedup();
+    if !exemptions.is_empty() {
+        w.ensure_space(LINE_HEIGHT);
+        w.text(MARGIN, &format!("Osnov za oslobođenje od PDV-a: {}", exemptions.join(", ")), SIZE_SMALL, false);
+        w.y += LINE_HEIGHT;
+    }
+    if numbering::sequence_key(&input.document.invoice.document_type) == numbering::RACUN {
+        let reference = match ips::model97_reference(&input.document.invoice.invoice_number) {
+            Some(reference) => format!("Model {}, poziv na broj: {}", ips::MODEL_97, &reference[2..]),
+            None => format!("Poziv na broj: {}", input.document.invoice.invoice_number),
+        };
+        w.ensure_space(LINE_HEIGHT);
+        w.text(MARGIN, &reference, SIZE_TEXT, false);
+        w.y += LINE_HEIGHT;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{Invoice, InvoiceItem};
+    use crate::company::BankAccount;
+
+    fn company() -> CompanyProfile {
+        CompanyProfile {
+            name: "Среħа 2024 ДОО Београд (Стари Град)".to_string(),
+            legal_name: None,
+            pib: "114407658".to_string(),
+            mb: "22019309".to_string(),
+            street: Some("Мајке Јевросиме 35".to_string()),
+            city: Some("Београд".to_string()),
+            postal_code: None,
+            country_code: "RS".to_string(),
+            email: None,
+            phone: None,
+            website: None,
+            logo: None,
+            invoice_footer: Some("Hvala na saradnji! Reklamacije u roku od 8 dana.".to_string()),
+            bank_accounts: vec![BankAccount {
+                id: Some(1),
+                account_number: "190-0000000085540-29".to_string(),
+                bank_name: Some("Alta Banka".to_string()),
+                currency: "RSD".to_string(),
+                is_default: true,
+            }],
+            updated_at: None,
+        }
+    }
+
+    fn document(document_type: &str, item_count: usize) -> InvoiceWithItems {
+        let items = (0..item_count)
+            .map(|i| InvoiceItem {
+                id: None,
+                invoice_id: "inv".to_string(),
+                product_id: format!("SR-{:03}", i),
+                product_name: format!("Čaj od šipka — Шипак {} (pakovanje od 50 g, suvi plodovi)", i),
+                quantity: 2.0,
+                unit_weight_g: None,
+                price: 1234.5,
+                total: 2469.0,
+                discount_pct: None,
+                vat_rate: Some(pricing::VAT_REDUCED),
+                vat_exemption_code: None,
+                vat_amount: None,
+                credited_item_id: None,
+            })
+            .collect();
+        InvoiceWithItems {
+            invoice: Invoice {
+                id: Some("inv".to_string()),
+                invoice_number: "12/2026".to_string(),
+                document_type: document_type.to_string(),
+                client_id: None,
+                client_name: Some("Кафана Ђурђевак".to_string()),
+                date: "2026-03-05".to_string(),
+                due_date: Some("2026-03-20".to_string()),
+                total: 0.0,
+                status: "confirmed".to_string(),
+                notes: Some("Isporuka na adresu kupca".to_string()),
+                created_at: None,
+                paid: None,
+                delivered: None,
+                currency: Some("RSD".to_string()),
+                exchange_rate: None,
+                exchange_rate_date: None,
+                subtotal: None,
+                vat_total: None,
+                vat_rate: None,
+                vat_exemption_code: None,
+                source_document_id: None,
+                amount_paid: None,
+                balance: None,
+                payment_status: None,
+                sef_invoice_id: None,
+                sef_status: None,
+                sef_sent_at: None,
+                sef_error: None,
+                created_by: None,
+                modified_by: None,
+            },
+            items,
+            tax_breakdown: Vec::new(),
+            source_documents: Vec::new(),
+            derived_documents: Vec::new(),
+        }
+    }
+
+    fn page_count(bytes: &[u8]) -> usize {
+        printpdf::lopdf::Document::load_mem(bytes).unwrap().get_pages().len()
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(1234567.891), "1.234.567,89");
+        assert_eq!(format_amount(-50.0), "-50,00");
+        assert_eq!(format_amount(0.5), "0,50");
+        assert_eq!(format_decimal(2.5), "2,5");
+    }
+
+    #[test]
+    fn test_archive_path_matches_html_layout() {
+        let path = archive_path(Path::new("/data"), "racun", "2026-03-05", "12/2026");
+        assert_eq!(path, PathBuf::from("/data/invoices/racun/2026/03/12-2026.pdf"));
+        let path = archive_path(Path::new("/data"), "delivery", "05.11.2025", "o3/2025");
+        assert_eq!(path, PathBuf::from("/data/invoices/delivery/2025/11/o3-2025.pdf"));
+    }
+
+    #[test]
+    fn test_render_racun_and_otpremnica() {
+        let company = company();
+        for document_type in ["racun", "predracun", "delivery"] {
+            let doc = document(document_type, 3);
+            let bytes = render_document_pdf(&PdfInput {
+                document: &doc,
+                company: &company,
+                customer: None,
+            })
+            .unwrap();
+            assert!(bytes.starts_with(b"%PDF"));
+            assert_eq!(page_count(&bytes), 1, "{}", document_type);
+        }
+    }
+
+    #[test]
+    fn test_ips_qr_only_on_dinar_racun_and_predracun() {
+        let company = company();
+        let payload_for = |document_type: &str, currency: &str| {
+            let mut doc = document(document_type, 1);
+            doc.invoice.currency = Some(currency.to_string());
+            ips_payload(
+                &PdfInput {
+                    document: &doc,
+                    company: &company,
+                    customer: None,
+                },
+                2715.9,
+            )
+        };
+        let payload = payload_for("racun", "RSD").unwrap().unwrap();
+        assert!(payload.contains("|I:RSD2715,90|P:Кафана Ђурђевак|"), "{}", payload);
+        assert!(payload.ends_with("|RO:979812-2026"), "{}", payload);
+        assert!(payload_for("predracun", "RSD").unwrap().is_some());
+        assert!(payload_for("delivery", "RSD").unwrap().is_none());
+        assert!(payload_for("racun", "EUR").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ips_qr_uses_outstanding_balance() {
+        let mut company = company();
+        let mut doc = document("racun", 1);
+        let payload_for = |doc: &InvoiceWithItems, company: &CompanyProfile| {
+            ips_payload(
+                &PdfInput {
+                    document: doc,
+                    company,
+                    customer: None,
+                },
+                2715.9,
+            )
+        };
+        doc.invoice.balance = Some(715.9);
+        let payload = payload_for(&doc, &company).unwrap().unwrap();
+        assert!(payload.contains("|I:RSD715,90|"), "{}", payload);
+        // Оплаченному документу QR не нужен
+        doc.invoice.balance = Some(0.0);
+        assert_eq!(payload_for(&doc, &company), Ok(None));
+
+        // Неверный счёт фирмы — ошибка, а не молча пропавший QR
+        doc.invoice.balance = None;
+        company.bank_accounts[0].account_number = "190-123".to_string();
+        let err = payload_for(&doc, &company).unwrap_err();
+        assert!(err.starts_with("IPS QR for 12/2026: Invalid IPS field R"), "{}", err);
+    }
+
+    #[test]
+    fn test_long_document_spans_pages() {
+        let company = company();
+        let doc = document("racun", 80);
+        let bytes = render_document_pdf(&PdfInput {
+            document: &doc,
+            company: &company,
+            customer: None,
+        })
+        .unwrap();
+        assert!(page_count(&bytes) >= 3);
+    }
+
+    #[test]
+    fn test_wrap_cyrillic_text() {
+        let (doc, _, _) = PdfDocument::new("wrap", Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        let font = Font::load(&doc, FONT_REGULAR).unwrap();
+        // у шрифта есть глифы для обоих писем — ширина не падает на запасное значение
+        assert!(font.face.glyph_index('Ђ').is_some() && font.face.glyph_index('ž').is_some());
+        let lines = font.wrap("Ђурђевак чај од шипка, pakovanje 50 g", SIZE_TEXT, 30.0);
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|l| font.width(l, SIZE_TEXT) <= 30.0));
+        assert_eq!(lines.join(" "), "Ђурђевак чај од шипка, pakovanje 50 g");
+    }
+}
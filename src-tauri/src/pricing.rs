@@ -0,0 +1,308 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Общая ставка ПДВ (opšta stopa)
+pub const VAT_STANDARD: f64 = 20.0;
+/// Пониженная ставка ПДВ (posebna stopa)
+pub const VAT_REDUCED: f64 = 10.0;
+/// Освобождение от ПДВ — требует код основания освобождения
+pub const VAT_EXEMPT: f64 = 0.0;
+
+/// Допуск сравнения с суммами из UI: JS не округляет промежуточные значения,
+/// поэтому на каждую строку допускаем до полпары расхождения.
+const TOLERANCE_BASE: f64 = 0.01;
+const TOLERANCE_PER_LINE: f64 = 0.005;
+
+/// Округление денежной суммы до пары (2 знака), половина — от нуля.
+/// Небольшой сдвиг компенсирует двоичное представление (1.005 * 100 = 100.49999...).
+pub fn round_money(value: f64) -> f64 {
+    let scaled = value * 100.0;
+    (scaled + scaled.signum() * 1e-9).round() / 100.0
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineInput {
+    pub quantity: f64,
+    /// Цена за единицу без ПДВ
+    pub unit_price: f64,
+    /// Рабат в процентах (0..=100)
+    pub discount_pct: f64,
+    pub vat_rate: f64,
+    pub vat_exemption_code: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricedLine {
+    pub gross: f64,
+    pub discount_amount: f64,
+    /// Основа (без ПДВ, после рабата) — это и есть `invoice_items.total`
+    pub net: f64,
+    pub vat_rate: f64,
+    pub vat_exemption_code: Option<String>,
+    pub vat_amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxBreakdown {
+    pub vat_rate: f64,
+    pub vat_exemption_code: Option<String>,
+    pub taxable_amount: f64,
+    pub vat_amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvoiceTotals {
+    pub lines: Vec<PricedLine>,
+    pub breakdown: Vec<TaxBreakdown>,
+    pub subtotal: f64,
+    pub vat_total: f64,
+    pub total: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PricingError {
+    InvalidNumber { line: usize },
+    InvalidDiscount { line: usize, discount_pct: f64 },
+    UnsupportedVatRate { line: usize, vat_rate: f64 },
+    MissingExemptionCode { line: usize },
+    UnexpectedExemptionCode { line: usize },
+    LineTotalMismatch { line: usize, client: f64, server: f64 },
+    TotalMismatch { client: f64, server: f64 },
+}
+
+impl fmt::Display for PricingError {
+    // Номера строк в сообщениях — с 1, как в UI
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PricingError::InvalidNumber { line } => {
+                write!(f, "Item {}: quantity and price must be numbers", line + 1)
+            }
+            PricingError::InvalidDiscount { line, discount_pct } => {
+                write!(f, "Item {}: discount {}% is out of range 0-100", line + 1, discount_pct)
+            }
+            PricingError::UnsupportedVatRate { line, vat_rate } => {
+                write!(f, "Item {}: unsupported PDV rate {}% (allowed 20, 10, 0)", line + 1, vat_rate)
+            }
+            PricingError::MissingExemptionCode { line } => {
+                write!(f, "Item {}: PDV exemption code is required for 0% rate", line + 1)
+            }
+            PricingError::UnexpectedExemptionCode { line } => {
+                write!(f, "Item {}: PDV exemption code is only allowed for 0% rate", line + 1)
+            }
+            PricingError::LineTotalMismatch { line, client, server } => write!(
+                f,
+                "Item {}: total {:.2} does not match calculated {:.2}",
+                line + 1,
+                client,
+                server
+            ),
+            PricingError::TotalMismatch { client, server } => write!(
+                f,
+                "Invoice total {:.2} does not match calculated {:.2}",
+                client, server
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PricingError {}
+
+fn price_line(index: usize, line: &LineInput) -> Result<PricedLine, PricingError> {
+    if !line.quantity.is_finite() || !line.unit_price.is_finite() || !line.discount_pct.is_finite() {
+        return Err(PricingError::InvalidNumber { line: index });
+    }
+    if !(0.0..=100.0).contains(&line.discount_pct) {
+        return Err(PricingError::InvalidDiscount {
+            line: index,
+            discount_pct: line.discount_pct,
+        });
+    }
+    let exemption_code = line
+        .vat_exemption_code
+        .as_deref()
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(str::to_string);
+    if line.vat_rate == VAT_EXEMPT {
+        if exemption_code.is_none() {
+            return Err(PricingError::MissingExemptionCode { line: index });
+        }
+    } else if line.vat_rate == VAT_STANDARD || line.vat_rate == VAT_REDUCED {
+        if exemption_code.is_some() {
+            return Err(PricingError::UnexpectedExemptionCode { line: index });
+        }
+    } else {
+        return Err(PricingError::UnsupportedVatRate {
+            line: index,
+            vat_rate: line.vat_rate,
+        });
+    }
+
+    let gross = round_money(line.quantity * line.unit_price);
+    let discount_amount = round_money(gross * line.discount_pct / 100.0);
+    let net = round_money(gross - discount_amount);
+    Ok(PricedLine {
+        gross,
+        discount_amount,
+        net,
+        vat_rate: line.vat_rate,
+        vat_amount: round_money(net * line.vat_rate / 100.0),
+        vat_exemption_code: exemption_code,
+    })
+}
+
+/// Считает строки, разбивку по ставкам и итоги документа.
+/// ПДВ документа считается от суммы основ каждой ставки (а не суммой ПДВ строк),
+/// поэтому `vat_total` может отличаться от суммы `PricedLine::vat_amount` на пару.
+pub fn price_invoice(lines: &[LineInput]) -> Result<InvoiceTotals, PricingError> {
+    let priced = lines
+        .iter()
+        .enumerate()
+        .map(|(idx, line)| price_line(idx, line))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Порядок разбивки: 20%, 10%, затем освобождения по коду
+    let mut breakdown: Vec<TaxBreakdown> = Vec::new();
+    for line in &priced {
+        match breakdown
+            .iter_mut()
+            .find(|b| b.vat_rate == line.vat_rate && b.vat_exemption_code == line.vat_exemption_code)
+        {
+            Some(b) => b.taxable_amount = round_money(b.taxable_amount + line.net),
+            None => breakdown.push(TaxBreakdown {
+                vat_rate: line.vat_rate,
+                vat_exemption_code: line.vat_exemption_code.clone(),
+                taxable_amount: line.net,
+                vat_amount: 0.0,
+            }),
+        }
+    }
+    for b in &mut breakdown {
+        b.vat_amount = round_money(b.taxable_amount * b.vat_rate / 100.0);
+    }
+    breakdown.sort_by(|a, b| {
+        b.vat_rate
+            .partial_cmp(&a.vat_rate)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.vat_exemption_code.cmp(&b.vat_exemption_code))
+    });
+
+    let subtotal = round_money(breakdown.iter().map(|b| b.taxable_amount).sum());
+    let vat_total = round_money(breakdown.iter().map(|b| b.vat_amount).sum());
+    Ok(InvoiceTotals {
+        lines: priced,
+        breakdown,
+        subtotal,
+        vat_total,
+        total: round_money(subtotal + vat_total),
+    })
+}
+
+/// Сверяет суммы, присланные UI, с посчитанными на сервере.
+pub fn check_client_totals(
+    totals: &InvoiceTotals,
+    client_line_totals: &[f64],
+    client_total: f64,
+) -> Result<(), PricingError> {
+    for (idx, (line, client)) in totals.lines.iter().zip(client_line_totals).enumerate() {
+        if !client.is_finite() || (line.net - client).abs() > TOLERANCE_BASE {
+            return Err(PricingError::LineTotalMismatch {
+                line: idx,
+                client: *client,
+                server: line.net,
+            });
+        }
+    }
+    let tolerance = TOLERANCE_BASE + TOLERANCE_PER_LINE * totals.lines.len() as f64;
+    if !client_total.is_finite() || (totals.total - client_total).abs() > tolerance {
+        return Err(PricingError::TotalMismatch {
+            client: client_total,
+            server: totals.total,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(quantity: f64, unit_price: f64, discount_pct: f64, vat_rate: f64) -> LineInput {
+        LineInput {
+            quantity,
+            unit_price,
+            discount_pct,
+            vat_rate,
+            vat_exemption_code: None,
+        }
+    }
+
+    #[test]
+    fn test_round_money_half_away_from_zero() {
+        assert_eq!(round_money(1.005), 1.01);
+        assert_eq!(round_money(2.675), 2.68);
+        assert_eq!(round_money(-1.005), -1.01);
+        assert_eq!(round_money(10.0), 10.0);
+    }
+
+    #[test]
+    fn test_discount_and_mixed_rates() {
+        let mut exempt = line(1.0, 50.0, 0.0, VAT_EXEMPT);
+        exempt.vat_exemption_code = Some("PDV-RS-24-1-16".to_string());
+        let totals = price_invoice(&[
+            line(3.0, 333.33, 10.0, VAT_STANDARD),
+            line(2.0, 100.0, 0.0, VAT_REDUCED),
+            line(1.0, 0.99, 0.0, VAT_STANDARD),
+            exempt,
+        ])
+        .unwrap();
+
+        // 999.99 - 100.00 (рабат) = 899.99
+        assert_eq!(totals.lines[0].discount_amount, 100.0);
+        assert_eq!(totals.lines[0].net, 899.99);
+
+        assert_eq!(totals.breakdown.len(), 3);
+        assert_eq!(totals.breakdown[0].vat_rate, VAT_STANDARD);
+        assert_eq!(totals.breakdown[0].taxable_amount, 900.98);
+        assert_eq!(totals.breakdown[0].vat_amount, 180.2);
+        assert_eq!(totals.breakdown[1].vat_rate, VAT_REDUCED);
+        assert_eq!(totals.breakdown[1].vat_amount, 20.0);
+        assert_eq!(totals.breakdown[2].vat_exemption_code.as_deref(), Some("PDV-RS-24-1-16"));
+        assert_eq!(totals.breakdown[2].vat_amount, 0.0);
+
+        assert_eq!(totals.subtotal, 1150.98);
+        assert_eq!(totals.vat_total, 200.2);
+        assert_eq!(totals.total, 1351.18);
+    }
+
+    #[test]
+    fn test_exemption_code_rules() {
+        assert_eq!(
+            price_invoice(&[line(1.0, 10.0, 0.0, VAT_EXEMPT)]),
+            Err(PricingError::MissingExemptionCode { line: 0 })
+        );
+        let mut wrong = line(1.0, 10.0, 0.0, VAT_STANDARD);
+        wrong.vat_exemption_code = Some("PDV-RS-24-1-16".to_string());
+        assert_eq!(price_invoice(&[wrong]), Err(PricingError::UnexpectedExemptionCode { line: 0 }));
+        assert!(matches!(
+            price_invoice(&[line(1.0, 10.0, 0.0, 18.0)]),
+            Err(PricingError::UnsupportedVatRate { .. })
+        ));
+    }
+
+    #[test]
+    fn test_client_totals_are_checked() {
+        let totals = price_invoice(&[line(3.0, 33.333, 0.0, VAT_STANDARD)]).unwrap();
+        // UI без округления: 99.999 * 1.2 = 119.9988
+        assert!(check_client_totals(&totals, &[99.999], 119.9988).is_ok());
+        assert!(matches!(
+            check_client_totals(&totals, &[99.999], 100.0),
+            Err(PricingError::TotalMismatch { .. })
+        ));
+        assert!(matches!(
+            check_client_totals(&totals, &[90.0], 119.9988),
+            Err(PricingError::LineTotalMismatch { line: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::database::Database;
+use crate::numbering;
+use crate::payments;
+use chrono::{Duration, NaiveDate};
+use rusqlite::{Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReceivablesRequest {
+    /// Дата отчёта YYYY-MM-DD; если null — сегодня
+    pub as_of: Option<String>,
+    /// опционально: только один клиент (invoices.client_id)
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AgingBuckets {
+    /// срок оплаты ещё не наступил
+    pub current: f64,
+    pub days_1_30: f64,
+    pub days_31_60: f64,
+    pub days_61_90: f64,
+    pub days_90_plus: f64,
+    pub total: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenInvoice {
+    pub id: String,
+    pub invoice_number: String,
+    pub date: String,
+    /// Фактический срок: due_date документа, иначе дата + installment_term клиента
+    pub due_date: String,
+    pub days_overdue: i64,
+    pub currency: String,
+    pub total: f64,
+    /// Остаток в валюте документа
+    pub balance: f64,
+    /// Остаток в RSD (по курсу документа)
+    pub balance_rsd: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientReceivables {
+    pub client_id: Option<String>,
+    pub client_name: String,
+    pub installment_term: Option<i32>,
+    pub buckets: AgingBuckets,
+    pub oldest_days_overdue: i64,
+    pub invoices: Vec<OpenInvoice>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceivablesReport {
+    pub as_of: String,
+    pub clients: Vec<ClientReceivables>,
+    pub totals: AgingBuckets,
+    pub open_invoices: u32,
+}
+
+#[derive(Debug, Clone)]
+struct OpenItem {
+    id: String,
+    invoice_number: String,
+    client_id: Option<String>,
+    client_name: String,
+    date: String,
+    due_date: Option<String>,
+    total: f64,
+    currency: String,
+    exchange_rate: Option<f64>,
+    amount_paid: f64,
+    credited_total: f64,
+    installment: bool,
+    installment_term: Option<i32>,
+}
+
+pub struct ReceivablesService;
+
+impl ReceivablesService {
+    pub fn generate(db: &Database, req: ReceivablesRequest) -> Result<ReceivablesReport, String> {
+        let as_of = match &req.as_of {
+            Some(s) => payments::parse_date(s).ok_or_else(|| format!("Invalid report date {}", s))?,
+            None => payments::today(),
+        };
+        let conn = db.conn()?;
+        build_report(&conn, req.client_id.as_deref(), as_of)
+    }
+}
+
+fn build_report(conn: &Connection, client_id: Option<&str>, as_of: NaiveDate) -> Result<ReceivablesReport, String> {
+    let items = load_open_items(conn, client_id)?;
+
+    let mut by_client: HashMap<String, ClientReceivables> = HashMap::new();
+    let mut totals = AgingBuckets::default();
+    let mut open_invoices = 0;
+
+    for item in items {
+        let state = payments::payment_state(
+            numbering::RACUN,
+            item.total,
+            item.credited_total,
+            item.amount_paid,
+            None,
+            as_of,
+        );
+        if state.balance <= 0.0 {
+            continue;
+        }
+        let Some(due) = effective_due_date(&item) else {
+            continue;
+        };
+        let days_overdue = (as_of - due).num_days().max(0);
+        let balance_rsd = round2(state.balance * rsd_rate(&item));
+
+        add_to_bucket(&mut totals, days_overdue, balance_rsd);
+        open_invoices += 1;
+
+        let key = item.client_id.clone().unwrap_or_else(|| item.client_name.clone());
+        let client = by_client.entry(key).or_insert_with(|| ClientReceivables {
+            client_id: item.client_id.clone(),
+            client_name: item.client_name.clone(),
+            installment_term: if item.installment { item.installment_term } else { None },
+            buckets: AgingBuckets::default(),
+            oldest_days_overdue: 0,
+            invoices: Vec::new(),
+        });
+        add_to_bucket(&mut client.buckets, days_overdue, balance_rsd);
+        client.oldest_days_overdue = client.oldest_days_overdue.max(days_overdue);
+        client.invoices.push(OpenInvoice {
+            id: item.id,
+            invoice_number: item.invoice_number,
+            date: item.date,
+            due_date: due.format("%Y-%m-%d").to_string(),
+            days_overdue,
+            currency: item.currency,
+            total: item.total,
+            balance: state.balance,
+            balance_rsd,
+        });
+    }
+
+    let mut clients: Vec<ClientReceivables> = by_client.into_values().collect();
+    for c in &mut clients {
+        c.invoices.sort_by_key(|inv| std::cmp::Reverse(inv.days_overdue));
+    }
+    // Крупнейшие должники сверху
+    clients.sort_by(|a, b| {
+        b.buckets
+            .total
+            .partial_cmp(&a.buckets.total)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.client_name.cmp(&b.client_name))
+    });
+
+    Ok(ReceivablesReport {
+        as_of: as_of.format("%Y-%m-%d").to_string(),
+        clients,
+        totals,
+        open_invoices,
+    })
+}
+
+fn load_open_items(conn: &Connection, client_id: Option<&str>) -> Result<Vec<OpenItem>, String> {
+    // Требования — только выставленные рачуны (отклонённые и отменённые в SEF не в счёт);
+    // кредит-ноты уменьшают остаток через CREDITED_TOTAL_SQL.
+    // invoices.client_id — clients.id (с миграции 12); в старых выгрузках встречался МБ клиента.
+    let sql = format!(
+        "SELECT id, invoice_number, document_type, client_id, COALESCE(client_name, ''), date, due_date, total, \
+                COALESCE(currency, 'RSD'), exchange_rate, {}, {}, \
+                (SELECT c.installment FROM clients c WHERE CAST(c.id AS TEXT) = invoices.client_id OR c.mb = invoices.client_id LIMIT 1), \
+                (SELECT c.installment_term FROM clients c WHERE CAST(c.id AS TEXT) = invoices.client_id OR c.mb = invoices.client_id LIMIT 1) \
+         FROM invoices \
+         WHERE status NOT IN ('draft', 'rejected', 'cancelled') AND (?1 IS NULL OR client_id = ?1)",
+        payments::AMOUNT_PAID_SQL,
+        payments::CREDITED_TOTAL_SQL
+    );
+    let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([client_id], |row| Ok((row.get::<_, String>(2)?, item_from_row(row)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows
+        .into_iter()
+        .filter(|(document_type, _)| numbering::sequence_key(document_type) == numbering::RACUN)
+        .map(|(_, item)| item)
+        .collect())
+}
+
+fn item_from_row(row: &Row<'_>) -> rusqlite::Result<OpenItem> {
+    let installment: Option<i32> = row.get(12)?;
+    Ok(OpenItem {
+        id: row.get(0)?,
+        invoice_number: row.get(1)?,
+        client_id: row.get(3)?,
+        client_name: row.get(4)?,
+        date: row.get(5)?,
+        due_date: row.get(6)?,
+        total: row.get(7)?,
+        currency: row.get(8)?,
+        exchange_rate: row.get(9)?,
+        amount_paid: row.get(10)?,
+        credited_total: row.get(11)?,
+        installment: installment.unwrap_or(0) != 0,
+        installment_term: row.get(13)?,
+    })
+}
+
+/// Срок оплаты: явный due_date документа; иначе дата документа + отсрочка клиента
+/// (если клиент работает в рассрочку); иначе — дата документа.
+fn effective_due_date(item: &OpenItem) -> Option<NaiveDate> {
+    if let Some(due) = item.due_date.as_deref().and_then(payments::parse_date) {
+        return Some(due);
+    }
+    let date = payments::parse_date(&item.date)?;
+    match (item.installment, item.installment_term) {
+        (true, Some(days)) if days > 0 => Some(date + Duration::days(days as i64)),
+        _ => Some(date),
+    }
+}
+
+fn rsd_rate(item: &OpenItem) -> f64 {
+    if item.currency == "RSD" {
+        return 1.0;
+    }
+    item.exchange_rate.filter(|r| *r > 0.0).unwrap_or(1.0)
+}
+
+fn add_to_bucket(b: &mut AgingBuckets, days_overdue: i64, amount: f64) {
+    let slot = match days_overdue {
+        0 => &mut b.current,
+        1..=30 => &mut b.days_1_30,
+        31..=60 => &mut b.days_31_60,
+        61..=90 => &mut b.days_61_90,
+        _ => &mut b.days_90_plus,
+    };
+    *slot = round2(*slot + amount);
+    b.total = round2(b.total + amount);
+}
+
+fn round2(v: f64) -> f64 {
+    (v * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+    use rusqlite::params;
+
+    fn insert_invoice(conn: &Connection, id: &str, client_id: &str, date: &str, due_date: Option<&str>, total: f64) {
+        conn.execute(
+            "INSERT INTO invoices (id, invoice_number, document_type, client_id, client_name, date, due_date, total, status, created_at)
+             VALUES (?1, ?1, 'racun', ?2, ?2, ?3, ?4, ?5, 'confirmed', '')",
+            params![id, client_id, date, due_date, total],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_aging_buckets_and_installment_term() {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO clients (id, name, mb, installment, installment_term, created_at) VALUES (111, 'Kafana', '20000111', 1, 45, '');
+             INSERT INTO clients (id, name, mb, created_at) VALUES (222, 'Pekara', '20000222', '');",
+        )
+        .unwrap();
+
+        // Срок по installment_term: 2026-01-01 + 45 = 2026-02-15 -> 44 дня просрочки на 2026-03-31
+        insert_invoice(&conn, "a", "111", "2026-01-01", None, 1000.0);
+        insert_invoice(&conn, "b", "111", "2026-03-20", Some("2026-04-10"), 500.0);
+        insert_invoice(&conn, "c", "222", "2025-11-01", Some("2025-11-15"), 300.0);
+        insert_invoice(&conn, "d", "222", "2026-03-01", Some("2026-03-15"), 200.0);
+        // Оплачен полностью — в отчёт не попадает
+        insert_invoice(&conn, "e", "222", "2026-03-01", None, 100.0);
+        conn.execute_batch(
+            "INSERT INTO payments (id, invoice_id, date, amount, currency, method, created_at) VALUES
+                ('p1', 'e', '2026-03-02', 100.0, 'RSD', 'cash', ''),
+                ('p2', 'a', '2026-03-02', 250.0, 'RSD', 'cash', '');",
+        )
+        .unwrap();
+
+        let as_of = NaiveDate::from_ymd_opt(2026, 3, 31).unwrap();
+        let report = build_report(&conn, None, as_of).unwrap();
+
+        assert_eq!(report.open_invoices, 4);
+        assert_eq!(
+            report.totals,
+            AgingBuckets {
+                current: 500.0,
+                days_1_30: 200.0,
+                days_31_60: 750.0,
+                days_61_90: 0.0,
+                days_90_plus: 300.0,
+                total: 1750.0,
+            }
+        );
+        assert_eq!(report.clients[0].client_id.as_deref(), Some("111"));
+        assert_eq!(report.clients[0].installment_term, Some(45));
+        assert_eq!(report.clients[0].invoices[0].due_date, "2026-02-15");
+        assert_eq!(report.clients[1].oldest_days_overdue, 136);
+
+        let one = build_report(&conn, Some("222"), as_of).unwrap();
+        assert_eq!(one.clients.len(), 1);
+        assert_eq!(one.totals.total, 500.0);
+    }
+}
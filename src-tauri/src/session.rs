@@ -0,0 +1,176 @@
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::audit::Actor;
+use crate::users::{Permission, Role};
+
+/// Сессия без обращений дольше этого закрывается — нужен повторный вход
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone)]
+pub struct SignedIn {
+    pub actor: Actor,
+    pub role: Role,
+    /// Клиент профиля роли client (users.client_id)
+    pub client_id: Option<i64>,
+}
+
+struct ActiveSession {
+    id: String,
+    user: SignedIn,
+    started_at: String,
+    last_seen: Instant,
+}
+
+/// Текущая сессия для UI (восстановление после перезагрузки окна)
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub user_id: Option<String>,
+    pub username: String,
+    pub role: Role,
+    pub started_at: String,
+    /// Через сколько секунд бездействия сессия закроется
+    pub idle_timeout_secs: u64,
+}
+
+/// Сессия вошедшего пользователя. Открывается командой login, закрывается logout
+/// или по IDLE_TIMEOUT. Каждая команда проверяет здесь права и берёт автора
+/// для audit_log и created_by / modified_by; успешная проверка продлевает сессию.
+#[derive(Default)]
+pub struct Session {
+    active: RwLock<Option<ActiveSession>>,
+}
+
+impl Session {
+    pub fn sign_in(&self, actor: Actor, role: Role, client_id: Option<i64>) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        *self.active.write().unwrap_or_else(|e| e.into_inner()) = Some(ActiveSession {
+            id: id.clone(),
+            user: SignedIn { actor, role, client_id },
+            started_at: chrono::Utc::now().to_rfc3339(),
+            last_seen: Instant::now(),
+        });
+        id
+    }
+
+    /// Закрывает сессию; возвращает имя вышедшего пользователя.
+    pub fn sign_out(&self) -> Option<String> {
+        self.active
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .map(|s| s.user.actor.username)
+    }
+
+    pub fn signed_in(&self) -> Result<SignedIn, String> {
+        self.touch(Instant::now())
+    }
+
+    pub fn info(&self) -> Result<SessionInfo, String> {
+        self.touch(Instant::now())?;
+        let active = self.active.read().unwrap_or_else(|e| e.into_inner());
+        let session = active.as_ref().ok_or("Not signed in")?;
+        Ok(SessionInfo {
+            session_id: session.id.clone(),
+            user_id: session.user.actor.user_id.clone(),
+            username: session.user.actor.username.clone(),
+            role: session.user.role,
+            started_at: session.started_at.clone(),
+            idle_timeout_secs: IDLE_TIMEOUT.as_secs(),
+        })
+    }
+
+    /// Автор изменения, если у него есть право `permission`.
+    pub fn require(&self, permission: Permission) -> Result<Actor, String> {
+        Ok(self.authorize(permission)?.actor)
+    }
+
+    /// Проверяет право `permission` и возвращает клиента, которым ограничено чтение:
+    /// профиль роли client видит только документы своего клиента, остальные — все (None).
+    pub fn require_scoped(&self, permission: Permission) -> Result<Option<i64>, String> {
+        let user = self.authorize(permission)?;
+        match (user.role, user.client_id) {
+            (Role::Client, None) => Err(format!("Client profile {} has no client", user.actor.username)),
+            (Role::Client, client_id) => Ok(client_id),
+            _ => Ok(None),
+        }
+    }
+
+    fn authorize(&self, permission: Permission) -> Result<SignedIn, String> {
+        let user = self.signed_in()?;
+        if !user.role.allows(permission) {
+            return Err(format!(
+                "Permission denied: {} ({}) cannot {}",
+                user.actor.username,
+                user.role.as_str(),
+                permission.as_str()
+            ));
+        }
+        Ok(user)
+    }
+
+    /// Проверяет сессию на момент `now` и продлевает её; просроченная закрывается.
+    fn touch(&self, now: Instant) -> Result<SignedIn, String> {
+        let mut active = self.active.write().unwrap_or_else(|e| e.into_inner());
+        let session = active.as_mut().ok_or("Not signed in")?;
+        if now.saturating_duration_since(session.last_seen) > IDLE_TIMEOUT {
+            println!("⏱️ Сессия {} закрыта по бездействию", session.user.actor.username);
+            *active = None;
+            return Err("Session expired".to_string());
+        }
+        session.last_seen = now;
+        Ok(session.user.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warehouse_actor() -> Actor {
+        Actor {
+            user_id: Some("u2".to_string()),
+            username: "magacin".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_require_checks_role() {
+        let session = Session::default();
+        assert_eq!(session.require(Permission::InvoicesView).unwrap_err(), "Not signed in");
+
+        session.sign_in(warehouse_actor(), Role::Warehouse, None);
+        assert_eq!(session.require(Permission::WarehouseEdit).unwrap(), warehouse_actor());
+        let denied = session.require(Permission::InvoicesDelete).unwrap_err();
+        assert!(denied.contains("invoices.delete"), "{}", denied);
+        assert_eq!(session.require_scoped(Permission::WarehouseView), Ok(None));
+
+        // Профиль клиента ограничен своим клиентом
+        session.sign_in(warehouse_actor(), Role::Client, Some(7));
+        assert_eq!(session.require_scoped(Permission::InvoicesView), Ok(Some(7)));
+        assert!(session.require_scoped(Permission::InvoicesEdit).is_err());
+
+        assert_eq!(session.sign_out().as_deref(), Some("magacin"));
+        assert_eq!(session.signed_in().unwrap_err(), "Not signed in");
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let session = Session::default();
+        session.sign_in(warehouse_actor(), Role::Warehouse, None);
+        let start = Instant::now();
+
+        // активность продлевает сессию
+        let active = start + IDLE_TIMEOUT - Duration::from_secs(1);
+        assert!(session.touch(active).is_ok());
+        assert!(session.touch(active + IDLE_TIMEOUT).is_ok());
+
+        let idle = active + IDLE_TIMEOUT * 2 + Duration::from_secs(1);
+        assert_eq!(session.touch(idle).unwrap_err(), "Session expired");
+        assert_eq!(session.touch(idle).unwrap_err(), "Not signed in");
+    }
+}
@@ -0,0 +1,877 @@
+use crate::commands::{pricing_lines, InvoiceWithItems, LinkedDocument};
+use crate::numbering;
+use crate::pricing::{self, InvoiceTotals};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+/// Профиль EN 16931 с расширением SEF (так же подписан UBL, который генерирует сама eFaktura)
+pub const CUSTOMIZATION_ID: &str = "urn:cen.eu:en16931:2017#compliant#urn:mfin.gov.rs:srbdt:2022";
+/// Коммерческий рачун
+const INVOICE_TYPE_CODE: &str = "380";
+/// Кредит-нота (книжно одобрење)
+const CREDIT_NOTE_TYPE_CODE: &str = "381";
+/// Датум промета = датум издавања
+const VAT_POINT_DATE_CODE: &str = "35";
+/// Плаћање на рачун (credit transfer)
+const PAYMENT_MEANS_CREDIT_TRANSFER: &str = "30";
+/// Шифра PIB в SEF для EndpointID
+const ENDPOINT_SCHEME_PIB: &str = "9948";
+/// Единица по умолчанию: H87 = komad
+const DEFAULT_UNIT_CODE: &str = "H87";
+const BASE_CURRENCY: &str = "RSD";
+
+const NS_INVOICE: &str = "urn:oasis:names:specification:ubl:schema:xsd:Invoice-2";
+const NS_CREDIT_NOTE: &str = "urn:oasis:names:specification:ubl:schema:xsd:CreditNote-2";
+const NS_CAC: &str = "urn:oasis:names:specification:ubl:schema:xsd:CommonAggregateComponents-2";
+const NS_CBC: &str = "urn:oasis:names:specification:ubl:schema:xsd:CommonBasicComponents-2";
+
+/// Сторона документа (продавец или покупатель).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UblParty {
+    pub name: String,
+    /// Полное юридическое имя; если нет — `name`
+    pub registration_name: Option<String>,
+    pub pib: Option<String>,
+    pub mb: Option<String>,
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub postal_code: Option<String>,
+    /// ISO 3166-1 alpha-2
+    pub country_code: String,
+}
+
+/// Отпремница к рачуну: номер идёт в DespatchDocumentReference, PDF — вложением.
+/// Поля совпадают с `currentInvoiceData.efakturaOtpremnica` в UI.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OtpremnicaAttachment {
+    pub number: Option<String>,
+    pub pdf_base64: Option<String>,
+    pub pdf_filename: Option<String>,
+    pub pdf_mime: Option<String>,
+}
+
+pub struct UblInput<'a> {
+    pub document: &'a InvoiceWithItems,
+    pub supplier: &'a UblParty,
+    pub customer: &'a UblParty,
+    /// Текущий рачун продавца для PaymentMeans
+    pub bank_account: &'a str,
+    pub otpremnica: Option<&'a OtpremnicaAttachment>,
+}
+
+/// Покупатель по `invoices.client_id` (clients.id или МБ клиента — документы из синхронизации).
+pub fn load_customer(conn: &Connection, client_id: &str) -> Result<Option<UblParty>, String> {
+    conn.query_row(
+        "SELECT name, legal_name, pib, mb, address, street, house_number, city, postal_code, country
+         FROM clients WHERE CAST(id AS TEXT) = ?1 OR mb = ?1 LIMIT 1",
+        [client_id],
+        |row| {
+            let address: Option<String> = row.get(4)?;
+            let street: Option<String> = row.get(5)?;
+            let house_number: Option<String> = row.get(6)?;
+            let street = match non_empty(street) {
+                Some(s) => Some(match non_empty(house_number) {
+                    Some(n) => format!("{} {}", s, n),
+                    None => s,
+                }),
+                None => non_empty(address),
+            };
+            let country: Option<String> = row.get(9)?;
+            Ok(UblParty {
+                name: row.get(0)?,
+                registration_name: non_empty(row.get(1)?),
+                pib: non_empty(row.get(2)?),
+                mb: non_empty(row.get(3)?),
+                street,
+                city: non_empty(row.get(7)?),
+                postal_code: non_empty(row.get(8)?),
+                country_code: country_code(country.as_deref()),
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// В clients.country хранится название страны или код; для UBL нужен ISO-код.
+fn country_code(country: Option<&str>) -> String {
+    match country.map(str::trim) {
+        Some(c) if c.len() == 2 && c.chars().all(|ch| ch.is_ascii_alphabetic()) => c.to_uppercase(),
+        _ => "RS".to_string(),
+    }
+}
+
+/// Категория ПДВ по EN 16931 для ставки и кода освобождения SEF (PDV-RS-<član>-...):
+/// чл. 24 — Z (с правом на вычет), чл. 25 — E (без права на вычет),
+/// чл. 10 — AE (обратное начисление), прочее — O (вне системы ПДВ).
+pub fn vat_category(vat_rate: f64, exemption_code: Option<&str>) -> &'static str {
+    if vat_rate > 0.0 {
+        return "S";
+    }
+    let article = exemption_code
+        .and_then(|code| code.trim().strip_prefix("PDV-RS-"))
+        .and_then(|rest| rest.split('-').next())
+        .unwrap_or("");
+    match article.trim_end_matches(|c: char| c.is_ascii_alphabetic()) {
+        "24" => "Z",
+        "25" => "E",
+        "10" => "AE",
+        _ => "O",
+    }
+}
+
+fn amount(value: f64) -> String {
+    format!("{:.2}", pricing::round_money(value))
+}
+
+/// Количество и проценты — без хвостовых нулей (3, 2.5, 0.125)
+fn decimal(value: f64) -> String {
+    let s = format!("{:.4}", value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" {
+        "0".to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+fn digits_only(value: &str) -> String {
+    value.chars().filter(char::is_ascii_digit).collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Минимальный писатель XML с отступами в 2 пробела — вывод стабилен для golden-тестов.
+struct XmlWriter {
+    out: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn new() -> Self {
+        XmlWriter {
+            out: String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"),
+            depth: 0,
+        }
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+    }
+
+    fn attrs(&mut self, attrs: &[(&str, &str)]) {
+        for (name, value) in attrs {
+            self.out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+    }
+
+    fn open(&mut self, tag: &str, attrs: &[(&str, &str)]) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(tag);
+        self.attrs(attrs);
+        self.out.push_str(">\n");
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.out.push_str(&format!("</{}>\n", tag));
+    }
+
+    fn leaf(&mut self, tag: &str, text: &str) {
+        self.leaf_with(tag, &[], text);
+    }
+
+    fn leaf_with(&mut self, tag: &str, attrs: &[(&str, &str)], text: &str) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(tag);
+        self.attrs(attrs);
+        self.out.push_str(&format!(">{}</{}>\n", escape(text), tag));
+    }
+
+    fn money(&mut self, tag: &str, currency: &str, value: f64) {
+        self.leaf_with(tag, &[("currencyID", currency)], &amount(value));
+    }
+
+    fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// Элементы, которыми UBL Invoice и CreditNote отличаются друг от друга
+struct DocumentKind {
+    root: &'static str,
+    namespace: &'static str,
+    type_code_tag: &'static str,
+    type_code: &'static str,
+    line_tag: &'static str,
+    quantity_tag: &'static str,
+}
+
+const INVOICE_KIND: DocumentKind = DocumentKind {
+    root: "Invoice",
+    namespace: NS_INVOICE,
+    type_code_tag: "cbc:InvoiceTypeCode",
+    type_code: INVOICE_TYPE_CODE,
+    line_tag: "cac:InvoiceLine",
+    quantity_tag: "cbc:InvoicedQuantity",
+};
+
+const CREDIT_NOTE_KIND: DocumentKind = DocumentKind {
+    root: "CreditNote",
+    namespace: NS_CREDIT_NOTE,
+    type_code_tag: "cbc:CreditNoteTypeCode",
+    type_code: CREDIT_NOTE_TYPE_CODE,
+    line_tag: "cac:CreditNoteLine",
+    quantity_tag: "cbc:CreditedQuantity",
+};
+
+/// Сериализует рачун в UBL 2.1 (Invoice) по профилю SEF, кредит-ноту — в CreditNote
+/// со ссылкой на исходный рачун (BillingReference).
+/// Суммы пересчитываются из позиций тем же `pricing`, что и при сохранении документа.
+pub fn build_invoice_xml(input: &UblInput) -> Result<String, String> {
+    let invoice = &input.document.invoice;
+    let number = invoice.invoice_number.trim();
+    if number.is_empty() {
+        return Err("Invoice has no number".to_string());
+    }
+    if input.document.items.is_empty() {
+        return Err(format!("Invoice {} has no items", number));
+    }
+    let credited_invoice = match numbering::sequence_key(&invoice.document_type) {
+        numbering::RACUN => None,
+        numbering::KNJIZNO_ODOBRENJE => Some(credited_invoice(input.document)?),
+        _ => {
+            return Err(format!(
+                "UBL export is only available for racun and credit notes, not {}",
+                invoice.document_type
+            ))
+        }
+    };
+    let kind = if credited_invoice.is_some() { &CREDIT_NOTE_KIND } else { &INVOICE_KIND };
+    // Кредит-нота хранит количества со знаком минус, а в CreditNote суммы положительные
+    let sign = if credited_invoice.is_some() { -1.0 } else { 1.0 };
+    let supplier_pib = required(input.supplier.pib.as_deref(), "Company PIB is not set")?;
+    let supplier_mb = required(input.supplier.mb.as_deref(), "Company MB is not set")?;
+    let customer_pib = required(
+        input.customer.pib.as_deref(),
+        &format!("Client {} has no PIB", input.customer.name),
+    )?;
+    let customer_mb = required(
+        input.customer.mb.as_deref(),
+        &format!("Client {} has no MB", input.customer.name),
+    )?;
+    let bank_account = digits_only(input.bank_account);
+    if bank_account.is_empty() {
+        return Err("Company bank account is not set".to_string());
+    }
+
+    let totals = price_items(input.document, sign)?;
+    let currency = invoice.currency.as_deref().unwrap_or(BASE_CURRENCY).trim().to_uppercase();
+    let currency = currency.as_str();
+    let issue_date = invoice.date.get(..10).unwrap_or(&invoice.date);
+    // Для валютных рачуна SEF требует сумму ПДВ и в динарах (TaxCurrencyCode)
+    let exchange_rate = invoice
+        .exchange_rate
+        .filter(|r| currency != BASE_CURRENCY && *r > 0.0);
+
+    let mut w = XmlWriter::new();
+    w.open(
+        kind.root,
+        &[("xmlns", kind.namespace), ("xmlns:cac", NS_CAC), ("xmlns:cbc", NS_CBC)],
+    );
+    w.leaf("cbc:CustomizationID", CUSTOMIZATION_ID);
+    w.leaf("cbc:ID", number);
+    w.leaf("cbc:IssueDate", issue_date);
+    // В CreditNote нет DueDate в шапке
+    if let Some(due) = invoice
+        .due_date
+        .as_deref()
+        .filter(|d| !d.trim().is_empty() && credited_invoice.is_none())
+    {
+        w.leaf("cbc:DueDate", due.get(..10).unwrap_or(due));
+    }
+    w.leaf(kind.type_code_tag, kind.type_code);
+    if let Some(rate) = exchange_rate {
+        let rate_date = invoice.exchange_rate_date.as_deref().unwrap_or(issue_date);
+        w.leaf(
+            "cbc:Note",
+            &format!("Exchange rate (NBS) {}/RSD={} on {}", currency, rate, rate_date),
+        );
+    }
+    w.leaf("cbc:DocumentCurrencyCode", currency);
+    if exchange_rate.is_some() {
+        w.leaf("cbc:TaxCurrencyCode", BASE_CURRENCY);
+    }
+    w.open("cac:InvoicePeriod", &[]);
+    w.leaf("cbc:DescriptionCode", VAT_POINT_DATE_CODE);
+    w.close("cac:InvoicePeriod");
+
+    if let Some(original) = credited_invoice {
+        w.open("cac:BillingReference", &[]);
+        w.open("cac:InvoiceDocumentReference", &[]);
+        w.leaf("cbc:ID", original.invoice_number.trim());
+        w.leaf("cbc:IssueDate", original.date.get(..10).unwrap_or(&original.date));
+        w.close("cac:InvoiceDocumentReference");
+        w.close("cac:BillingReference");
+    }
+    write_otpremnica(&mut w, input.otpremnica);
+
+    w.open("cac:AccountingSupplierParty", &[]);
+    write_party(&mut w, input.supplier, supplier_pib, supplier_mb);
+    w.close("cac:AccountingSupplierParty");
+    w.open("cac:AccountingCustomerParty", &[]);
+    write_party(&mut w, input.customer, customer_pib, customer_mb);
+    w.close("cac:AccountingCustomerParty");
+
+    w.open("cac:Delivery", &[]);
+    w.leaf("cbc:ActualDeliveryDate", issue_date);
+    w.close("cac:Delivery");
+
+    w.open("cac:PaymentMeans", &[]);
+    w.leaf("cbc:PaymentMeansCode", PAYMENT_MEANS_CREDIT_TRANSFER);
+    w.leaf("cbc:PaymentID", number);
+    w.open("cac:PayeeFinancialAccount", &[]);
+    w.leaf("cbc:ID", &bank_account);
+    w.close("cac:PayeeFinancialAccount");
+    w.close("cac:PaymentMeans");
+
+    w.open("cac:TaxTotal", &[]);
+    w.money("cbc:TaxAmount", currency, totals.vat_total);
+    for b in &totals.breakdown {
+        w.open("cac:TaxSubtotal", &[]);
+        w.money("cbc:TaxableAmount", currency, b.taxable_amount);
+        w.money("cbc:TaxAmount", currency, b.vat_amount);
+        write_tax_category(&mut w, "cac:TaxCategory", b.vat_rate, b.vat_exemption_code.as_deref(), true);
+        w.close("cac:TaxSubtotal");
+    }
+    w.close("cac:TaxTotal");
+    if let Some(rate) = exchange_rate {
+        w.open("cac:TaxTotal", &[]);
+        w.money("cbc:TaxAmount", BASE_CURRENCY, totals.vat_total * rate);
+        w.close("cac:TaxTotal");
+    }
+
+    w.open("cac:LegalMonetaryTotal", &[]);
+    w.money("cbc:LineExtensionAmount", currency, totals.subtotal);
+    w.money("cbc:TaxExclusiveAmount", currency, totals.subtotal);
+    w.money("cbc:TaxInclusiveAmount", currency, totals.total);
+    w.money("cbc:PayableAmount", currency, totals.total);
+    w.close("cac:LegalMonetaryTotal");
+
+    for (idx, (item, line)) in input.document.items.iter().zip(&totals.lines).enumerate() {
+        w.open(kind.line_tag, &[]);
+        w.leaf("cbc:ID", &(idx + 1).to_string());
+        w.leaf_with(
+            kind.quantity_tag,
+            &[("unitCode", DEFAULT_UNIT_CODE)],
+            &decimal(sign * item.quantity),
+        );
+        w.money("cbc:LineExtensionAmount", currency, line.net);
+        if line.discount_amount > 0.0 {
+            w.open("cac:AllowanceCharge", &[]);
+            w.leaf("cbc:ChargeIndicator", "false");
+            w.leaf("cbc:MultiplierFactorNumeric", &decimal(item.discount_pct.unwrap_or(0.0)));
+            w.money("cbc:Amount", currency, line.discount_amount);
+            w.money("cbc:BaseAmount", currency, line.gross);
+            w.close("cac:AllowanceCharge");
+        }
+        w.open("cac:Item", &[]);
+        w.leaf("cbc:Name", &item.product_name);
+        w.open("cac:SellersItemIdentification", &[]);
+        w.leaf("cbc:ID", &item.product_id);
+        w.close("cac:SellersItemIdentification");
+        write_tax_category(
+            &mut w,
+            "cac:ClassifiedTaxCategory",
+            line.vat_rate,
+            line.vat_exemption_code.as_deref(),
+            false,
+        );
+        w.close("cac:Item");
+        w.open("cac:Price", &[]);
+        w.money("cbc:PriceAmount", currency, item.price);
+        w.close("cac:Price");
+        w.close(kind.line_tag);
+    }
+
+    w.close(kind.root);
+    Ok(w.finish())
+}
+
+/// Исходный рачун кредит-ноты — ближайший источник в цепочке документа
+fn credited_invoice(document: &InvoiceWithItems) -> Result<&LinkedDocument, String> {
+    document
+        .source_documents
+        .first()
+        .filter(|source| numbering::sequence_key(&source.document_type) == numbering::RACUN)
+        .ok_or_else(|| format!("Credit note {} has no original racun", document.invoice.invoice_number))
+}
+
+fn required<'a>(value: Option<&'a str>, message: &str) -> Result<&'a str, String> {
+    value
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| message.to_string())
+}
+
+/// `sign` = -1 переворачивает отрицательные количества кредит-ноты
+fn price_items(document: &InvoiceWithItems, sign: f64) -> Result<InvoiceTotals, String> {
+    let mut lines = pricing_lines(&document.invoice, &document.items);
+    for line in &mut lines {
+        line.quantity *= sign;
+    }
+    pricing::price_invoice(&lines).map_err(|e| e.to_string())
+}
+
+fn write_party(w: &mut XmlWriter, party: &UblParty, pib: &str, mb: &str) {
+    let pib = digits_only(pib);
+    w.open("cac:Party", &[]);
+    w.leaf_with("cbc:EndpointID", &[("schemeID", ENDPOINT_SCHEME_PIB)], &pib);
+    w.open("cac:PartyName", &[]);
+    w.leaf("cbc:Name", &party.name);
+    w.close("cac:PartyName");
+    w.open("cac:PostalAddress", &[]);
+    if let Some(street) = &party.street {
+        w.leaf("cbc:StreetName", street);
+    }
+    if let Some(city) = &party.city {
+        w.leaf("cbc:CityName", city);
+    }
+    if let Some(postal_code) = &party.postal_code {
+        w.leaf("cbc:PostalZone", postal_code);
+    }
+    w.open("cac:Country", &[]);
+    w.leaf("cbc:IdentificationCode", &party.country_code);
+    w.close("cac:Country");
+    w.close("cac:PostalAddress");
+    w.open("cac:PartyTaxScheme", &[]);
+    w.leaf("cbc:CompanyID", &format!("RS{}", pib));
+    w.open("cac:TaxScheme", &[]);
+    w.leaf("cbc:ID", "VAT");
+    w.close("cac:TaxScheme");
+    w.close("cac:PartyTaxScheme");
+    w.open("cac:PartyLegalEntity", &[]);
+    w.leaf("cbc:RegistrationName", party.registration_name.as_deref().unwrap_or(&party.name));
+    w.leaf("cbc:CompanyID", &digits_only(mb));
+    w.close("cac:PartyLegalEntity");
+    w.close("cac:Party");
+}
+
+/// Категория ПДВ; код освобождения пишется только в разбивке (TaxSubtotal), не в строках.
+fn write_tax_category(w: &mut XmlWriter, tag: &str, vat_rate: f64, exemption_code: Option<&str>, with_exemption: bool) {
+    w.open(tag, &[]);
+    w.leaf("cbc:ID", vat_category(vat_rate, exemption_code));
+    w.leaf("cbc:Percent", &decimal(vat_rate));
+    if with_exemption {
+        if let Some(code) = exemption_code {
+            w.leaf("cbc:TaxExemptionReasonCode", code);
+        }
+    }
+    w.open("cac:TaxScheme", &[]);
+    w.leaf("cbc:ID", "VAT");
+    w.close("cac:TaxScheme");
+    w.close(tag);
+}
+
+fn write_otpremnica(w: &mut XmlWriter, otpremnica: Option<&OtpremnicaAttachment>) {
+    let Some(otpremnica) = otpremnica else {
+        return;
+    };
+    let number = otpremnica.number.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if let Some(number) = number {
+        w.open("cac:DespatchDocumentReference", &[]);
+        w.leaf("cbc:ID", number);
+        w.close("cac:DespatchDocumentReference");
+    }
+
+    let pdf: String = otpremnica
+        .pdf_base64
+        .as_deref()
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if pdf.is_empty() {
+        return;
+    }
+    let filename = otpremnica
+        .pdf_filename
+        .as_deref()
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| match number {
+            Some(n) => format!("OTPREMNICA-{}.pdf", n.replace('/', "-")),
+            None => "otpremnica.pdf".to_string(),
+        });
+    let mime = otpremnica
+        .pdf_mime
+        .as_deref()
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .unwrap_or("application/pdf");
+    w.open("cac:AdditionalDocumentReference", &[]);
+    w.leaf("cbc:ID", &filename);
+    w.open("cac:Attachment", &[]);
+    w.leaf_with(
+        "cbc:EmbeddedDocumentBinaryObject",
+        &[("mimeCode", mime), ("filename", &filename)],
+        &pdf,
+    );
+    w.close("cac:Attachment");
+    w.close("cac:AdditionalDocumentReference");
+}
+
+// ==================== ВХОДЯЩИЕ ДОКУМЕНТЫ ====================
+
+/// Шапка входящего рачуна поставщика — то, что показываем в списке и по чему ищем поставщика.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PurchaseInvoiceHeader {
+    pub invoice_number: String,
+    /// 380 — рачун, 381 — кредитно одобрење, 386 — авансни рачун
+    pub invoice_type_code: Option<String>,
+    pub issue_date: Option<String>,
+    pub due_date: Option<String>,
+    pub currency: Option<String>,
+    pub total: Option<f64>,
+    pub vat_total: Option<f64>,
+    pub supplier_name: Option<String>,
+    pub supplier_pib: Option<String>,
+    pub supplier_mb: Option<String>,
+}
+
+/// Разбирает UBL Invoice/CreditNote, в т.ч. внутри конверта SEF (DocumentEnvelope).
+/// Пути сравниваются по локальным именам — префиксы пространств имён у поставщиков разные.
+pub fn parse_purchase_invoice(xml: &str) -> Result<PurchaseInvoiceHeader, String> {
+    use quick_xml::events::Event;
+    use quick_xml::Reader;
+
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut header = PurchaseInvoiceHeader::default();
+    let mut found_root = false;
+
+    loop {
+        match reader.read_event().map_err(|e| format!("Invalid UBL: {}", e))? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                found_root |= name == "Invoice" || name == "CreditNote";
+                stack.push(name);
+                text.clear();
+            }
+            Event::Text(t) => text.push_str(&t.decode().map_err(|e| format!("Invalid UBL: {}", e))?),
+            Event::GeneralRef(r) => {
+                let name = r.decode().map_err(|e| format!("Invalid UBL: {}", e))?;
+                if let Some(ch) = r.resolve_char_ref().map_err(|e| format!("Invalid UBL: {}", e))? {
+                    text.push(ch);
+                } else if let Some(resolved) = quick_xml::escape::resolve_predefined_entity(&name) {
+                    text.push_str(resolved);
+                }
+            }
+            Event::End(_) => {
+                if let Some(path) = document_path(&stack) {
+                    apply_purchase_field(&mut header, &path, text.trim());
+                }
+                stack.pop();
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if !found_root {
+        return Err("UBL document has no Invoice or CreditNote element".to_string());
+    }
+    if header.invoice_number.is_empty() {
+        return Err("UBL document has no cbc:ID".to_string());
+    }
+    Ok(header)
+}
+
+/// Путь элемента относительно корня Invoice/CreditNote: "AccountingSupplierParty/Party/EndpointID"
+fn document_path(stack: &[String]) -> Option<String> {
+    let root = stack.iter().position(|n| n == "Invoice" || n == "CreditNote")?;
+    Some(stack[root + 1..].join("/"))
+}
+
+fn apply_purchase_field(header: &mut PurchaseInvoiceHeader, path: &str, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    let text = Some(value.to_string());
+    match path {
+        "ID" => header.invoice_number = value.to_string(),
+        "InvoiceTypeCode" | "CreditNoteTypeCode" => header.invoice_type_code = text,
+        "IssueDate" => header.issue_date = text,
+        "DueDate" => header.due_date = text,
+        "DocumentCurrencyCode" => header.currency = text,
+        "LegalMonetaryTotal/PayableAmount" => header.total = value.parse().ok(),
+        // Первый TaxTotal — в валюте документа; второй (TaxCurrencyCode) пропускаем
+        "TaxTotal/TaxAmount" if header.vat_total.is_none() => header.vat_total = value.parse().ok(),
+        "AccountingSupplierParty/Party/PartyName/Name" => header.supplier_name = text,
+        "AccountingSupplierParty/Party/PartyLegalEntity/RegistrationName" if header.supplier_name.is_none() => {
+            header.supplier_name = text
+        }
+        "AccountingSupplierParty/Party/EndpointID" => header.supplier_pib = Some(digits_only(value)),
+        "AccountingSupplierParty/Party/PartyTaxScheme/CompanyID" if header.supplier_pib.is_none() => {
+            header.supplier_pib = Some(digits_only(value))
+        }
+        "AccountingSupplierParty/Party/PartyLegalEntity/CompanyID" => header.supplier_mb = Some(digits_only(value)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{Invoice, InvoiceItem};
+    use crate::{company, migrations};
+
+    const OUR_BANK_ACCOUNT: &str = "190-0000000085540-29";
+
+    /// Продавец — профиль фирмы, который кладёт миграция
+    fn our_company() -> UblParty {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        company::load_profile(&conn).unwrap().to_ubl_party()
+    }
+
+    /// Эталоны лежат в src-tauri/testdata/ubl; UPDATE_GOLDEN=1 cargo test — перезаписать их
+    /// после намеренного изменения формата.
+    fn assert_golden(name: &str, actual: &str) {
+        let path = std::path::Path::new(file!()).parent().unwrap().join("../testdata/ubl").join(name);
+        if std::env::var("UPDATE_GOLDEN").is_ok() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        assert_eq!(actual, expected, "UBL differs from {}", path.display());
+    }
+
+    fn document(currency: &str, items: Vec<InvoiceItem>) -> InvoiceWithItems {
+        InvoiceWithItems {
+            invoice: Invoice {
+                id: Some("inv-1".to_string()),
+                invoice_number: "12/2026".to_string(),
+                document_type: "racun".to_string(),
+                client_id: Some("1".to_string()),
+                client_name: Some("Kafana & Bar".to_string()),
+                date: "2026-03-01".to_string(),
+                due_date: Some("2026-03-16".to_string()),
+                total: 0.0,
+                status: "confirmed".to_string(),
+                notes: None,
+                created_at: None,
+                paid: None,
+                delivered: None,
+                currency: Some(currency.to_string()),
+                exchange_rate: if currency == "RSD" { None } else { Some(117.1732) },
+                exchange_rate_date: Some("2026-02-28".to_string()),
+                subtotal: None,
+                vat_total: None,
+                vat_rate: Some(pricing::VAT_STANDARD),
+                vat_exemption_code: None,
+                source_document_id: None,
+                amount_paid: None,
+                balance: None,
+                payment_status: None,
+                sef_invoice_id: None,
+                sef_status: None,
+                sef_sent_at: None,
+                sef_error: None,
+                created_by: None,
+                modified_by: None,
+            },
+            items,
+            tax_breakdown: Vec::new(),
+            source_documents: Vec::new(),
+            derived_documents: Vec::new(),
+        }
+    }
+
+    fn item(code: &str, quantity: f64, price: f64, discount_pct: Option<f64>, vat_rate: Option<f64>, exemption: Option<&str>) -> InvoiceItem {
+        InvoiceItem {
+            id: None,
+            invoice_id: "inv-1".to_string(),
+            product_id: code.to_string(),
+            product_name: format!("Proizvod {}", code),
+            quantity,
+            unit_weight_g: None,
+            price,
+            total: 0.0,
+            discount_pct,
+            vat_rate,
+            vat_exemption_code: exemption.map(str::to_string),
+            vat_amount: None,
+            credited_item_id: None,
+        }
+    }
+
+    fn customer() -> UblParty {
+        UblParty {
+            name: "Kafana & Bar".to_string(),
+            registration_name: Some("Kafana & Bar DOO Novi Sad".to_string()),
+            pib: Some("100000024".to_string()),
+            mb: Some("08000001".to_string()),
+            street: Some("Zmaj Jovina 1".to_string()),
+            city: Some("Novi Sad".to_string()),
+            postal_code: Some("21000".to_string()),
+            country_code: "RS".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_vat_categories() {
+        assert_eq!(vat_category(20.0, None), "S");
+        assert_eq!(vat_category(10.0, None), "S");
+        assert_eq!(vat_category(0.0, Some("PDV-RS-24-1-2")), "Z");
+        assert_eq!(vat_category(0.0, Some("PDV-RS-25-2-3")), "E");
+        assert_eq!(vat_category(0.0, Some("PDV-RS-10-2-1")), "AE");
+        assert_eq!(vat_category(0.0, Some("PDV-RS-11-1")), "O");
+    }
+
+    #[test]
+    fn test_golden_mixed_vat_with_otpremnica() {
+        let doc = document(
+            "RSD",
+            vec![
+                item("SR-001", 3.0, 333.33, Some(10.0), None, None),
+                item("SR-002", 2.0, 100.0, None, Some(pricing::VAT_REDUCED), None),
+                item("SR-003", 1.0, 50.0, None, Some(pricing::VAT_EXEMPT), Some("PDV-RS-24-1-16")),
+            ],
+        );
+        let otpremnica = OtpremnicaAttachment {
+            number: Some("o5/2026".to_string()),
+            pdf_base64: Some("JVBERi0xLjQK\nJSVFT0YK".to_string()),
+            pdf_filename: None,
+            pdf_mime: None,
+        };
+        let xml = build_invoice_xml(&UblInput {
+            document: &doc,
+            supplier: &our_company(),
+            customer: &customer(),
+            bank_account: OUR_BANK_ACCOUNT,
+            otpremnica: Some(&otpremnica),
+        })
+        .unwrap();
+        assert_golden("racun_mixed_vat.xml", &xml);
+    }
+
+    #[test]
+    fn test_golden_foreign_currency() {
+        let doc = document("EUR", vec![item("SR-010", 4.0, 12.5, None, None, None)]);
+        let xml = build_invoice_xml(&UblInput {
+            document: &doc,
+            supplier: &our_company(),
+            customer: &customer(),
+            bank_account: OUR_BANK_ACCOUNT,
+            otpremnica: None,
+        })
+        .unwrap();
+        assert_golden("racun_eur.xml", &xml);
+    }
+
+    #[test]
+    fn test_golden_credit_note() {
+        // Кредит-нота хранит количества со знаком минус
+        let mut doc = document("RSD", vec![item("SR-001", -2.0, 333.33, Some(10.0), None, None)]);
+        doc.invoice.invoice_number = "KO-1/2026".to_string();
+        doc.invoice.document_type = numbering::KNJIZNO_ODOBRENJE.to_string();
+        doc.invoice.date = "2026-03-10".to_string();
+        doc.source_documents.push(LinkedDocument {
+            id: "inv-0".to_string(),
+            invoice_number: "12/2026".to_string(),
+            document_type: "racun".to_string(),
+            date: "2026-03-01".to_string(),
+            total: 1199.99,
+        });
+        let (supplier, customer) = (our_company(), customer());
+        let build = |doc: &InvoiceWithItems| {
+            build_invoice_xml(&UblInput {
+                document: doc,
+                supplier: &supplier,
+                customer: &customer,
+                bank_account: OUR_BANK_ACCOUNT,
+                otpremnica: None,
+            })
+        };
+        assert_golden("knjizno_odobrenje.xml", &build(&doc).unwrap());
+
+        doc.source_documents.clear();
+        assert!(build(&doc).unwrap_err().contains("has no original racun"));
+    }
+
+    #[test]
+    fn test_customer_without_pib_is_rejected() {
+        let doc = document("RSD", vec![item("SR-001", 1.0, 10.0, None, None, None)]);
+        let mut buyer = customer();
+        buyer.pib = None;
+        let err = build_invoice_xml(&UblInput {
+            document: &doc,
+            supplier: &our_company(),
+            customer: &buyer,
+            bank_account: OUR_BANK_ACCOUNT,
+            otpremnica: None,
+        })
+        .unwrap_err();
+        assert_eq!(err, "Client Kafana & Bar has no PIB");
+    }
+
+    #[test]
+    fn test_parse_purchase_invoice_in_sef_envelope() {
+        let doc = document("RSD", vec![item("SR-001", 2.0, 100.0, None, None, None)]);
+        let xml = build_invoice_xml(&UblInput {
+            document: &doc,
+            supplier: &customer(),
+            customer: &our_company(),
+            bank_account: OUR_BANK_ACCOUNT,
+            otpremnica: None,
+        })
+        .unwrap();
+        let body = xml.trim_start_matches("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let envelope = format!(
+            "<env:DocumentEnvelope xmlns:env=\"urn:eFaktura:MinFinrs:envelop:schema\">\
+             <env:DocumentHeader><env:SalesInvoiceId>77</env:SalesInvoiceId></env:DocumentHeader>\
+             <env:DocumentBody>{}</env:DocumentBody></env:DocumentEnvelope>",
+            body
+        );
+
+        let header = parse_purchase_invoice(&envelope).unwrap();
+        assert_eq!(
+            header,
+            PurchaseInvoiceHeader {
+                invoice_number: "12/2026".to_string(),
+                invoice_type_code: Some("380".to_string()),
+                issue_date: Some("2026-03-01".to_string()),
+                due_date: Some("2026-03-16".to_string()),
+                currency: Some("RSD".to_string()),
+                total: Some(240.0),
+                vat_total: Some(40.0),
+                supplier_name: Some("Kafana & Bar".to_string()),
+                supplier_pib: Some("100000024".to_string()),
+                supplier_mb: Some("08000001".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,642 @@
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// Пароли короче не принимаем ни при создании, ни при смене
+pub const MIN_PASSWORD_LEN: usize = 10;
+/// Самые частые пароли (в нижнем регистре) — отклоняем даже при достаточной длине
+const COMMON_PASSWORDS: [&str; 8] = [
+    "password123",
+    "1234567890",
+    "qwerty1234",
+    "admin12345",
+    "lozinka123",
+    "parol12345",
+    "1q2w3e4r5t",
+    "0987654321",
+];
+
+/// После стольких неудачных входов подряд каждая следующая попытка ждёт THROTTLE_DELAY × n
+const THROTTLE_AFTER: i64 = 3;
+const THROTTLE_DELAY_SECS: i64 = 30;
+/// На столько неудачных входов подряд — блокировка на LOCKOUT_MINUTES
+const MAX_FAILED_LOGINS: i64 = 5;
+const LOCKOUT_MINUTES: i64 = 15;
+
+#[cfg(not(test))]
+const HASH_COST: u32 = bcrypt::DEFAULT_COST;
+// bcrypt с рабочей стоимостью в debug-сборке тестов идёт секундами
+#[cfg(test)]
+const HASH_COST: u32 = 4;
+
+/// Что разрешено пользователю. Команды проверяют право через `Session::require`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Permission {
+    #[serde(rename = "clients.view")]
+    ClientsView,
+    #[serde(rename = "clients.edit")]
+    ClientsEdit,
+    #[serde(rename = "products.view")]
+    ProductsView,
+    #[serde(rename = "products.edit")]
+    ProductsEdit,
+    #[serde(rename = "invoices.view")]
+    InvoicesView,
+    #[serde(rename = "invoices.edit")]
+    InvoicesEdit,
+    #[serde(rename = "invoices.delete")]
+    InvoicesDelete,
+    #[serde(rename = "payments.edit")]
+    PaymentsEdit,
+    #[serde(rename = "warehouse.view")]
+    WarehouseView,
+    #[serde(rename = "warehouse.edit")]
+    WarehouseEdit,
+    #[serde(rename = "suppliers.view")]
+    SuppliersView,
+    #[serde(rename = "suppliers.edit")]
+    SuppliersEdit,
+    #[serde(rename = "reports.view")]
+    ReportsView,
+    #[serde(rename = "settings.edit")]
+    SettingsEdit,
+    #[serde(rename = "audit.view")]
+    AuditView,
+    #[serde(rename = "users.manage")]
+    UsersManage,
+    /// Шифрование файла базы, резервные копии и восстановление
+    #[serde(rename = "database.manage")]
+    DatabaseManage,
+}
+
+use Permission::*;
+
+const ALL_PERMISSIONS: &[Permission] = &[
+    ClientsView, ClientsEdit, ProductsView, ProductsEdit, InvoicesView, InvoicesEdit, InvoicesDelete,
+    PaymentsEdit, WarehouseView, WarehouseEdit, SuppliersView, SuppliersEdit, ReportsView, SettingsEdit,
+    AuditView, UsersManage, DatabaseManage,
+];
+const ACCOUNTANT: &[Permission] = &[
+    ClientsView, ClientsEdit, ProductsView, InvoicesView, InvoicesEdit, InvoicesDelete, PaymentsEdit,
+    WarehouseView, SuppliersView, SuppliersEdit, ReportsView, SettingsEdit, AuditView,
+];
+const SALES: &[Permission] = &[
+    ClientsView, ClientsEdit, ProductsView, InvoicesView, InvoicesEdit, WarehouseView, ReportsView,
+];
+const WAREHOUSE: &[Permission] = &[
+    ClientsView, ProductsView, ProductsEdit, InvoicesView, WarehouseView, WarehouseEdit, SuppliersView,
+];
+/// Профиль клиента: документы и отпремнице только своего клиента (users.client_id,
+/// Session::require_scoped), каталог и склад — общие
+const CLIENT: &[Permission] = &[ProductsView, InvoicesView, WarehouseView];
+
+impl Permission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClientsView => "clients.view",
+            ClientsEdit => "clients.edit",
+            ProductsView => "products.view",
+            ProductsEdit => "products.edit",
+            InvoicesView => "invoices.view",
+            InvoicesEdit => "invoices.edit",
+            InvoicesDelete => "invoices.delete",
+            PaymentsEdit => "payments.edit",
+            WarehouseView => "warehouse.view",
+            WarehouseEdit => "warehouse.edit",
+            SuppliersView => "suppliers.view",
+            SuppliersEdit => "suppliers.edit",
+            ReportsView => "reports.view",
+            SettingsEdit => "settings.edit",
+            AuditView => "audit.view",
+            UsersManage => "users.manage",
+            DatabaseManage => "database.manage",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Accountant,
+    Sales,
+    Warehouse,
+    Client,
+}
+
+pub const ROLES: [Role; 5] = [Role::Admin, Role::Accountant, Role::Sales, Role::Warehouse, Role::Client];
+
+impl Role {
+    pub fn parse(role: &str) -> Option<Role> {
+        ROLES.into_iter().find(|r| r.as_str() == role.trim())
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Accountant => "accountant",
+            Role::Sales => "sales",
+            Role::Warehouse => "warehouse",
+            Role::Client => "client",
+        }
+    }
+
+    pub fn permissions(self) -> &'static [Permission] {
+        match self {
+            Role::Admin => ALL_PERMISSIONS,
+            Role::Accountant => ACCOUNTANT,
+            Role::Sales => SALES,
+            Role::Warehouse => WAREHOUSE,
+            Role::Client => CLIENT,
+        }
+    }
+
+    pub fn allows(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+/// Роль и её права — для экрана управления пользователями
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleInfo {
+    pub role: Role,
+    pub permissions: &'static [Permission],
+}
+
+pub fn roles() -> Vec<RoleInfo> {
+    ROLES
+        .into_iter()
+        .map(|role| RoleInfo {
+            role,
+            permissions: role.permissions(),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserAccount {
+    pub id: String,
+    pub username: String,
+    pub full_name: Option<String>,
+    pub role: String,
+    /// Только для роли client
+    pub client_id: Option<i64>,
+    pub is_active: bool,
+    pub created_at: String,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+    pub full_name: Option<String>,
+    pub client_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserUpdate {
+    pub role: Role,
+    pub full_name: Option<String>,
+    pub client_id: Option<i64>,
+}
+
+/// Учётка для входа: хэш пароля проверяет login, счётчики — check_throttle
+pub struct Credentials {
+    pub account: UserAccount,
+    pub password_hash: String,
+    pub failed_logins: i64,
+    pub last_failed_login: Option<String>,
+    pub locked_until: Option<String>,
+}
+
+/// Для первого запуска: пока пользователей нет, UI показывает создание администратора
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetupStatus {
+    pub needs_setup: bool,
+}
+
+const USER_COLUMNS: &str = "id, username, full_name, role, client_id, is_active, created_at, updated_at";
+
+fn account_from_row(row: &rusqlite::Row) -> rusqlite::Result<UserAccount> {
+    Ok(UserAccount {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        full_name: row.get(2)?,
+        role: row.get(3)?,
+        client_id: row.get(4)?,
+        is_active: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+pub fn list(conn: &Connection) -> Result<Vec<UserAccount>, String> {
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM users ORDER BY is_active DESC, username", USER_COLUMNS))
+        .map_err(|e| e.to_string())?;
+    let users = stmt
+        .query_map([], account_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(users)
+}
+
+pub fn load(conn: &Connection, id: &str) -> Result<UserAccount, String> {
+    conn.query_row(
+        &format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS),
+        [id],
+        account_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("User {} not found", id))
+}
+
+pub fn credentials(conn: &Connection, username: &str) -> Result<Option<Credentials>, String> {
+    conn.query_row(
+        &format!(
+            "SELECT {}, password, failed_logins, last_failed_login, locked_until FROM users WHERE username = ?1",
+            USER_COLUMNS
+        ),
+        [username.trim()],
+        |row| {
+            Ok(Credentials {
+                account: account_from_row(row)?,
+                password_hash: row.get(8)?,
+                failed_logins: row.get(9)?,
+                last_failed_login: row.get(10)?,
+                locked_until: row.get(11)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+/// Требования к паролю — одни для первого администратора, новых пользователей и смены пароля.
+pub fn check_password_strength(username: &str, password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(format!("Password must be at least {} characters", MIN_PASSWORD_LEN));
+    }
+    if !password.chars().any(char::is_alphabetic) || !password.chars().any(|c| !c.is_alphabetic()) {
+        return Err("Password must contain letters and digits or symbols".to_string());
+    }
+    let lower = password.to_lowercase();
+    let username = username.trim().to_lowercase();
+    if !username.is_empty() && lower.contains(&username) {
+        return Err("Password must not contain the username".to_string());
+    }
+    if COMMON_PASSWORDS.contains(&lower.as_str()) {
+        return Err("Password is too common".to_string());
+    }
+    Ok(())
+}
+
+fn hash_password(username: &str, password: &str) -> Result<String, String> {
+    check_password_strength(username, password)?;
+    bcrypt::hash(password, HASH_COST).map_err(|e| e.to_string())
+}
+
+pub fn setup_status(conn: &Connection) -> Result<SetupStatus, String> {
+    let users: i64 = conn
+        .query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    Ok(SetupStatus { needs_setup: users == 0 })
+}
+
+/// Владелец при первом запуске. Пока есть хоть один пользователь, команда не работает.
+pub fn create_initial_admin(tx: &Transaction, username: &str, password: &str, full_name: Option<&str>) -> Result<UserAccount, String> {
+    if !setup_status(tx)?.needs_setup {
+        return Err("Initial admin already exists".to_string());
+    }
+    create(
+        tx,
+        &NewUser {
+            username: username.to_string(),
+            password: password.to_string(),
+            role: Role::Admin,
+            full_name: full_name.map(str::to_string),
+            client_id: None,
+        },
+    )
+}
+
+fn parse_time(value: Option<&str>) -> Option<DateTime<Utc>> {
+    value
+        .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+        .map(|v| v.with_timezone(&Utc))
+}
+
+/// Можно ли сейчас пробовать пароль: блокировка после MAX_FAILED_LOGINS и
+/// растущая пауза после THROTTLE_AFTER неудач подряд.
+pub fn check_throttle(credentials: &Credentials, now: DateTime<Utc>) -> Result<(), String> {
+    if let Some(locked_until) = parse_time(credentials.locked_until.as_deref()) {
+        if now < locked_until {
+            return Err(format!("Account locked until {}", locked_until.to_rfc3339()));
+        }
+    }
+    if credentials.failed_logins >= THROTTLE_AFTER {
+        if let Some(last_failed) = parse_time(credentials.last_failed_login.as_deref()) {
+            let delay = Duration::seconds(THROTTLE_DELAY_SECS * (credentials.failed_logins - THROTTLE_AFTER + 1));
+            let wait = (last_failed + delay - now).num_seconds();
+            if wait > 0 {
+                return Err(format!("Too many failed attempts, retry in {} s", wait));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Неудачный вход. Возвращает true, если учётная запись только что заблокирована.
+/// Счётчик увеличивается в SQL внутри транзакции: параллельные попытки не теряют неудачи.
+pub fn record_failed_login(tx: &Transaction, id: &str, now: DateTime<Utc>) -> Result<bool, String> {
+    let failed: i64 = tx
+        .query_row(
+            "UPDATE users SET failed_logins = failed_logins + 1, last_failed_login = ?1 WHERE id = ?2
+             RETURNING failed_logins",
+            params![now.to_rfc3339(), id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    let locked = failed >= MAX_FAILED_LOGINS;
+    if locked {
+        // после блокировки счёт начинается заново
+        tx.execute(
+            "UPDATE users SET failed_logins = 0, locked_until = ?1 WHERE id = ?2",
+            params![(now + Duration::minutes(LOCKOUT_MINUTES)).to_rfc3339(), id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(locked)
+}
+
+/// Проверка пароля при входе. Для неизвестного логина bcrypt считается по заглушке,
+/// чтобы по времени ответа нельзя было узнать, есть ли такой пользователь.
+pub fn verify_password(credentials: Option<&Credentials>, password: &str) -> bool {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    let hash = match credentials {
+        Some(credentials) => credentials.password_hash.as_str(),
+        None => DUMMY_HASH.get_or_init(|| bcrypt::hash("unknown-user-password", HASH_COST).unwrap_or_default()),
+    };
+    bcrypt::verify(password, hash).unwrap_or(false) && credentials.is_some()
+}
+
+pub fn record_successful_login(conn: &Connection, id: &str, now: DateTime<Utc>) -> Result<(), String> {
+    conn.execute(
+        "UPDATE users SET failed_logins = 0, last_failed_login = NULL, locked_until = NULL, last_login_at = ?1 WHERE id = ?2",
+        params![now.to_rfc3339(), id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn clean(value: Option<&str>) -> Option<String> {
+    value.map(str::trim).filter(|v| !v.is_empty()).map(str::to_string)
+}
+
+/// Профиль клиента обязательно привязан к клиенту, остальные роли — нет.
+fn check_client(conn: &Connection, role: Role, client_id: Option<i64>) -> Result<Option<i64>, String> {
+    match (role, client_id) {
+        (Role::Client, None) => Err("Client profile requires a client".to_string()),
+        (Role::Client, Some(id)) => {
+            let exists: bool = conn
+                .query_row("SELECT EXISTS(SELECT 1 FROM clients WHERE id = ?1)", [id], |row| row.get(0))
+                .map_err(|e| e.to_string())?;
+            if exists {
+                Ok(Some(id))
+            } else {
+                Err(format!("Client {} not found", id))
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Без активного администратора управлять пользователями станет некому.
+fn ensure_admin_remains(tx: &Transaction, changed_id: &str) -> Result<(), String> {
+    let admins: i64 = tx
+        .query_row(
+            "SELECT COUNT(*) FROM users WHERE role = 'admin' AND is_active = 1 AND id <> ?1",
+            [changed_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if admins == 0 {
+        return Err("At least one active admin is required".to_string());
+    }
+    Ok(())
+}
+
+pub fn create(tx: &Transaction, user: &NewUser) -> Result<UserAccount, String> {
+    let username = user.username.trim();
+    if username.is_empty() {
+        return Err("Username is required".to_string());
+    }
+    let taken: bool = tx
+        .query_row("SELECT EXISTS(SELECT 1 FROM users WHERE username = ?1)", [username], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if taken {
+        return Err(format!("User {} already exists", username));
+    }
+    let client_id = check_client(tx, user.role, user.client_id)?;
+    let password_hash = hash_password(username, &user.password)?;
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = Utc::now().to_rfc3339();
+    tx.execute(
+        "INSERT INTO users (id, username, password, role, full_name, client_id, is_active, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1, ?7)",
+        params![id, username, password_hash, user.role.as_str(), clean(user.full_name.as_deref()), client_id, now],
+    )
+    .map_err(|e| e.to_string())?;
+    load(tx, &id)
+}
+
+pub fn update(tx: &Transaction, id: &str, update: &UserUpdate) -> Result<UserAccount, String> {
+    let current = load(tx, id)?;
+    if current.role == Role::Admin.as_str() && update.role != Role::Admin {
+        ensure_admin_remains(tx, id)?;
+    }
+    let client_id = check_client(tx, update.role, update.client_id)?;
+    tx.execute(
+        "UPDATE users SET role = ?1, full_name = ?2, client_id = ?3, updated_at = ?4 WHERE id = ?5",
+        params![update.role.as_str(), clean(update.full_name.as_deref()), client_id, Utc::now().to_rfc3339(), id],
+    )
+    .map_err(|e| e.to_string())?;
+    load(tx, id)
+}
+
+/// Пользователей не удаляем: их имя остаётся в журнале изменений и документах.
+pub fn set_active(tx: &Transaction, id: &str, active: bool) -> Result<UserAccount, String> {
+    let current = load(tx, id)?;
+    if !active && current.role == Role::Admin.as_str() {
+        ensure_admin_remains(tx, id)?;
+    }
+    tx.execute(
+        "UPDATE users SET is_active = ?1, updated_at = ?2 WHERE id = ?3",
+        params![active, Utc::now().to_rfc3339(), id],
+    )
+    .map_err(|e| e.to_string())?;
+    load(tx, id)
+}
+
+/// Новый пароль снимает и блокировку после неудачных входов.
+pub fn reset_password(tx: &Transaction, id: &str, new_password: &str) -> Result<(), String> {
+    let account = load(tx, id)?;
+    let password_hash = hash_password(&account.username, new_password)?;
+    tx.execute(
+        "UPDATE users SET password = ?1, updated_at = ?2, failed_logins = 0, last_failed_login = NULL, locked_until = NULL
+         WHERE id = ?3",
+        params![password_hash, Utc::now().to_rfc3339(), id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Смена своего пароля: нужен текущий.
+pub fn change_password(tx: &Transaction, id: &str, old_password: &str, new_password: &str) -> Result<(), String> {
+    let password_hash: String = tx
+        .query_row("SELECT password FROM users WHERE id = ?1", [id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if !bcrypt::verify(old_password, &password_hash).unwrap_or(false) {
+        return Err("Current password is incorrect".to_string());
+    }
+    reset_password(tx, id, new_password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        migrations::run(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO clients (id, name, mb, created_at) VALUES (7, 'Klijent', '21000001', 'now')",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn new_user(username: &str, role: Role) -> NewUser {
+        NewUser {
+            username: username.to_string(),
+            password: "Skladiste-2026".to_string(),
+            role,
+            full_name: None,
+            client_id: None,
+        }
+    }
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(Role::Admin.allows(Permission::UsersManage));
+        assert!(Role::Accountant.allows(Permission::InvoicesDelete));
+        assert!(!Role::Warehouse.allows(Permission::InvoicesDelete));
+        assert!(Role::Warehouse.allows(Permission::WarehouseEdit));
+        assert!(!Role::Sales.allows(Permission::PaymentsEdit));
+        assert!(!Role::Client.allows(Permission::ClientsView));
+        assert_eq!(Role::parse("warehouse"), Some(Role::Warehouse));
+        assert_eq!(Role::parse("root"), None);
+        assert_eq!(
+            serde_json::to_value(Role::Sales.permissions()).unwrap()[0],
+            serde_json::json!("clients.view")
+        );
+    }
+
+    #[test]
+    fn test_create_disable_and_passwords() {
+        let mut conn = test_conn();
+        let tx = conn.transaction().unwrap();
+        let admin = create(&tx, &new_user("admin", Role::Admin)).unwrap();
+        let ana = create(&tx, &new_user(" ana ", Role::Warehouse)).unwrap();
+        assert_eq!(ana.username, "ana");
+        assert!(create(&tx, &new_user("ana", Role::Sales)).unwrap_err().contains("already exists"));
+        let short = NewUser { password: "kratka".to_string(), ..new_user("marko", Role::Sales) };
+        assert!(create(&tx, &short).unwrap_err().contains("at least 10"));
+        // профиль клиента без клиента не создаётся
+        assert!(create(&tx, &new_user("svda", Role::Client)).is_err());
+        let client = NewUser { client_id: Some(7), ..new_user("svda", Role::Client) };
+        assert_eq!(create(&tx, &client).unwrap().client_id, Some(7));
+
+        // единственного администратора нельзя отключить или понизить
+        assert!(set_active(&tx, &admin.id, false).is_err());
+        let demote = UserUpdate { role: Role::Sales, full_name: None, client_id: None };
+        assert!(update(&tx, &admin.id, &demote).is_err());
+        assert!(!set_active(&tx, &ana.id, false).unwrap().is_active);
+
+        assert!(change_password(&tx, &ana.id, "pogresna", "nova-lozinka").is_err());
+        change_password(&tx, &ana.id, "Skladiste-2026", "nova-lozinka").unwrap();
+        let stored = credentials(&tx, "ana").unwrap().unwrap();
+        assert!(verify_password(Some(&stored), "nova-lozinka"));
+        assert!(!verify_password(Some(&stored), "Skladiste-2026"));
+        assert!(!stored.account.is_active);
+        // неизвестный логин тоже проходит через bcrypt и не принимает никакой пароль
+        assert!(credentials(&tx, "nepoznat").unwrap().is_none());
+        assert!(!verify_password(None, "unknown-user-password"));
+    }
+
+    #[test]
+    fn test_password_strength() {
+        assert!(check_password_strength("ana", "Skladiste-2026").is_ok());
+        assert!(check_password_strength("ana", "kratka1").unwrap_err().contains("at least"));
+        assert!(check_password_strength("ana", "samoslovaaa").unwrap_err().contains("letters and digits"));
+        assert!(check_password_strength("ana", "12345678901").unwrap_err().contains("letters and digits"));
+        assert!(check_password_strength("marko", "Marko-2026!").unwrap_err().contains("username"));
+        assert!(check_password_strength("ana", "Password123").unwrap_err().contains("too common"));
+    }
+
+    #[test]
+    fn test_initial_admin_only_once() {
+        let mut conn = test_conn();
+        assert!(setup_status(&conn).unwrap().needs_setup);
+        let tx = conn.transaction().unwrap();
+        assert!(create_initial_admin(&tx, "vlasnik", "admin", None).is_err());
+        let admin = create_initial_admin(&tx, "vlasnik", "Firma#Beograd1", Some("Vlasnik")).unwrap();
+        assert_eq!(admin.role, "admin");
+        assert!(!setup_status(&tx).unwrap().needs_setup);
+        let again = create_initial_admin(&tx, "drugi", "Drugi#Admin22", None).unwrap_err();
+        assert_eq!(again, "Initial admin already exists");
+    }
+
+    #[test]
+    fn test_throttle_and_lockout() {
+        let mut conn = test_conn();
+        let tx = conn.transaction().unwrap();
+        let ana = create(&tx, &new_user("ana", Role::Sales)).unwrap();
+        let start = Utc::now();
+        let state = |tx: &Transaction| credentials(tx, "ana").unwrap().unwrap();
+
+        for n in 0..3 {
+            assert!(check_throttle(&state(&tx), start).is_ok());
+            assert!(!record_failed_login(&tx, &ana.id, start + Duration::seconds(n)).unwrap());
+        }
+        assert_eq!(state(&tx).failed_logins, 3);
+        // после трёх неудач — пауза 30 с
+        let third = start + Duration::seconds(2);
+        assert!(check_throttle(&state(&tx), third + Duration::seconds(10)).unwrap_err().contains("retry in 20 s"));
+        assert!(check_throttle(&state(&tx), third + Duration::seconds(30)).is_ok());
+
+        record_failed_login(&tx, &ana.id, third + Duration::seconds(30)).unwrap();
+        let locked_at = third + Duration::seconds(120);
+        assert!(record_failed_login(&tx, &ana.id, locked_at).unwrap());
+        let locked = state(&tx);
+        assert!(check_throttle(&locked, locked_at + Duration::minutes(14)).unwrap_err().starts_with("Account locked until"));
+        assert!(check_throttle(&locked, locked_at + Duration::minutes(16)).is_ok());
+
+        // успешный вход и сброс пароля снимают счётчики
+        record_successful_login(&tx, &ana.id, locked_at + Duration::minutes(16)).unwrap();
+        let cleared = state(&tx);
+        assert_eq!((cleared.failed_logins, cleared.locked_until), (0, None));
+    }
+}
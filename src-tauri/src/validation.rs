@@ -0,0 +1,283 @@
+use serde::Serialize;
+use std::fmt;
+
+use crate::commands::{Client, Supplier};
+
+/// Названия Сербии, которые встречаются в clients.country / suppliers.country
+/// (справочник стран хранит русские названия, старые записи — сербские и коды).
+const DOMESTIC_COUNTRIES: [&str; 5] = ["сербия", "srbija", "србија", "serbia", "rs"];
+
+pub const CODE_REQUIRED: &str = "required";
+pub const CODE_FORMAT: &str = "format";
+pub const CODE_CHECKSUM: &str = "checksum";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    /// Имя поля как в JSON команды: mb, pib
+    pub field: &'static str,
+    /// required / format / checksum
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Результат проверки: errors блокируют сохранение, warnings — только показываются
+/// (иностранные клиенты и поставщики, у которых свои форматы номеров).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    pub errors: Vec<FieldError>,
+    pub warnings: Vec<FieldError>,
+}
+
+/// Сколько строк таблицы ссылается на удаляемую запись
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub table: &'static str,
+    pub count: i64,
+}
+
+/// Ошибка команд, у которых есть проверка полей: фронтенд получает объект
+/// `{ kind: "validation", message, fields }` и может подсветить конкретные поля.
+/// Удаление записи, на которую ссылаются документы, — `{ kind: "inUse", entity, id, usages }`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum CommandError {
+    Validation { message: String, fields: Vec<FieldError> },
+    InUse {
+        message: String,
+        entity: &'static str,
+        id: String,
+        usages: Vec<Usage>,
+    },
+    Other { message: String },
+}
+
+impl CommandError {
+    /// «client 5 is in use by 3 invoices, 1 deliveries»
+    pub fn in_use(entity: &'static str, id: impl ToString, usages: Vec<Usage>) -> Self {
+        let id = id.to_string();
+        let by = usages
+            .iter()
+            .map(|u| format!("{} {}", u.count, u.table.replace('_', " ")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        CommandError::InUse {
+            message: format!("{} {} is in use by {}", entity, id, by),
+            entity,
+            id,
+            usages,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Validation { message, .. }
+            | CommandError::InUse { message, .. }
+            | CommandError::Other { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Other { message }
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::Other {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl ValidationReport {
+    /// Err, если есть блокирующие ошибки; предупреждения пишем в лог.
+    pub fn into_result(self, entity: &str) -> Result<(), CommandError> {
+        for warning in &self.warnings {
+            println!("⚠️ {} {}: {}", entity, warning.field, warning.message);
+        }
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        let message = self
+            .errors
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect::<Vec<_>>()
+            .join("; ");
+        Err(CommandError::Validation {
+            message,
+            fields: self.errors,
+        })
+    }
+
+    fn push(&mut self, warn_only: bool, error: FieldError) {
+        if warn_only {
+            self.warnings.push(error);
+        } else {
+            self.errors.push(error);
+        }
+    }
+}
+
+/// Контрольная цифра PIB по ISO 7064 MOD 11,10.
+pub fn pib_checksum_ok(pib: &str) -> bool {
+    let digits: Vec<u32> = pib.chars().filter_map(|c| c.to_digit(10)).collect();
+    if digits.len() != 9 || pib.chars().count() != 9 {
+        return false;
+    }
+    let mut product = 10;
+    for digit in &digits[..8] {
+        let mut sum = (digit + product) % 10;
+        if sum == 0 {
+            sum = 10;
+        }
+        product = (2 * sum) % 11;
+    }
+    (11 - product) % 10 == digits[8]
+}
+
+fn is_domestic(country: Option<&str>) -> bool {
+    match country.map(str::trim).filter(|c| !c.is_empty()) {
+        None => true,
+        Some(c) => DOMESTIC_COUNTRIES.contains(&c.to_lowercase().as_str()),
+    }
+}
+
+fn check_pib(report: &mut ValidationReport, pib: Option<&str>, warn_only: bool) {
+    let Some(pib) = pib.map(str::trim).filter(|p| !p.is_empty()) else {
+        return;
+    };
+    if pib.len() != 9 || !pib.chars().all(|c| c.is_ascii_digit()) {
+        report.push(
+            warn_only,
+            FieldError {
+                field: "pib",
+                code: CODE_FORMAT,
+                message: format!("PIB {} must be 9 digits", pib),
+            },
+        );
+    } else if !pib_checksum_ok(pib) {
+        report.push(
+            warn_only,
+            FieldError {
+                field: "pib",
+                code: CODE_CHECKSUM,
+                message: format!("PIB {} has an invalid check digit", pib),
+            },
+        );
+    }
+}
+
+fn check_mb(report: &mut ValidationReport, mb: Option<&str>, required: bool, warn_only: bool) {
+    match mb.map(str::trim).filter(|m| !m.is_empty()) {
+        None if required => report.errors.push(FieldError {
+            field: "mb",
+            code: CODE_REQUIRED,
+            message: "MB is required".to_string(),
+        }),
+        None => {}
+        Some(mb) if mb.len() != 8 || !mb.chars().all(|c| c.is_ascii_digit()) => report.push(
+            warn_only,
+            FieldError {
+                field: "mb",
+                code: CODE_FORMAT,
+                message: format!("MB {} must be 8 digits", mb),
+            },
+        ),
+        Some(_) => {}
+    }
+}
+
+/// Клиент: MB обязателен всегда (по нему идёт синхронизация), формат и PIB проверяются
+/// строго только для сербских клиентов.
+pub fn validate_client(client: &Client) -> ValidationReport {
+    let warn_only = !is_domestic(client.country.as_deref());
+    let mut report = ValidationReport::default();
+    check_mb(&mut report, Some(&client.mb), true, warn_only);
+    check_pib(&mut report, client.pib.as_deref(), warn_only);
+    report
+}
+
+/// Поставщик: у иностранных вместо MB заполнен reg_number — для них только предупреждения.
+pub fn validate_supplier(supplier: &Supplier) -> ValidationReport {
+    let has_reg_number = supplier.reg_number.as_deref().is_some_and(|r| !r.trim().is_empty());
+    let warn_only = has_reg_number || !is_domestic(supplier.country.as_deref());
+    let mut report = ValidationReport::default();
+    check_mb(&mut report, supplier.mb.as_deref(), false, warn_only);
+    check_pib(&mut report, supplier.pib.as_deref(), warn_only);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pib_checksum() {
+        assert!(pib_checksum_ok("114407658"));
+        assert!(pib_checksum_ok("114703885"));
+        assert!(!pib_checksum_ok("114407659"));
+        assert!(!pib_checksum_ok("11440765"));
+        assert!(!pib_checksum_ok("11440765a"));
+    }
+
+    #[test]
+    fn test_domestic_errors_and_foreign_warnings() {
+        let mut report = ValidationReport::default();
+        check_mb(&mut report, Some("2201930"), true, false);
+        check_pib(&mut report, Some("114407659"), false);
+        let fields: Vec<_> = report.errors.iter().map(|e| (e.field, e.code)).collect();
+        assert_eq!(fields, vec![("mb", CODE_FORMAT), ("pib", CODE_CHECKSUM)]);
+        assert!(report.warnings.is_empty());
+
+        let mut report = ValidationReport::default();
+        check_mb(&mut report, Some("HRB 12345"), true, true);
+        check_pib(&mut report, Some("DE123456789"), true);
+        assert!(report.errors.is_empty());
+        assert_eq!(report.warnings.len(), 2);
+        assert!(report.clone().into_result("client").is_ok());
+
+        // пустой MB у клиента — ошибка даже для иностранца
+        let mut report = ValidationReport::default();
+        check_mb(&mut report, Some("  "), true, true);
+        assert_eq!(report.errors[0].code, CODE_REQUIRED);
+    }
+
+    #[test]
+    fn test_is_domestic() {
+        assert!(is_domestic(None));
+        assert!(is_domestic(Some("Сербия")));
+        assert!(is_domestic(Some("Srbija")));
+        assert!(!is_domestic(Some("Германия")));
+    }
+
+    #[test]
+    fn test_validation_error_serializes_per_field() {
+        let report = ValidationReport {
+            errors: vec![FieldError {
+                field: "pib",
+                code: CODE_CHECKSUM,
+                message: "PIB 114407659 has an invalid check digit".to_string(),
+            }],
+            warnings: Vec::new(),
+        };
+        let err = report.into_result("client").unwrap_err();
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "kind": "validation",
+                "message": "PIB 114407659 has an invalid check digit",
+                "fields": [{ "field": "pib", "code": "checksum", "message": "PIB 114407659 has an invalid check digit" }]
+            })
+        );
+    }
+}